@@ -5,15 +5,159 @@
 //!
 //! # Features
 //!
-//! - [`yap complete`](crate::complete): read a prompt from `STDIN`, print the
-//!   response to `STDOUT`
-//! - [`yap chat [prompt]`](crate::chat): chat with an LLM in your terminal
+//! - [`yap complete`](yap_core::complete): read a prompt from `STDIN`, print
+//!   the response to `STDOUT`
+//!   - `yap complete --batch`: complete JSONL prompts concurrently, printing
+//!     JSONL results in input order
+//!   - `yap complete --lang rust` (or `--filename foo.rs`): tell the LLM what
+//!     language it is completing, and strip any markdown fences from its
+//!     response (see `--keep-fences`)
+//!   - `yap complete --suffix-file after.txt`: fill in the middle, between
+//!     `STDIN` and the suffix (or at a `<CURSOR>` marker in `STDIN`)
+//!   - `yap complete --prompt-file prompt.txt < code.rs`: reuse a long prompt,
+//!     with `STDIN` sent as context
+//! - [`yap batch submit|status|fetch`](yap_core::batch): run large jobs through
+//!   the OpenAI Batch API at half the cost
+//! - [`yap finetune prepare|submit|status`](yap_core::finetune): fine-tune a
+//!   model on your chats, and use it anywhere with `yap --model ft:...`
+//! - [`yap ask [question]`](yap_core::ask): ask a one-off question, optionally
+//!   with `--file` context, without touching chat history
+//! - [`yap ctx add|remove|list|clear`](yap_core::ctx): keep a per-project set
+//!   of files which are attached to every `yap chat` and `yap complete` request
+//! - [`yap index`](yap_core::index): embed the project's files, caching
+//!   embeddings so that only changed code is embedded again
+//! - [`yap similar`](yap_core::similar): find indexed code which is similar to
+//!   a snippet on `STDIN`, i.e, to spot duplicated logic before refactoring
+//! - [`yap map`](yap_core::repomap): print a compact map of the project's files
+//!   and public symbols; attach it to `chat`, `complete`, or `ask` with
+//!   `--repo-map`
+//! - [`yap chat [prompt]`](yap_core::chat): chat with an LLM in your terminal
 //!   - `yap chat --new [prompt]`: begin a chat session in your terminal, with
-//!     persistent chat history via [crate::db]
+//!     persistent chat history via [yap_core::db]
 //!   - `yap chat --resume [chat-id]`: resume a previous chat from `yap chatlog`
-//! - [`yap annotate`](crate::annotate): receive feedback on chunks of code
-//! - [`yap chatlog`](crate::chatlog): view chat history
-//! - [`yap recap`](crate::recap): view your conversation so far
+//!   - each project (git repository, or directory) has its own active chat, so
+//!     chatting in one project doesn't change the active chat in another
+//!   - `yap chat --new --system-file <file>`: begin a chat with a custom system
+//!     prompt
+//!   - `yap chat --set-model <model>`: switch the model which the chat is
+//!     pinned to
+//!   - `yap chat --tag <tag>`: tag the chat, so that `yap chatlog --tag <tag>`
+//!     can find it
+//!   - `yap chat --tmux-pane [prompt]`: attach the scrollback of the current
+//!     tmux pane, i.e, to ask what an error means
+//!   - `yap chat --ephemeral [prompt]`: ask a throwaway question in the context
+//!     of the active chat, without saving it to the chat history
+//!   - `yap chat --jsonl`: send role/content messages from `STDIN` as JSONL,
+//!     and print the reply as JSONL, without touching chat history
+//!   - `yap chat --amend [prompt]`: replace your last message, i.e, to fix a
+//!     typo, and get a new reply instead of the old one
+//!   - `yap chat --retry`: discard the last reply and ask again, optionally
+//!     with `--temperature` or `yap --model`
+//!   - `yap chat --pop`: remove the last exchange from the chat, so that it
+//!     doesn't derail the rest of the conversation
+//!   - `yap chat --stream [prompt]`: print the response as it is generated;
+//!     Ctrl-C stops early and keeps the partial response
+//!   - set `$YAP_PASSPHRASE` (or `encryption_key_file` in `config.json`) to
+//!     [encrypt](yap_core::crypt) chat history at rest
+//! - [`yap annotate`](yap_core::annotate): receive feedback on chunks of code
+//!   - `yap annotate --interactive`: accept, reject, or edit each annotation
+//!     before it is written
+//!   - `yap annotate --format json|sarif`: print annotations for CI systems and
+//!     editors instead of inlining them into the file
+//!   - annotations may apply to a range of lines, such as a whole loop; they
+//!     are marked "applies to lines 10-18", and JSON output keeps
+//!     `line_number` (the first line) and adds `line_end`
+//!   - `yap annotate --format markdown`: print annotations for a pull request
+//!     review, with suggested replacements as GitHub suggestions
+//!   - `yap annotate --format patch | yap apply`: apply the replacements which
+//!     annotations suggest
+//!   - `yap annotate --diff`: only annotate lines changed in `git diff` (or a
+//!     diff on `STDIN`)
+//!   - `yap annotate --dir src --include '*.rs' --exclude 'tests/*'`: annotate
+//!     a whole tree, and print how many annotations each file received
+//!   - `yap annotate --symbol save_chat`: annotate one function, type, or
+//!     `impl` block, found with [tree-sitter](yap_core::syntax) (or heuristics,
+//!     for languages without a grammar)
+//!   - `yap annotate --position eol`: append short notes to the end of the
+//!     annotated line, or place them `after` it
+//!   - `yap annotate --summary`: begin the file with an overview, for a quick
+//!     orientation before the line-level notes
+//! - [`yap apply`](yap_core::apply): apply patches written by an LLM
+//!   - `yap apply --chat --interactive`: review each hunk before it is applied,
+//!     like `git add -p`
+//! - [`yap refactor`](yap_core::refactor): make coordinated changes across
+//!   several files
+//! - [`yap agent --command "cargo test" [goal]`](yap_core::agent): let the LLM
+//!   read files, edit them, and run a build or test command, with confirmation,
+//!   for up to `--max-steps` rounds until the goal is reached
+//!   - [`sandbox`](yap_core::sandbox) in `config.json`: run the command with
+//!     only allowed environment variables, a timeout, and optionally no network
+//! - [`yap run workflow.yaml`](yap_core::workflow): run a multi-step pipeline,
+//!   i.e, summarize, then review, then write tests, where each step can use
+//!   the outputs of earlier steps
+//! - [`yap undo`](yap_core::backup): restore the files changed by the last
+//!   `agent`, `annotate`, `apply`, `refactor`, or `run`, even outside of git
+//! - [`yap changelog <range>`](yap_core::changelog): generate release notes
+//!   from git history
+//! - [`yap review`](yap_core::review): review your changes before committing
+//!   - `yap review --ci --base origin/main --fail-on warning`: gate CI on an
+//!     LLM review, and write a JSON report
+//!   - [`.yaprules.json`](yap_core::rules): naming, error-handling, and
+//!     forbidden API rules for `yap review` and `yap annotate` to enforce
+//!   - `yap review --github owner/repo#123 --post`: review a GitHub pull
+//!     request, and leave the findings as a pending review
+//!   - `yap review --gitlab group/project!123 --post`: review a GitLab merge
+//!     request, and start discussions on the lines with findings
+//! - [`yap commit`](yap_core::commit): write commit messages, or check them
+//!   with `--verify`
+//!   - `yap commit --convention conventional|gitmoji|custom-template`: make
+//!     messages follow a convention, rewriting them until they do
+//! - [`yap hook install`](yap_core::hook): run `yap review` and
+//!   `yap commit --verify` from git hooks
+//! - [`yap chatlog`](yap_core::chatlog): view chat history
+//!   - `yap chatlog --format json`: print chat history as JSON records
+//!   - `yap chatlog --pick`: fuzzy-search chats, and activate the one you pick
+//!   - `yap chatlog --grep <regex>`: search every chat for matching messages
+//!   - `yap chatlog --diff <uuid> <uuid>`: show where two chats diverge
+//!   - `yap chatlog --since <date> --before <date>` (or `--today`): find chats
+//!     from a particular day, i.e, `--since 2024-12-03 --before 2024-12-04`
+//! - [`output_filters`](yap_core::filter): post-process each subcommand's
+//!   output before it is printed or written, i.e, by stripping fences or
+//!   running `rustfmt`
+//! - [`confirm`](yap_core::confirm) in `config.json`: choose which file writes,
+//!   commands, and patches need confirmation, or never skip it with `strict`
+//! - `pre_request` and `post_response` hooks in `config.json`: pipe each
+//!   request and response to a shell command, for logging, notifications, or
+//!   policy enforcement
+//! - [`yap export`](yap_core::archive): back up your chats and settings, and
+//!   restore them with `yap import`
+//! - [`yap stats`](yap_core::usage): summarize your API usage
+//! - [`yap models`](yap_core::models): list the models your API key can use,
+//!   and what each is for
+//! - [`yap serve`](yap_core::serve): serve editor plugins from a long-lived
+//!   process
+//!   - `yap daemon`: serve the same protocol over a Unix socket, keeping
+//!     connections to OpenAI warm between requests; while it runs, `yap
+//!     complete`, `yap chat`, and `yap annotate` are answered by it (set
+//!     `YAP_NO_DAEMON=1` to opt out)
+//!   - [`yap-core`](yap_core): or, embed `yap`'s logic in Rust tools and
+//!     plugins directly; the `yap` binary is a thin CLI over this library
+//! - [`yap pin --message <n> --file <file>`](yap_core::pin): always send a
+//!   message or file with the active chat, i.e, project conventions or an API
+//!   spec
+//! - [`yap memory add|list|rm`](yap_core::memory): remember your preferences in
+//!   every new chat, i.e, `yap memory add "I prefer thiserror over anyhow"`
+//! - [`yap recap`](yap_core::recap): view your conversation so far
+//!   - `yap recap --stats`: show the model, tokens, and cost of each reply, and
+//!     what the whole conversation cost
+//! - [`yap watch --file <file> --prompt <prompt>`](yap_core::watch): ask about
+//!   a file each time it changes, or re-run any command given after `--`
+//! - [`yap imagine [prompt]`](yap_core::imagine): generate an image, and save
+//!   it as a PNG
+//! - [`yap say`](yap_core::say): read `STDIN` (or the last chat reply, with
+//!   `--last`) aloud, or save it to an audio file
+//! - [`yap transcribe <file>`](yap_core::transcribe): transcribe an audio file,
+//!   or send it to the active chat with `--into-chat`
 //!
 //! # Installation
 //!
@@ -32,6 +176,9 @@
 //! # Setup
 //!
 //! To start using `yap` you need to set `OPENAI_API_KEY` in your environment.
+//! Alternatively, `yap` can read the key from a file, a command like `pass show
+//! openai`, or your OS keychain; see `api_key_file`, `api_key_command`, and
+//! `api_key_keychain` in [yap_core::config].
 //!
 //! With an API key available, you can start using `yap`!
 //!
@@ -52,7 +199,7 @@
 //!
 //! # Additional Documentation
 //!
-//! Links below to `[crate::config]`, etc. will be functional if you view the
+//! Links below to `[yap_core::config]`, etc. will be functional if you view the
 //! cargo-docs for this crate;
 //!
 //! ```bash
@@ -61,21 +208,87 @@
 //!
 //! # Configuration
 //!
-//! See [crate::config].
+//! See [yap_core::config].
 //!
 //! # Persistence
 //!
-//! See [crate::db].
+//! See [yap_core::db]. Files are backed up before `yap` changes them; see
+//! [yap_core::backup].
+//!
+//! # Exit Codes
+//!
+//! `yap` exits with one of these codes, so that scripts can tell what went
+//! wrong without parsing error messages.
+//!
+//! | Code | Meaning |
+//! | ---- | ------- |
+//! | 0 | Success |
+//! | 1 | Any other error |
+//! | 2 | The OpenAI API key is missing or was rejected |
+//! | 3 | A network error; OpenAI could not be reached |
+//! | 4 | The model refused the request |
+//! | 5 | The prompt or chat is too long for the model's context window |
+//! | 6 | Rate limited, or out of credits (HTTP 429) |
+//! | 7 | Any other unsuccessful HTTP response |
+//! | 8 | `yap review` found problems at or above `--fail-on` |
+//! | 64 | Invalid command-line arguments |
 //!
 //! # Debugging
 //!
-//! `yap` uses the [log] and [env_logger] crates. You can configure logging
-//! via the `RUST_LOG` environment variable;
+//! `yap` uses the [log] and [env_logger](https://docs.rs/env_logger) crates.
+//! You can configure logging via the `RUST_LOG` environment variable;
 //!
 //! ```bash
 //! echo "tell me a story" | RUST_LOG=debug yap complete
 //! ```
 //!
+//! To diagnose failures after the fact (i.e, under an editor plugin, which
+//! hides `STDERR`), set `log_file` in `config.json` to also log to
+//! `~/.local/state/yap/logs/yap.log`, which is rotated by size; see
+//! [yap_core::logfile].
+//!
+//! To see exactly what would be sent to OpenAI (i.e, to debug the prompts
+//! built by `yap annotate`), pass `--dry-run`. The request is printed to
+//! `STDOUT` instead of being sent;
+//!
+//! ```bash
+//! yap --dry-run annotate --file src/main.rs
+//! ```
+//!
+//! Similarly, `--show-prompt` prints each prompt to `STDERR` before it is sent,
+//! with an estimated token count for each message. This shows how the system
+//! prompt, context files, and chat history add up.
+//!
+//! When `yap` can't make sense of a response (i.e, from a proxy which returns
+//! something unusual), pass `--debug-http <dir>` to save each request and its
+//! response to a JSON file in `<dir>`, with the API key scrubbed. These files
+//! can be attached to bug reports. `yap serve` and `yap daemon` don't support
+//! it.
+//!
+//! To run `yap` offline (i.e, in CI, or for a demo), pass `--provider mock` or
+//! set `YAP_MOCK=1`. No API key is needed; requests are answered from fixture
+//! files in `$YAP_MOCK_DIR` (or `$XDG_CONFIG_HOME/yap/mock`), named for the
+//! endpoint, like `chat-completions.json`;
+//!
+//! ```json
+//! { "content": "Hello from the mock!" }
+//! ```
+//!
+//! Numbered fixtures (`chat-completions.2.json`) answer the second request, and
+//! so on, to script a conversation. Files saved by `--debug-http` work as
+//! fixtures, too. Without a fixture, chat completions get a canned reply.
+//!
+//! To capture realistic fixtures, set `YAP_RECORD=<dir>`; responses from
+//! OpenAI are saved to `<dir>` (with the API key scrubbed) as well as used.
+//! `YAP_REPLAY=<dir>` serves them back in the same order, and fails on any
+//! request which wasn't recorded. `yap`'s own regression tests replay the
+//! fixtures in `yap-core/fixtures/`.
+//!
+//! ```bash
+//! YAP_RECORD=fixtures/hello yap chat --new hello
+//! YAP_REPLAY=fixtures/hello yap chat --new hello
+//! ```
+//!
 //! # Alternatives to `yap`
 //!
 //! A brief review of other CLI tool sfor working with LLMs, comparing them
@@ -151,20 +364,16 @@
 //!
 //! </details>
 
-mod annotate;
-mod chat;
-mod chatlog;
-mod complete;
-mod config;
-mod constants;
-mod db;
-mod err;
-mod openai;
-mod recap;
-mod term;
-
 use clap::{Parser, Subcommand};
+use log::warn;
 use std::{path::PathBuf, process::exit};
+use yap_core::{
+    agent, annotate, apply, archive, ask, backup, batch, changelog, chat,
+    chatlog, commit, complete, context, ctx, date, err, finetune, github,
+    gitlab, hook, imagine, index, logfile, memory, models, openai, pin, recap,
+    refactor, repomap, review, say, serve, similar, transcribe, usage, watch,
+    workflow,
+};
 
 /// `yap`'s command-line interface.
 #[derive(Debug, Parser)]
@@ -173,97 +382,1195 @@ use std::{path::PathBuf, process::exit};
 struct Cli {
     #[command(subcommand)]
     command: Command,
-    #[clap(value_enum)]
+    /// Overrides `model` and `command_models` in `config.json`. One of
+    /// gpt-4o-mini, gpt-4o, o1, o1-mini, or o3-mini, or a fine-tuned model ID
+    /// starting with `ft:`.
     #[arg(short, long)]
     model: Option<openai::Model>,
+    /// Sample deterministically, on a best-effort basis. The
+    /// `system_fingerprint` of each response is printed to STDERR; repeated
+    /// requests with the same seed and fingerprint should return the same
+    /// result.
+    #[arg(long)]
+    seed: Option<i64>,
+    /// How hard reasoning models (o1, o3-mini, etc.) should think. Ignored
+    /// for other models, including o1-mini.
+    #[arg(long, value_enum)]
+    reasoning_effort: Option<openai::ReasoningEffort>,
+    /// Print the first request which would be sent to OpenAI, and stop
+    /// without sending it. No API key is needed.
+    #[arg(long, default_value = "false")]
+    dry_run: bool,
+    /// Print each prompt to STDERR before it is sent, with an estimated
+    /// token count for each message.
+    #[arg(long, default_value = "false")]
+    show_prompt: bool,
+    /// Save each request to OpenAI, and its response, as a JSON file in this
+    /// directory, with the API key scrubbed.
+    #[arg(long, value_name = "DIR")]
+    debug_http: Option<PathBuf>,
+    /// Where requests are sent. `mock` answers from fixture files in
+    /// `$YAP_MOCK_DIR`, offline and without an API key; setting `YAP_MOCK=1`
+    /// does the same.
+    #[arg(long, value_enum)]
+    provider: Option<openai::Provider>,
 }
 
 /// `yap` subcommands (`complete`, `chat`, etc.)
 #[derive(Debug, Subcommand)]
 enum Command {
     /// Print completion for STDIN to STDOUT.
-    Complete,
+    Complete {
+        /// Always send the request, ignoring (and not updating) the response
+        /// cache.
+        #[arg(long, default_value = "false")]
+        no_cache: bool,
+        /// Request this many candidate completions.
+        #[arg(long = "n")]
+        n: Option<u8>,
+        /// Print candidates as a JSON array of messages.
+        #[arg(long, default_value = "false")]
+        json: bool,
+        /// Read JSONL prompts from STDIN (JSON strings, or objects with a
+        /// `prompt` field), and print one JSON result per line, in order.
+        #[arg(long, default_value = "false", conflicts_with = "json")]
+        batch: bool,
+        /// The language of the input, by name or extension (e.g, `rust` or
+        /// `rs`), which is mentioned in the system prompt.
+        #[arg(long, conflicts_with = "batch")]
+        lang: Option<String>,
+        /// The file which the input comes from. Unless `--lang` is given,
+        /// the language is guessed from the file's extension.
+        #[arg(long, conflicts_with = "batch")]
+        filename: Option<PathBuf>,
+        /// A file containing the code which follows `STDIN`, so that the
+        /// completion fills in the middle. Alternatively, put a `<CURSOR>`
+        /// marker in `STDIN`.
+        #[arg(long, conflicts_with = "batch")]
+        suffix_file: Option<PathBuf>,
+        /// Attach a map of the project's files and public symbols; see
+        /// `yap map`.
+        #[arg(long, default_value = "false", conflicts_with = "batch")]
+        repo_map: bool,
+        /// Read the prompt from this file. STDIN, if any, is sent before the
+        /// prompt as context; i.e, `yap complete --prompt-file review.txt <
+        /// src/main.rs`.
+        #[arg(long, conflicts_with_all = ["batch", "suffix_file"])]
+        prompt_file: Option<PathBuf>,
+        /// Strip markdown fences, and any prose around them, from the
+        /// completion, so that it can be inserted into a file. This is the
+        /// default with `--lang` or `--filename`.
+        #[arg(long, conflicts_with = "batch")]
+        strip_fences: bool,
+        /// Print the completion as the LLM wrote it, even with `--lang` or
+        /// `--filename`.
+        #[arg(long, conflicts_with_all = ["batch", "strip_fences"])]
+        keep_fences: bool,
+    },
     /// Chat with LLMs in your terminal.
     Chat {
         #[arg(long, short, default_value = "false")]
         new: bool,
         #[arg(long, short)]
         resume: Option<uuid::Uuid>,
+        /// Print responses verbatim, without markdown styling. Styling is
+        /// only applied when STDOUT is a terminal.
+        #[arg(long, default_value = "false")]
+        raw: bool,
+        /// What to do if the chat no longer fits in the model's context
+        /// window. Overrides `context_strategy` in `config.json`.
+        #[arg(long, value_enum)]
+        context_strategy: Option<context::Strategy>,
+        /// Request this many candidate responses, and choose which one is
+        /// kept in the chat history.
+        #[arg(long = "n")]
+        n: Option<u8>,
+        /// Print the response verbatim as it is generated. Press Ctrl-C to
+        /// stop early; the partial response is kept in the chat history,
+        /// marked as truncated.
+        #[arg(long, default_value = "false", conflicts_with = "n")]
+        stream: bool,
+        /// Switch the model which this chat is pinned to. Chats are pinned
+        /// to the model they were started with; `yap --model` only
+        /// overrides the pinned model for one message.
+        #[arg(long)]
+        set_model: Option<openai::Model>,
+        /// Use the contents of this file as the new chat's system prompt,
+        /// instead of `chat_system_prompt.txt` or the default prompt.
+        #[arg(long, requires = "new")]
+        system_file: Option<PathBuf>,
+        /// Add a tag to the chat. May be repeated.
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        /// Send the active chat's history (or the history of `--resume`),
+        /// but don't save this exchange, or change the active chat.
+        #[arg(
+            long,
+            default_value = "false",
+            conflicts_with_all = ["new", "set_model", "system_file", "tags"]
+        )]
+        ephemeral: bool,
+        /// Read messages from STDIN as JSONL (`{"role": "user", "content":
+        /// "..."}`), and print the reply as JSONL. Chat history is neither
+        /// used nor changed.
+        #[arg(
+            long,
+            default_value = "false",
+            conflicts_with_all = [
+                "new", "resume", "raw", "context_strategy", "stream",
+                "set_model", "system_file", "tags", "repo_map", "ephemeral",
+                "tmux_pane", "amend", "retry", "temperature", "pop",
+                "prompt",
+            ]
+        )]
+        jsonl: bool,
+        /// Attach a map of the project's files and public symbols; see
+        /// `yap map`.
+        #[arg(long, default_value = "false")]
+        repo_map: bool,
+        /// Attach the last 200 lines of a tmux pane to the prompt; by
+        /// default, the current pane. Otherwise, any target which `tmux -t`
+        /// accepts, e.g. `--tmux-pane=%3` or `--tmux-pane={last}`.
+        #[arg(
+            long,
+            num_args = 0..=1,
+            require_equals = true,
+            value_name = "PANE"
+        )]
+        tmux_pane: Option<Option<String>>,
+        /// Replace the last message in the chat with `prompt`, and ask
+        /// again; i.e, to fix a typo. The previous reply is discarded.
+        #[arg(
+            long,
+            default_value = "false",
+            requires = "prompt",
+            conflicts_with_all = ["new", "system_file"]
+        )]
+        amend: bool,
+        /// Remove your last message, and the reply to it, from the chat;
+        /// i.e, when an exchange went off the rails.
+        #[arg(
+            long,
+            default_value = "false",
+            conflicts_with_all = [
+                "new", "system_file", "amend", "retry", "ephemeral", "prompt",
+            ]
+        )]
+        pop: bool,
+        /// Discard the last reply, and ask again. Combine with `--model` or
+        /// `--temperature` for a different answer.
+        #[arg(
+            long,
+            default_value = "false",
+            conflicts_with_all = [
+                "new", "system_file", "amend", "tmux_pane", "prompt",
+            ]
+        )]
+        retry: bool,
+        /// Sample more (or less) randomly, from 0 to 2. Ignored by
+        /// reasoning models.
+        #[arg(long)]
+        temperature: Option<f32>,
+        prompt: Vec<String>,
+    },
+    /// Apply a patch written by an LLM to files in the current directory.
+    Apply {
+        /// Read the patch from the last LLM message in the active chat,
+        /// instead of from STDIN.
+        #[arg(long, default_value = "false")]
+        chat: bool,
+        /// Show where each hunk would be applied without changing any files.
+        #[arg(long, default_value = "false")]
+        dry_run: bool,
+        /// Show each hunk, and choose whether to apply, skip, or edit it,
+        /// like `git add -p`. Requires a terminal, so the patch is usually
+        /// read with `--chat`.
+        #[arg(short, long, default_value = "false")]
+        interactive: bool,
+        /// Skip the confirmation which `confirm.patches` in `config.json` asks
+        /// for.
+        #[arg(short, long, default_value = "false")]
+        yes: bool,
+    },
+    /// Ask a one-off question. Chat history is neither used nor changed.
+    Ask {
+        /// Files to include as context. May be repeated. Directories are
+        /// included recursively, except for files matched by `.gitignore` or
+        /// `.yapignore`.
+        #[arg(short, long)]
+        file: Vec<PathBuf>,
+        /// Print the answer verbatim, without markdown styling.
+        #[arg(long, default_value = "false")]
+        raw: bool,
+        /// Attach a map of the project's files and public symbols; see
+        /// `yap map`.
+        #[arg(long, default_value = "false")]
+        repo_map: bool,
+        question: Vec<String>,
+    },
+    /// Ask LLMs for coordinated changes across several files.
+    Refactor {
+        /// The files which may be changed. Files which don't exist yet may
+        /// be created.
+        #[arg(short, long, required = true)]
+        file: Vec<PathBuf>,
+        /// Apply the changes without asking for confirmation.
+        #[arg(short, long, default_value = "false")]
+        yes: bool,
         prompt: Vec<String>,
     },
+    /// Let LLMs iterate toward a goal; reading files, proposing edits, and
+    /// running a build or test command.
+    Agent {
+        /// The build or test command which the agent may run; i.e, `cargo
+        /// test`.
+        #[arg(short, long)]
+        command: Option<String>,
+        /// The directory which the command runs in.
+        #[arg(long, default_value = ".")]
+        cwd: PathBuf,
+        /// Kill the command after this many seconds; overrides
+        /// `sandbox.timeout` in `config.json`.
+        #[arg(long)]
+        timeout: Option<u64>,
+        /// Run the command without network access.
+        #[arg(long, default_value = "false")]
+        no_network: bool,
+        /// Stop with an error if the goal isn't reached within this many
+        /// steps.
+        #[arg(long, default_value = "10")]
+        max_steps: usize,
+        /// Apply edits and run the command without asking for confirmation.
+        #[arg(short, long, default_value = "false")]
+        yes: bool,
+        #[arg(required = true)]
+        goal: Vec<String>,
+    },
+    /// Run a multi-step workflow, where each step's prompt may use the
+    /// outputs of earlier steps.
+    Run {
+        /// A YAML file describing the workflow's steps.
+        workflow: PathBuf,
+        /// Set or override one of the workflow's variables; i.e, `--var
+        /// file=src/main.rs`. May be repeated.
+        #[arg(
+            long = "var",
+            value_parser = workflow::parse_var,
+            value_name = "KEY=VALUE"
+        )]
+        vars: Vec<(String, String)>,
+        /// Write each step's `output` without asking for confirmation.
+        #[arg(short, long, default_value = "false")]
+        yes: bool,
+    },
+    /// Serve `complete`, `chat`, and `annotate` to editor plugins over a
+    /// localhost socket, as line-delimited JSON-RPC. Each request must pass
+    /// the token in `~/.local/state/yap/serve.token` as `token`.
+    Serve {
+        #[arg(long, default_value = "7411")]
+        port: u16,
+    },
+    /// Like `serve`, but over a Unix socket, for editor plugins and scripts
+    /// on this machine. Connections to OpenAI are kept warm between
+    /// requests, and `yap complete`, `yap chat`, and `yap annotate` send
+    /// their requests to the daemon while it runs.
+    Daemon {
+        /// Defaults to `~/.local/state/yap/daemon.sock`.
+        #[arg(long)]
+        socket: Option<PathBuf>,
+    },
+    /// Summarize git history into Markdown release notes.
+    Changelog {
+        /// A revision range for `git log`, like `v1.0..HEAD`.
+        range: String,
+    },
+    /// Ask LLMs to review your changes. Exits non-zero if the review finds
+    /// errors.
+    Review {
+        /// Review staged changes instead of unstaged changes.
+        #[arg(long, default_value = "false")]
+        diff_cached: bool,
+        /// Review the commits since the current branch forked from this
+        /// ref, like `origin/main`, instead of unstaged changes.
+        #[arg(long, conflicts_with_all = ["diff_cached", "forge"])]
+        base: Option<String>,
+        /// Exit with code 8 if any finding is at least this severe.
+        #[arg(long, value_enum, default_value_t = review::Severity::Error)]
+        fail_on: review::Severity,
+        /// Run as a CI step; also write a JSON report of the review to
+        /// `--report`.
+        #[arg(long, default_value = "false")]
+        ci: bool,
+        /// Where `--ci` writes its report.
+        #[arg(long, default_value = "yap-review.json", requires = "ci")]
+        report: PathBuf,
+        /// Review a GitHub pull request, like `owner/repo#123`, instead of
+        /// local changes. Requires `$GITHUB_TOKEN`.
+        #[arg(
+            long,
+            value_name = "OWNER/REPO#N",
+            group = "forge",
+            conflicts_with = "diff_cached"
+        )]
+        github: Option<github::PullRequest>,
+        /// Review a GitLab merge request, like `group/project!123`, instead
+        /// of local changes. Requires `$GITLAB_TOKEN`; set `$GITLAB_URL` for
+        /// self-hosted instances.
+        #[arg(
+            long,
+            value_name = "PROJECT!N",
+            group = "forge",
+            conflicts_with = "diff_cached"
+        )]
+        gitlab: Option<gitlab::MergeRequest>,
+        /// Post the findings on the pull request as a pending review, which
+        /// you can edit and submit on GitHub, or on the merge request as
+        /// discussions.
+        #[arg(long, default_value = "false", requires = "forge")]
+        post: bool,
+    },
+    /// Print a commit message for the staged changes.
+    Commit {
+        /// Instead of writing a message, check that the message in this file
+        /// describes the staged changes. Exits non-zero if it does not.
+        #[arg(long)]
+        verify: Option<PathBuf>,
+        /// Require messages to follow a convention. Written messages which
+        /// don't are rewritten, and `--verify` fails.
+        #[arg(long, value_enum)]
+        convention: Option<commit::Convention>,
+        /// With `--convention custom-template`, a regex which the subject
+        /// line must match; i.e, `'^\[[A-Z]+-\d+\] '`.
+        #[arg(long, required_if_eq("convention", "custom-template"))]
+        template: Option<String>,
+    },
+    /// Manage git hooks which run `yap`.
+    Hook {
+        #[command(subcommand)]
+        command: HookCommand,
+    },
+    /// Run large jobs through the OpenAI Batch API, at half the cost.
+    Batch {
+        #[command(subcommand)]
+        command: BatchCommand,
+    },
+    /// Fine-tune a model on your chats, and then use it with `yap --model
+    /// ft:...`. The model to fine-tune is chosen with `yap --model`.
+    Finetune {
+        #[command(subcommand)]
+        command: FinetuneCommand,
+    },
+    /// Maintain the set of files which are attached to every `yap chat` and
+    /// `yap complete` request in this project.
+    Ctx {
+        #[command(subcommand)]
+        command: CtxCommand,
+    },
+    /// Write chats (or, with `--all`, all of yap's state and config) to
+    /// STDOUT as a `.tar.gz` archive, for `yap import`.
+    Export {
+        #[arg(long, required_unless_present = "chats")]
+        all: bool,
+        /// Export only these chats.
+        #[arg(conflicts_with = "all")]
+        chats: Vec<uuid::Uuid>,
+    },
+    /// Import an archive from `yap export`. Nothing is overwritten; chats
+    /// whose UUIDs collide with different local chats get new UUIDs.
+    Import {
+        /// The archive to import. If unset, the archive is read from STDIN.
+        file: Option<PathBuf>,
+        /// Add config files from the archive without asking for
+        /// confirmation.
+        #[arg(short, long, default_value = "false")]
+        yes: bool,
+    },
+    /// Generate an image from a prompt, and save it as a PNG.
+    Imagine {
+        /// If unset, the prompt is read from STDIN.
+        prompt: Vec<String>,
+        #[arg(long, value_enum, default_value_t)]
+        size: openai::images_api::Size,
+        /// The PNG file to create. Existing files are never overwritten.
+        #[arg(short, long, default_value = "image.png")]
+        output: PathBuf,
+    },
+    /// Embed the project's files for semantic search. Only chunks which
+    /// changed since the last run are sent to OpenAI.
+    Index,
+    /// Print a map of the project's files, with the line number and
+    /// signature of each public function and type.
+    Map {
+        /// Cut the map short at roughly this many tokens.
+        #[arg(long, default_value_t = repomap::DEFAULT_MAX_TOKENS)]
+        max_tokens: usize,
+    },
+    /// Print the chunks of code in the `yap index` which are most similar to
+    /// the snippet on STDIN.
+    Similar {
+        /// Print this many results.
+        #[arg(long, default_value = "10")]
+        limit: usize,
+    },
+    /// Read the text on STDIN aloud.
+    Say {
+        /// Read the last reply in the active chat, instead of STDIN.
+        #[arg(long, default_value = "false")]
+        last: bool,
+        #[arg(long, value_enum, default_value_t)]
+        voice: openai::audio_api::Voice,
+        /// Write the audio to this `.mp3` or `.wav` file, instead of playing
+        /// it.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Summarize your usage of the OpenAI API.
+    Stats,
+    /// List the models which your API key can use, and what each is for.
+    Models {
+        /// Only list chat models which `yap --model` accepts.
+        #[arg(long, default_value = "false")]
+        chat: bool,
+    },
+    /// Re-run a yap command each time a file changes, for continuous
+    /// feedback while editing. By default, `--prompt` is asked about the
+    /// files with `yap ask`; pass another command after `--` instead, i.e,
+    /// `yap watch --file src/lib.rs -- review`.
+    Watch {
+        /// The files to watch. May be repeated.
+        #[arg(short, long, required = true)]
+        file: Vec<PathBuf>,
+        #[arg(short, long, required_unless_present = "command")]
+        prompt: Option<String>,
+        /// The yap command to run, without the leading `yap`.
+        #[arg(last = true, conflicts_with = "prompt")]
+        command: Vec<String>,
+    },
+    /// Restore the files changed by the last `agent`, `annotate`, `apply`,
+    /// `refactor`, or `run`, from the backups saved before they were changed.
+    Undo {
+        /// Restore the files without asking for confirmation.
+        #[arg(short, long, default_value = "false")]
+        yes: bool,
+    },
+    /// Print the transcript of an audio file (flac, m4a, mp3, mp4, ogg, wav,
+    /// or webm).
+    Transcribe {
+        file: PathBuf,
+        /// Send the transcript to the active chat as a prompt, instead of
+        /// printing it.
+        #[arg(long, default_value = "false")]
+        into_chat: bool,
+    },
+    /// Pin messages or files to the active chat, so that they are always
+    /// sent, even once the chat outgrows the context window. Without any
+    /// arguments, lists the chat's messages; pinned messages are marked
+    /// with `*`.
+    Pin {
+        /// The number of a message to pin, as listed by `yap pin`. May be
+        /// repeated.
+        #[arg(long = "message", short)]
+        messages: Vec<usize>,
+        /// Pin the last message in the chat.
+        #[arg(long, default_value = "false")]
+        last: bool,
+        /// A file (or directory) to send with every message in this chat.
+        /// May be repeated.
+        #[arg(long = "file", short)]
+        files: Vec<PathBuf>,
+        /// Unpin the messages or files, instead.
+        #[arg(long, default_value = "false")]
+        remove: bool,
+    },
+    /// Remember facts about you and your preferences in every new chat.
+    Memory {
+        #[command(subcommand)]
+        command: MemoryCommand,
+    },
     /// Print the history of your current chat thread.
-    Recap,
+    Recap {
+        #[arg(long, value_enum, default_value_t)]
+        format: recap::Format,
+        /// Print a table of the messages instead, with the model, token
+        /// counts, and cost of each reply, and the total cost.
+        #[arg(long, default_value = "false", conflicts_with = "format")]
+        stats: bool,
+    },
     /// Print the chat log in most-recently-used order.
     Chatlog {
         /// Truncate the output to the most recent N chats, ordered by time
         /// of last message.
         #[arg(long, default_value = "10")]
         trunc: Option<usize>,
+        /// Only show chats with this tag. May be repeated, in which case
+        /// chats must have every tag.
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        #[arg(long, value_enum, default_value_t)]
+        format: chatlog::Format,
+        /// Choose a chat from an interactive, fuzzy-searchable list, and make
+        /// it the active chat. If `STDOUT` is not a terminal, the chat's UUID
+        /// is printed instead.
+        #[arg(long, conflicts_with = "format")]
+        pick: bool,
+        /// Search every chat for messages matching this regular expression,
+        /// and print each matching line with the chat's UUID, the role, and
+        /// when the message was written.
+        #[arg(long, conflicts_with_all = ["format", "pick"])]
+        grep: Option<String>,
+        /// Show where two chats diverge, i.e, after forking a chat, and
+        /// summarize the messages on each side.
+        #[arg(
+            long,
+            num_args = 2,
+            value_names = ["UUID_A", "UUID_B"],
+            conflicts_with_all = [
+                "format", "pick", "grep", "tags", "since", "before", "today",
+            ]
+        )]
+        diff: Vec<uuid::Uuid>,
+        /// Only show chats which were active on or after this date
+        /// (YYYY-MM-DD, in UTC).
+        #[arg(long, value_parser = date::parse, value_name = "DATE")]
+        since: Option<u64>,
+        /// Only show chats which began before this date (YYYY-MM-DD, in
+        /// UTC).
+        #[arg(long, value_parser = date::parse, value_name = "DATE")]
+        before: Option<u64>,
+        /// Only show chats which were active today, since midnight in the
+        /// local time zone.
+        #[arg(long, default_value = "false", conflicts_with = "since")]
+        today: bool,
     },
     /// Ask LLMs for feedback on all or part of a file.
     Annotate {
         #[arg(short, long)]
         prompt: Option<String>,
-        #[arg(short, long)]
-        file: PathBuf,
+        /// The file to annotate. With `--diff`, this limits annotations to
+        /// changes in this file.
+        #[arg(
+            short,
+            long,
+            required_unless_present_any = ["diff", "dir"],
+            conflicts_with = "dir"
+        )]
+        file: Option<PathBuf>,
+        /// Annotate every file in this directory, recursively, and print how
+        /// many annotations each file received. Ignored files are skipped.
+        #[arg(
+            long,
+            conflicts_with_all = ["diff", "line_start", "line_end", "symbol"]
+        )]
+        dir: Option<PathBuf>,
+        /// With `--dir`, only annotate files matching this glob; i.e,
+        /// `'*.rs'`. May be repeated.
+        #[arg(long, requires = "dir")]
+        include: Vec<String>,
+        /// With `--dir`, skip files matching this glob; i.e, `'tests/*'`.
+        /// May be repeated.
+        #[arg(long, requires = "dir")]
+        exclude: Vec<String>,
         /// If unset, we will start from the first line of the file.
-        #[arg(short = 's', long)]
+        #[arg(short = 's', long, conflicts_with = "diff")]
         line_start: Option<usize>,
         /// If unset, we will end at the last line of the file.
-        #[arg(short = 'e', long)]
+        #[arg(short = 'e', long, conflicts_with = "diff")]
         line_end: Option<usize>,
-        /// Override the default comment prefix of `//`. Yap currently makes
-        /// no effort to infer the comment-type from the file name.
-        #[arg(long, default_value = "// ")]
-        comment_prefix: String,
-        /// Set a comment suffix. This is unset by default, but you may
-        /// with to set it to something like `*/` to match a prefix of `/*`,
-        /// `-->` for HTML.
+        /// Annotate only this function, type, or `impl` block, instead of a
+        /// range of lines. Methods may be qualified; e.g, `Chat::new`. For
+        /// languages which `yap` can't parse, the symbol is found by
+        /// heuristics, which may be less precise.
+        #[arg(long, conflicts_with_all = ["diff", "line_start", "line_end"])]
+        symbol: Option<String>,
+        /// Only annotate changed lines. The diff is read from STDIN, or else
+        /// from `git diff` if STDIN is a terminal.
+        #[arg(long, default_value = "false")]
+        diff: bool,
+        /// Override the comment prefix, which is otherwise chosen by the
+        /// file's extension (see `comment_styles` in `config.json`), or else
+        /// `// `.
+        #[arg(long)]
+        comment_prefix: Option<String>,
+        /// Override the comment suffix; i.e, ` */` to match a prefix of
+        /// `/* `.
         #[arg(long)]
         comment_suffix: Option<String>,
+        /// Where to put each annotation: on the lines `before` or `after` the
+        /// line it is about, or at the end of that line (`eol`), which suits
+        /// short notes.
+        #[arg(long, value_enum, default_value_t)]
+        position: annotate::Position,
+        /// Also ask for an overview of each file, which is inserted as a
+        /// comment block at the top of the file.
+        #[arg(long, conflicts_with = "diff")]
+        summary: bool,
+        /// `inline` writes annotations into `file` as comments. `json`,
+        /// `sarif`, and `markdown` print annotations to STDOUT instead,
+        /// leaving `file` untouched. `patch` prints suggested replacements as
+        /// a diff for `yap apply`.
+        #[arg(long, value_enum, default_value_t)]
+        format: annotate::Format,
+        /// Step through each annotation, and accept, reject, or edit it,
+        /// before anything is written to the file.
+        #[arg(short, long, default_value = "false", conflicts_with = "format")]
+        interactive: bool,
+    },
+}
+
+/// `yap hook` subcommands.
+#[derive(Debug, Subcommand)]
+enum HookCommand {
+    /// Install `pre-commit` and `commit-msg` hooks in the current repository.
+    Install {
+        /// Only install this hook.
+        #[arg(long, value_enum)]
+        only: Option<hook::Hook>,
+        /// Replace existing hooks which were not installed by `yap`.
+        #[arg(long, default_value = "false")]
+        force: bool,
+    },
+}
+
+/// `yap batch` subcommands.
+#[derive(Debug, Subcommand)]
+enum BatchCommand {
+    /// Submit JSONL prompts from STDIN, in the same format as `yap complete
+    /// --batch`, and print the batch ID.
+    Submit {
+        /// Request this many candidate completions per prompt.
+        #[arg(long = "n")]
+        n: Option<u8>,
+    },
+    /// Show the progress of a batch. Defaults to the last submitted batch.
+    Status { id: Option<String> },
+    /// Print the results of a batch as JSONL, in input order. Defaults to
+    /// the last submitted batch.
+    Fetch { id: Option<String> },
+}
+
+/// `yap finetune` subcommands.
+#[derive(Debug, Subcommand)]
+enum FinetuneCommand {
+    /// Write chats as a JSONL training file. Defaults to the active chat.
+    Prepare {
+        /// A chat to include. May be repeated.
+        #[arg(long)]
+        chat: Vec<uuid::Uuid>,
+        /// Include every chat with this tag. If repeated, chats must have
+        /// every tag.
+        #[arg(long)]
+        tag: Vec<String>,
+        /// Write the training file here, instead of to STDOUT.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Upload a training file, start a fine-tuning job, and print its ID.
+    Submit {
+        file: PathBuf,
+        /// Included in the fine-tuned model's ID.
+        #[arg(long)]
+        suffix: Option<String>,
+    },
+    /// Show the progress of a job. Defaults to the last submitted job.
+    Status {
+        id: Option<String>,
+        /// Keep checking until the job succeeds or fails.
+        #[arg(long, default_value = "false")]
+        wait: bool,
+    },
+}
+
+/// `yap memory` subcommands.
+#[derive(Debug, Subcommand)]
+enum MemoryCommand {
+    /// Remember something; i.e, `yap memory add "I prefer thiserror over
+    /// anyhow"`.
+    Add {
+        #[arg(required = true)]
+        text: Vec<String>,
+    },
+    /// Print every memory, numbered for `yap memory rm`.
+    List,
+    /// Forget memories, by the numbers printed by `yap memory list`.
+    Rm {
+        #[arg(required = true)]
+        numbers: Vec<usize>,
     },
 }
 
+/// `yap ctx` subcommands.
+#[derive(Debug, Subcommand)]
+enum CtxCommand {
+    /// Add files or directories to this project's context.
+    Add {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Remove files or directories from this project's context.
+    Remove {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Print this project's context files.
+    List,
+    /// Remove every file from this project's context.
+    Clear,
+}
+
 impl Command {
-    fn dispatch(
-        &self,
-        preferred_model: Option<openai::Model>,
-    ) -> Result<(), err::Error> {
-        let open_ai = openai::OpenAI::from_env(preferred_model)?;
+    /// The subcommand's name, as used for `command_models` in `config.json`.
+    fn name(&self) -> &'static str {
         match self {
+            Self::Complete { .. } => "complete",
+            Self::Chat { .. } => "chat",
+            Self::Apply { .. } => "apply",
+            Self::Ask { .. } => "ask",
+            Self::Refactor { .. } => "refactor",
+            Self::Agent { .. } => "agent",
+            Self::Run { .. } => "run",
+            Self::Changelog { .. } => "changelog",
+            Self::Review { .. } => "review",
+            Self::Commit { .. } => "commit",
+            Self::Hook { .. } => "hook",
+            Self::Batch { .. } => "batch",
+            Self::Finetune { .. } => "finetune",
+            Self::Ctx { .. } => "ctx",
+            Self::Export { .. } => "export",
+            Self::Import { .. } => "import",
+            Self::Imagine { .. } => "imagine",
+            Self::Index => "index",
+            Self::Map { .. } => "map",
+            Self::Similar { .. } => "similar",
+            Self::Say { .. } => "say",
+            Self::Stats => "stats",
+            Self::Models { .. } => "models",
+            Self::Watch { .. } => "watch",
+            Self::Undo { .. } => "undo",
+            Self::Transcribe { .. } => "transcribe",
+            Self::Serve { .. } => "serve",
+            Self::Daemon { .. } => "daemon",
+            Self::Pin { .. } => "pin",
+            Self::Memory { .. } => "memory",
+            Self::Recap { .. } => "recap",
+            Self::Chatlog { .. } => "chatlog",
+            Self::Annotate { .. } => "annotate",
+        }
+    }
+
+    fn dispatch(&self, client: &openai::ClientOpts) -> Result<(), err::Error> {
+        // Only commands which talk to the LLM need an API key. `--model`
+        // takes precedence over `config.json`.
+        let open_ai = || openai::OpenAI::from_env(self.name(), client);
+        // A running `yap daemon` can answer `complete`, `chat`, and
+        // `annotate`, unless these flags change how requests are made.
+        let daemon = || {
+            let local = client.model.is_some()
+                || client.seed.is_some()
+                || client.reasoning_effort.is_some()
+                || client.dry_run
+                || client.show_prompt
+                || client.debug_http.is_some()
+                || client.provider.is_some();
+            if local {
+                None
+            } else {
+                serve::Daemon::connect()
+            }
+        };
+        match self {
+            Self::Chat { jsonl: true, n, .. } => chat::jsonl(&open_ai()?, *n),
+            Self::Chat {
+                pop: true, resume, ..
+            } => chat::pop_exchange(*resume),
             Self::Chat {
                 new,
                 prompt,
                 resume,
-            } => chat::chat(&open_ai, prompt, *new, resume.as_ref()),
-            Self::Chatlog { trunc } => chatlog::chatlog(*trunc),
-            Self::Complete => complete::complete(&open_ai),
+                raw,
+                context_strategy,
+                n,
+                stream,
+                set_model,
+                system_file,
+                tags,
+                repo_map,
+                ephemeral,
+                tmux_pane,
+                amend,
+                retry,
+                temperature,
+                ..
+            } => {
+                let opts = chat::Opts {
+                    new: *new,
+                    resume: *resume,
+                    raw: *raw,
+                    context_strategy: *context_strategy,
+                    n: *n,
+                    stream: *stream,
+                    set_model: set_model.clone(),
+                    system_file: system_file.clone(),
+                    tags: tags.clone(),
+                    repo_map: *repo_map,
+                    ephemeral: *ephemeral,
+                    tmux_pane: tmux_pane.clone(),
+                    amend: *amend,
+                    retry: *retry,
+                    temperature: *temperature,
+                };
+                match chat::can_forward(prompt, &opts).then(daemon).flatten() {
+                    Some(mut daemon) => {
+                        chat::forward(&mut daemon, prompt, &opts)
+                    }
+                    None => chat::chat(&open_ai()?, prompt, &opts),
+                }
+            }
+            Self::Chatlog { diff, .. } if !diff.is_empty() => {
+                chatlog::diff(&diff[0], &diff[1])
+            }
+            Self::Chatlog {
+                trunc,
+                tags,
+                format,
+                pick,
+                grep,
+                since,
+                before,
+                today,
+                ..
+            } => {
+                let filter =
+                    chatlog::Filter::new(tags.clone(), *since, *before, *today);
+                match (pick, grep) {
+                    (_, Some(pattern)) => chatlog::grep(pattern, &filter),
+                    (true, None) => chatlog::pick(&filter),
+                    (false, None) => chatlog::chatlog(*trunc, &filter, *format),
+                }
+            }
+            Self::Complete {
+                no_cache,
+                n,
+                batch: true,
+                ..
+            } => complete::complete_batch(&open_ai()?, *no_cache, *n),
+            Self::Complete {
+                no_cache,
+                n,
+                json,
+                lang,
+                filename,
+                suffix_file,
+                repo_map,
+                prompt_file,
+                strip_fences,
+                keep_fences,
+                ..
+            } => {
+                let opts = complete::Opts {
+                    no_cache: *no_cache,
+                    n: *n,
+                    json: *json,
+                    lang: lang.clone(),
+                    filename: filename.clone(),
+                    suffix_file: suffix_file.clone(),
+                    repo_map: *repo_map,
+                    prompt_file: prompt_file.clone(),
+                    strip_fences: match (strip_fences, keep_fences) {
+                        (true, _) => Some(true),
+                        (_, true) => Some(false),
+                        _ => None,
+                    },
+                };
+                match (!json).then(daemon).flatten() {
+                    Some(mut daemon) => complete::forward(&mut daemon, &opts),
+                    None => complete::complete(&open_ai()?, &opts),
+                }
+            }
             Self::Annotate {
                 prompt,
                 file,
+                dir,
+                include,
+                exclude,
                 line_start,
                 line_end,
+                symbol,
                 comment_prefix,
                 comment_suffix,
-            } => annotate::annotate(
-                &open_ai,
-                prompt.as_deref(),
+                position,
+                summary,
+                format,
+                diff,
+                interactive,
+            } => match (file, dir, diff) {
+                (_, Some(dir), false) => annotate::annotate_dir(
+                    &open_ai()?,
+                    prompt.as_deref(),
+                    dir,
+                    include,
+                    exclude,
+                    comment_prefix.as_deref(),
+                    comment_suffix.as_deref(),
+                    *position,
+                    *summary,
+                    *format,
+                    *interactive,
+                ),
+                (file, _, true) => annotate::annotate_diff(
+                    &open_ai()?,
+                    prompt.as_deref(),
+                    file.as_deref(),
+                    comment_prefix.as_deref(),
+                    comment_suffix.as_deref(),
+                    *position,
+                    *format,
+                    *interactive,
+                ),
+                (Some(file), None, false) => {
+                    let (line_start, line_end) = match symbol {
+                        Some(symbol) => annotate::symbol_lines(file, symbol)?,
+                        None => (line_start.unwrap_or(1), *line_end),
+                    };
+                    match daemon() {
+                        Some(mut daemon) => annotate::forward(
+                            &mut daemon,
+                            prompt.as_deref(),
+                            file,
+                            line_start,
+                            line_end,
+                            comment_prefix.as_deref(),
+                            comment_suffix.as_deref(),
+                            *position,
+                            *summary,
+                            *format,
+                            *interactive,
+                        ),
+                        None => annotate::annotate(
+                            &open_ai()?,
+                            prompt.as_deref(),
+                            file,
+                            line_start,
+                            line_end,
+                            comment_prefix.as_deref(),
+                            comment_suffix.as_deref(),
+                            *position,
+                            *summary,
+                            *format,
+                            *interactive,
+                        ),
+                    }
+                }
+                (None, None, false) => Err(err::Error::default()
+                    .wrap(err::Oops::AnnotateError)
+                    .because("--file is required without --diff".into())),
+            },
+            Self::Pin {
+                messages,
+                last,
+                files,
+                remove,
+            } => pin::pin(messages, *last, files, *remove),
+            Self::Memory { command } => match command {
+                MemoryCommand::Add { text } => memory::add(text),
+                MemoryCommand::List => memory::list(),
+                MemoryCommand::Rm { numbers } => memory::remove(numbers),
+            },
+            Self::Recap { format, stats } => recap::recap(*format, *stats),
+            Self::Ctx { command } => match command {
+                CtxCommand::Add { files } => ctx::add(files),
+                CtxCommand::Remove { files } => ctx::remove(files),
+                CtxCommand::List => ctx::list(),
+                CtxCommand::Clear => ctx::clear(),
+            },
+            Self::Export { all, chats } => archive::export(*all, chats),
+            Self::Import { file, yes } => {
+                archive::import(file.as_deref(), *yes)
+            }
+            Self::Imagine {
+                prompt,
+                size,
+                output,
+            } => imagine::imagine(&open_ai()?, prompt, *size, output),
+            Self::Index => index::index(&open_ai()?),
+            Self::Map { max_tokens } => repomap::print(*max_tokens),
+            Self::Similar { limit } => similar::similar(&open_ai()?, *limit),
+            Self::Say {
+                last,
+                voice,
+                output,
+            } => say::say(&open_ai()?, *voice, *last, output.as_deref()),
+            Self::Stats => usage::stats(),
+            Self::Models { chat } => models::models(&open_ai()?, *chat),
+            Self::Watch {
                 file,
-                line_start.unwrap_or(1),
-                *line_end,
-                comment_prefix,
-                comment_suffix,
+                prompt,
+                command,
+            } => watch::watch(file, prompt.as_deref(), command),
+            Self::Undo { yes } => backup::undo(*yes),
+            Self::Transcribe { file, into_chat } => {
+                transcribe::transcribe(&open_ai()?, file, *into_chat)
+            }
+            Self::Serve { .. } if client.dry_run => Err(err::Error::default()
+                .wrap(err::Oops::ServeError)
+                .because("--dry-run is not supported by `yap serve`".into())),
+            Self::Serve { .. } if client.debug_http.is_some() => {
+                Err(err::Error::default().wrap(err::Oops::ServeError).because(
+                    "--debug-http is not supported by `yap serve`".into(),
+                ))
+            }
+            Self::Serve { port } => serve::serve(*port, client),
+            Self::Daemon { .. } if client.dry_run => Err(err::Error::default()
+                .wrap(err::Oops::ServeError)
+                .because("--dry-run is not supported by `yap daemon`".into())),
+            Self::Daemon { .. } if client.debug_http.is_some() => {
+                Err(err::Error::default().wrap(err::Oops::ServeError).because(
+                    "--debug-http is not supported by `yap daemon`".into(),
+                ))
+            }
+            Self::Daemon { socket } => serve::daemon(socket.as_deref(), client),
+            Self::Finetune { command } => match command {
+                FinetuneCommand::Prepare { chat, tag, output } => {
+                    finetune::prepare(chat, tag, output.as_deref())
+                }
+                FinetuneCommand::Submit { file, suffix } => {
+                    finetune::submit(&open_ai()?, file, suffix.as_deref())
+                }
+                FinetuneCommand::Status { id, wait } => {
+                    finetune::status(&open_ai()?, id.as_deref(), *wait)
+                }
+            },
+            Self::Batch { command } => match command {
+                BatchCommand::Submit { n } => batch::submit(&open_ai()?, *n),
+                BatchCommand::Status { id } => {
+                    batch::status(&open_ai()?, id.as_deref())
+                }
+                BatchCommand::Fetch { id } => {
+                    batch::fetch(&open_ai()?, id.as_deref())
+                }
+            },
+            Self::Apply {
+                chat,
+                dry_run,
+                interactive,
+                yes,
+            } => apply::apply(*chat, *dry_run, *interactive, *yes),
+            Self::Ask {
+                file,
+                raw,
+                repo_map,
+                question,
+            } => ask::ask(
+                &open_ai()?,
+                &question.join(" "),
+                file,
+                *raw,
+                *repo_map,
+            ),
+            Self::Agent {
+                command,
+                cwd,
+                timeout,
+                no_network,
+                max_steps,
+                yes,
+                goal,
+            } => agent::agent(
+                &open_ai()?,
+                &agent::Opts {
+                    goal: &goal.join(" "),
+                    command: command.as_deref(),
+                    cwd,
+                    timeout: *timeout,
+                    no_network: *no_network,
+                    max_steps: *max_steps,
+                    yes: *yes,
+                },
             ),
-            Self::Recap => recap::recap(),
+            Self::Run {
+                workflow,
+                vars,
+                yes,
+            } => workflow::run(&open_ai()?, workflow, vars, *yes),
+            Self::Refactor { file, yes, prompt } => {
+                refactor::refactor(&open_ai()?, &prompt.join(" "), file, *yes)
+            }
+            Self::Changelog { range } => {
+                changelog::changelog(&open_ai()?, range)
+            }
+            Self::Review {
+                diff_cached,
+                base,
+                fail_on,
+                ci,
+                report,
+                github,
+                gitlab,
+                post,
+            } => {
+                let gate = review::Gate {
+                    fail_on: *fail_on,
+                    report: ci.then_some(report.as_path()),
+                };
+                match (github, gitlab) {
+                    (Some(pr), _) => {
+                        review::review_github(&open_ai()?, pr, *post, gate)
+                    }
+                    (_, Some(mr)) => {
+                        review::review_gitlab(&open_ai()?, mr, *post, gate)
+                    }
+                    _ => review::review(
+                        &open_ai()?,
+                        *diff_cached,
+                        base.as_deref(),
+                        gate,
+                    ),
+                }
+            }
+            Self::Commit {
+                verify,
+                convention,
+                template,
+            } => {
+                let rule = convention
+                    .map(|c| commit::Rule::new(c, template.as_deref()))
+                    .transpose()?;
+                commit::commit(&open_ai()?, verify.as_deref(), rule.as_ref())
+            }
+            Self::Hook {
+                command: HookCommand::Install { only, force },
+            } => hook::install(*only, *force),
         }
     }
 }
 
+/// The exit code for invalid arguments; see "Exit Codes" above.
+const EXIT_USAGE: i32 = 64;
+
 fn main() {
-    env_logger::init();
-    let args: Cli = Cli::parse();
-    if let Err(e) = args.command.dispatch(args.model) {
-        e.display();
-        exit(1);
+    logfile::init();
+    let args: Cli = Cli::try_parse().unwrap_or_else(|e| {
+        let _ = e.print();
+        // `--help` and `--version` are "errors" too, but successful ones.
+        exit(if e.use_stderr() { EXIT_USAGE } else { 0 });
+    });
+    let client = openai::ClientOpts {
+        model: args.model,
+        seed: args.seed,
+        reasoning_effort: args.reasoning_effort,
+        dry_run: args.dry_run,
+        show_prompt: args.show_prompt,
+        debug_http: args.debug_http.clone(),
+        provider: args.provider,
+    };
+    if let Err(e) = args.command.dispatch(&client) {
+        let code = e.exit_code();
+        // A dry run stops with an error once the request is printed.
+        if code != 0 {
+            // Errors are only printed to `STDERR`, which editor plugins may
+            // hide, so they are logged too; see [logfile].
+            warn!("yap {} failed: {e}", args.command.name());
+            e.display();
+        }
+        exit(code);
     };
 }
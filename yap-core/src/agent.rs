@@ -0,0 +1,491 @@
+//! An opt-in, bounded agent loop with `yap agent`, for goals which take a few
+//! rounds of reading code, changing it, and checking the result; i.e,
+//!
+//! ```bash
+//! yap agent --command "cargo test" Fix the failing test in src/diff.rs
+//! ```
+//!
+//! Each step, the LLM chooses one action:
+//!
+//! - `read_file`: read a file in the current directory
+//! - `list_files`: list the files in a directory, like [files::expand]
+//! - `edit`: propose search/replace edits, like [crate::refactor]
+//! - `run`: run the `--command` (i.e, a build or test) in the
+//!   [sandbox](crate::sandbox), and read its output
+//! - `finish`: stop, with a summary of what was done
+//! - `give_up`: stop, with a summary of what went wrong
+//!
+//! The result of each action is sent back to the LLM. Edits and commands are
+//! previewed, and only happen after [confirmation](crate::confirm); a rejected
+//! action is reported to the LLM, which may try something else. Paths must be
+//! relative, and may not leave the current directory, even through a
+//! symlink. The loop ends after `--max-steps`, which is an error, as is
+//! giving up. Edited files are backed up, so `yap undo` reverts every edit
+//! made by the agent.
+
+use crate::{
+    backup,
+    config::Settings,
+    confirm::{confirm, Operation},
+    constants,
+    err::{Error, Oops},
+    files,
+    openai::{
+        chat, CompletionPayload, Content, Message, OpenAI, PayloadOpts,
+        ResponseFormat, Role,
+    },
+    refactor::{self, Edit},
+    sandbox::Sandbox,
+};
+use log::debug;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Component, Path, PathBuf},
+};
+
+/// File contents and command output beyond this many bytes are cut short
+/// before they are sent to the LLM.
+const OUTPUT_LIMIT: usize = 16_000;
+
+fn get_json_schema() -> Value {
+    json!({
+      "name": "agent_step",
+      "schema": {
+        "type": "object",
+        "properties": {
+          "thought": {
+            "type": "string",
+            "description": "One or two sentences about what to do next, and why."
+          },
+          "action": {
+            "type": "string",
+            "enum": ["read_file", "list_files", "edit", "run", "finish", "give_up"]
+          },
+          "path": {
+            "type": ["string", "null"],
+            "description": "The file for read_file, or the directory for list_files."
+          },
+          "edits": {
+            "type": ["array", "null"],
+            "description": "Search/replace edits for edit, applied in order.",
+            "items": {
+              "type": "object",
+              "properties": {
+                "file": { "type": "string" },
+                "search": {
+                  "type": "string",
+                  "description": "Text which appears exactly once in the file. Empty to create a new file."
+                },
+                "replace": { "type": "string" }
+              },
+              "required": ["file", "search", "replace"],
+              "additionalProperties": false
+            }
+          },
+          "summary": {
+            "type": ["string", "null"],
+            "description": "For finish or give_up; what was done, or what went wrong."
+          }
+        },
+        "required": ["thought", "action", "path", "edits", "summary"],
+        "additionalProperties": false
+      },
+      "strict": true
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct Step {
+    thought: String,
+    action: Action,
+    path: Option<PathBuf>,
+    edits: Option<Vec<Edit>>,
+    summary: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum Action {
+    ReadFile,
+    ListFiles,
+    Edit,
+    Run,
+    Finish,
+    GiveUp,
+}
+
+/// How a step turned out.
+enum Outcome {
+    /// Tell the LLM, and keep going.
+    Observe(String),
+    Finish(String),
+    GiveUp(String),
+}
+
+fn oops(why: String) -> Error {
+    Error::default().wrap(Oops::AgentError).because(why)
+}
+
+/// Options for `yap agent`.
+pub struct Opts<'a> {
+    pub goal: &'a str,
+    /// The build or test command which the agent may run.
+    pub command: Option<&'a str>,
+    /// The directory which the command runs in.
+    pub cwd: &'a Path,
+    /// Overrides the sandbox's `timeout`, in seconds.
+    pub timeout: Option<u64>,
+    /// Run the command without network access, regardless of the sandbox's
+    /// `network`.
+    pub no_network: bool,
+    pub max_steps: usize,
+    /// Edit files and run the command without asking for confirmation.
+    pub yes: bool,
+}
+
+/// Entrypoint for `yap agent`.
+pub fn agent(open_ai: &OpenAI, opts: &Opts) -> Result<(), Error> {
+    let mut sandbox = Settings::load()?.sandbox;
+    if let Some(timeout) = opts.timeout {
+        sandbox.timeout = timeout;
+    }
+    if opts.no_network {
+        sandbox.network = false;
+    }
+    let mut messages = vec![
+        Message::new(Role::System, constants::DEFAULT_AGENT_PROMPT.into()),
+        Message::new(Role::User, goal_message(opts, &sandbox)),
+    ];
+    for i in 1..=opts.max_steps {
+        let (content, step) = next_step(open_ai, &messages)?;
+        eprintln!("[{i}/{}] {}", opts.max_steps, step.thought);
+        messages.push(Message::new(Role::Assistant, content));
+        match act(&step, opts, &sandbox)? {
+            Outcome::Observe(observation) => {
+                messages.push(Message::new(Role::User, observation));
+            }
+            Outcome::Finish(summary) => {
+                println!("{summary}");
+                return Ok(());
+            }
+            Outcome::GiveUp(summary) => {
+                return Err(oops(format!("The agent gave up: {summary}")));
+            }
+        }
+    }
+    Err(oops(format!(
+        "The goal was not reached within {} steps; pass --max-steps to allow more",
+        opts.max_steps
+    )))
+}
+
+fn goal_message(opts: &Opts, sandbox: &Sandbox) -> String {
+    let command = match opts.command {
+        Some(command) => format!(
+            "The `run` action runs `{command}` in `{}`, with a {} second timeout{}.",
+            opts.cwd.display(),
+            sandbox.timeout,
+            if sandbox.network {
+                ""
+            } else {
+                ", without network access"
+            }
+        ),
+        None => "There is no command to run, so `run` is unavailable.".into(),
+    };
+    format!(
+        "{command} You may take up to {} steps.\n\nGoal: {}",
+        opts.max_steps, opts.goal
+    )
+}
+
+fn next_step(
+    open_ai: &OpenAI,
+    messages: &[Message],
+) -> Result<(String, Step), Error> {
+    let payload = CompletionPayload::new(
+        open_ai,
+        messages.to_vec(),
+        PayloadOpts {
+            response_format: ResponseFormat::JsonSchema {
+                json_schema: get_json_schema(),
+            },
+            ..Default::default()
+        },
+    );
+    let response = chat(open_ai, &payload).map_err(|e| {
+        e.wrap(Oops::AgentError)
+            .because("Error while requesting the next step".into())
+    })?;
+    let content = match response.choices[0].message.parse()? {
+        Content::Normal(c) => c.to_string(),
+        Content::Refusal(r) => {
+            return Err(Error::default()
+                .wrap(Oops::OpenAIRefusal)
+                .wrap(Oops::AgentError)
+                .because(format!("OpenAI refused to continue: {r}")))
+        }
+    };
+    let step = serde_json::from_str(&content).map_err(|e| {
+        debug!("Bad response content: {content}");
+        oops(format!("Could not deserialize the next step: {e}"))
+    })?;
+    Ok((content, step))
+}
+
+fn act(step: &Step, opts: &Opts, sandbox: &Sandbox) -> Result<Outcome, Error> {
+    let summary = || step.summary.clone().unwrap_or_default();
+    Ok(match step.action {
+        Action::ReadFile => Outcome::Observe(match &step.path {
+            Some(path) => read_file(path),
+            None => "read_file needs a `path`.".into(),
+        }),
+        Action::ListFiles => Outcome::Observe(list_files(
+            step.path.as_deref().unwrap_or(Path::new(".")),
+        )?),
+        Action::Edit => Outcome::Observe(match &step.edits {
+            Some(edits) if !edits.is_empty() => edit(edits, opts.yes)?,
+            _ => "edit needs at least one of `edits`.".into(),
+        }),
+        Action::Run => Outcome::Observe(match opts.command {
+            Some(command) => run(command, opts.cwd, sandbox, opts.yes)?,
+            None => "There is no command to run.".into(),
+        }),
+        Action::Finish => Outcome::Finish(summary()),
+        Action::GiveUp => Outcome::GiveUp(summary()),
+    })
+}
+
+/// Paths from the LLM must stay inside of the current directory.
+fn check_path(path: &Path) -> Result<(), String> {
+    let cwd = env::current_dir()
+        .map_err(|e| format!("Could not find the current directory: {e}"))?;
+    check_path_in(&cwd, path)
+}
+
+/// Like [check_path], for `root`. Symlinks are followed, so that a link to
+/// somewhere else can't be used to escape. A file which doesn't exist yet is
+/// checked by its nearest ancestor which does.
+fn check_path_in(root: &Path, path: &Path) -> Result<(), String> {
+    let outside = || {
+        format!(
+            "{path:?} is outside of the current directory; use a relative path without `..`."
+        )
+    };
+    if path.is_absolute()
+        || path.components().any(|c| c == Component::ParentDir)
+    {
+        return Err(outside());
+    }
+    let joined = root.join(path);
+    // `symlink_metadata` finds dangling links too, which can't be resolved.
+    let existing = joined
+        .ancestors()
+        .find(|a| a.symlink_metadata().is_ok())
+        .unwrap_or(root);
+    let resolve = |p: &Path| {
+        p.canonicalize()
+            .map_err(|e| format!("Could not resolve {p:?}: {e}"))
+    };
+    if resolve(existing)?.starts_with(resolve(root)?) {
+        Ok(())
+    } else {
+        Err(outside())
+    }
+}
+
+fn read_file(path: &Path) -> String {
+    if let Err(problem) = check_path(path) {
+        return problem;
+    }
+    eprintln!("  read {}", path.display());
+    match fs::read_to_string(path) {
+        Ok(contents) => format!(
+            "File: {}\n```\n{}\n```",
+            path.display(),
+            truncate(&contents)
+        ),
+        Err(e) => format!("Could not read {path:?}: {e}"),
+    }
+}
+
+fn list_files(dir: &Path) -> Result<String, Error> {
+    if let Err(problem) = check_path(dir) {
+        return Ok(problem);
+    }
+    if !dir.is_dir() {
+        return Ok(format!("{dir:?} is not a directory."));
+    }
+    eprintln!("  list {}", dir.display());
+    let listing: Vec<String> = files::expand(&[dir.to_path_buf()])?
+        .iter()
+        .map(|f| f.strip_prefix("./").unwrap_or(f).display().to_string())
+        .collect();
+    Ok(truncate(&listing.join("\n")))
+}
+
+fn edit(edits: &[Edit], yes: bool) -> Result<String, Error> {
+    let mut originals = BTreeMap::new();
+    for edit in edits {
+        if let Err(problem) = check_path(&edit.file) {
+            return Ok(problem);
+        }
+        let contents = if edit.file.exists() {
+            match fs::read_to_string(&edit.file) {
+                Ok(contents) => Some(contents),
+                Err(e) => {
+                    return Ok(format!("Could not read {:?}: {e}", edit.file))
+                }
+            }
+        } else {
+            None
+        };
+        originals.insert(edit.file.clone(), contents);
+    }
+    let changes = match refactor::plan(&originals, edits) {
+        Ok(changes) => changes,
+        Err(e) => return Ok(e.to_string()),
+    };
+    for edit in edits {
+        eprint!("{}", refactor::preview(edit));
+    }
+    if !confirm(Operation::FileWrite, "Apply these edits? [y/N] ", yes)? {
+        return Ok(
+            "The user rejected these edits, so no files were changed.".into()
+        );
+    }
+    for (file, contents) in &changes {
+        backup::save("agent", file).map_err(|e| e.wrap(Oops::AgentError))?;
+        // New files may be in directories which don't exist yet.
+        file.parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(file, contents))
+            .map_err(|e| oops(format!("Could not write {file:?}: {e}")))?;
+        eprintln!("  updated {}", file.display());
+    }
+    let changed: Vec<String> =
+        changes.keys().map(|f| f.display().to_string()).collect();
+    Ok(format!("Applied the edits to {}.", changed.join(", ")))
+}
+
+fn run(
+    command: &str,
+    cwd: &Path,
+    sandbox: &Sandbox,
+    yes: bool,
+) -> Result<String, Error> {
+    if !confirm(Operation::Shell, &format!("Run `{command}`? [y/N] "), yes)? {
+        return Ok(format!("The user declined to run `{command}`."));
+    }
+    let output = sandbox
+        .run(command, cwd)
+        .map_err(|e| e.wrap(Oops::AgentError))?;
+    match output.status {
+        Some(status) => eprintln!("  `{command}` exited with {status}"),
+        None => eprintln!("  `{command}` timed out"),
+    }
+    Ok(output.report(command))
+}
+
+/// Keep the end of `text`, which is where compilers and test runners print
+/// their summaries.
+fn truncate(text: &str) -> String {
+    if text.len() <= OUTPUT_LIMIT {
+        return text.to_string();
+    }
+    let mut start = text.len() - OUTPUT_LIMIT;
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    format!("[{start} bytes cut]\n{}", &text[start..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_path() {
+        assert!(check_path(Path::new("src/main.rs")).is_ok());
+        assert!(check_path(Path::new("./Cargo.toml")).is_ok());
+        assert!(check_path(Path::new("/etc/passwd")).is_err());
+        assert!(check_path(Path::new("src/../../secret")).is_err());
+        assert!(check_path(Path::new("new/dir/file.rs")).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_path_symlink() {
+        use std::os::unix::fs::symlink;
+        let dir = env::temp_dir()
+            .join(format!("yap-test-agent-check-path-{}", std::process::id()));
+        let (root, outside) = (dir.join("root"), dir.join("outside"));
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        symlink(&outside, root.join("escape")).unwrap();
+        symlink(outside.join("missing"), root.join("dangling")).unwrap();
+
+        assert!(check_path_in(&root, Path::new("src")).is_ok());
+        assert!(check_path_in(&root, Path::new("src/new/file.rs")).is_ok());
+        assert!(check_path_in(&root, Path::new("escape")).is_err());
+        assert!(check_path_in(&root, Path::new("escape/file.rs")).is_err());
+        assert!(check_path_in(&root, Path::new("dangling")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_step() {
+        let step: Step = serde_json::from_str(
+            r#"{
+                "thought": "Fix the typo.",
+                "action": "edit",
+                "path": null,
+                "edits": [{"file": "a.rs", "search": "fn mian", "replace": "fn main"}],
+                "summary": null
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(step.action, Action::Edit);
+        assert_eq!(step.edits.unwrap()[0].file, PathBuf::from("a.rs"));
+        let opts = Opts {
+            goal: "",
+            command: None,
+            cwd: Path::new("."),
+            timeout: None,
+            no_network: false,
+            max_steps: 1,
+            yes: false,
+        };
+        let give_up = Step {
+            thought: String::new(),
+            action: Action::GiveUp,
+            path: None,
+            edits: None,
+            summary: Some("stuck".into()),
+        };
+        assert!(matches!(
+            act(&give_up, &opts, &Sandbox::default()).unwrap(),
+            Outcome::GiveUp(s) if s == "stuck"
+        ));
+        let run = Step {
+            action: Action::Run,
+            ..give_up
+        };
+        assert!(matches!(
+            act(&run, &opts, &Sandbox::default()).unwrap(),
+            Outcome::Observe(_)
+        ));
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("short"), "short");
+        let long = "é".repeat(OUTPUT_LIMIT);
+        let cut = truncate(&long);
+        assert!(cut.starts_with(&format!("[{OUTPUT_LIMIT} bytes cut]\n")));
+        assert!(cut.ends_with('é'));
+    }
+}
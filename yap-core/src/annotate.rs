@@ -0,0 +1,1918 @@
+//! Annotate a source-code files.
+
+use crate::{
+    backup, config, constants, diff,
+    err::{Error, Oops},
+    files, lang,
+    openai::{
+        chat_async, estimate_tokens, CompletionPayload, CompletionResponse,
+        Content, Message, OpenAI, PayloadOpts, ResponseFormat, Role,
+    },
+    pool, rules,
+    serve::Daemon,
+    syntax,
+    term::{self, Decision},
+};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use serde_json::{from_str, json, to_string_pretty, Value};
+use std::{
+    collections::HashSet,
+    fmt::Write as FmtWrite,
+    fs::{read_to_string, File},
+    io::{self, BufRead, BufReader, Cursor, IsTerminal, Read, Write},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
+
+/// Files whose contents exceed this fraction of the model's context window
+/// are annotated in chunks; see [chunk_lines].
+const CHUNK_BUDGET: f64 = 0.25;
+
+/// The number of lines of context shared by adjacent chunks.
+const CHUNK_OVERLAP: usize = 20;
+
+/// How `yap annotate` should deliver annotations.
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum Format {
+    /// Insert annotations into the file as comments.
+    #[default]
+    Inline,
+    /// Print annotations to `STDOUT` as JSON, leaving the file untouched.
+    Json,
+    /// Print a [SARIF](https://sarifweb.azurewebsites.net/) log to `STDOUT`,
+    /// leaving the file untouched. SARIF logs can be uploaded to GitHub as
+    /// code-scanning results.
+    Sarif,
+    /// Print annotations to `STDOUT` as Markdown, leaving the file
+    /// untouched. Replacements are written as GitHub suggestions, so the
+    /// output can be pasted into a pull request review.
+    Markdown,
+    /// Print the replacements suggested by annotations to `STDOUT` as a
+    /// unified diff, which can be piped into `yap apply`. Annotations
+    /// without a replacement are left out.
+    Patch,
+}
+
+/// With `summary`, the LLM must also summarize the file as a whole.
+fn get_json_schema(summary: bool) -> Value {
+    let mut schema = json!({
+      "name": "source_file_annotations",
+      "schema": {
+        "type": "object",
+        "properties": {
+          "annotations": {
+            "type": "array",
+            "description": "A list of annotations related to the source file.",
+            "items": {
+              "type": "object",
+              "properties": {
+                "line_start": {
+                  "type": "integer",
+                  "description": "The line number in the source file where the annotation applies, or the first line of the block it applies to."
+                },
+                "line_end": {
+                  "type": ["integer", "null"],
+                  "description": "The last line of the block which the annotation applies to, if it is about more than one line; otherwise null."
+                },
+                "content": {
+                  "type": "string",
+                  "description": "The content of the annotation."
+                },
+                "replacement": {
+                  "type": ["object", "null"],
+                  "description": "Code to replace lines `line_start` through `line_end` with, if the annotation suggests a concrete change; otherwise null.",
+                  "properties": {
+                    "line_end": {
+                      "type": "integer",
+                      "description": "The last line replaced, which may equal `line_start`."
+                    },
+                    "code": {
+                      "type": "string",
+                      "description": "The replacement lines, without line numbers."
+                    }
+                  },
+                  "required": ["line_end", "code"],
+                  "additionalProperties": false
+                }
+              },
+              "required": ["line_start", "line_end", "content", "replacement"],
+              "additionalProperties": false
+            }
+          }
+        },
+        "required": ["annotations"],
+        "additionalProperties": false
+      },
+      "strict": true
+    });
+    if summary {
+        schema["schema"]["properties"]["summary"] = json!({
+          "type": "string",
+          "description": "A brief overview of the source file as a whole."
+        });
+        schema["schema"]["required"] = json!(["annotations", "summary"]);
+    }
+    schema
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct AnnotationResponse {
+    annotations: Vec<Annotation>,
+    /// Only requested with `yap annotate --summary`.
+    #[serde(default)]
+    summary: Option<String>,
+}
+
+/// Annotations destined for one file.
+#[derive(Debug, Deserialize, Serialize)]
+struct FileAnnotations {
+    file: PathBuf,
+    annotations: Vec<Annotation>,
+    /// An overview of the whole file, which goes before the line-level
+    /// annotations.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    summary: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Annotation {
+    /// Written as `line_number`, as it was before annotations could span a
+    /// range of lines, so that consumers of `--format json` and `yap daemon`
+    /// keep working; `line_end` is simply a new field. The LLM is asked for
+    /// `line_start`, which is also accepted.
+    #[serde(rename = "line_number", alias = "line_start")]
+    line_start: usize,
+    /// Set when the annotation is about a block of lines, rather than one
+    /// line; see [Annotation::span].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    line_end: Option<usize>,
+    content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    replacement: Option<Replacement>,
+}
+
+/// Code suggested in place of lines `line_start..=line_end`. The replacement
+/// has its own `line_end`, which need not match the annotation's.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Replacement {
+    line_end: usize,
+    code: String,
+}
+
+impl Annotation {
+    /// The lines which the annotation's replacement would replace.
+    fn lines(&self) -> RangeInclusive<usize> {
+        let end = self
+            .replacement
+            .as_ref()
+            .map_or(self.line_start, |r| r.line_end);
+        self.line_start..=end.max(self.line_start)
+    }
+
+    /// The lines which the annotation applies to, including any which its
+    /// replacement would replace.
+    fn span(&self) -> RangeInclusive<usize> {
+        let end = self.line_end.unwrap_or(self.line_start);
+        self.line_start..=end.max(*self.lines().end())
+    }
+
+    /// [Annotation::text], noting the range of lines it applies to if it
+    /// covers more than one; for display next to a single line, as an inline
+    /// comment or during `--interactive` review.
+    fn located_text(&self) -> String {
+        match self.line_end {
+            Some(end) if end > self.line_start => format!(
+                "(applies to lines {}-{end}) {}",
+                self.line_start,
+                self.text()
+            ),
+            _ => self.text(),
+        }
+    }
+
+    /// The annotation's content, followed by its replacement (if any) as a
+    /// fenced `suggestion` block.
+    fn text(&self) -> String {
+        match &self.replacement {
+            Some(Replacement { code, .. }) => format!(
+                "{}\n\n```suggestion\n{}\n```",
+                self.content,
+                code.trim_end_matches('\n')
+            ),
+            None => self.content.clone(),
+        }
+    }
+}
+
+/// Send the prompt and file hunk to OpenAI, and then deliver the annotations
+/// according to `format`. With [Format::Inline], annotations are applied
+/// directly to the file, wrapped by `comment_prefix` and `comment_suffix`,
+/// or else by the file's comment style (see [CommentStyles]), and placed
+/// according to `position`. `line_start` and `line_end` should be 1-based
+/// indexes. With `summary`, an overview of the file is also requested, and
+/// written as a comment block at the top of the file; see [prepend_summary].
+///
+/// If `interactive` is set, each annotation is shown to the user to accept,
+/// reject, or edit before anything is written; see [review].
+///
+/// Warning: `annotate` takes the asumption that the end-user is using version
+/// control on the `file`, which will be mutated in-place. The presumed
+/// use-case for `yap annotate` is for use on version-controlled source
+/// code i.e, in a [git](https://git-scm.com/) repository.
+#[allow(clippy::too_many_arguments)]
+pub fn annotate(
+    open_ai: &OpenAI,
+    user_prompt: Option<&str>,
+    file: &Path,
+    line_start: usize,
+    line_end: Option<usize>,
+    comment_prefix: Option<&str>,
+    comment_suffix: Option<&str>,
+    position: Position,
+    summary: bool,
+    format: Format,
+    interactive: bool,
+) -> Result<(), Error> {
+    let file_contents = read_file(file)?;
+    let response = pool::block_on(annotate_contents(
+        open_ai,
+        user_prompt,
+        file,
+        &file_contents,
+        line_start,
+        line_end,
+        summary,
+    ))?;
+    let styles = CommentStyles::load(comment_prefix, comment_suffix, position)?;
+    deliver(
+        vec![FileAnnotations {
+            file: file.to_path_buf(),
+            annotations: response.annotations,
+            summary: response.summary,
+        }],
+        format,
+        &styles,
+        interactive,
+    )
+    .map(|_| ())
+}
+
+/// Like [annotate], but the annotations are requested from `yap daemon`.
+/// They are still delivered by this process, so every `format` works.
+#[allow(clippy::too_many_arguments)]
+pub fn forward(
+    daemon: &mut Daemon,
+    user_prompt: Option<&str>,
+    file: &Path,
+    line_start: usize,
+    line_end: Option<usize>,
+    comment_prefix: Option<&str>,
+    comment_suffix: Option<&str>,
+    position: Position,
+    summary: bool,
+    format: Format,
+    interactive: bool,
+) -> Result<(), Error> {
+    let results: FileAnnotations = daemon.call(
+        "annotate",
+        json!({
+            "file": file,
+            "line_start": line_start,
+            "line_end": line_end,
+            "prompt": user_prompt,
+            "summary": summary,
+        }),
+    )?;
+    let styles = CommentStyles::load(comment_prefix, comment_suffix, position)?;
+    deliver(vec![results], format, &styles, interactive).map(|_| ())
+}
+
+/// Entrypoint for `yap annotate --dir`. Every file in `dir` which matches
+/// `include` and not `exclude` is annotated in full (see [files::walk]), and
+/// then a summary of how many annotations each file received is printed to
+/// `STDERR`. Files which are not valid UTF-8 are skipped. The remaining
+/// arguments are as for [annotate].
+#[allow(clippy::too_many_arguments)]
+pub fn annotate_dir(
+    open_ai: &OpenAI,
+    user_prompt: Option<&str>,
+    dir: &Path,
+    include: &[String],
+    exclude: &[String],
+    comment_prefix: Option<&str>,
+    comment_suffix: Option<&str>,
+    position: Position,
+    summary: bool,
+    format: Format,
+    interactive: bool,
+) -> Result<(), Error> {
+    let files = files::walk(dir, include, exclude)
+        .map_err(|e| e.wrap(Oops::AnnotateError))?;
+    let files: Vec<(PathBuf, String)> = files
+        .into_iter()
+        .filter_map(|file| match read_to_string(&file) {
+            Ok(contents) => Some((file, contents)),
+            Err(e) => {
+                eprintln!("Skipping {}: {e}", file.display());
+                None
+            }
+        })
+        .collect();
+    if files.is_empty() {
+        eprintln!("Nothing to annotate; no files in {dir:?} matched.");
+        return Ok(());
+    }
+
+    let results = pool::block_on(pool::map(
+        &files,
+        open_ai.max_concurrency,
+        |(file, contents)| async move {
+            let response = annotate_contents(
+                open_ai,
+                user_prompt,
+                file,
+                contents,
+                1,
+                None,
+                summary,
+            )
+            .await
+            .map_err(|e| {
+                e.wrap(Oops::AnnotateError)
+                    .because(format!("Could not annotate {file:?}"))
+            })?;
+            Ok(FileAnnotations {
+                file: file.clone(),
+                annotations: response.annotations,
+                summary: response.summary,
+            })
+        },
+    ))
+    .into_iter()
+    .collect::<Result<Vec<_>, Error>>()?;
+
+    let styles = CommentStyles::load(comment_prefix, comment_suffix, position)?;
+    let delivered = deliver(results, format, &styles, interactive)?;
+    eprint!("{}", summarize(&delivered));
+    Ok(())
+}
+
+/// A table of how many annotations each file received.
+fn summarize(results: &[FileAnnotations]) -> String {
+    let width = results
+        .iter()
+        .map(|r| r.file.display().to_string().len())
+        .max()
+        .unwrap_or(0);
+    let mut out = String::new();
+    let mut total = 0;
+    for FileAnnotations {
+        file, annotations, ..
+    } in results
+    {
+        total += annotations.len();
+        let _ = writeln!(
+            out,
+            "{:<width$}  {}",
+            file.display().to_string(),
+            annotations.len()
+        );
+    }
+    let _ = writeln!(
+        out,
+        "{total} annotation{} in {} file{}",
+        if total == 1 { "" } else { "s" },
+        results.len(),
+        if results.len() == 1 { "" } else { "s" }
+    );
+    out
+}
+
+/// Annotate lines `line_start..=line_end` of `file`, whose contents are
+/// `file_contents`, in as many chunks as its size requires. With `summary`,
+/// the file is summarized, too.
+async fn annotate_contents(
+    open_ai: &OpenAI,
+    user_prompt: Option<&str>,
+    file: &Path,
+    file_contents: &str,
+    line_start: usize,
+    line_end: Option<usize>,
+    summary: bool,
+) -> Result<AnnotationResponse, Error> {
+    let budget = open_ai.model.context_window() as f64 * CHUNK_BUDGET;
+    let chunks = chunk_lines(
+        file_contents,
+        line_start,
+        line_end,
+        budget as usize,
+        &syntax::boundaries(file, file_contents),
+    );
+    annotate_chunks(open_ai, user_prompt, chunks, summary).await
+}
+
+/// Annotations for lines `line_start..=line_end` of `file`, as a JSON
+/// object with `file` and `annotations` fields, without touching the file.
+/// With `summary`, an overview of the file is included as a `summary` field.
+/// Used by [crate::serve].
+pub fn annotations(
+    open_ai: &OpenAI,
+    user_prompt: Option<&str>,
+    file: &Path,
+    line_start: usize,
+    line_end: Option<usize>,
+    summary: bool,
+) -> Result<Value, Error> {
+    let file_contents = read_file(file)?;
+    let response = pool::block_on(annotate_contents(
+        open_ai,
+        user_prompt,
+        file,
+        &file_contents,
+        line_start,
+        line_end,
+        summary,
+    ))?;
+    Ok(json!(FileAnnotations {
+        file: file.to_path_buf(),
+        annotations: response.annotations,
+        summary: response.summary,
+    }))
+}
+
+/// Entrypoint for `yap annotate --diff`. Only the hunks of `diff` are sent to
+/// the LLM, and only annotations which land on added or modified lines are
+/// kept. Files matched by `.yapignore` are skipped (see [files]), unless
+/// `file` names them. If `diff` is `None`, the diff is read from `STDIN` when
+/// `STDIN` is not a terminal, or else from `git diff` (limited to `file`, if
+/// provided). Files are read relative to the current directory, so `git
+/// diff` is run with `--relative`; from a subdirectory, only changes within
+/// it are annotated. `interactive` is as for [annotate].
+#[allow(clippy::too_many_arguments)]
+pub fn annotate_diff(
+    open_ai: &OpenAI,
+    user_prompt: Option<&str>,
+    file: Option<&Path>,
+    comment_prefix: Option<&str>,
+    comment_suffix: Option<&str>,
+    position: Position,
+    format: Format,
+    interactive: bool,
+) -> Result<(), Error> {
+    let diff_text = if io::stdin().is_terminal() {
+        let mut args = vec!["--relative"];
+        if let Some(file) = file {
+            args.push("--");
+            args.push(file.to_str().ok_or_else(|| {
+                Error::default()
+                    .wrap(Oops::AnnotateError)
+                    .because(format!("{file:?} is not a unicode path"))
+            })?);
+        }
+        diff::git_diff(&args)
+    } else {
+        let mut buf = String::new();
+        io::stdin()
+            .read_to_string(&mut buf)
+            .map_err(|e| {
+                Error::default()
+                    .wrap(Oops::StdinReadError)
+                    .because(e.kind().to_string())
+            })
+            .map(|_| buf)
+    }
+    .map_err(|e| {
+        e.wrap(Oops::AnnotateError)
+            .because("Could not get a diff to annotate".into())
+    })?;
+    let mut file_diffs = diff::parse(&diff_text).map_err(|e| {
+        e.wrap(Oops::AnnotateError)
+            .because("Could not parse the diff to annotate".into())
+    })?;
+    if let Some(file) = file {
+        file_diffs.retain(|d| d.path == file);
+    } else {
+        let ignores = files::Ignores::load();
+        file_diffs.retain(|d| !ignores.ignores(&d.path));
+    }
+    if file_diffs.is_empty() {
+        eprintln!("Nothing to annotate; the diff is empty.");
+        return Ok(());
+    }
+
+    let results = pool::block_on(pool::map(
+        &file_diffs,
+        open_ai.max_concurrency,
+        |file_diff| async move {
+            let (target_contents, changed) = number_hunks(file_diff);
+            let mut annotations = get_annotations(
+                open_ai,
+                user_prompt,
+                target_contents,
+                Some(constants::ANNOTATE_DIFF_INSTRUCTIONS),
+                false,
+            )
+            .await
+            .map_err(|e| {
+                e.wrap(Oops::AnnotateError).because(format!(
+                    "Could not annotate changes to {:?}",
+                    file_diff.path
+                ))
+            })?
+            .annotations;
+            annotations.retain(|a| a.span().any(|n| changed.contains(&n)));
+            Ok(FileAnnotations {
+                file: file_diff.path.clone(),
+                annotations,
+                summary: None,
+            })
+        },
+    ))
+    .into_iter()
+    .collect::<Result<Vec<_>, Error>>()?;
+
+    let styles = CommentStyles::load(comment_prefix, comment_suffix, position)?;
+    deliver(results, format, &styles, interactive).map(|_| ())
+}
+
+/// The first and last lines of `symbol` in `file`; see [syntax::find].
+pub fn symbol_lines(
+    file: &Path,
+    symbol: &str,
+) -> Result<(usize, Option<usize>), Error> {
+    let lines = syntax::find(file, &read_file(file)?, symbol)?;
+    Ok((*lines.start(), Some(*lines.end())))
+}
+
+fn read_file(file: &Path) -> Result<String, Error> {
+    read_to_string(files::resolve(file)).map_err(|e| {
+        Error::default().wrap(Oops::AnnotateError).because(format!(
+            "Error while opening the file to annotate ({file:?}): {e}"
+        ))
+    })
+}
+
+/// Write or print annotations for each file according to `format`. With
+/// `interactive`, inline annotations are [review]ed first. Returns the
+/// annotations which were delivered.
+fn deliver(
+    results: Vec<FileAnnotations>,
+    format: Format,
+    styles: &CommentStyles,
+    interactive: bool,
+) -> Result<Vec<FileAnnotations>, Error> {
+    match format {
+        Format::Inline => {
+            let results = if interactive {
+                review(results)?
+            } else {
+                results
+            };
+            for FileAnnotations {
+                file,
+                annotations,
+                summary,
+            } in &results
+            {
+                if annotations.is_empty() && summary.is_none() {
+                    continue;
+                }
+                let file_contents = read_file(file)?;
+                write_inline(
+                    file,
+                    file_contents,
+                    annotations.clone(),
+                    summary.as_deref(),
+                    styles.for_file(file),
+                    styles.position,
+                )?;
+            }
+            Ok(results)
+        }
+        Format::Json => print_json(&to_json(&results)).map(|_| results),
+        Format::Sarif => print_json(&to_sarif(&results)).map(|_| results),
+        Format::Markdown => {
+            print!("{}", to_markdown(&results));
+            Ok(results)
+        }
+        Format::Patch => {
+            print!("{}", to_patch(&results)?);
+            Ok(results)
+        }
+    }
+}
+
+/// Show each annotation next to the line it belongs to, and ask the user
+/// whether to keep it. Returns the annotations which were accepted, or
+/// edited in [term::edit]. Nothing is written until every annotation has
+/// been reviewed; quitting keeps only the annotations accepted so far. File
+/// summaries are kept as they are.
+fn review(
+    results: Vec<FileAnnotations>,
+) -> Result<Vec<FileAnnotations>, Error> {
+    // Set by `AcceptAll` or `Quit`.
+    let mut rest = None;
+    let mut reviewed = Vec::with_capacity(results.len());
+    for FileAnnotations {
+        file,
+        mut annotations,
+        summary,
+    } in results
+    {
+        annotations.sort_by_key(|a| a.line_start);
+        let contents = read_file(&file)?;
+        let lines: Vec<&str> = contents.lines().collect();
+        let mut kept = Vec::new();
+        for mut annotation in annotations {
+            match rest {
+                Some(Decision::AcceptAll) => {
+                    kept.push(annotation);
+                    continue;
+                }
+                Some(_) => break,
+                None => {}
+            }
+            let at = annotation.line_start;
+            eprintln!("\n{}:{at}", file.display());
+            for n in at.saturating_sub(2).max(1)..=at {
+                if let Some(line) = lines.get(n - 1) {
+                    eprintln!("{n:>5} | {line}");
+                }
+            }
+            for line in annotation.located_text().lines() {
+                eprintln!("      yap :: {line}");
+            }
+            loop {
+                let answer = term::ask(
+                    "Keep this annotation? [y]es, [n]o, [e]dit, [a]ll, [q]uit: ",
+                )?
+                .ok_or_else(|| {
+                    Error::default().wrap(Oops::AnnotateError).because(
+                        "STDIN is not a terminal, so annotations cannot be reviewed".into(),
+                    )
+                })?;
+                match Decision::parse(&answer) {
+                    Some(Decision::Accept) => kept.push(annotation),
+                    Some(Decision::Reject) => {}
+                    Some(Decision::Edit) => {
+                        let edited = term::edit(&annotation.content)?;
+                        if edited.trim().is_empty() {
+                            eprintln!(
+                                "The annotation is empty, so it was dropped."
+                            );
+                        } else {
+                            annotation.content = edited.trim().to_string();
+                            kept.push(annotation);
+                        }
+                    }
+                    Some(Decision::AcceptAll) => {
+                        kept.push(annotation);
+                        rest = Some(Decision::AcceptAll);
+                    }
+                    Some(Decision::Quit) => rest = Some(Decision::Quit),
+                    None => continue,
+                }
+                break;
+            }
+        }
+        reviewed.push(FileAnnotations {
+            file,
+            annotations: kept,
+            summary,
+        });
+    }
+    Ok(reviewed)
+}
+
+/// A window of lines to annotate in one request. Adjacent chunks overlap by
+/// [CHUNK_OVERLAP] lines, so that the LLM has some context at the edges of
+/// each chunk, but each line is owned by exactly one chunk.
+#[derive(Debug)]
+struct Chunk {
+    /// Annotations outside of this range are discarded, since the line
+    /// belongs to a neighboring chunk.
+    owned: RangeInclusive<usize>,
+    /// The numbered lines sent to the LLM, including overlap.
+    text: String,
+}
+
+/// Split lines `line_start..=line_end` into chunks of roughly `budget`
+/// tokens each. Small files will have a single chunk. Where possible, chunks
+/// end at one of `boundaries` (see [syntax::boundaries]), so that functions
+/// and types aren't split between chunks.
+fn chunk_lines(
+    file_contents: &str,
+    line_start: usize,
+    line_end: Option<usize>,
+    budget: usize,
+    boundaries: &[usize],
+) -> Vec<Chunk> {
+    let line_start = line_start.max(1);
+    let total = file_contents.split("\n").count();
+    let line_end = line_end.unwrap_or(total).min(total);
+    let sizes: Vec<usize> = file_contents
+        .split("\n")
+        .map(|l| estimate_tokens(l) + 1)
+        .collect();
+
+    let mut chunks = Vec::new();
+    let mut start = line_start;
+    while start <= line_end {
+        let mut end = start;
+        let mut size = sizes[start - 1];
+        while end < line_end && size + sizes[end] <= budget {
+            size += sizes[end];
+            end += 1;
+        }
+        if end < line_end {
+            if let Some(boundary) =
+                boundaries.iter().rev().find(|b| (start..end).contains(b))
+            {
+                end = *boundary;
+            }
+        }
+        let context_start = start.saturating_sub(CHUNK_OVERLAP).max(line_start);
+        let context_end = (end + CHUNK_OVERLAP).min(line_end);
+        chunks.push(Chunk {
+            owned: start..=end,
+            text: number_lines(file_contents, context_start, Some(context_end)),
+        });
+        start = end + 1;
+    }
+    chunks
+}
+
+/// Annotate each chunk concurrently (see [crate::pool]), and merge the
+/// results. Since no chunk sees the whole file, the summary of a chunked
+/// file is the summaries of each chunk, in order.
+async fn annotate_chunks(
+    open_ai: &OpenAI,
+    user_prompt: Option<&str>,
+    chunks: Vec<Chunk>,
+    summary: bool,
+) -> Result<AnnotationResponse, Error> {
+    if chunks.len() == 1 {
+        let chunk = chunks.into_iter().next().expect("there is one chunk");
+        return get_annotations(
+            open_ai,
+            user_prompt,
+            chunk.text,
+            None,
+            summary,
+        )
+        .await;
+    }
+    debug!("Annotating in {} chunks", chunks.len());
+    let results = pool::map(&chunks, open_ai.max_concurrency, |chunk| {
+        let instructions = format!(
+            "The file is too large to annotate all at once, so you are only seeing a portion of it. Only annotate lines {} through {}; other lines are provided for context.",
+            chunk.owned.start(),
+            chunk.owned.end()
+        );
+        async move {
+            get_annotations(
+                open_ai,
+                user_prompt,
+                chunk.text.clone(),
+                Some(&instructions),
+                summary,
+            )
+            .await
+            .map(|mut r| {
+                r.annotations
+                    .retain(|a| chunk.owned.contains(&a.line_start));
+                r
+            })
+        }
+    })
+    .await;
+    let mut merged = AnnotationResponse::default();
+    let mut summaries = Vec::new();
+    for result in results {
+        let result = result?;
+        merged.annotations.extend(result.annotations);
+        summaries.extend(result.summary);
+    }
+    if summary {
+        merged.summary = Some(summaries.join("\n\n"));
+    }
+    Ok(merged)
+}
+
+/// Prefix lines `line_start..=line_end` of `file_contents` with their line
+/// number.
+///
+/// I think that enumerating lines before firing the file off to the LLM will
+/// improve the annotation response. It seems like asking for annotations
+/// without numbering the lines is a lot like the classic "how many R's are in
+/// the word strawberry," question. In order to provide a correct response,
+/// the LLM needs to reason through counting the lines itself, but
+/// <https://youtu.be/QhMo4WlBmGM?si=O0BFajfZrM0SzJDc>
+fn number_lines(
+    file_contents: &str,
+    line_start: usize,
+    line_end: Option<usize>,
+) -> String {
+    let line_start = line_start.max(1);
+    file_contents
+        .split("\n")
+        .enumerate()
+        .skip(line_start - 1)
+        .take(line_end.map(|v| v + 1 - line_start).unwrap_or(usize::MAX))
+        .fold(
+            String::with_capacity(file_contents.len()),
+            |mut acc, (idx, line)| {
+                writeln!(acc, "{} {}", idx + 1, line).expect(
+                    "can write into accumulator while enumerating the file to annotate"
+                );
+                acc
+            },
+        )
+}
+
+/// Like [number_lines], but for the hunks of a diff. Added lines are marked
+/// with a `+`. Also returns the set of added line numbers.
+fn number_hunks(file_diff: &diff::FileDiff) -> (String, HashSet<usize>) {
+    let mut changed = HashSet::new();
+    let mut out = String::new();
+    for hunk in &file_diff.hunks {
+        out.push_str("...\n");
+        for (lineno, line, added) in hunk.new_lines() {
+            let marker = if added {
+                changed.insert(lineno);
+                '+'
+            } else {
+                ' '
+            };
+            writeln!(out, "{lineno} {marker} {line}").expect(
+                "can write into accumulator while enumerating hunks to annotate",
+            );
+        }
+    }
+    (out, changed)
+}
+
+/// Ask the LLM for annotations on `target_contents`, which has already been
+/// prefixed with line numbers. `instructions` are sent as an additional
+/// system message, if provided. With `summary`, the response also includes a
+/// summary of the file.
+async fn get_annotations(
+    open_ai: &OpenAI,
+    user_prompt: Option<&str>,
+    target_contents: String,
+    instructions: Option<&str>,
+    summary: bool,
+) -> Result<AnnotationResponse, Error> {
+    let custom_prompt = config::ConfigFile::AnnotateSystemPrompt
+        .load()
+        .map_err(|e| {
+            e.wrap(Oops::AnnotateError).because(
+                "Needed to load annotate system prompt to do annotations"
+                    .into(),
+            )
+        })?;
+    let system_prompt = rules::with_rules(
+        custom_prompt
+            .as_deref()
+            .unwrap_or(constants::DEFAULT_ANNOTATE_PROMPT),
+    )
+    .map_err(|e| e.wrap(Oops::AnnotateError))?;
+    let payload = CompletionPayload::new(
+        open_ai,
+        vec![
+            Some(Message::new(Role::System, system_prompt)),
+            instructions.map(|i| Message::new(Role::System, i.into())),
+            summary.then(|| {
+                Message::new(
+                    Role::System,
+                    constants::ANNOTATE_SUMMARY_INSTRUCTIONS.into(),
+                )
+            }),
+            Some(Message::new(Role::User, target_contents)),
+            Some(match user_prompt {
+                Some(prompt) => Message::new(Role::User, prompt.into()),
+                None => Message::new(Role::System,
+                    "The end-user did not provide a specific prompt. Provide generally useful annotations on the file above".into()
+                )
+            }),
+        ]
+        .into_iter()
+        .flatten()
+        .collect(),
+        PayloadOpts {
+            response_format: ResponseFormat::JsonSchema {
+                json_schema: get_json_schema(summary),
+            },
+            ..Default::default()
+        },
+    );
+    let response = chat_async(open_ai, &payload).await.map_err(|e| {
+        e.wrap(Oops::AnnotateError)
+            .because("Error after sending annotation payload to OpenAI".into())
+    })?;
+    parse_annotations(&response)
+}
+
+/// The annotations in the LLM's `response`.
+fn parse_annotations(
+    response: &CompletionResponse,
+) -> Result<AnnotationResponse, Error> {
+    let message = &response.choices[0].message;
+    let content = message.parse().map_err(|e| {
+        e.wrap(Oops::AnnotateError)
+            .because("Could not parse OpenAi response content".into())
+    })?;
+    let annotation_str = match content {
+        Content::Normal(c) => Ok(c),
+        Content::Refusal(r) => Err(Error::default()
+            .wrap(Oops::OpenAIRefusal)
+            .wrap(Oops::AnnotateError)
+            .because(format!(
+            "OpenAI sent a refusal in response to your annotation request: {r}"
+        ))),
+    }?;
+    let response: AnnotationResponse =
+        from_str(annotation_str).map_err(|e| {
+            debug!("Bad response content: {annotation_str}");
+            Error::default().wrap(Oops::AnnotateError).because(format!(
+                "Failed to deserialize annotation string into annotations: {e}"
+            ))
+        })?;
+
+    debug!("Received annotations {:?}", response.annotations);
+
+    Ok(response)
+}
+
+/// Rewrite `file` with `annotations` inlined as comments, and `summary` at
+/// the top.
+fn write_inline(
+    file: &Path,
+    file_contents: String,
+    annotations: Vec<Annotation>,
+    summary: Option<&str>,
+    file_type_info: FileTypeInfo,
+    position: Position,
+) -> Result<(), Error> {
+    let cursor = Cursor::new(file_contents);
+    let reader = BufReader::new(cursor);
+    let mut write_buffer = vec![];
+    apply_annotations(
+        reader,
+        &mut write_buffer,
+        annotations,
+        file_type_info,
+        position,
+    )
+    .map_err(|e| {
+        e.wrap(Oops::AnnotateError)
+            .because(format!("Error occurred while annotating {file:?}"))
+    })?;
+    let write_buffer = match summary {
+        Some(summary) => prepend_summary(
+            &String::from_utf8_lossy(&write_buffer),
+            summary,
+            file_type_info,
+        )
+        .into_bytes(),
+        None => write_buffer,
+    };
+
+    backup::save("annotate", file).map_err(|e| e.wrap(Oops::AnnotateError))?;
+    File::create(file)
+        .map_err(|e| {
+            Error::default().wrap(Oops::AnnotateError).because(format!(
+                "Could not open annotation target ({file:?}) for writing: {e}"
+            ))
+        })?
+        .write(&write_buffer)
+        .map_err(|e| {
+            Error::default().wrap(Oops::AnnotateError).because(format!(
+                "Error while writing annotations into {file:?}: {e}"
+            ))
+        })?;
+
+    Ok(())
+}
+
+fn print_json(value: &Value) -> Result<(), Error> {
+    let out = to_string_pretty(value).map_err(|e| {
+        Error::default()
+            .wrap(Oops::AnnotateError)
+            .because(format!("Could not serialize annotations: {e}"))
+    })?;
+    println!("{out}");
+    Ok(())
+}
+
+fn to_json(results: &[FileAnnotations]) -> Value {
+    json!(results)
+}
+
+/// Build a minimal SARIF 2.1.0 log. Each annotation becomes a `note`-level
+/// result located at its line in its file, and each summary becomes a
+/// `yap/summary` result located at its file.
+fn to_sarif(results: &[FileAnnotations]) -> Value {
+    let summaries: Vec<Value> = results
+        .iter()
+        .filter_map(|r| {
+            let uri = r.file.to_string_lossy().replace('\\', "/");
+            r.summary.as_ref().map(|summary| {
+                json!({
+                    "ruleId": "yap/summary",
+                    "level": "note",
+                    "message": { "text": summary },
+                    "locations": [{
+                        "physicalLocation": {
+                            "artifactLocation": { "uri": uri }
+                        }
+                    }]
+                })
+            })
+        })
+        .collect();
+    let results: Vec<Value> = results
+        .iter()
+        .flat_map(|r| {
+            let uri = r.file.to_string_lossy().replace('\\', "/");
+            r.annotations.iter().map(move |a| (uri.clone(), a))
+        })
+        .map(|(uri, a)| {
+            let mut result = json!({
+                "ruleId": "yap/annotation",
+                "level": "note",
+                "message": { "text": a.content },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": uri },
+                        "region": {
+                            "startLine": a.line_start,
+                            "endLine": a.span().end()
+                        }
+                    }
+                }]
+            });
+            if let Some(replacement) = &a.replacement {
+                let mut code =
+                    replacement.code.trim_end_matches('\n').to_string();
+                code.push('\n');
+                result["fixes"] = json!([{
+                    "description": { "text": "Suggested by yap" },
+                    "artifactChanges": [{
+                        "artifactLocation": { "uri": uri },
+                        "replacements": [{
+                            "deletedRegion": {
+                                "startLine": a.line_start,
+                                "endLine": a.lines().end()
+                            },
+                            "insertedContent": { "text": code }
+                        }]
+                    }]
+                }]);
+            }
+            result
+        })
+        .collect();
+    let results = [summaries, results].concat();
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "yap",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": "https://github.com/jdevries3133/yap",
+                    "rules": [{
+                        "id": "yap/annotation",
+                        "shortDescription": { "text": "LLM annotation" }
+                    }, {
+                        "id": "yap/summary",
+                        "shortDescription": { "text": "LLM file summary" }
+                    }]
+                }
+            },
+            "results": results
+        }]
+    })
+}
+
+/// Render annotations as Markdown, with a heading for each file, followed by
+/// its summary. Replacements become GitHub suggestion blocks; see
+/// [Annotation::text].
+fn to_markdown(results: &[FileAnnotations]) -> String {
+    let mut out = String::new();
+    for FileAnnotations {
+        file,
+        annotations,
+        summary,
+    } in results
+    {
+        if annotations.is_empty() && summary.is_none() {
+            continue;
+        }
+        let mut annotations = annotations.clone();
+        annotations.sort_by_key(|a| a.line_start);
+        let _ = writeln!(out, "## {}\n", file.display());
+        if let Some(summary) = summary {
+            let _ = writeln!(out, "{}\n", summary.trim());
+        }
+        for annotation in annotations {
+            let lines = annotation.span();
+            if lines.start() == lines.end() {
+                let _ = writeln!(out, "**Line {}**\n", lines.start());
+            } else {
+                let _ = writeln!(
+                    out,
+                    "**Lines {}-{}**\n",
+                    lines.start(),
+                    lines.end()
+                );
+            }
+            let _ = writeln!(out, "{}\n", annotation.text());
+        }
+    }
+    out
+}
+
+/// A unified diff of the replacements suggested for each file, for
+/// `yap apply`. Replacements which overlap an earlier one, or which fall
+/// outside of the file, are skipped with a warning.
+fn to_patch(results: &[FileAnnotations]) -> Result<String, Error> {
+    let mut out = String::new();
+    for FileAnnotations {
+        file, annotations, ..
+    } in results
+    {
+        let mut annotations: Vec<&Annotation> = annotations
+            .iter()
+            .filter(|a| a.replacement.is_some())
+            .collect();
+        if annotations.is_empty() {
+            continue;
+        }
+        annotations.sort_by_key(|a| a.line_start);
+        let contents = read_file(file)?;
+        out.push_str(&patch_file(file, &contents, &annotations));
+    }
+    Ok(out)
+}
+
+fn patch_file(
+    file: &Path,
+    contents: &str,
+    annotations: &[&Annotation],
+) -> String {
+    let lines: Vec<&str> = contents.lines().collect();
+    let path = file.to_string_lossy().replace('\\', "/");
+    let mut out = format!("--- a/{path}\n+++ b/{path}\n");
+    // Each hunk shifts the lines after it by the difference in length.
+    let mut offset: isize = 0;
+    let mut next_free = 1;
+    let mut hunks = 0;
+    for annotation in annotations {
+        let Some(replacement) = &annotation.replacement else {
+            continue;
+        };
+        let range = annotation.lines();
+        if *range.start() < next_free || *range.end() > lines.len() {
+            eprintln!(
+                "Skipping the replacement for {}:{}, which overlaps another or is out of range",
+                file.display(),
+                range.start()
+            );
+            continue;
+        }
+        next_free = range.end() + 1;
+        let old = &lines[range.start() - 1..*range.end()];
+        let new: Vec<&str> = if replacement.code.is_empty() {
+            vec![]
+        } else {
+            replacement.code.trim_end_matches('\n').lines().collect()
+        };
+        let new_start = *range.start() as isize + offset;
+        let _ = writeln!(
+            out,
+            "@@ -{},{} +{new_start},{} @@",
+            range.start(),
+            old.len(),
+            new.len()
+        );
+        for line in old {
+            let _ = writeln!(out, "-{line}");
+        }
+        for line in &new {
+            let _ = writeln!(out, "+{line}");
+        }
+        offset += new.len() as isize - old.len() as isize;
+        hunks += 1;
+    }
+    if hunks == 0 {
+        return String::new();
+    }
+    out
+}
+
+/// Where inline annotations go, relative to the line they are about.
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum Position {
+    /// On the lines above.
+    #[default]
+    Before,
+    /// On the lines below.
+    After,
+    /// At the end of the line. Annotations which span several lines, such as
+    /// those with a suggested replacement, go before the line instead.
+    Eol,
+}
+
+/// Decides how annotations are commented in each file. In order of
+/// precedence, the comment prefix comes from `--comment-prefix`,
+/// `comment_styles` in `config.json` (see [config::Settings::comment_style]),
+/// the file's [lang::Language], or else `// `. `--comment-suffix` replaces
+/// the suffix of any of these.
+struct CommentStyles<'a> {
+    prefix: Option<&'a str>,
+    suffix: Option<&'a str>,
+    position: Position,
+    settings: config::Settings,
+}
+
+impl<'a> CommentStyles<'a> {
+    fn load(
+        prefix: Option<&'a str>,
+        suffix: Option<&'a str>,
+        position: Position,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            prefix,
+            suffix,
+            position,
+            settings: config::Settings::load()
+                .map_err(|e| e.wrap(Oops::AnnotateError))?,
+        })
+    }
+
+    fn for_file(&self, file: &Path) -> FileTypeInfo<'_> {
+        if let Some(prefix) = self.prefix {
+            return FileTypeInfo::new(prefix, self.suffix);
+        }
+        let (prefix, suffix) = self
+            .settings
+            .comment_style(file)
+            .map(|s| (s.prefix.as_str(), s.suffix.as_str()))
+            .or_else(|| lang::by_path(file).map(|l| l.comment))
+            .unwrap_or(("// ", ""));
+        FileTypeInfo::new(prefix, Some(self.suffix.unwrap_or(suffix)))
+    }
+}
+
+#[derive(Clone, Copy)]
+struct FileTypeInfo<'a> {
+    comment_suffix: &'a str,
+    comment_prefix: &'a str,
+}
+
+impl<'a> FileTypeInfo<'a> {
+    fn new(prefix: &'a str, suffix: Option<&'a str>) -> Self {
+        Self {
+            comment_prefix: prefix,
+            comment_suffix: suffix.as_ref().map_or("", |v| v),
+        }
+    }
+}
+
+fn apply_annotations<R: BufRead, W: Write>(
+    reader: R,
+    writer: &mut W,
+    mut annotations: Vec<Annotation>,
+    file_type_info: FileTypeInfo,
+    position: Position,
+) -> Result<(), Error> {
+    annotations.sort_by_key(|a| a.line_start);
+
+    let mut annotations_iter = annotations.into_iter();
+    let mut current_annotation = annotations_iter.next();
+
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| {
+            Error::default().wrap(Oops::AnnotateError).because(format!(
+                "I/O error while reading file to annotate: {e}"
+            ))
+        })?;
+        if let Some(annotation) = &current_annotation {
+            if line_number + 1 == annotation.line_start {
+                let text = annotation.located_text();
+                let comment = yapify_annotation_content(&text, file_type_info);
+                match position {
+                    Position::After => write!(writer, "{line}\n{comment}\n"),
+                    Position::Eol if !text.contains('\n') => {
+                        if line.trim().is_empty() {
+                            writeln!(writer, "{comment}")
+                        } else {
+                            writeln!(writer, "{line}  {}", comment.trim_start())
+                        }
+                    }
+                    Position::Before | Position::Eol => {
+                        write!(writer, "{comment}\n{line}\n")
+                    }
+                }
+                .map_err(|e| {
+                    Error::default().wrap(Oops::AnnotateError).because(format!(
+                        "Error while writing annotation into output: {e:?}"
+                    ))
+                })?;
+                current_annotation = annotations_iter.next();
+            } else {
+                writeln!(writer, "{}", line).map_err(|e| Error::default().wrap(Oops::AnnotateError).because(
+                        format!(
+                            "Error while writing from reader to writer (lineno does not match): {e:?}"
+                        )
+                ))?;
+            }
+        } else {
+            writeln!(writer, "{}", line).map_err(|e| Error::default().wrap(Oops::AnnotateError).because(
+                    format!(
+                        "Error while writing from reader to writer (no annotation): {e:?}"
+                    )
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+/// Insert `summary` as a comment block at the top of `contents`, after the
+/// shebang line, if there is one.
+fn prepend_summary(
+    contents: &str,
+    summary: &str,
+    file_type_info: FileTypeInfo,
+) -> String {
+    let split = if contents.starts_with("#!") {
+        contents.find('\n').map_or(contents.len(), |i| i + 1)
+    } else {
+        0
+    };
+    let (shebang, rest) = contents.split_at(split);
+    let mut out = shebang.to_string();
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+    out.push_str(&yapify_annotation_content(summary, file_type_info));
+    out.push('\n');
+    out.push_str(rest);
+    out
+}
+
+/// Transforms potentially multi-line content into;
+///
+/// ```plain
+/// {' ' * left_padding}{prefix}yap :: {content}{suffix}
+/// ```
+fn yapify_annotation_content(
+    content: &'_ str,
+    file_type_info: FileTypeInfo,
+) -> String {
+    let mut output = String::with_capacity(content.len());
+    for line in content.lines() {
+        output.push_str(file_type_info.comment_prefix);
+        output.push_str("yap :: ");
+        output.push_str(line);
+        output.push_str(file_type_info.comment_suffix);
+        output.push('\n');
+    }
+    // Remove the trailing newline.
+    output.pop();
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::openai::chat;
+    use std::io::{BufReader, Cursor};
+
+    #[test]
+    fn test_replay_annotations() {
+        let open_ai = OpenAI::replaying(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/annotate"),
+        );
+        let payload = CompletionPayload::new(
+            &open_ai,
+            vec![Message::new(
+                Role::User,
+                number_lines("fn main() {\n    let x = 1;\n}\n", 1, None),
+            )],
+            PayloadOpts {
+                response_format: ResponseFormat::JsonSchema {
+                    json_schema: get_json_schema(false),
+                },
+                ..Default::default()
+            },
+        );
+        let response = chat(&open_ai, &payload).unwrap();
+        let AnnotationResponse {
+            annotations,
+            summary,
+        } = parse_annotations(&response).unwrap();
+        assert!(summary.is_none());
+        assert_eq!(annotations.len(), 2);
+        assert_eq!(annotations[0].lines(), 2..=2);
+        assert_eq!(
+            annotations[0].replacement.as_ref().unwrap().code,
+            "    let _x = 1;"
+        );
+        assert_eq!(annotations[1].line_start, 3);
+        assert!(annotations[1].content.contains("`Result`"));
+        assert!(annotations[1].replacement.is_none());
+    }
+
+    #[test]
+    fn test_comment_styles() {
+        let mut settings = config::Settings::default();
+        settings.comment_styles.insert(
+            "njk".into(),
+            config::CommentStyle {
+                prefix: "{# ".into(),
+                suffix: " #}".into(),
+            },
+        );
+        settings.comment_styles.insert(
+            "py".into(),
+            config::CommentStyle {
+                prefix: "## ".into(),
+                suffix: "".into(),
+            },
+        );
+        let styles = CommentStyles {
+            prefix: None,
+            suffix: None,
+            position: Position::Before,
+            settings,
+        };
+        let style = |file: &str| {
+            let info = styles.for_file(Path::new(file));
+            (info.comment_prefix, info.comment_suffix)
+        };
+        assert_eq!(style("page.njk"), ("{# ", " #}"));
+        assert_eq!(style("app.py"), ("## ", ""));
+        assert_eq!(style("index.html"), ("<!-- ", " -->"));
+        assert_eq!(style("main.rs"), ("// ", ""));
+        assert_eq!(style("README"), ("// ", ""));
+
+        let styles = CommentStyles {
+            prefix: Some("; "),
+            ..styles
+        };
+        assert_eq!(styles.for_file(Path::new("page.njk")).comment_suffix, "");
+    }
+
+    #[test]
+    fn test_apply_annotation_positions() {
+        let input = "fn main() {\n    run();\n}\n";
+        let annotations = vec![
+            Annotation {
+                line_start: 2,
+                line_end: None,
+                content: "may panic".into(),
+                replacement: None,
+            },
+            Annotation {
+                line_start: 3,
+                line_end: None,
+                content: "end\nof main".into(),
+                replacement: None,
+            },
+        ];
+        let apply = |position| {
+            let mut output = Vec::new();
+            apply_annotations(
+                BufReader::new(Cursor::new(input)),
+                &mut output,
+                annotations.clone(),
+                typical_info(),
+                position,
+            )
+            .unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        assert_eq!(
+            apply(Position::After),
+            "fn main() {\n    run();\n// yap :: may panic\n}\n\
+            // yap :: end\n// yap :: of main\n"
+        );
+        assert_eq!(
+            apply(Position::Eol),
+            "fn main() {\n    run();  // yap :: may panic\n\
+            // yap :: end\n// yap :: of main\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_prepend_summary() {
+        assert_eq!(
+            prepend_summary(
+                "fn main() {}\n",
+                "Runs.\nQuickly.",
+                typical_info()
+            ),
+            "// yap :: Runs.\n// yap :: Quickly.\nfn main() {}\n"
+        );
+        assert_eq!(
+            prepend_summary("#!/bin/sh\necho hi\n", "Greets.", hash_info()),
+            "#!/bin/sh\n# yap :: Greets.\necho hi\n"
+        );
+    }
+
+    fn hash_info() -> FileTypeInfo<'static> {
+        FileTypeInfo::new("# ", None)
+    }
+
+    fn typical_info() -> FileTypeInfo<'static> {
+        FileTypeInfo::new("// ", Some(""))
+    }
+
+    fn html_info() -> FileTypeInfo<'static> {
+        FileTypeInfo::new("<!-- ", Some(" -->"))
+    }
+
+    #[test]
+    fn test_apply_annotation() {
+        let input_data = "#!/bin/sh
+
+echo 'hello world'"
+            .to_string();
+
+        let annotations = vec![Annotation {
+            line_start: 3,
+            line_end: None,
+            content: r#"this will print "hello world" to STDOUT"#.into(),
+            replacement: None,
+        }];
+        let expected_output = r##"#!/bin/sh
+
+// yap :: this will print "hello world" to STDOUT
+echo 'hello world'
+"##;
+
+        let reader = BufReader::new(Cursor::new(input_data));
+        let mut output = Vec::new();
+        let mut writer = Cursor::new(&mut output);
+
+        apply_annotations(
+            reader,
+            &mut writer,
+            annotations,
+            typical_info(),
+            Position::Before,
+        )
+        .unwrap();
+
+        let result = String::from_utf8(output).unwrap();
+        assert_eq!(result, expected_output);
+    }
+    #[test]
+    fn test_apply_annotation_out_of_order() {
+        let input_data = "#!/bin/sh
+
+echo 'hello world'
+
+exit 1
+"
+        .to_string();
+
+        let annotations = vec![
+            Annotation {
+            line_start: 5,
+            line_end: None,
+            content: r"Exit with non-zero status, indicating that an error has occurred.".into(),
+                replacement: None,
+            },
+            Annotation {
+            line_start: 3,
+            line_end: None,
+            content: r#"print "hello world" to STDOUT"#.into(),
+            replacement: None,
+        }];
+        let expected_output = r##"#!/bin/sh
+
+// yap :: print "hello world" to STDOUT
+echo 'hello world'
+
+// yap :: Exit with non-zero status, indicating that an error has occurred.
+exit 1
+"##;
+
+        let reader = BufReader::new(Cursor::new(input_data));
+        let mut output = Vec::new();
+        let mut writer = Cursor::new(&mut output);
+
+        apply_annotations(
+            reader,
+            &mut writer,
+            annotations,
+            typical_info(),
+            Position::Before,
+        )
+        .unwrap();
+
+        let result = String::from_utf8(output).unwrap();
+        assert_eq!(result, expected_output);
+    }
+    #[test]
+    fn test_apply_annotation_multi_line() {
+        let input_data = "// main.rs
+
+value.as_ref().map(|i| i.as_str()).iter().reduce(String::new(), |a, v| {
+    a.push(v);
+    a
+})
+";
+        let annotations = vec![Annotation {
+            line_start: 3,
+            line_end: None,
+            content: "It does that\nIt does this\nIt does other thing".into(),
+            replacement: None,
+        }];
+
+        let expected_output = "// main.rs
+
+// yap :: It does that
+// yap :: It does this
+// yap :: It does other thing
+value.as_ref().map(|i| i.as_str()).iter().reduce(String::new(), |a, v| {
+    a.push(v);
+    a
+})
+";
+        let reader = BufReader::new(Cursor::new(input_data));
+        let mut output = Vec::new();
+        let mut writer = Cursor::new(&mut output);
+
+        apply_annotations(
+            reader,
+            &mut writer,
+            annotations,
+            typical_info(),
+            Position::Before,
+        )
+        .unwrap();
+
+        let result = String::from_utf8(output).unwrap();
+        println!("{}\n{}", result, expected_output);
+        assert_eq!(result, expected_output);
+    }
+    #[test]
+    fn test_apply_annotation_for_html_like_syntax() {
+        let input_data = "<!-- This is a comment -->
+<!DOCTYPE html>
+<html>
+<head>
+    <title>Test Document</title>
+</head>
+<body>
+    <h1>Hello World</h1>
+</body>
+</html>
+"
+        .to_string();
+
+        let annotations = vec![
+            Annotation {
+                line_start: 2,
+                line_end: None,
+                content: "This comment provides context for the HTML document."
+                    .into(),
+                replacement: None,
+            },
+            Annotation {
+                line_start: 8,
+                line_end: None,
+                content: "This is the main heading of the page.".into(),
+                replacement: None,
+            },
+        ];
+
+        let expected_output = r##"<!-- This is a comment -->
+<!-- yap :: This comment provides context for the HTML document. -->
+<!DOCTYPE html>
+<html>
+<head>
+    <title>Test Document</title>
+</head>
+<body>
+<!-- yap :: This is the main heading of the page. -->
+    <h1>Hello World</h1>
+</body>
+</html>
+"##;
+
+        let reader = BufReader::new(Cursor::new(input_data));
+        let mut output = Vec::new();
+        let mut writer = Cursor::new(&mut output);
+
+        apply_annotations(
+            reader,
+            &mut writer,
+            annotations,
+            html_info(),
+            Position::Before,
+        )
+        .unwrap();
+
+        let result = String::from_utf8(output).unwrap();
+        assert_eq!(result, expected_output);
+    }
+    #[test]
+    fn test_summarize() {
+        let results = vec![
+            FileAnnotations {
+                file: PathBuf::from("src/main.rs"),
+                annotations: vec![Annotation {
+                    line_start: 1,
+                    line_end: None,
+                    content: "unwrap".into(),
+                    replacement: None,
+                }],
+                summary: None,
+            },
+            FileAnnotations {
+                file: PathBuf::from("src/a.rs"),
+                annotations: vec![],
+                summary: None,
+            },
+        ];
+        assert_eq!(
+            summarize(&results),
+            "src/main.rs  1\nsrc/a.rs     0\n1 annotation in 2 files\n"
+        );
+    }
+
+    #[test]
+    fn test_to_sarif() {
+        let annotations = vec![Annotation {
+            line_start: 7,
+            line_end: None,
+            content: "consider handling this error".into(),
+            replacement: None,
+        }];
+        let sarif = to_sarif(&[FileAnnotations {
+            file: PathBuf::from("src/main.rs"),
+            annotations,
+            summary: Some("The CLI's entrypoint".into()),
+        }]);
+        let summary = &sarif["runs"][0]["results"][0];
+        assert_eq!(summary["ruleId"], "yap/summary");
+        assert_eq!(summary["message"]["text"], "The CLI's entrypoint");
+        let result = &sarif["runs"][0]["results"][1];
+        assert_eq!(sarif["version"], "2.1.0");
+        assert_eq!(result["message"]["text"], "consider handling this error");
+        let location = &result["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "src/main.rs");
+        assert_eq!(location["region"]["startLine"], 7);
+    }
+
+    fn suggestion(
+        line_start: usize,
+        line_end: usize,
+        code: &str,
+    ) -> Annotation {
+        Annotation {
+            line_start,
+            line_end: None,
+            content: "simplify".into(),
+            replacement: Some(Replacement {
+                line_end,
+                code: code.into(),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_to_markdown() {
+        let markdown = to_markdown(&[FileAnnotations {
+            file: PathBuf::from("src/main.rs"),
+            annotations: vec![suggestion(3, 4, "let x = 1;\n")],
+            summary: Some("Sets x.".into()),
+        }]);
+        assert_eq!(
+            markdown,
+            "## src/main.rs\n\nSets x.\n\n**Lines 3-4**\n\nsimplify\n\n\
+            ```suggestion\nlet x = 1;\n```\n\n"
+        );
+    }
+
+    #[test]
+    fn test_range_annotation() {
+        let annotation: Annotation = from_str(
+            r#"{"line_start": 2, "line_end": 4, "content": "a loop", "replacement": null}"#,
+        )
+        .unwrap();
+        assert_eq!(annotation.span(), 2..=4);
+        assert_eq!(annotation.lines(), 2..=2);
+        assert_eq!(
+            json!(annotation),
+            json!({"line_number": 2, "line_end": 4, "content": "a loop"})
+        );
+
+        let mut output = Vec::new();
+        apply_annotations(
+            BufReader::new(Cursor::new("a\nb\nc\nd\n")),
+            &mut output,
+            vec![annotation.clone()],
+            typical_info(),
+            Position::Before,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a\n// yap :: (applies to lines 2-4) a loop\nb\nc\nd\n"
+        );
+
+        let markdown = to_markdown(&[FileAnnotations {
+            file: PathBuf::from("x.txt"),
+            annotations: vec![annotation],
+            summary: None,
+        }]);
+        assert!(markdown.contains("**Lines 2-4**\n\na loop\n"));
+    }
+
+    #[test]
+    fn test_line_number_is_line_start() {
+        let annotation: Annotation =
+            from_str(r#"{"line_number": 7, "content": "hi"}"#).unwrap();
+        assert_eq!(annotation.line_start, 7);
+        assert_eq!(annotation.span(), 7..=7);
+        // Consumers of the JSON output which predate ranges still work.
+        assert_eq!(
+            json!(annotation),
+            json!({"line_number": 7, "content": "hi"})
+        );
+    }
+
+    #[test]
+    fn test_patch_file() {
+        let contents = "a\nb\nc\nd\ne\n";
+        let annotations = [
+            suggestion(2, 3, "B"),
+            suggestion(3, 3, "overlaps"),
+            suggestion(4, 4, "D1\nD2"),
+        ];
+        let annotations: Vec<&Annotation> = annotations.iter().collect();
+        assert_eq!(
+            patch_file(Path::new("x.txt"), contents, &annotations),
+            "--- a/x.txt\n+++ b/x.txt\n\
+            @@ -2,2 +2,1 @@\n-b\n-c\n+B\n\
+            @@ -4,1 +3,2 @@\n-d\n+D1\n+D2\n"
+        );
+        let parsed = diff::parse(&patch_file(
+            Path::new("x.txt"),
+            contents,
+            &annotations,
+        ))
+        .unwrap();
+        assert_eq!(parsed[0].hunks.len(), 2);
+    }
+    #[test]
+    fn test_number_lines() {
+        let contents = "a\nb\nc\nd";
+        assert_eq!(number_lines(contents, 1, None), "1 a\n2 b\n3 c\n4 d\n");
+        assert_eq!(number_lines(contents, 2, Some(3)), "2 b\n3 c\n");
+    }
+    #[test]
+    fn test_number_hunks() {
+        let file_diff = &diff::parse(
+            "+++ b/x.sh\n@@ -4,2 +4,3 @@\n echo a\n+echo b\n echo c\n",
+        )
+        .unwrap()[0];
+        let (numbered, changed) = number_hunks(file_diff);
+        assert_eq!(numbered, "...\n4   echo a\n5 + echo b\n6   echo c\n");
+        assert_eq!(changed, HashSet::from([5]));
+    }
+    #[test]
+    fn test_chunk_lines() {
+        let contents = (1..=100)
+            .map(|i| format!("line{i:03}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        // Each line is 7 bytes, so ~2 tokens plus 1 for the newline.
+        let chunks = chunk_lines(&contents, 1, None, 90, &[]);
+        assert_eq!(chunks.len(), 4);
+        assert_eq!(chunks[0].owned, 1..=30);
+        assert_eq!(chunks[1].owned, 31..=60);
+        assert_eq!(chunks[3].owned, 91..=100);
+        assert!(chunks[1].text.starts_with("11 line011\n"));
+        assert!(chunks[1].text.ends_with("80 line080\n"));
+
+        let chunks = chunk_lines(&contents, 5, Some(10), 90, &[]);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].owned, 5..=10);
+
+        let chunks = chunk_lines(&contents, 1, None, 90, &[12, 25, 70]);
+        assert_eq!(chunks[0].owned, 1..=25);
+        assert_eq!(chunks[1].owned, 26..=55);
+    }
+}
@@ -0,0 +1,561 @@
+//! Apply patches written by an LLM to files in the current directory.
+//!
+//! The patch is read from `STDIN`, or from the last LLM message in the active
+//! chat with `--chat`. If the text contains fenced `diff` or `patch` code
+//! blocks, only those blocks are used. LLMs are sloppy with line numbers, so
+//! hunks are located by searching for their context lines near the position
+//! given in the hunk header, and whitespace differences are tolerated if no
+//! exact match exists. If any hunk cannot be located, nothing is written.
+//!
+//! With `--interactive`, each hunk is shown before anything is located or
+//! written, and can be accepted, skipped, or edited, like `git add -p`.
+//! Otherwise, if `confirm.patches` is set (see [crate::confirm]), the whole
+//! patch is confirmed before it is written.
+//!
+//! Patches which delete files are not supported; those files are ignored.
+
+use crate::{
+    backup,
+    confirm::{self, Operation},
+    db,
+    diff::{self, FileDiff, Hunk, Line},
+    err::{Error, Oops},
+    openai::Role,
+    term::{self, Decision},
+};
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+};
+
+/// Entrypoint for `yap apply`.
+pub fn apply(
+    from_chat: bool,
+    dry_run: bool,
+    interactive: bool,
+    yes: bool,
+) -> Result<(), Error> {
+    let text = if from_chat {
+        last_chat_reply()?
+    } else {
+        let mut buf = String::new();
+        io::stdin().read_to_string(&mut buf).map_err(|e| {
+            Error::default()
+                .wrap(Oops::ApplyError)
+                .wrap(Oops::StdinReadError)
+                .because(e.kind().to_string())
+        })?;
+        buf
+    };
+    let patch = extract_patch(&text);
+    let file_diffs = diff::parse_lenient(&patch).map_err(|e| {
+        e.wrap(Oops::ApplyError)
+            .because("Could not parse the patch".into())
+    })?;
+    if file_diffs.is_empty() {
+        return Err(Error::default()
+            .wrap(Oops::ApplyError)
+            .because("No patch was found in the input".into()));
+    }
+    let file_diffs = if interactive {
+        review(file_diffs)?
+    } else {
+        file_diffs
+    };
+    if file_diffs.is_empty() {
+        println!("No hunks were accepted; no files were changed.");
+        return Ok(());
+    }
+
+    let plans = plan(&file_diffs)?;
+    for plan in &plans {
+        print!("{}", plan.preview());
+    }
+    if dry_run {
+        println!("Dry run; no files were changed.");
+        return Ok(());
+    }
+    // Hunks which were reviewed interactively have been confirmed already.
+    if !interactive
+        && !confirm::confirm(Operation::Patch, "Apply this patch? [y/N] ", yes)
+            .map_err(|e| e.wrap(Oops::ApplyError))?
+    {
+        eprintln!("No files were changed.");
+        return Ok(());
+    }
+    for plan in &plans {
+        plan.write()?;
+    }
+    Ok(())
+}
+
+fn last_chat_reply() -> Result<String, Error> {
+    let id = db::get_active_chat()?.ok_or_else(|| {
+        Error::default()
+            .wrap(Oops::ApplyError)
+            .because("No chat is active, so there is nothing to apply".into())
+    })?;
+    db::get_chat(&id)?
+        .messages
+        .into_iter()
+        .rev()
+        .find(|m| matches!(m.role, Role::Assistant))
+        .and_then(|m| m.content)
+        .ok_or_else(|| {
+            Error::default()
+                .wrap(Oops::ApplyError)
+                .because("The active chat has no LLM messages to apply".into())
+        })
+}
+
+/// If `text` has fenced `diff` or `patch` blocks, join their contents.
+/// Otherwise, assume that all of `text` is a patch.
+fn extract_patch(text: &str) -> String {
+    let mut blocks = String::new();
+    let mut in_block = false;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if let Some(lang) = trimmed.strip_prefix("```") {
+            if in_block {
+                in_block = false;
+            } else if matches!(lang.trim(), "diff" | "patch") {
+                in_block = true;
+            }
+        } else if in_block {
+            blocks.push_str(line);
+            blocks.push('\n');
+        }
+    }
+    if blocks.is_empty() {
+        text.to_string()
+    } else {
+        blocks
+    }
+}
+
+/// Show each hunk, and ask the user whether to apply it. Returns the file
+/// diffs with only the hunks which were accepted, or edited in
+/// [term::edit]; files without any accepted hunks are dropped.
+fn review(file_diffs: Vec<FileDiff>) -> Result<Vec<FileDiff>, Error> {
+    let color = term::styled_stderr();
+    // Set by `AcceptAll` or `Quit`.
+    let mut rest = None;
+    let mut reviewed = Vec::with_capacity(file_diffs.len());
+    for FileDiff { path, hunks } in file_diffs {
+        let total = hunks.len();
+        let mut kept = Vec::new();
+        for (i, hunk) in hunks.into_iter().enumerate() {
+            match rest {
+                Some(Decision::AcceptAll) => {
+                    kept.push(hunk);
+                    continue;
+                }
+                Some(_) => break,
+                None => {}
+            }
+            eprintln!("\n{} (hunk {} of {total})", path.display(), i + 1);
+            eprint!("{}", render_hunk(&hunk, color));
+            loop {
+                let answer = term::ask(
+                    "Apply this hunk? [y]es, [n]o, [e]dit, [a]ll, [q]uit: ",
+                )?
+                .ok_or_else(|| {
+                    Error::default().wrap(Oops::ApplyError).because(
+                        "STDIN is not a terminal, so hunks cannot be reviewed; use --chat to read the patch from the active chat instead".into(),
+                    )
+                })?;
+                match Decision::parse(&answer) {
+                    Some(Decision::Accept) => kept.push(hunk),
+                    Some(Decision::Reject) => {}
+                    Some(Decision::Edit) => {
+                        let edited = term::edit(&format!(
+                            "{EDIT_HELP}{}",
+                            render_hunk(&hunk, false)
+                        ))?;
+                        match parse_edited_hunk(&path, &edited) {
+                            Ok(hunks) if hunks.is_empty() => eprintln!(
+                                "The hunk is empty, so it was dropped."
+                            ),
+                            Ok(hunks) => kept.extend(hunks),
+                            Err(e) => {
+                                e.display();
+                                continue;
+                            }
+                        }
+                    }
+                    Some(Decision::AcceptAll) => {
+                        kept.push(hunk);
+                        rest = Some(Decision::AcceptAll);
+                    }
+                    Some(Decision::Quit) => rest = Some(Decision::Quit),
+                    None => continue,
+                }
+                break;
+            }
+        }
+        if !kept.is_empty() {
+            reviewed.push(FileDiff { path, hunks: kept });
+        }
+    }
+    Ok(reviewed)
+}
+
+const EDIT_HELP: &str = "\
+# Edit the hunk below, then save and quit. Lines starting with `#` are
+# removed. To keep a `-` line, change the `-` to a space; to drop a `+`
+# line, delete it. Delete everything to skip the hunk.
+";
+
+/// Format `hunk` like `git diff` does, with added lines in green and removed
+/// lines in red if `color` is set.
+fn render_hunk(hunk: &Hunk, color: bool) -> String {
+    let (green, red, cyan, reset) = if color {
+        ("\x1b[32m", "\x1b[31m", "\x1b[36m", "\x1b[0m")
+    } else {
+        ("", "", "", "")
+    };
+    let old_len = hunk.old_lines().len();
+    let new_len = hunk.lines.len()
+        - hunk
+            .lines
+            .iter()
+            .filter(|l| matches!(l, Line::Removed(_)))
+            .count();
+    let mut out = format!(
+        "{cyan}@@ -{},{old_len} +{},{new_len} @@{reset}\n",
+        hunk.old_start, hunk.new_start
+    );
+    for line in &hunk.lines {
+        out.push_str(&match line {
+            Line::Context(l) => format!(" {l}\n"),
+            Line::Added(l) => format!("{green}+{l}{reset}\n"),
+            Line::Removed(l) => format!("{red}-{l}{reset}\n"),
+        });
+    }
+    out
+}
+
+/// Parse a hunk which the user edited in [review]. It may have been split
+/// into several hunks, or emptied.
+fn parse_edited_hunk(path: &Path, edited: &str) -> Result<Vec<Hunk>, Error> {
+    let body: String = edited
+        .lines()
+        .filter(|l| !l.starts_with('#'))
+        .map(|l| format!("{l}\n"))
+        .collect();
+    if body.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    let patch = format!("--- a/{0}\n+++ b/{0}\n{body}", path.display());
+    let mut file_diffs = diff::parse_lenient(&patch).map_err(|e| {
+        e.wrap(Oops::ApplyError)
+            .because("Could not parse the edited hunk".into())
+    })?;
+    match file_diffs.pop() {
+        Some(file_diff) if !file_diff.hunks.is_empty() => Ok(file_diff.hunks),
+        _ => Err(Error::default()
+            .wrap(Oops::ApplyError)
+            .because("The edited hunk has no `@@` header; try again".into())),
+    }
+}
+
+/// The changes which will be made to one file.
+#[derive(Debug)]
+struct Plan {
+    path: PathBuf,
+    /// `None` if the patch creates the file.
+    original: Option<String>,
+    result: String,
+    placements: Vec<Placement>,
+}
+
+/// Where a hunk was found in the original file.
+#[derive(Debug, PartialEq)]
+struct Placement {
+    /// 1-based line number in the original file.
+    line: usize,
+    /// Whether the hunk only matched after ignoring whitespace.
+    fuzzy: bool,
+}
+
+impl Plan {
+    fn preview(&self) -> String {
+        let mut out = format!(
+            "{}{}\n",
+            self.path.display(),
+            if self.original.is_none() {
+                " (new file)"
+            } else {
+                ""
+            }
+        );
+        for (i, p) in self.placements.iter().enumerate() {
+            out.push_str(&format!(
+                "  hunk {} applies at line {}{}\n",
+                i + 1,
+                p.line,
+                if p.fuzzy {
+                    " (ignoring whitespace)"
+                } else {
+                    ""
+                }
+            ));
+        }
+        out
+    }
+
+    fn write(&self) -> Result<(), Error> {
+        backup::save("apply", &self.path)
+            .map_err(|e| e.wrap(Oops::ApplyError))?;
+        if let Some(parent) = self.path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent).map_err(|e| {
+                    Error::default().wrap(Oops::ApplyError).because(format!(
+                        "Could not create directory {parent:?}: {e}"
+                    ))
+                })?;
+            }
+        }
+        fs::write(&self.path, &self.result).map_err(|e| {
+            Error::default()
+                .wrap(Oops::ApplyError)
+                .because(format!("Could not write {:?}: {e}", self.path))
+        })
+    }
+}
+
+/// Work out how to apply every file diff, without writing anything. Fails
+/// if any hunk in any file cannot be located.
+fn plan(file_diffs: &[FileDiff]) -> Result<Vec<Plan>, Error> {
+    let mut plans = Vec::with_capacity(file_diffs.len());
+    let mut failures = Vec::new();
+    for file_diff in file_diffs {
+        let path = &file_diff.path;
+        if !is_safe_path(path) {
+            failures
+                .push(format!("{path:?} is outside of the current directory"));
+            continue;
+        }
+        let original = if path.exists() {
+            Some(fs::read_to_string(path).map_err(|e| {
+                Error::default()
+                    .wrap(Oops::ApplyError)
+                    .because(format!("Could not read {path:?}: {e}"))
+            })?)
+        } else {
+            None
+        };
+        match apply_hunks(original.as_deref().unwrap_or(""), &file_diff.hunks) {
+            Ok((result, placements)) => plans.push(Plan {
+                path: path.clone(),
+                original,
+                result,
+                placements,
+            }),
+            Err(hunks) => failures.extend(hunks.into_iter().map(|i| {
+                format!("hunk {} of {path:?} does not apply cleanly", i + 1)
+            })),
+        }
+    }
+    if failures.is_empty() {
+        Ok(plans)
+    } else {
+        Err(Error::default().wrap(Oops::ApplyError).because(format!(
+            "Refusing to apply the patch; no files were changed.\n{}",
+            failures.join("\n")
+        )))
+    }
+}
+
+/// Only relative paths which stay within the current directory are allowed.
+fn is_safe_path(path: &Path) -> bool {
+    path.components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+/// Apply `hunks` to `contents`. On failure, returns the indices of hunks
+/// which could not be located.
+fn apply_hunks(
+    contents: &str,
+    hunks: &[Hunk],
+) -> Result<(String, Vec<Placement>), Vec<usize>> {
+    let lines: Vec<&str> = contents.lines().collect();
+    let mut out: Vec<&str> = Vec::with_capacity(lines.len());
+    let mut placements = Vec::with_capacity(hunks.len());
+    let mut failures = Vec::new();
+    // Lines before `cursor` have already been copied into `out`.
+    let mut cursor = 0;
+    // How far hunks have been from where their headers said they would be.
+    let mut drift: isize = 0;
+
+    for (i, hunk) in hunks.iter().enumerate() {
+        let old = hunk.old_lines();
+        let expected = if hunk.old_start == 0 {
+            cursor
+        } else {
+            (hunk.old_start as isize - 1 + drift).max(cursor as isize) as usize
+        };
+        let Some((pos, fuzzy)) = find_hunk(&lines, &old, cursor, expected)
+        else {
+            failures.push(i);
+            continue;
+        };
+        if hunk.old_start != 0 {
+            drift = pos as isize - (hunk.old_start as isize - 1);
+        }
+        out.extend_from_slice(&lines[cursor..pos]);
+        out.extend(hunk.lines.iter().filter_map(|l| match l {
+            Line::Context(l) | Line::Added(l) => Some(l.as_str()),
+            Line::Removed(_) => None,
+        }));
+        cursor = pos + old.len();
+        placements.push(Placement {
+            line: pos + 1,
+            fuzzy,
+        });
+    }
+    if !failures.is_empty() {
+        return Err(failures);
+    }
+    out.extend_from_slice(&lines[cursor..]);
+
+    let mut result = out.join("\n");
+    if contents.is_empty() || contents.ends_with('\n') {
+        result.push('\n');
+    }
+    Ok((result, placements))
+}
+
+/// Find the position at or after `cursor` where `old` appears in `lines`,
+/// preferring positions closest to `expected`. Exact matches are preferred
+/// over matches which ignore leading and trailing whitespace.
+fn find_hunk(
+    lines: &[&str],
+    old: &[&str],
+    cursor: usize,
+    expected: usize,
+) -> Option<(usize, bool)> {
+    if old.is_empty() {
+        return Some((expected.min(lines.len()), false));
+    }
+    if lines.len() < old.len() || cursor > lines.len() - old.len() {
+        return None;
+    }
+    let mut candidates: Vec<usize> =
+        (cursor..=lines.len() - old.len()).collect();
+    candidates.sort_by_key(|&p| p.abs_diff(expected));
+
+    let exact = |p: &usize| lines[*p..*p + old.len()] == *old;
+    let fuzzy = |p: &usize| {
+        lines[*p..*p + old.len()]
+            .iter()
+            .zip(old)
+            .all(|(a, b)| a.trim() == b.trim())
+    };
+    candidates
+        .iter()
+        .find(|p| exact(p))
+        .map(|p| (*p, false))
+        .or_else(|| candidates.iter().find(|p| fuzzy(p)).map(|p| (*p, true)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGINAL: &str = "fn main() {
+    let a = 1;
+    let b = 2;
+    println!(\"{}\", a + b);
+}
+";
+
+    #[test]
+    fn test_extract_patch() {
+        let text = "Here's the fix:\n```diff\n--- a/x\n+++ b/x\n```\nDone!";
+        assert_eq!(extract_patch(text), "--- a/x\n+++ b/x\n");
+        assert_eq!(extract_patch("--- a/x\n+++ b/x"), "--- a/x\n+++ b/x");
+    }
+
+    #[test]
+    fn test_apply_with_wrong_line_numbers() {
+        let patch = "--- a/main.rs
++++ b/main.rs
+@@ -10,2 +10,2 @@
+     let b = 2;
+-    println!(\"{}\", a + b);
++    println!(\"{}\", a * b);
+";
+        let hunks = &diff::parse_lenient(patch).unwrap()[0].hunks;
+        let (result, placements) = apply_hunks(ORIGINAL, hunks).unwrap();
+        assert_eq!(result, ORIGINAL.replace("a + b", "a * b"));
+        assert_eq!(
+            placements,
+            vec![Placement {
+                line: 3,
+                fuzzy: false
+            }]
+        );
+    }
+
+    #[test]
+    fn test_apply_ignoring_whitespace() {
+        let patch = "--- a/main.rs
++++ b/main.rs
+@@ -2,1 +2,1 @@
+-  let a = 1;
++    let a = 10;
+";
+        let hunks = &diff::parse_lenient(patch).unwrap()[0].hunks;
+        let (result, placements) = apply_hunks(ORIGINAL, hunks).unwrap();
+        assert_eq!(result, ORIGINAL.replace("a = 1", "a = 10"));
+        assert!(placements[0].fuzzy);
+    }
+
+    #[test]
+    fn test_refuse_when_hunk_does_not_apply() {
+        let patch = "--- a/main.rs
++++ b/main.rs
+@@ -2,1 +2,1 @@
+-    let c = 3;
++    let c = 4;
+";
+        let hunks = &diff::parse_lenient(patch).unwrap()[0].hunks;
+        assert_eq!(apply_hunks(ORIGINAL, hunks).unwrap_err(), vec![0]);
+    }
+
+    #[test]
+    fn test_edit_hunk_round_trip() {
+        let patch = "--- a/main.rs
++++ b/main.rs
+@@ -2,2 +2,2 @@
+-    let a = 1;
++    let a = 10;
+     let b = 2;
+";
+        let hunk = &diff::parse_lenient(patch).unwrap()[0].hunks[0];
+        let rendered = render_hunk(hunk, false);
+        assert_eq!(rendered, patch.split_once("b/main.rs\n").unwrap().1);
+        assert!(render_hunk(hunk, true).contains("\x1b[32m+    let a = 10;"));
+
+        let edited = format!(
+            "{EDIT_HELP}{}",
+            rendered.replace("+    let a = 10;", "+    let a = 11;")
+        );
+        let hunks = parse_edited_hunk(Path::new("main.rs"), &edited).unwrap();
+        let (result, _) = apply_hunks(ORIGINAL, &hunks).unwrap();
+        assert_eq!(result, ORIGINAL.replace("a = 1", "a = 11"));
+
+        let path = Path::new("main.rs");
+        assert!(parse_edited_hunk(path, EDIT_HELP).unwrap().is_empty());
+        assert!(parse_edited_hunk(path, "+ no header").is_err());
+    }
+
+    #[test]
+    fn test_unsafe_paths() {
+        assert!(is_safe_path(Path::new("src/main.rs")));
+        assert!(!is_safe_path(Path::new("../main.rs")));
+        assert!(!is_safe_path(Path::new("/etc/passwd")));
+    }
+}
@@ -0,0 +1,385 @@
+//! Move `yap`'s history between machines with `yap export` and `yap import`.
+//!
+//! An export is a gzipped tarball. Files from the state directory
+//! (`~/.local/state/yap`) are stored under `state/`, and, with `--all`,
+//! files from the config directory are stored under `config/`. Caches and
+//! per-project state are not exported; see [SKIPPED].
+//!
+//! Imports never overwrite anything. If an imported chat has the same UUID
+//! as a different local chat, the imported chat is given a new UUID. Usage
+//! records are merged, and other files are only added if they don't already
+//! exist. Config files can run commands (i.e, hooks and `api_key_command`),
+//! so they are listed, and only added after confirmation; see
+//! [crate::confirm].
+
+use crate::{
+    config,
+    confirm::{confirm, Operation},
+    db,
+    err::{Error, Oops},
+};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, IsTerminal, Read, Write},
+    path::{Component, Path, PathBuf},
+};
+use uuid::Uuid;
+
+/// Directories in the state directory which are not exported. Caches are
+/// easily rebuilt, and per-project state is keyed by paths on this machine.
+/// Backups record absolute paths, too; restoring them elsewhere with
+/// `yap undo` would overwrite unrelated files. Logs describe this machine's
+/// runs of `yap`, and are of no use anywhere else.
+const SKIPPED: &[&str] = &[
+    "cache",
+    "embeddings",
+    "active_chats",
+    "contexts",
+    "indexes",
+    "backups",
+    "logs",
+];
+
+fn oops(why: String) -> Error {
+    Error::default().wrap(Oops::ArchiveError).because(why)
+}
+
+/// Entrypoint for `yap export`. Writes everything (with `all`), or just
+/// `chats`, to `STDOUT`.
+pub fn export(all: bool, chats: &[Uuid]) -> Result<(), Error> {
+    if io::stdout().is_terminal() {
+        return Err(oops(
+            "Refusing to write an archive to the terminal; redirect STDOUT to a file, e.g. `yap export --all > yap-backup.tar.gz`".into(),
+        ));
+    }
+    let stdout = io::stdout().lock();
+    let mut builder =
+        tar::Builder::new(GzEncoder::new(stdout, Compression::default()));
+    let state = db::get_or_create_persistence_dir()?;
+    let mut count = 0;
+    if all {
+        count += append_dir(&mut builder, &state, Path::new("state"))?;
+        let config = config::get_or_create_yap_cfg_dir()?;
+        count += append_dir(&mut builder, &config, Path::new("config"))?;
+    } else {
+        for uuid in chats {
+            // Fail early if the chat doesn't exist.
+            db::get_chat(uuid)?;
+            let name = PathBuf::from("chats").join(format!("{uuid}.json"));
+            append_file(
+                &mut builder,
+                &state.join(&name),
+                &Path::new("state").join(name),
+            )?;
+            count += 1;
+        }
+    }
+    builder
+        .into_inner()
+        .and_then(|gz| gz.finish())
+        .and_then(|mut out| out.flush())
+        .map_err(|e| oops(format!("Could not finish the archive: {e}")))?;
+    eprintln!("Exported {count} files.");
+    Ok(())
+}
+
+/// Recursively add the files in `dir` to the archive, under `prefix`.
+/// Sockets (like `yap daemon`'s, if it wasn't shut down cleanly) and other
+/// special files can't be archived, and are skipped. Returns the number of
+/// files added.
+fn append_dir<W: Write>(
+    builder: &mut tar::Builder<W>,
+    dir: &Path,
+    prefix: &Path,
+) -> Result<usize, Error> {
+    let entries = fs::read_dir(dir)
+        .map_err(|e| oops(format!("Could not read {dir:?}: {e}")))?;
+    let mut count = 0;
+    for entry in entries {
+        let path = entry
+            .map_err(|e| oops(format!("Could not read {dir:?}: {e}")))?
+            .path();
+        let Some(name) = path.file_name() else {
+            continue;
+        };
+        if prefix == Path::new("state") && SKIPPED.iter().any(|s| name == *s) {
+            continue;
+        }
+        if path.is_dir() {
+            count += append_dir(builder, &path, &prefix.join(name))?;
+        } else if path.is_file() {
+            append_file(builder, &path, &prefix.join(name))?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+fn append_file<W: Write>(
+    builder: &mut tar::Builder<W>,
+    path: &Path,
+    name: &Path,
+) -> Result<(), Error> {
+    builder
+        .append_path_with_name(path, name)
+        .map_err(|e| oops(format!("Could not archive {path:?}: {e}")))
+}
+
+/// What happened to each file during an import.
+#[derive(Debug, Default)]
+struct Report {
+    added: usize,
+    renamed: usize,
+    skipped: usize,
+}
+
+/// Entrypoint for `yap import`. Reads an archive from `file`, or `STDIN`.
+/// With `yes`, config files are added without asking.
+pub fn import(file: Option<&Path>, yes: bool) -> Result<(), Error> {
+    let mut compressed = Vec::new();
+    match file {
+        Some(path) => fs::File::open(path)
+            .and_then(|mut f| f.read_to_end(&mut compressed)),
+        None => io::stdin().read_to_end(&mut compressed),
+    }
+    .map_err(|e| oops(format!("Could not read the archive: {e}")))?;
+
+    let state = db::get_or_create_persistence_dir()?;
+    let config = config::get_or_create_yap_cfg_dir()?;
+    let mut archive = tar::Archive::new(GzDecoder::new(&compressed[..]));
+    let entries = archive
+        .entries()
+        .map_err(|e| oops(format!("Could not read the archive: {e}")))?;
+    let mut report = Report::default();
+    // Imported chat UUIDs which had to be changed, so that `active_chat` can
+    // follow them.
+    let mut renames: HashMap<Uuid, Uuid> = HashMap::new();
+    let mut active_chat = None;
+    // Config files which don't exist here, to be added after confirmation.
+    let mut configs = Vec::new();
+    for entry in entries {
+        let mut entry = entry
+            .map_err(|e| oops(format!("Could not read the archive: {e}")))?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry
+            .path()
+            .map_err(|e| oops(format!("Bad path in the archive: {e}")))?
+            .into_owned();
+        if name
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+        {
+            return Err(oops(format!("Unsafe path in the archive: {name:?}")));
+        }
+        let mut contents = Vec::new();
+        entry
+            .read_to_end(&mut contents)
+            .map_err(|e| oops(format!("Could not read {name:?}: {e}")))?;
+
+        if let Ok(rest) = name.strip_prefix("config") {
+            let path = config.join(rest);
+            if path.exists() {
+                report.skipped += 1;
+            } else {
+                configs.push((path, contents));
+            }
+        } else if let Ok(rest) = name.strip_prefix("state") {
+            if rest == Path::new("active_chat") {
+                active_chat = Some(contents);
+            } else if rest == Path::new("usage.jsonl") {
+                merge_lines(&state.join(rest), &contents)?;
+            } else if rest.starts_with("chats") {
+                import_chat(
+                    &state,
+                    rest,
+                    &contents,
+                    &mut renames,
+                    &mut report,
+                )?;
+            } else {
+                add_if_missing(&state.join(rest), &contents, &mut report)?;
+            }
+        } else {
+            return Err(oops(format!(
+                "Unexpected path in the archive: {name:?}"
+            )));
+        }
+    }
+
+    if !configs.is_empty() {
+        import_configs(&configs, yes, &mut report)?;
+    }
+
+    // The imported active chat only becomes active if there isn't one here.
+    if let Some(contents) = active_chat {
+        let imported = String::from_utf8_lossy(&contents);
+        if let (Ok(uuid), None) =
+            (Uuid::parse_str(imported.trim()), db::get_active_chat()?)
+        {
+            db::set_chat_id(renames.get(&uuid).unwrap_or(&uuid))?;
+        }
+    }
+    eprintln!(
+        "Imported {} files; {} chats were given new UUIDs, and {} files already existed.",
+        report.added, report.renamed, report.skipped
+    );
+    Ok(())
+}
+
+/// Add config files from the archive, if the user confirms it.
+fn import_configs(
+    configs: &[(PathBuf, Vec<u8>)],
+    yes: bool,
+    report: &mut Report,
+) -> Result<(), Error> {
+    eprintln!("The archive has config files which don't exist here:");
+    for (path, _) in configs {
+        eprintln!("  {}", path.display());
+    }
+    if !confirm(Operation::FileWrite, "Add them? [y/N] ", yes)
+        .map_err(|e| e.wrap(Oops::ArchiveError))?
+    {
+        eprintln!("Did not add config files.");
+        report.skipped += configs.len();
+        return Ok(());
+    }
+    for (path, contents) in configs {
+        write(path, contents, report)?;
+    }
+    Ok(())
+}
+
+/// Import a chat to `state/<name>`, unless an identical chat is already
+/// there. If a different chat has the same UUID, the imported chat gets a
+/// new UUID.
+fn import_chat(
+    state: &Path,
+    name: &Path,
+    contents: &[u8],
+    renames: &mut HashMap<Uuid, Uuid>,
+    report: &mut Report,
+) -> Result<(), Error> {
+    let path = state.join(name);
+    match fs::read(&path) {
+        Err(_) => write(&path, contents, report),
+        Ok(existing) if existing == contents => {
+            report.skipped += 1;
+            Ok(())
+        }
+        Ok(_) if already_imported(&path, contents) => {
+            report.skipped += 1;
+            Ok(())
+        }
+        Ok(_) => {
+            let old = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| Uuid::parse_str(s).ok())
+                .ok_or_else(|| {
+                    oops(format!("{name:?} is not named by a UUID"))
+                })?;
+            let new = Uuid::new_v4();
+            renames.insert(old, new);
+            report.renamed += 1;
+            write(
+                &path.with_file_name(format!("{new}.json")),
+                contents,
+                report,
+            )
+        }
+    }
+}
+
+/// Whether any chat next to `path` has exactly `contents`, i.e, because it
+/// was given a new UUID by an earlier import.
+fn already_imported(path: &Path, contents: &[u8]) -> bool {
+    let Some(Ok(entries)) = path.parent().map(fs::read_dir) else {
+        return false;
+    };
+    entries
+        .flatten()
+        .any(|entry| fs::read(entry.path()).is_ok_and(|c| c == contents))
+}
+
+fn add_if_missing(
+    path: &Path,
+    contents: &[u8],
+    report: &mut Report,
+) -> Result<(), Error> {
+    if path.exists() {
+        report.skipped += 1;
+        Ok(())
+    } else {
+        write(path, contents, report)
+    }
+}
+
+fn write(
+    path: &Path,
+    contents: &[u8],
+    report: &mut Report,
+) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| oops(format!("Could not create {parent:?}: {e}")))?;
+    }
+    fs::write(path, contents)
+        .map_err(|e| oops(format!("Could not write {path:?}: {e}")))?;
+    report.added += 1;
+    Ok(())
+}
+
+/// Append the lines of `contents` which `path` doesn't already have.
+fn merge_lines(path: &Path, contents: &[u8]) -> Result<(), Error> {
+    let existing = fs::read_to_string(path).unwrap_or_default();
+    let merged = merge(&existing, &String::from_utf8_lossy(contents));
+    fs::write(path, merged)
+        .map_err(|e| oops(format!("Could not write {path:?}: {e}")))
+}
+
+fn merge(existing: &str, imported: &str) -> String {
+    let seen: HashSet<&str> = existing.lines().collect();
+    let mut merged = existing.to_string();
+    if !merged.is_empty() && !merged.ends_with('\n') {
+        merged.push('\n');
+    }
+    for line in imported.lines() {
+        if !line.trim().is_empty() && !seen.contains(line) {
+            merged.push_str(line);
+            merged.push('\n');
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge() {
+        assert_eq!(merge("a\nb", "b\nc\n\n"), "a\nb\nc\n");
+        assert_eq!(merge("", "a\n"), "a\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_append_dir_skips_sockets() {
+        let dir = std::env::temp_dir()
+            .join(format!("yap-test-archive-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("chats")).unwrap();
+        fs::write(dir.join("chats/a.json"), "{}").unwrap();
+        let _listener =
+            std::os::unix::net::UnixListener::bind(dir.join("daemon.sock"))
+                .unwrap();
+        let mut builder = tar::Builder::new(Vec::new());
+        let count = append_dir(&mut builder, &dir, Path::new("x")).unwrap();
+        assert_eq!(count, 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,78 @@
+//! Ask a one-off question, optionally about some files.
+//!
+//! Unlike `yap chat`, `yap ask` never reads or writes chat history, so it is
+//! safe to use from scripts without disturbing the active chat.
+
+use crate::{
+    config::ConfigFile,
+    constants,
+    err::{Error, Oops},
+    files, filter, markdown,
+    openai::{
+        chat, CompletionPayload, Content, Message, OpenAI, PayloadOpts, Role,
+    },
+    repomap, term,
+};
+use std::{fs, path::PathBuf};
+
+/// Entrypoint for `yap ask`. Each of `files` is sent as context before the
+/// question; directories are expanded with [files::expand]. The answer is
+/// styled with [markdown::render] when [term::styled], unless `raw` is set.
+/// The [repomap] is sent first if `repo_map` is set.
+pub fn ask(
+    open_ai: &OpenAI,
+    question: &str,
+    files: &[PathBuf],
+    raw: bool,
+    repo_map: bool,
+) -> Result<(), Error> {
+    if question.trim().is_empty() {
+        return Err(Error::default()
+            .wrap(Oops::AskError)
+            .because("The question is empty!".into()));
+    }
+    let system_prompt = ConfigFile::AskSystemPrompt
+        .load()
+        .map_err(|e| {
+            e.wrap(Oops::AskError)
+                .because("Could not load the ask system prompt".into())
+        })?
+        .unwrap_or(constants::DEFAULT_ASK_PROMPT.into());
+    let mut messages = vec![Message::new(Role::System, system_prompt)];
+    if repo_map {
+        messages.push(repomap::message()?);
+    }
+    for file in &files::expand(files)? {
+        let contents = fs::read_to_string(file).map_err(|e| {
+            Error::default()
+                .wrap(Oops::AskError)
+                .because(format!("Could not read {file:?}: {e}"))
+        })?;
+        messages.push(Message::new(
+            Role::User,
+            format!("File: {}\n```\n{contents}\n```", file.display()),
+        ));
+    }
+    messages.push(Message::new(Role::User, question.into()));
+
+    let payload =
+        CompletionPayload::new(open_ai, messages, PayloadOpts::default());
+    let response = chat(open_ai, &payload)?;
+    match response.choices[0].message.parse()? {
+        Content::Normal(answer) => {
+            let answer = filter::apply("ask", answer)?;
+            if !raw && term::styled() {
+                println!("{}", markdown::render(&answer))
+            } else {
+                println!("{answer}")
+            }
+        }
+        Content::Refusal(refusal) => {
+            return Err(Error::default()
+                .wrap(Oops::OpenAIRefusal)
+                .wrap(Oops::AskError)
+                .because(format!("OpenAI refused to answer: {refusal}")))
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,262 @@
+//! Back up files in `~/.local/state/yap/backups` before `yap` changes them,
+//! so that changes can be recovered even outside of a git repository.
+//!
+//! Each backup is stored under the SHA-256 hash of its contents, so backing
+//! up the same contents twice only stores them once. Every change is also
+//! appended to `backups/log.jsonl` as a [Record]; all the changes made by one
+//! run of `yap` share an [Record::operation] ID. Backups are never deleted
+//! by `yap`, but feel free to delete the directory at any time. Backups are
+//! specific to this machine, so `yap export` leaves them out.
+//!
+//! `yap undo` restores every file changed by the most recent operation, and
+//! removes the operation from the log, so running it again undoes the one
+//! before. Files which `yap` created are deleted.
+
+use crate::{
+    confirm::{self, Operation},
+    db,
+    err::{Error, Oops},
+};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+/// One file which was changed by `yap`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Record {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// Shared by every file changed by one run of `yap`.
+    pub operation: Uuid,
+    /// The `yap` subcommand which changed the file; i.e, `annotate`.
+    pub command: String,
+    /// Absolute path to the file which was changed.
+    pub path: PathBuf,
+    /// Hash of the file's original contents, or `None` if `yap` created the
+    /// file.
+    pub backup: Option<String>,
+}
+
+fn oops(why: String) -> Error {
+    Error::default().wrap(Oops::BackupError).because(why)
+}
+
+fn get_or_create_backup_dir() -> Result<PathBuf, Error> {
+    let dir = db::get_or_create_persistence_dir()?.join("backups");
+    if !dir.exists() {
+        fs::create_dir_all(&dir).map_err(|e| {
+            oops(format!("Failed to create backup directory: {e}"))
+        })?;
+    }
+    Ok(dir)
+}
+
+fn operation() -> Uuid {
+    static OPERATION: OnceLock<Uuid> = OnceLock::new();
+    *OPERATION.get_or_init(Uuid::new_v4)
+}
+
+fn hash(contents: &[u8]) -> String {
+    Sha256::digest(contents)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Back up `path` before `command` changes it. If this fails, the file must
+/// not be changed.
+pub fn save(command: &str, path: &Path) -> Result<(), Error> {
+    let dir = get_or_create_backup_dir()?;
+    let path = path
+        .canonicalize()
+        .or_else(|_| std::env::current_dir().map(|cwd| cwd.join(path)))
+        .map_err(|e| oops(format!("Could not resolve {path:?}: {e}")))?;
+    let backup = if path.exists() {
+        let contents = fs::read(&path)
+            .map_err(|e| oops(format!("Could not read {path:?}: {e}")))?;
+        let hash = hash(&contents);
+        let backup_path = dir.join(&hash);
+        if !backup_path.exists() {
+            fs::write(&backup_path, contents).map_err(|e| {
+                oops(format!("Could not write {backup_path:?}: {e}"))
+            })?;
+        }
+        Some(hash)
+    } else {
+        None
+    };
+    append(
+        &dir,
+        &Record {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            operation: operation(),
+            command: command.to_string(),
+            path,
+            backup,
+        },
+    )
+}
+
+/// Entrypoint for `yap undo`. Asks for confirmation as [confirm::confirm]
+/// does.
+pub fn undo(yes: bool) -> Result<(), Error> {
+    let dir = get_or_create_backup_dir()?;
+    let mut records = load(&dir)?;
+    let Some(last) = records.last().map(|r| r.operation) else {
+        println!("There is nothing to undo.");
+        return Ok(());
+    };
+    let (undone, rest): (Vec<_>, Vec<_>) =
+        records.drain(..).partition(|r| r.operation == last);
+    let restores = restores(&undone);
+
+    let command = &undone[0].command;
+    println!("`yap {command}` changed:");
+    for record in &restores {
+        println!(
+            "  {}{}",
+            record.path.display(),
+            if record.backup.is_none() {
+                " (will be deleted)"
+            } else {
+                ""
+            }
+        );
+    }
+    let confirmed = confirm::confirm(
+        Operation::FileWrite,
+        "Restore these files? [y/N] ",
+        yes,
+    )
+    .map_err(|e| e.wrap(Oops::BackupError))?;
+    if !confirmed {
+        eprintln!("No files were changed.");
+        return Ok(());
+    }
+
+    for record in restores {
+        restore(&dir, record)?;
+    }
+    rewrite(&dir, &rest)
+}
+
+/// The records to restore from one operation. If a file was changed more
+/// than once, only its earliest backup is restored.
+fn restores(operation: &[Record]) -> Vec<&Record> {
+    let mut restores: Vec<&Record> = Vec::new();
+    for record in operation {
+        if !restores.iter().any(|r| r.path == record.path) {
+            restores.push(record);
+        }
+    }
+    restores
+}
+
+fn restore(dir: &Path, record: &Record) -> Result<(), Error> {
+    let path = &record.path;
+    match &record.backup {
+        Some(hash) => {
+            let contents = fs::read(dir.join(hash)).map_err(|e| {
+                oops(format!("Could not read the backup of {path:?}: {e}"))
+            })?;
+            fs::write(path, contents).map_err(|e| {
+                oops(format!("Could not restore {path:?}: {e}"))
+            })?;
+            println!("Restored {}", path.display());
+        }
+        None => {
+            if path.exists() {
+                fs::remove_file(path).map_err(|e| {
+                    oops(format!("Could not delete {path:?}: {e}"))
+                })?;
+            }
+            println!("Deleted {}", path.display());
+        }
+    }
+    Ok(())
+}
+
+fn load(dir: &Path) -> Result<Vec<Record>, Error> {
+    let path = dir.join("log.jsonl");
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let text = fs::read_to_string(&path)
+        .map_err(|e| oops(format!("Could not read {path:?}: {e}")))?;
+    Ok(text
+        .lines()
+        .filter_map(|line| {
+            serde_json::from_str(line)
+                .inspect_err(|e| debug!("Skipping bad backup record: {e}"))
+                .ok()
+        })
+        .collect())
+}
+
+fn rewrite(dir: &Path, records: &[Record]) -> Result<(), Error> {
+    let path = dir.join("log.jsonl");
+    let mut text = String::new();
+    for record in records {
+        text.push_str(&serde_json::to_string(record).map_err(|e| {
+            oops(format!("Could not serialize backup record: {e}"))
+        })?);
+        text.push('\n');
+    }
+    fs::write(&path, text)
+        .map_err(|e| oops(format!("Could not write {path:?}: {e}")))
+}
+
+fn append(dir: &Path, record: &Record) -> Result<(), Error> {
+    let path = dir.join("log.jsonl");
+    let mut line = serde_json::to_string(record)
+        .map_err(|e| oops(format!("Could not serialize backup record: {e}")))?;
+    line.push('\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut f| f.write_all(line.as_bytes()))
+        .map_err(|e| oops(format!("Could not write to {path:?}: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash() {
+        assert_eq!(
+            hash(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_ne!(hash(b"yap"), hash(b"yap\n"));
+    }
+
+    #[test]
+    fn test_restores_earliest_backup() {
+        let operation = Uuid::new_v4();
+        let record = |path: &str, backup: &str| Record {
+            timestamp: 0,
+            operation,
+            command: "apply".into(),
+            path: PathBuf::from(path),
+            backup: Some(backup.into()),
+        };
+        let records = [record("/a", "1"), record("/b", "2"), record("/a", "3")];
+        let restores = restores(&records);
+        assert_eq!(restores.len(), 2);
+        assert_eq!(restores[0].backup.as_deref(), Some("1"));
+        assert_eq!(restores[1].path, PathBuf::from("/b"));
+    }
+}
@@ -0,0 +1,228 @@
+//! Run large, offline jobs through the
+//! [OpenAI Batch API](https://platform.openai.com/docs/guides/batch), which
+//! costs half as much as regular requests but may take up to 24 hours.
+//!
+//! `yap batch submit` reads prompts from `STDIN` in the same JSONL format as
+//! `yap complete --batch` (see [crate::complete::complete_batch]), uploads
+//! them, and prints the batch ID. `yap batch status` shows the batch's
+//! progress, and `yap batch fetch` prints the results as JSONL, in input
+//! order, in the same format as `yap complete --batch`.
+//!
+//! Submitted inputs are kept in `~/.local/state/yap/batches`, so that results
+//! can be matched back up with their inputs. `status` and `fetch` use the
+//! most recently submitted batch if no ID is given.
+
+use crate::{
+    complete, db,
+    err::{Error, Oops},
+    openai::{batch_api, CompletionResponse, OpenAI},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Read},
+    path::PathBuf,
+};
+
+/// What we remember about a submitted batch.
+#[derive(Debug, Serialize, Deserialize)]
+struct Submission {
+    n: Option<u8>,
+    /// The output record for each input line, without results yet.
+    records: Vec<Map<String, Value>>,
+}
+
+fn get_or_create_batch_dir() -> Result<PathBuf, Error> {
+    let dir = db::get_or_create_persistence_dir()?.join("batches");
+    fs::create_dir_all(&dir).map_err(|e| {
+        Error::default()
+            .wrap(Oops::BatchError)
+            .because(format!("Failed to create batch directory: {e}"))
+    })?;
+    Ok(dir)
+}
+
+/// The given batch ID, or else the most recently submitted one.
+fn resolve_id(id: Option<&str>) -> Result<String, Error> {
+    if let Some(id) = id {
+        return Ok(id.to_string());
+    }
+    let path = get_or_create_batch_dir()?.join("latest");
+    fs::read_to_string(&path)
+        .map(|id| id.trim().to_string())
+        .map_err(|_| {
+            Error::default().wrap(Oops::BatchError).because(
+                "No batch ID was given, and no batch has been submitted".into(),
+            )
+        })
+}
+
+/// Entrypoint for `yap batch submit`.
+pub fn submit(open_ai: &OpenAI, n: Option<u8>) -> Result<(), Error> {
+    let mut input = String::new();
+    io::stdin().read_to_string(&mut input).map_err(|e| {
+        Error::default()
+            .wrap(Oops::BatchError)
+            .wrap(Oops::StdinReadError)
+            .because(e.kind().to_string())
+    })?;
+    let system_prompt = complete::load_system_prompt()?;
+    let mut records = Vec::new();
+    let mut jsonl = String::new();
+    for (i, line) in input.lines().filter(|l| !l.trim().is_empty()).enumerate()
+    {
+        let (record, prompt) =
+            complete::parse_batch_line(line).map_err(|e| {
+                e.wrap(Oops::BatchError)
+                    .because(format!("Line {} of the input is invalid", i + 1))
+            })?;
+        let request = json!({
+            "custom_id": i.to_string(),
+            "method": "POST",
+            "url": "/v1/chat/completions",
+            "body": complete::payload(open_ai, &system_prompt, &[], prompt, n),
+        });
+        jsonl.push_str(&request.to_string());
+        jsonl.push('\n');
+        records.push(record);
+    }
+    if records.is_empty() {
+        return Err(Error::default()
+            .wrap(Oops::BatchError)
+            .because("There are no prompts on STDIN".into()));
+    }
+
+    let file_id = batch_api::upload_batch_file(open_ai, &jsonl)?;
+    let batch = batch_api::create_batch(open_ai, &file_id)?;
+    let dir = get_or_create_batch_dir()?;
+    let submission = serde_json::to_string(&Submission { n, records })
+        .map_err(|e| {
+            Error::default()
+                .wrap(Oops::BatchError)
+                .because(format!("Could not serialize the submission: {e}"))
+        })?;
+    fs::write(dir.join(format!("{}.json", batch.id)), submission)
+        .and_then(|_| fs::write(dir.join("latest"), &batch.id))
+        .map_err(|e| {
+            Error::default()
+                .wrap(Oops::BatchError)
+                .because(format!("Could not save batch {}: {e}", batch.id))
+        })?;
+    println!("{}", batch.id);
+    Ok(())
+}
+
+/// Entrypoint for `yap batch status`.
+pub fn status(open_ai: &OpenAI, id: Option<&str>) -> Result<(), Error> {
+    let batch = batch_api::get_batch(open_ai, &resolve_id(id)?)?;
+    match batch.request_counts {
+        Some(c) => println!(
+            "{}: {} ({} of {} completed, {} failed)",
+            batch.id, batch.status, c.completed, c.total, c.failed
+        ),
+        None => println!("{}: {}", batch.id, batch.status),
+    }
+    Ok(())
+}
+
+/// Entrypoint for `yap batch fetch`.
+pub fn fetch(open_ai: &OpenAI, id: Option<&str>) -> Result<(), Error> {
+    let id = resolve_id(id)?;
+    let batch = batch_api::get_batch(open_ai, &id)?;
+    let mut lines = Vec::new();
+    for file_id in [&batch.output_file_id, &batch.error_file_id]
+        .into_iter()
+        .flatten()
+    {
+        lines.push(batch_api::get_file_content(open_ai, file_id)?);
+    }
+    if lines.is_empty() {
+        return Err(Error::default().wrap(Oops::BatchError).because(format!(
+            "Batch {id} has no results yet; its status is {:?}",
+            batch.status
+        )));
+    }
+
+    let submission: Option<Submission> = fs::read_to_string(
+        get_or_create_batch_dir()?.join(format!("{id}.json")),
+    )
+    .ok()
+    .and_then(|s| serde_json::from_str(&s).ok());
+    let n = submission.as_ref().and_then(|s| s.n);
+    let mut results: HashMap<usize, Result<CompletionResponse, Error>> =
+        HashMap::new();
+    for line in lines.iter().flat_map(|l| l.lines()) {
+        if let Some((i, result)) = parse_result_line(line) {
+            results.insert(i, result);
+        }
+    }
+
+    let count = submission
+        .as_ref()
+        .map_or(results.keys().max().map_or(0, |m| m + 1), |s| {
+            s.records.len()
+        });
+    let mut records = submission.map(|s| s.records).unwrap_or_default();
+    for i in 0..count {
+        let mut record =
+            records.get_mut(i).map(std::mem::take).unwrap_or_else(|| {
+                Map::from_iter([("custom_id".into(), json!(i.to_string()))])
+            });
+        let result = results.remove(&i).unwrap_or_else(|| {
+            Err(Error::default()
+                .wrap(Oops::BatchError)
+                .because("No result was returned for this prompt".into()))
+        });
+        complete::add_result(&mut record, result, n);
+        println!("{}", Value::Object(record));
+    }
+    Ok(())
+}
+
+/// Parse one line of a batch output or error file into its input index and
+/// result.
+fn parse_result_line(
+    line: &str,
+) -> Option<(usize, Result<CompletionResponse, Error>)> {
+    let value: Value = serde_json::from_str(line).ok()?;
+    let index = value["custom_id"].as_str()?.parse().ok()?;
+    let response = &value["response"];
+    let result = if response["status_code"] == 200 {
+        serde_json::from_value(response["body"].clone()).map_err(|e| {
+            Error::default()
+                .wrap(Oops::BatchError)
+                .because(format!("Could not deserialize the response: {e}"))
+        })
+    } else {
+        let message = value["error"]["message"]
+            .as_str()
+            .or(response["body"]["error"]["message"].as_str())
+            .unwrap_or("unknown error");
+        Err(Error::default()
+            .wrap(Oops::BatchError)
+            .because(message.to_string()))
+    };
+    Some((index, result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_result_line() {
+        let ok = r#"{"custom_id": "3", "response": {"status_code": 200, "body": {"choices": [{"message": {"role": "assistant", "content": "hi"}, "finish_reason": "stop"}]}}, "error": null}"#;
+        let (i, result) = parse_result_line(ok).unwrap();
+        assert_eq!(i, 3);
+        assert_eq!(
+            result.unwrap().choices[0].message.content.as_deref(),
+            Some("hi")
+        );
+
+        let failed = r#"{"custom_id": "0", "response": {"status_code": 400, "body": {"error": {"message": "bad"}}}, "error": null}"#;
+        let (_, result) = parse_result_line(failed).unwrap();
+        assert!(result.unwrap_err().summary().contains("bad"));
+    }
+}
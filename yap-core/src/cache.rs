@@ -0,0 +1,105 @@
+//! Cache completion responses in `~/.local/state/yap/cache`, so that running
+//! the same prompt twice (i.e, from a script) doesn't cost twice.
+//!
+//! Responses are keyed by a SHA-256 hash of the full request payload, which
+//! includes the model, messages, and parameters. Entries expire after
+//! `cache_ttl` seconds (see [crate::config::Settings]). Pass `--no-cache` to
+//! bypass the cache, or feel free to delete the cache directory at any time.
+
+use crate::{
+    db,
+    err::{Error, Oops},
+    openai::{CompletionPayload, CompletionResponse},
+};
+use log::debug;
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, create_dir_all, File},
+    path::PathBuf,
+    time::Duration,
+};
+
+fn get_or_create_cache_dir() -> Result<PathBuf, Error> {
+    let dir = db::get_or_create_persistence_dir()?.join("cache");
+    if !dir.exists() {
+        create_dir_all(&dir).map_err(|e| {
+            Error::default()
+                .wrap(Oops::CacheError)
+                .because(format!("Failed to create cache directory: {e}"))
+        })?;
+    }
+    Ok(dir)
+}
+
+fn key(payload: &CompletionPayload) -> Result<String, Error> {
+    let body = serde_json::to_vec(payload).map_err(|e| {
+        Error::default()
+            .wrap(Oops::CacheError)
+            .because(format!("Could not serialize payload: {e}"))
+    })?;
+    Ok(Sha256::digest(body)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+/// Look up a cached response to `payload`, if one exists and is younger than
+/// `ttl`.
+pub fn get(
+    payload: &CompletionPayload,
+    ttl: Duration,
+) -> Result<Option<CompletionResponse>, Error> {
+    let path =
+        get_or_create_cache_dir()?.join(format!("{}.json", key(payload)?));
+    if !path.exists() {
+        return Ok(None);
+    }
+    let age = fs::metadata(&path)
+        .and_then(|m| m.modified())
+        .map_err(|e| {
+            Error::default()
+                .wrap(Oops::CacheError)
+                .because(format!("Could not read metadata of {path:?}: {e}"))
+        })?
+        .elapsed()
+        .unwrap_or_default();
+    if age > ttl {
+        debug!("Cache entry {path:?} is expired");
+        return Ok(None);
+    }
+    let file = File::open(&path).map_err(|e| {
+        Error::default()
+            .wrap(Oops::CacheError)
+            .because(format!("Could not open {path:?}: {e}"))
+    })?;
+    match serde_json::from_reader(file) {
+        Ok(response) => {
+            debug!("Cache hit: {path:?}");
+            Ok(Some(response))
+        }
+        Err(e) => {
+            // A corrupt entry is just a cache miss; it'll be overwritten.
+            debug!("Ignoring unreadable cache entry {path:?}: {e}");
+            Ok(None)
+        }
+    }
+}
+
+/// Store `response` as the cached response to `payload`.
+pub fn put(
+    payload: &CompletionPayload,
+    response: &CompletionResponse,
+) -> Result<(), Error> {
+    let path =
+        get_or_create_cache_dir()?.join(format!("{}.json", key(payload)?));
+    let file = File::create(&path).map_err(|e| {
+        Error::default()
+            .wrap(Oops::CacheError)
+            .because(format!("Could not create {path:?}: {e}"))
+    })?;
+    serde_json::to_writer(file, response).map_err(|e| {
+        Error::default()
+            .wrap(Oops::CacheError)
+            .because(format!("Could not write {path:?}: {e}"))
+    })
+}
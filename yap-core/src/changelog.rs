@@ -0,0 +1,242 @@
+//! Generate release notes from git history.
+//!
+//! Commits in the given range are read with `git log`, and summarized by the
+//! LLM into Markdown release notes, grouped by the kind of change. Histories
+//! which are too large for one request are split into chunks; each chunk is
+//! summarized separately (concurrently; see [crate::pool]), and the partial
+//! notes are then merged into one changelog.
+
+use crate::{
+    config::ConfigFile,
+    constants,
+    err::{Error, Oops},
+    filter,
+    openai::{
+        chat_async, estimate_tokens, CompletionPayload, Content, Message,
+        OpenAI, PayloadOpts, Role,
+    },
+    pool,
+};
+use log::debug;
+use std::process::Command;
+
+/// Each chunk of commits may use up to this fraction of the context window,
+/// leaving room for the system prompt and the response.
+const CHUNK_FRACTION: f64 = 0.5;
+
+/// Separates fields within a commit in our `git log` format.
+const FIELD_SEP: char = '\x1f';
+/// Separates commits in our `git log` format.
+const RECORD_SEP: char = '\x1e';
+
+#[derive(Debug, PartialEq)]
+struct Commit {
+    hash: String,
+    subject: String,
+    body: String,
+}
+
+impl Commit {
+    fn render(&self) -> String {
+        if self.body.is_empty() {
+            format!("{} {}\n", self.hash, self.subject)
+        } else {
+            format!("{} {}\n\n{}\n", self.hash, self.subject, self.body)
+        }
+    }
+}
+
+/// Entrypoint for `yap changelog`. `range` is any revision range accepted
+/// by `git log`, like `v1.0..HEAD`.
+pub fn changelog(open_ai: &OpenAI, range: &str) -> Result<(), Error> {
+    let commits = parse_log(&git_log(range)?);
+    if commits.is_empty() {
+        return Err(Error::default()
+            .wrap(Oops::ChangelogError)
+            .because(format!("There are no commits in {range:?}")));
+    }
+    let budget =
+        (open_ai.model.context_window() as f64 * CHUNK_FRACTION) as usize;
+    let chunks = chunk(&commits, budget);
+    debug!(
+        "Summarizing {} commits in {} chunks",
+        commits.len(),
+        chunks.len()
+    );
+
+    let notes = pool::block_on(async {
+        if chunks.len() == 1 {
+            return summarize(open_ai, &chunks[0]).await;
+        }
+        eprintln!("Summarizing {} chunks of history...", chunks.len());
+        let partial = pool::map(&chunks, open_ai.max_concurrency, |c| {
+            summarize(open_ai, c)
+        })
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, Error>>()?;
+        merge(open_ai, &partial).await
+    })?;
+    println!("{}", filter::apply("changelog", &notes)?.trim_end());
+    Ok(())
+}
+
+fn git_log(range: &str) -> Result<String, Error> {
+    let output = Command::new("git")
+        .args([
+            "log",
+            "--no-merges",
+            "--no-color",
+            &format!("--format=%h{FIELD_SEP}%s{FIELD_SEP}%b{RECORD_SEP}"),
+            range,
+            "--",
+        ])
+        .output()
+        .map_err(|e| {
+            Error::default()
+                .wrap(Oops::CommandError)
+                .because(format!("could not run `git log`: {e}"))
+        })?;
+    if !output.status.success() {
+        return Err(Error::default().wrap(Oops::CommandError).because(
+            format!(
+                "`git log` failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ));
+    }
+    String::from_utf8(output.stdout).map_err(|e| {
+        Error::default()
+            .wrap(Oops::StringError)
+            .because(format!("`git log` output is not utf-8: {e}"))
+    })
+}
+
+fn parse_log(log: &str) -> Vec<Commit> {
+    log.split(RECORD_SEP)
+        .filter_map(|record| {
+            let mut fields = record.trim().splitn(3, FIELD_SEP);
+            let hash = fields.next().filter(|h| !h.is_empty())?;
+            Some(Commit {
+                hash: hash.to_string(),
+                subject: fields.next().unwrap_or_default().trim().to_string(),
+                body: fields.next().unwrap_or_default().trim().to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Split commits into chunks of roughly `budget` tokens. A commit which is
+/// larger than `budget` on its own gets a chunk to itself.
+fn chunk(commits: &[Commit], budget: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for commit in commits {
+        let text = commit.render();
+        if !current.is_empty()
+            && estimate_tokens(&current) + estimate_tokens(&text) > budget
+        {
+            chunks.push(std::mem::take(&mut current));
+        }
+        current.push_str(&text);
+        current.push('\n');
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+async fn summarize(open_ai: &OpenAI, commits: &str) -> Result<String, Error> {
+    let system_prompt = ConfigFile::ChangelogSystemPrompt
+        .load()
+        .map_err(|e| {
+            e.wrap(Oops::ChangelogError)
+                .because("Could not load the changelog system prompt".into())
+        })?
+        .unwrap_or(constants::DEFAULT_CHANGELOG_PROMPT.into());
+    send(
+        open_ai,
+        vec![
+            Message::new(Role::System, system_prompt),
+            Message::new(Role::User, commits.into()),
+        ],
+    )
+    .await
+}
+
+/// Combine release notes for each chunk into one changelog.
+async fn merge(open_ai: &OpenAI, partial: &[String]) -> Result<String, Error> {
+    send(
+        open_ai,
+        vec![
+            Message::new(
+                Role::System,
+                constants::CHANGELOG_MERGE_PROMPT.into(),
+            ),
+            Message::new(Role::User, partial.join("\n\n---\n\n")),
+        ],
+    )
+    .await
+}
+
+async fn send(
+    open_ai: &OpenAI,
+    messages: Vec<Message>,
+) -> Result<String, Error> {
+    let payload =
+        CompletionPayload::new(open_ai, messages, PayloadOpts::default());
+    let response = chat_async(open_ai, &payload).await.map_err(|e| {
+        e.wrap(Oops::ChangelogError)
+            .because("Error while requesting release notes from OpenAI".into())
+    })?;
+    match response.choices[0].message.parse()? {
+        Content::Normal(c) => Ok(c.to_string()),
+        Content::Refusal(r) => Err(Error::default()
+            .wrap(Oops::OpenAIRefusal)
+            .wrap(Oops::ChangelogError)
+            .because(format!("OpenAI refused to write release notes: {r}"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_log() {
+        let log = "abc1234\x1fAdd a thing\x1fIt is great.\n\x1e\n\
+                   def5678\x1fFix a bug\x1f\x1e\n";
+        assert_eq!(
+            parse_log(log),
+            vec![
+                Commit {
+                    hash: "abc1234".into(),
+                    subject: "Add a thing".into(),
+                    body: "It is great.".into(),
+                },
+                Commit {
+                    hash: "def5678".into(),
+                    subject: "Fix a bug".into(),
+                    body: "".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_chunk() {
+        let commits: Vec<Commit> = (0..10)
+            .map(|i| Commit {
+                hash: format!("{i:07}"),
+                subject: "x".repeat(30),
+                body: String::new(),
+            })
+            .collect();
+        // Each rendered commit is ~10 tokens.
+        let chunks = chunk(&commits, 25);
+        assert_eq!(chunks.len(), 5);
+        assert_eq!(chunk(&commits, 1_000).len(), 1);
+        assert!(chunks.concat().contains("0000009"));
+    }
+}
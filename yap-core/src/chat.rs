@@ -0,0 +1,614 @@
+//! Maintain a chat session with LLMs in your terminal.
+//!
+//! Run `yap chat --help` for details.
+
+use crate::{
+    complete,
+    config::{ConfigFile, Settings},
+    constants, context, ctx, db,
+    err::{Error, Oops},
+    filter, markdown, memory,
+    openai::{
+        self, CompletionPayload, Content, Message, Model, PayloadOpts, Role,
+    },
+    pin, repomap,
+    serve::Daemon,
+    term, tmux,
+};
+use log::debug;
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use uuid::Uuid;
+
+/// Set by the Ctrl-C handler while a response is streaming.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Options for `yap chat`; see `yap chat --help`.
+#[derive(Debug, Default)]
+pub struct Opts {
+    /// Begin a new chat session.
+    pub new: bool,
+    /// Resume (and activate) this chat instead of the active chat.
+    pub resume: Option<Uuid>,
+    /// Print responses verbatim, without [markdown::render].
+    pub raw: bool,
+    /// Overrides `context_strategy` in `config.json`.
+    pub context_strategy: Option<context::Strategy>,
+    /// Request this many candidate responses.
+    pub n: Option<u8>,
+    /// Print responses verbatim as they are generated.
+    pub stream: bool,
+    /// Switch the model which is pinned to the chat.
+    pub set_model: Option<Model>,
+    /// With `new`, use the contents of this file as the chat's system
+    /// prompt instead of the global chat prompt.
+    pub system_file: Option<PathBuf>,
+    /// Tags to add to the chat, for filtering `yap chatlog`.
+    pub tags: Vec<String>,
+    /// Attach the [repomap] to this message.
+    pub repo_map: bool,
+    /// Send the chat's history, but don't save this exchange, or change the
+    /// active chat.
+    pub ephemeral: bool,
+    /// Attach the scrollback of this tmux pane to the prompt; `Some(None)`
+    /// is the current pane. See [tmux::capture].
+    pub tmux_pane: Option<Option<String>>,
+    /// Replace the last user message (and the replies to it) with this
+    /// prompt, instead of adding to the chat; see [pop].
+    pub amend: bool,
+    /// Drop the replies to the last user message, and ask again; see
+    /// [Turn::Retry].
+    pub retry: bool,
+    /// Sample more (or less) randomly, from 0 to 2.
+    pub temperature: Option<f32>,
+}
+
+/// What one turn of the chat adds to (or replaces in) the chat history.
+enum Turn {
+    /// Add a user message.
+    Prompt(String),
+    /// Replace the last user message, and the replies to it; see [pop].
+    Amend(String),
+    /// Keep the last user message, but replace the replies to it; see
+    /// [retry].
+    Retry,
+}
+
+/// Entrypoint for `yap chat`. If `new` is set, we will begin a new chat
+/// session, optionally with a custom system prompt from `system_file`.
+/// Responses are styled with [markdown::render] when [term::styled], unless
+/// `raw` is set. If `stream` is set, responses are printed
+/// verbatim as they are generated instead.
+///
+/// Each chat is pinned to the model it was started with, which is reused
+/// when the chat is resumed. `yap --model` overrides the pinned model for one
+/// turn, and `set_model` switches the pinned model.
+pub fn chat(
+    open_ai: &openai::OpenAI,
+    prompt: &[String],
+    opts: &Opts,
+) -> Result<(), Error> {
+    debug!("Chatting with prompt {prompt:?}");
+
+    if opts.resume.is_some() && opts.new {
+        return Err(Error::default().wrap(Oops::ChatError).because(
+            "Cannot specify --new and --resume together.".to_string(),
+        ));
+    }
+
+    // A dry run doesn't switch the active chat.
+    let activate = |id: &Uuid| {
+        if open_ai.dry_run {
+            Ok(())
+        } else {
+            db::set_chat_id(id)
+        }
+    };
+    let chat_id = if opts.ephemeral {
+        // Without an active chat, the exchange has no history at all.
+        match opts.resume {
+            Some(id) => id,
+            None => db::get_active_chat()?.unwrap_or_else(Uuid::new_v4),
+        }
+    } else if let Some(id) = opts.resume {
+        activate(&id)?;
+        id
+    } else if opts.new {
+        let id = Uuid::new_v4();
+        activate(&id)?;
+        id
+    } else {
+        db::get_active_chat()?.map_or_else(
+            || {
+                // Create a new chat if there is no active one.
+                let id = Uuid::new_v4();
+                activate(&id)?;
+                Ok(id)
+            },
+            Ok,
+        )?
+    };
+
+    if opts.system_file.is_some() && !opts.new {
+        return Err(Error::default()
+            .wrap(Oops::ChatError)
+            .because("--system-file can only be used with --new".to_string()));
+    }
+    // Options which change the chat itself are saved straight away, so that
+    // they stick even without a prompt.
+    let updates_chat = opts.system_file.is_some()
+        || opts.set_model.is_some()
+        || !opts.tags.is_empty();
+    if updates_chat {
+        let mut chat = db::get_chat(&chat_id)?;
+        if let Some(path) = &opts.system_file {
+            let system_prompt = fs::read_to_string(path).map_err(|e| {
+                Error::default().wrap(Oops::ChatError).because(format!(
+                    "Could not read system prompt file {path:?}: {e}"
+                ))
+            })?;
+            chat.messages = vec![Message::new(Role::System, system_prompt)];
+        }
+        if opts.set_model.is_some() {
+            chat.model = opts.set_model.clone();
+        }
+        for tag in &opts.tags {
+            if !chat.tags.contains(tag) {
+                chat.tags.push(tag.clone());
+            }
+        }
+        db::save_chat(&chat_id, &chat)?;
+    }
+
+    if let Some(t) = opts.temperature.filter(|t| !(0.0..=2.0).contains(t)) {
+        return Err(Error::default().wrap(Oops::ChatError).because(format!(
+            "--temperature must be between 0 and 2, not {t}"
+        )));
+    }
+    if prompt.is_empty() && opts.retry {
+        // The prompt is already in the chat.
+    } else if prompt.is_empty() && (opts.new || updates_chat) {
+        debug!("prompt is empty, but a new chat was started or the chat was updated. Exiting from chat early.");
+        return Ok(());
+    } else if prompt.is_empty() {
+        return Err(Error::default()
+            .wrap(Oops::ChatError)
+            .because("Prompt is empty!".to_string()));
+    }
+
+    let context_strategy = match opts.context_strategy {
+        Some(strategy) => strategy,
+        None => Settings::load()?.context_strategy,
+    };
+
+    resume_chat(open_ai, &chat_id, prompt, opts, context_strategy)
+}
+
+/// Whether `yap daemon` can answer this turn; see [forward]. The daemon only
+/// sends plain prompts, to the active chat, a new chat, or the `resume`d
+/// chat.
+pub fn can_forward(prompt: &[String], opts: &Opts) -> bool {
+    !prompt.is_empty()
+        && opts.context_strategy.is_none()
+        && opts.n.is_none()
+        && !opts.stream
+        && opts.set_model.is_none()
+        && opts.system_file.is_none()
+        && opts.tags.is_empty()
+        && !opts.repo_map
+        && !opts.ephemeral
+        && opts.tmux_pane.is_none()
+        && !opts.amend
+        && !opts.retry
+        && opts.temperature.is_none()
+}
+
+/// Like [chat], but the reply is requested from `yap daemon`, which saves
+/// it to the chat history. Only for options which [can_forward].
+pub fn forward(
+    daemon: &mut Daemon,
+    prompt: &[String],
+    opts: &Opts,
+) -> Result<(), Error> {
+    #[derive(Deserialize)]
+    struct Reply {
+        reply: String,
+    }
+    if let Some(id) = opts.resume {
+        db::set_chat_id(&id)?;
+    }
+    let Reply { reply } = daemon.call(
+        "chat",
+        json!({
+            "prompt": prompt.join(" "),
+            "chat_id": opts.resume,
+            "new": opts.new,
+        }),
+    )?;
+    print_reply(&reply, opts.raw)
+}
+
+/// Entrypoint for `yap chat --pop`. Removes the last user message, and the
+/// replies to it, from the chat `resume`, or else the active chat.
+pub fn pop_exchange(resume: Option<Uuid>) -> Result<(), Error> {
+    let id = match resume {
+        Some(id) => id,
+        None => db::get_active_chat()?.ok_or_else(|| {
+            Error::default()
+                .wrap(Oops::ChatError)
+                .because("No chat is active".into())
+        })?,
+    };
+    let mut chat = db::get_chat(&id)?;
+    pop(&mut chat)?;
+    db::save_chat(&id, &chat)?;
+    eprintln!("Removed the last exchange from chat {id}.");
+    Ok(())
+}
+
+/// If available, load the chat history associated with `id`, append the
+/// prompt to chat history, send to OpenAI, print the response, and then
+/// persist the new chat history. `context_strategy` decides what is sent if
+/// the history no longer fits in the model's context window.
+fn resume_chat(
+    open_ai: &openai::OpenAI,
+    id: &Uuid,
+    prompt: &[String],
+    opts: &Opts,
+    context_strategy: context::Strategy,
+) -> Result<(), Error> {
+    let mut prompt = prompt.join(" ");
+    // Unlike `ctx` files, the pane is saved with the prompt, since it won't
+    // be the same next time.
+    if let Some(target) = &opts.tmux_pane {
+        prompt = tmux::with_pane(&tmux::capture(target.as_deref())?, &prompt);
+    }
+    let turn = if opts.retry {
+        Turn::Retry
+    } else if opts.amend {
+        Turn::Amend(prompt)
+    } else {
+        Turn::Prompt(prompt)
+    };
+    let (mut chat, open_ai, messages) =
+        begin_turn(open_ai, id, turn, context_strategy, opts.repo_map)?;
+    let open_ai = &open_ai;
+    let payload = CompletionPayload::new(
+        open_ai,
+        messages,
+        PayloadOpts {
+            n: opts.n,
+            stream: opts.stream,
+            temperature: opts.temperature,
+            ..Default::default()
+        },
+    );
+    if opts.stream {
+        let message = stream_reply(open_ai, &payload)?;
+        chat.messages.push(message);
+        return save(id, &chat, opts);
+    }
+    let reply = openai::chat(open_ai, &payload)?;
+
+    let count = reply.choices.len();
+    for (i, choice) in reply.choices.iter().enumerate() {
+        if count > 1 {
+            println!("{}", complete::candidate_delimiter(i, count));
+        }
+        match choice.message.parse()? {
+            Content::Normal(msg) => print_reply(msg, opts.raw)?,
+            Content::Refusal(msg) => eprintln!("{msg}"),
+        };
+    }
+
+    let chosen = if count > 1 { pick_candidate(count)? } else { 0 };
+    chat.messages.push(reply.choices[chosen].message.clone());
+    save(id, &chat, opts)
+}
+
+/// Print a reply after running it through the `chat` [filter]s, styled with
+/// [markdown::render] unless `raw` is set.
+fn print_reply(msg: &str, raw: bool) -> Result<(), Error> {
+    let msg = filter::apply("chat", msg)?;
+    if !raw && term::styled() {
+        println!("{}", markdown::render(&msg))
+    } else {
+        println!("{msg}")
+    }
+    Ok(())
+}
+
+/// Save the chat, unless the exchange is ephemeral.
+fn save(id: &Uuid, chat: &db::Chat, opts: &Opts) -> Result<(), Error> {
+    if opts.ephemeral {
+        debug!("Not saving ephemeral exchange");
+        return Ok(());
+    }
+    db::save_chat(id, chat)
+}
+
+/// Send `prompt` to the chat `id`, save the reply to the chat history, and
+/// return it. This is a single, non-interactive turn of [resume_chat], for
+/// [crate::serve].
+pub fn reply(
+    open_ai: &openai::OpenAI,
+    id: &Uuid,
+    prompt: String,
+) -> Result<String, Error> {
+    let strategy = Settings::load()?.context_strategy;
+    let (mut chat, open_ai, messages) =
+        begin_turn(open_ai, id, Turn::Prompt(prompt), strategy, false)?;
+    let payload =
+        CompletionPayload::new(&open_ai, messages, PayloadOpts::default());
+    let message = openai::chat(&open_ai, &payload)?.choices[0].message.clone();
+    let text = match message.parse()? {
+        Content::Normal(msg) => msg.to_string(),
+        Content::Refusal(msg) => {
+            return Err(Error::default()
+                .wrap(Oops::OpenAIRefusal)
+                .wrap(Oops::ChatError)
+                .because(format!("The model refused: {msg}")))
+        }
+    };
+    chat.messages.push(message);
+    db::save_chat(id, &chat)?;
+    Ok(text)
+}
+
+/// Load the chat `id` and apply `turn`. Returns the chat, a client for the
+/// chat's pinned model, and the messages to send, which include the
+/// project's [ctx] files (and the [repomap], if `repo_map` is set) after the
+/// system prompt. Nothing is saved until the reply arrives, so an amended or
+/// retried exchange survives a failed request.
+fn begin_turn(
+    open_ai: &openai::OpenAI,
+    id: &Uuid,
+    turn: Turn,
+    context_strategy: context::Strategy,
+    repo_map: bool,
+) -> Result<(db::Chat, openai::OpenAI, Vec<Message>), Error> {
+    let mut chat = db::get_chat(id)?;
+    let model = match (open_ai.explicit_model, &chat.model) {
+        (false, Some(pinned)) => pinned.clone(),
+        _ => open_ai.model.clone(),
+    };
+    chat.model.get_or_insert_with(|| model.clone());
+    let open_ai = open_ai.with_model(model);
+
+    if chat.messages.is_empty() {
+        let system_prompt = ConfigFile::ChatSystemPrompt
+            .load()
+            .map_err(|e| {
+                e.wrap(Oops::ChatError)
+                    .because("Could not load system prompt during chat".into())
+            })?
+            .map_or(constants::DEFAULT_CHAT_PROMPT.to_string(), |p| p.clone());
+        chat.messages
+            .push(Message::new(Role::System, system_prompt));
+        chat.messages.extend(memory::message()?);
+    }
+    match turn {
+        Turn::Prompt(prompt) => {
+            chat.messages.push(Message::new(Role::User, prompt))
+        }
+        Turn::Amend(prompt) => {
+            pop(&mut chat)?;
+            chat.messages.push(Message::new(Role::User, prompt))
+        }
+        Turn::Retry => retry(&mut chat)?,
+    }
+    let mut messages = context::prepare(&open_ai, &mut chat, context_strategy)?;
+    let at = messages
+        .first()
+        .map_or(0, |m| usize::from(matches!(m.role, Role::System)));
+    let mut attached = ctx::messages()?;
+    attached.extend(pin::file_messages(&chat)?);
+    if repo_map {
+        attached.insert(0, repomap::message()?);
+    }
+    messages.splice(at..at, attached);
+    Ok((chat, open_ai, messages))
+}
+
+/// Drop the last user message, and everything after it; i.e, the replies to
+/// it, for `yap chat --pop` and `yap chat --amend`.
+fn pop(chat: &mut db::Chat) -> Result<(), Error> {
+    let last = chat
+        .messages
+        .iter()
+        .rposition(|m| matches!(m.role, Role::User))
+        .ok_or_else(|| {
+            Error::default()
+                .wrap(Oops::ChatError)
+                .because("The chat has no messages to remove".into())
+        })?;
+    chat.truncate(last);
+    Ok(())
+}
+
+/// Drop everything after the last user message; i.e, the replies to it, for
+/// `yap chat --retry`.
+fn retry(chat: &mut db::Chat) -> Result<(), Error> {
+    let last = chat
+        .messages
+        .iter()
+        .rposition(|m| matches!(m.role, Role::User))
+        .ok_or_else(|| {
+            Error::default()
+                .wrap(Oops::ChatError)
+                .because("There is no message to retry".into())
+        })?;
+    chat.truncate(last + 1);
+    Ok(())
+}
+
+/// Print the response as it arrives. Ctrl-C stops the response early, and
+/// the partial response is returned, marked as truncated, so that it can
+/// still be saved to the chat history.
+fn stream_reply(
+    open_ai: &openai::OpenAI,
+    payload: &CompletionPayload,
+) -> Result<Message, Error> {
+    ctrlc::set_handler(|| INTERRUPTED.store(true, Ordering::SeqCst)).map_err(
+        |e| {
+            Error::default()
+                .wrap(Oops::ChatError)
+                .because(format!("Could not handle Ctrl-C: {e}"))
+        },
+    )?;
+    let mut stdout = io::stdout();
+    let message =
+        openai::chat_stream(open_ai, payload, &INTERRUPTED, |delta| {
+            print!("{delta}");
+            let _ = stdout.flush();
+        })?;
+    println!();
+    if let Content::Refusal(msg) = message.parse()? {
+        eprintln!("{msg}");
+    }
+    if INTERRUPTED.load(Ordering::SeqCst) {
+        eprintln!("Interrupted; the partial response was saved to the chat.");
+    } else if message.truncated {
+        eprintln!("The response ended early; the partial response was saved to the chat.");
+    }
+    Ok(message)
+}
+
+/// Entrypoint for `yap chat --jsonl`. Reads messages from `STDIN`, one JSON
+/// object per line (i.e, `{"role": "user", "content": "hi"}`), and prints
+/// the reply as one JSON object; or one per candidate, if `n` is set. Chat
+/// history is neither used nor changed.
+pub fn jsonl(open_ai: &openai::OpenAI, n: Option<u8>) -> Result<(), Error> {
+    let mut messages = Vec::new();
+    for (i, line) in io::stdin().lines().enumerate() {
+        let line = line.map_err(|e| {
+            Error::default()
+                .wrap(Oops::ChatError)
+                .wrap(Oops::StdinReadError)
+                .because(e.kind().to_string())
+        })?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let message: Message = serde_json::from_str(&line).map_err(|e| {
+            Error::default()
+                .wrap(Oops::ChatError)
+                .because(format!("Line {} is not a valid message: {e}", i + 1))
+        })?;
+        messages.push(message);
+    }
+    if messages.is_empty() {
+        return Err(Error::default()
+            .wrap(Oops::ChatError)
+            .because("There are no messages on STDIN".into()));
+    }
+    let payload = CompletionPayload::new(
+        open_ai,
+        messages,
+        PayloadOpts {
+            n,
+            ..Default::default()
+        },
+    );
+    for choice in openai::chat(open_ai, &payload)?.choices {
+        let line = serde_json::to_string(&choice.message).map_err(|e| {
+            Error::default()
+                .wrap(Oops::ChatError)
+                .because(format!("Could not serialize the reply: {e}"))
+        })?;
+        println!("{line}");
+    }
+    Ok(())
+}
+
+/// Ask the user which of `count` candidates to keep in the chat history.
+/// Defaults to the first candidate.
+fn pick_candidate(count: usize) -> Result<usize, Error> {
+    loop {
+        let question =
+            format!("Which candidate should be kept in the chat? [1-{count}] ");
+        let Some(answer) = term::ask(&question)? else {
+            eprintln!("Keeping candidate 1, since STDIN is not a terminal.");
+            return Ok(0);
+        };
+        if answer.is_empty() {
+            return Ok(0);
+        }
+        match answer.parse::<usize>() {
+            Ok(i) if (1..=count).contains(&i) => return Ok(i - 1),
+            _ => eprintln!("Please enter a number from 1 to {count}."),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chat(messages: Vec<Message>) -> db::Chat {
+        db::Chat {
+            messages,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_pop() {
+        let mut chat = chat(vec![
+            Message::new(Role::System, "be brief".into()),
+            Message::new(Role::User, "hi".into()),
+            Message::new(Role::Assistant, "Hello!".into()),
+            Message::new(Role::User, "waht is rust".into()),
+            Message::new(Role::Assistant, "Unclear.".into()),
+        ]);
+        pop(&mut chat).unwrap();
+        assert_eq!(chat.messages.len(), 3);
+        assert_eq!(chat.messages[2].content.as_deref(), Some("Hello!"));
+
+        let mut chat =
+            self::chat(vec![Message::new(Role::System, "be brief".into())]);
+        assert!(pop(&mut chat).is_err());
+    }
+
+    #[test]
+    fn test_pop_summarized() {
+        let mut chat = chat(vec![
+            Message::new(Role::System, "be brief".into()),
+            Message::new(Role::User, "hi".into()),
+            Message::new(Role::Assistant, "Hello!".into()),
+            Message::new(Role::User, "waht is rust".into()),
+            Message::new(Role::Assistant, "Unclear.".into()),
+        ]);
+        chat.summary = Some(db::Summary {
+            content: "they said hi".into(),
+            covers: 3,
+        });
+        pop(&mut chat).unwrap();
+        pop(&mut chat).unwrap();
+        assert!(chat.summary.is_none());
+        let messages = context::to_send(&chat);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].content.as_deref(), Some("be brief"));
+    }
+
+    #[test]
+    fn test_retry() {
+        let mut chat = chat(vec![
+            Message::new(Role::System, "be brief".into()),
+            Message::new(Role::User, "hi".into()),
+            Message::new(Role::Assistant, "Hel".into()),
+        ]);
+        retry(&mut chat).unwrap();
+        assert_eq!(chat.messages.len(), 2);
+        assert_eq!(chat.messages[1].content.as_deref(), Some("hi"));
+        // A request which failed left no reply to replace.
+        retry(&mut chat).unwrap();
+        assert_eq!(chat.messages.len(), 2);
+    }
+}
@@ -0,0 +1,526 @@
+//! Print a list of all conversations, plus instructions for resuming a past
+//! conversation. Chat conversations are stored in `~/.local/state/yap/chats`.
+//! Feel free to manually cleanup chat files in this directory if you've
+//! accumulated too many chats. Chats are listed from the [db::ChatIndex],
+//! so listing thousands of chats doesn't mean loading each one.
+
+use crate::{
+    date, db,
+    err::{Error, Oops},
+    openai::{Message, Model},
+    picker, term,
+};
+use log::warn;
+use regex::Regex;
+use serde::Serialize;
+use std::{
+    fmt::Write,
+    io::{self, IsTerminal},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+/// How much of each message `yap chatlog --diff` shows.
+const DIFF_PREVIEW_CHARS: usize = 72;
+
+#[derive(Debug)]
+/// A sorted set of conversations, ordered by modified time, descending.
+struct ConversationSet(Vec<db::Conversation>);
+
+impl ConversationSet {
+    fn new(mut conversations: Vec<db::Conversation>) -> Result<Self, Error> {
+        let (result, mut tuples) =
+            conversations
+                .drain(..)
+                .fold((None, Vec::new()), |acc, convo| {
+                    let (mut result, mut sorted_vec) = acc;
+                    convo
+                        .accessed()
+                        .map(|time| {
+                            sorted_vec.push((time, convo));
+                        })
+                        .unwrap_or_else(|e| {
+                            result = Some(e);
+                        });
+                    (result, sorted_vec)
+                });
+        if let Some(err) = result {
+            return Err(err);
+        };
+
+        tuples.sort_by_key(|t| std::cmp::Reverse(t.0));
+        let sorted_set =
+            tuples.drain(..).fold(Vec::new(), |mut acc, (_, convo)| {
+                acc.push(convo);
+                acc
+            });
+        // If I want to call it a set I should technically validate that
+        // the paths are unique but whatever.
+        Ok(Self(sorted_set))
+    }
+
+    /// Look up to `limit` conversations (or all of them) in the `index`,
+    /// oldest first.
+    fn entries(
+        &self,
+        limit: Option<usize>,
+        index: &mut db::ChatIndex,
+    ) -> Result<Vec<Entry>, Error> {
+        let limit = (limit.unwrap_or(self.0.len()) + 1).min(self.0.len());
+        self.0[0..limit]
+            .iter()
+            .rev()
+            .map(|convo| Entry::load(convo, index))
+            .collect()
+    }
+}
+
+/// A summary of one conversation.
+#[derive(Debug, Serialize)]
+struct Entry {
+    uuid: Uuid,
+    /// The first line of the first message that the user sent.
+    title: Option<String>,
+    /// The first line of the most recent message that the user sent, or of
+    /// the first message if the user hasn't sent any.
+    last_message: Option<String>,
+    /// Seconds since the Unix epoch. `created` is not available on every
+    /// filesystem.
+    created: Option<u64>,
+    modified: u64,
+    accessed: u64,
+    message_count: usize,
+    tags: Vec<String>,
+    model: Option<Model>,
+}
+
+impl Entry {
+    fn load(
+        convo: &db::Conversation,
+        index: &mut db::ChatIndex,
+    ) -> Result<Self, Error> {
+        let entry = index.get(convo)?.clone();
+        Ok(Self {
+            uuid: convo.uuid()?,
+            title: entry.title,
+            last_message: entry.last_message,
+            created: convo.created().map(unix_secs),
+            modified: unix_secs(convo.modified()?),
+            accessed: unix_secs(convo.accessed()?),
+            message_count: entry.message_count,
+            tags: entry.tags,
+            model: entry.model,
+        })
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// One line for each conversation, with its UUID, when it was last active,
+/// how many messages it has, its model, its tags, and its title; in aligned
+/// columns. If `width` is given, titles are cut short to fit.
+fn render_plain(entries: &[Entry], width: Option<usize>, now: u64) -> String {
+    let rows: Vec<[String; 5]> = entries
+        .iter()
+        .filter_map(|entry| {
+            let title = entry.title.as_ref().or(entry.last_message.as_ref())?;
+            let tags = if entry.tags.is_empty() {
+                String::new()
+            } else {
+                format!("[{}] ", entry.tags.join(", "))
+            };
+            Some([
+                entry.uuid.to_string(),
+                date::ago(entry.modified, now),
+                format!("{} msgs", entry.message_count),
+                entry.model.as_ref().map_or("-".into(), Model::to_string),
+                format!("{tags}{title}"),
+            ])
+        })
+        .collect();
+    let mut widths = [0; 4];
+    for row in &rows {
+        for (w, cell) in widths.iter_mut().zip(row) {
+            *w = (*w).max(cell.chars().count());
+        }
+    }
+    let prefix_len = widths.iter().map(|w| w + 2).sum::<usize>();
+    let mut out = String::new();
+    for [uuid, ago, count, model, title] in &rows {
+        let [w0, w1, w2, w3] = widths;
+        let _ = write!(
+            out,
+            "{uuid:<w0$}  {ago:>w1$}  {count:>w2$}  {model:<w3$}  "
+        );
+        match width.map(|w| w.saturating_sub(prefix_len)) {
+            Some(max_len) if title.chars().count() > max_len => {
+                out.extend(title.chars().take(max_len.saturating_sub(3)));
+                out.push_str("...");
+            }
+            _ => out.push_str(title),
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// How `yap chatlog` should print conversations.
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum Format {
+    /// One line per chat, with instructions for resuming a chat.
+    #[default]
+    Plain,
+    /// A JSON array of records, with each chat's UUID, title, last message,
+    /// timestamps, and message count.
+    Json,
+}
+
+/// Which chats `yap chatlog` should show.
+#[derive(Debug, Default)]
+pub struct Filter {
+    /// Only chats with every one of these tags.
+    pub tags: Vec<String>,
+    /// Only chats which were active at or after this time, in seconds since
+    /// the Unix epoch.
+    pub since: Option<u64>,
+    /// Only chats which began before this time.
+    pub before: Option<u64>,
+}
+
+impl Filter {
+    /// With `today`, `since` is replaced by midnight at the start of today,
+    /// in the local time zone.
+    pub fn new(
+        tags: Vec<String>,
+        since: Option<u64>,
+        before: Option<u64>,
+        today: bool,
+    ) -> Self {
+        let since = if today {
+            Some(date::local_midnight(unix_secs(SystemTime::now())))
+        } else {
+            since
+        };
+        Self {
+            tags,
+            since,
+            before,
+        }
+    }
+    /// Whether a chat with `tags`, which began at `started` and was last
+    /// active at `modified`, matches the filter.
+    fn matches(&self, tags: &[String], started: u64, modified: u64) -> bool {
+        self.tags.iter().all(|t| tags.contains(t))
+            && self.since.is_none_or(|since| modified >= since)
+            && self.before.is_none_or(|before| started < before)
+    }
+    fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.since.is_none() && self.before.is_none()
+    }
+}
+
+/// List conversations, keeping only those which match the `filter`.
+fn conversations(
+    filter: &Filter,
+    index: &mut db::ChatIndex,
+) -> Result<ConversationSet, Error> {
+    let mut conversations = db::list_conversations()?;
+    index.retain(&conversations);
+    if !filter.is_empty() {
+        let mut matching = Vec::new();
+        for convo in conversations {
+            let modified = unix_secs(convo.modified()?);
+            let entry = index.get(&convo)?;
+            // Older chats don't record when they began, so fall back to
+            // when the file was created, where the filesystem knows.
+            let started = entry
+                .started
+                .or(convo.created().map(unix_secs))
+                .unwrap_or(modified);
+            if filter.matches(&entry.tags, started, modified) {
+                matching.push(convo);
+            }
+        }
+        conversations = matching;
+    }
+    ConversationSet::new(conversations)
+}
+
+/// Up to `limit` conversations (or all of them) which match the `filter`,
+/// oldest first. Chats are looked up in the [db::ChatIndex], which is
+/// brought up to date along the way.
+fn entries(filter: &Filter, limit: Option<usize>) -> Result<Vec<Entry>, Error> {
+    let mut index = db::ChatIndex::load()?;
+    let entries =
+        conversations(filter, &mut index)?.entries(limit, &mut index)?;
+    if let Err(e) = index.save() {
+        warn!("Could not update the chat index: {e:?}");
+    }
+    Ok(entries)
+}
+
+/// Load and print the chatlog, showing only chats which match the `filter`.
+pub fn chatlog(
+    trunc: Option<usize>,
+    filter: &Filter,
+    format: Format,
+) -> Result<(), Error> {
+    let entries = entries(filter, trunc)?;
+    if let Format::Json = format {
+        let out = serde_json::to_string_pretty(&entries).map_err(|e| {
+            Error::default()
+                .wrap(Oops::StringError)
+                .because(format!("Could not serialize chatlog: {e}"))
+        })?;
+        println!("{out}");
+        return Ok(());
+    }
+    let now = unix_secs(SystemTime::now());
+    println!("{}", render_plain(&entries, term::output_width(), now));
+    println!(
+        "To resume a previous chat, run;
+
+    yap chat --resume <uuid>"
+    );
+    Ok(())
+}
+
+/// Print each line of every message which matches the regular expression
+/// `pattern`, from the oldest chat to the newest. Each line is prefixed by
+/// its chat's UUID, the message's role, and when the message was written (or
+/// when the chat was last modified, for older chats which don't record when
+/// each message was written).
+pub fn grep(pattern: &str, filter: &Filter) -> Result<(), Error> {
+    let regex = Regex::new(pattern).map_err(|e| {
+        Error::default()
+            .wrap(Oops::StringError)
+            .because(format!("Invalid pattern {pattern:?}: {e}"))
+    })?;
+    let mut index = db::ChatIndex::load()?;
+    for convo in conversations(filter, &mut index)?.0.iter().rev() {
+        let uuid = convo.uuid()?;
+        let chat = db::get_chat(&uuid)?;
+        let modified = unix_secs(convo.modified()?);
+        for message in &chat.messages {
+            let Some(content) = &message.content else {
+                continue;
+            };
+            let when = date::datetime(message.created.unwrap_or(modified));
+            for line in content.lines().filter(|l| regex.is_match(l)) {
+                println!("{uuid} {} {when} :: {line}", message.role);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Entrypoint for `yap chatlog --diff`. Shows where the chats `a` and `b`
+/// diverge, i.e, after forking a chat, and summarizes each branch.
+pub fn diff(a: &Uuid, b: &Uuid) -> Result<(), Error> {
+    let (chat_a, chat_b) = (db::get_chat(a)?, db::get_chat(b)?);
+    print!(
+        "{}",
+        render_diff(
+            (a, &chat_a.messages),
+            (b, &chat_b.messages),
+            term::styled(),
+        )
+    );
+    Ok(())
+}
+
+/// The number of messages which `a` and `b` have in common, before they
+/// diverge.
+fn shared_prefix(a: &[Message], b: &[Message]) -> usize {
+    a.iter()
+        .zip(b)
+        .take_while(|(a, b)| {
+            a.role.to_string() == b.role.to_string() && a.content == b.content
+        })
+        .count()
+}
+
+/// The first line of `message`, cut off at [DIFF_PREVIEW_CHARS].
+fn preview(message: &Message) -> String {
+    let line = message
+        .content
+        .as_deref()
+        .and_then(|c| c.lines().find(|l| !l.trim().is_empty()))
+        .unwrap_or_default()
+        .trim();
+    if line.chars().count() > DIFF_PREVIEW_CHARS {
+        let cut: String = line.chars().take(DIFF_PREVIEW_CHARS - 3).collect();
+        format!("[{}] {cut}...", message.role)
+    } else {
+        format!("[{}] {line}", message.role)
+    }
+}
+
+fn render_diff(
+    (a, messages_a): (&Uuid, &[Message]),
+    (b, messages_b): (&Uuid, &[Message]),
+    color: bool,
+) -> String {
+    let (red, green, reset) = if color {
+        ("\x1b[31m", "\x1b[32m", "\x1b[0m")
+    } else {
+        ("", "", "")
+    };
+    let shared = shared_prefix(messages_a, messages_b);
+    let mut out = String::new();
+    match shared.checked_sub(1).map(|i| &messages_a[i]) {
+        Some(last) => {
+            let _ = writeln!(
+                out,
+                "The chats share their first {shared} messages, ending with;\n  {}",
+                preview(last)
+            );
+        }
+        None => out.push_str("The chats have no messages in common.\n"),
+    }
+    for (id, messages, sign, color) in
+        [(a, messages_a, '-', red), (b, messages_b, '+', green)]
+    {
+        let branch = &messages[shared..];
+        let tokens: usize = branch.iter().map(Message::estimate_tokens).sum();
+        let _ = writeln!(
+            out,
+            "\n{id} continues with {} messages (~{tokens} tokens)",
+            branch.len()
+        );
+        for message in branch {
+            let _ = writeln!(out, "{color}{sign} {}{reset}", preview(message));
+        }
+    }
+    out
+}
+
+/// Let the user choose a chat with [picker::pick], most recent first. The
+/// chosen chat becomes the active chat, or, if `STDOUT` is not a terminal,
+/// its UUID is printed so that it can be piped elsewhere.
+pub fn pick(filter: &Filter) -> Result<(), Error> {
+    let mut entries = entries(filter, None)?;
+    entries.reverse();
+    if entries.is_empty() {
+        return Err(Error::default()
+            .wrap(Oops::PickerError)
+            .because("There are no chats to choose from".into()));
+    }
+    let labels: Vec<String> = entries
+        .iter()
+        .map(|entry| {
+            let mut label =
+                entry.last_message.clone().unwrap_or("(empty chat)".into());
+            if !entry.tags.is_empty() {
+                label.push_str(&format!(" [{}]", entry.tags.join(", ")));
+            }
+            format!("{label} :: {}", entry.uuid)
+        })
+        .collect();
+    let Some(i) = picker::pick(&labels)? else {
+        return Ok(());
+    };
+    let uuid = entries[i].uuid;
+    if io::stdout().is_terminal() {
+        db::set_chat_id(&uuid)?;
+        eprintln!("Chat {uuid} is now active.");
+    } else {
+        println!("{uuid}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::openai::Role;
+
+    fn entry(message: &str) -> Entry {
+        Entry {
+            uuid: Uuid::nil(),
+            title: Some(message.into()),
+            last_message: Some(message.into()),
+            created: None,
+            modified: 0,
+            accessed: 0,
+            message_count: 2,
+            tags: vec![],
+            model: None,
+        }
+    }
+
+    #[test]
+    fn test_render_plain() {
+        let mut tagged = entry("héllo wörld");
+        tagged.uuid = Uuid::max();
+        tagged.tags = vec!["rust".into()];
+        tagged.model = Some(Model::Gpt4o);
+        tagged.message_count = 12;
+        tagged.modified = 3_600;
+        let entries = [entry("hi"), tagged];
+        let (nil, max) = (Uuid::nil(), Uuid::max());
+        assert_eq!(
+            render_plain(&entries, None, 7_200),
+            format!(
+                "{nil}  2h ago   2 msgs  -       hi\n\
+                {max}  1h ago  12 msgs  gpt-4o  [rust] héllo wörld\n"
+            )
+        );
+        // The columns before the title take 36 + 2 + 6 + 2 + 7 + 2 + 6 + 2
+        // = 63 characters.
+        let lines = render_plain(&entries, Some(73), 7_200);
+        assert!(lines.ends_with("[rust] ...\n"));
+        assert!(lines.lines().all(|l| l.chars().count() <= 73));
+    }
+
+    #[test]
+    fn test_filter() {
+        let tags = vec!["rust".to_string()];
+        let day = 86_400;
+        let filter = Filter::new(vec![], Some(10 * day), Some(20 * day), false);
+        // Active during the period.
+        assert!(filter.matches(&tags, 5 * day, 12 * day));
+        // Last active before the period.
+        assert!(!filter.matches(&tags, 5 * day, 9 * day));
+        // Began after the period.
+        assert!(!filter.matches(&tags, 20 * day, 21 * day));
+
+        let filter = Filter::new(vec!["go".into()], None, None, false);
+        assert!(!filter.matches(&tags, 0, 0));
+    }
+
+    #[test]
+    fn test_render_diff() {
+        let (a, b) = (Uuid::nil(), Uuid::max());
+        let shared = [
+            Message::new(Role::System, "be brief".into()),
+            Message::new(Role::User, "name a color".into()),
+        ];
+        let mut messages_a = shared.to_vec();
+        messages_a
+            .push(Message::new(Role::Assistant, "Red.\nIt's warm.".into()));
+        let mut messages_b = shared.to_vec();
+        messages_b.push(Message::new(Role::Assistant, "Blue.".into()));
+        messages_b.push(Message::new(Role::User, "why?".into()));
+
+        assert_eq!(shared_prefix(&messages_a, &messages_b), 2);
+        let diff = render_diff((&a, &messages_a), (&b, &messages_b), false);
+        assert_eq!(
+            diff,
+            format!(
+                "The chats share their first 2 messages, ending with;\n  \
+                [user] name a color\n\n\
+                {a} continues with 1 messages (~8 tokens)\n\
+                - [llm] Red.\n\n\
+                {b} continues with 2 messages (~11 tokens)\n\
+                + [llm] Blue.\n\
+                + [user] why?\n"
+            )
+        );
+    }
+}
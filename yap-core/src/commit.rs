@@ -0,0 +1,362 @@
+//! Write and check commit messages.
+//!
+//! `yap commit` prints a commit message for the staged changes. `yap commit
+//! --verify <file>` instead checks that the message in `<file>` describes the
+//! staged changes, and exits with a non-zero status if it does not, so it
+//! can be used as a `commit-msg` hook (see [crate::hook]). Changes which only
+//! touch whitespace are not sent to the LLM at all.
+//!
+//! With `--convention`, messages must also follow a [Convention]. Written
+//! messages which don't are sent back to the LLM with the problem, up to
+//! [ATTEMPTS] times in all; `--verify` fails without asking the LLM.
+
+use crate::{
+    config::ConfigFile,
+    constants,
+    diff::{self, git_diff},
+    err::{Error, Oops},
+    filter,
+    openai::{
+        chat, CompletionPayload, Content, Message, OpenAI, PayloadOpts,
+        ResponseFormat, Role,
+    },
+};
+use log::debug;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{fs, path::Path};
+
+/// How many messages `yap commit --convention` writes before giving up.
+const ATTEMPTS: usize = 3;
+
+/// A format which commit messages must follow.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum Convention {
+    /// <https://www.conventionalcommits.org>; i.e, `fix(db): close files`.
+    Conventional,
+    /// <https://gitmoji.dev>; i.e, `🐛 Close files`, or `:bug: Close files`.
+    Gitmoji,
+    /// The subject line must match the regex given with `--template`.
+    CustomTemplate,
+}
+
+/// A [Convention], ready to check messages against.
+pub struct Rule {
+    convention: Convention,
+    subject: Regex,
+}
+
+impl Rule {
+    /// `template` is required for, and only used by,
+    /// [Convention::CustomTemplate].
+    pub fn new(
+        convention: Convention,
+        template: Option<&str>,
+    ) -> Result<Self, Error> {
+        let pattern = match convention {
+            Convention::Conventional => {
+                r"^(feat|fix|docs|style|refactor|perf|test|build|ci|chore|revert)(\([\w\-./]+\))?!?: \S"
+            }
+            Convention::Gitmoji => {
+                r"^(:[a-z0-9_+\-]+:|\p{Extended_Pictographic}\x{FE0F}?) \S"
+            }
+            Convention::CustomTemplate => template.ok_or_else(|| {
+                Error::default().wrap(Oops::CommitError).because(
+                    "--convention custom-template requires --template".into(),
+                )
+            })?,
+        };
+        let subject = Regex::new(pattern).map_err(|e| {
+            Error::default()
+                .wrap(Oops::CommitError)
+                .because(format!("Invalid template {pattern:?}: {e}"))
+        })?;
+        Ok(Self {
+            convention,
+            subject,
+        })
+    }
+
+    /// Added to the system prompt, so that the first message usually
+    /// follows the convention.
+    fn instructions(&self) -> String {
+        match self.convention {
+            Convention::Conventional => "The subject line must follow the Conventional Commits specification: `type(scope): description`, where type is one of feat, fix, docs, style, refactor, perf, test, build, ci, chore, or revert. The scope is optional, and `!` after the type or scope marks a breaking change.".into(),
+            Convention::Gitmoji => "The subject line must follow gitmoji: it begins with the one emoji from https://gitmoji.dev which best fits the change, followed by a space and the description.".into(),
+            Convention::CustomTemplate => format!(
+                "The subject line must match this regular expression: {}",
+                self.subject.as_str()
+            ),
+        }
+    }
+
+    /// What is wrong with `message`, if it does not follow the convention.
+    fn check(&self, message: &str) -> Result<(), String> {
+        let subject = message.lines().next().unwrap_or_default();
+        if !self.subject.is_match(subject) {
+            let convention = match self.convention {
+                Convention::Conventional => "Conventional Commits",
+                Convention::Gitmoji => "gitmoji",
+                Convention::CustomTemplate => "template",
+            };
+            return Err(format!(
+                "the subject line {subject:?} does not follow {convention}"
+            ));
+        }
+        if let Some(blank) = message.lines().nth(1) {
+            if !blank.trim().is_empty() {
+                return Err(
+                    "the subject line must be followed by a blank line".into(),
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+fn get_json_schema() -> Value {
+    json!({
+      "name": "commit_message_verdict",
+      "schema": {
+        "type": "object",
+        "properties": {
+          "ok": {
+            "type": "boolean",
+            "description": "Whether the commit message accurately describes the change."
+          },
+          "reason": {
+            "type": "string",
+            "description": "If the message is not ok, what is wrong with it."
+          }
+        },
+        "required": ["ok", "reason"],
+        "additionalProperties": false
+      },
+      "strict": true
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct Verdict {
+    ok: bool,
+    reason: String,
+}
+
+/// Entrypoint for `yap commit`. If `verify` is set, the commit message in
+/// that file is checked instead of writing a new one. Messages must follow
+/// `rule`, if it is given.
+pub fn commit(
+    open_ai: &OpenAI,
+    verify: Option<&Path>,
+    rule: Option<&Rule>,
+) -> Result<(), Error> {
+    let diff_text = git_diff(&["--cached"]).map_err(|e| {
+        e.wrap(Oops::CommitError)
+            .because("Could not get the staged changes".into())
+    })?;
+    let file_diffs = diff::parse(&diff_text).map_err(|e| {
+        e.wrap(Oops::CommitError)
+            .because("Could not parse the staged changes".into())
+    })?;
+    let trivial = diff::is_trivial(&file_diffs);
+    match verify {
+        Some(_) if trivial => {
+            eprintln!("Skipping verification; the change is empty or whitespace-only.");
+            Ok(())
+        }
+        Some(path) => verify_message(open_ai, &diff_text, path, rule),
+        None if diff_text.trim().is_empty() => {
+            Err(Error::default().wrap(Oops::CommitError).because(
+                "Nothing is staged; stage changes with `git add` first".into(),
+            ))
+        }
+        None => {
+            let system_prompt = ConfigFile::CommitSystemPrompt
+                .load()
+                .map_err(|e| {
+                    e.wrap(Oops::CommitError).because(
+                        "Could not load the commit system prompt".into(),
+                    )
+                })?
+                .unwrap_or(constants::DEFAULT_COMMIT_PROMPT.into());
+            let message =
+                write_message(open_ai, system_prompt, diff_text, rule)?;
+            println!("{}", filter::apply("commit", &message)?.trim());
+            Ok(())
+        }
+    }
+}
+
+/// Ask the LLM for a commit message. If it does not follow `rule`, the
+/// problem is pointed out, and the LLM tries again.
+fn write_message(
+    open_ai: &OpenAI,
+    system_prompt: String,
+    diff_text: String,
+    rule: Option<&Rule>,
+) -> Result<String, Error> {
+    let system_prompt = match rule {
+        Some(rule) => {
+            format!("{}\n\n{}", system_prompt.trim_end(), rule.instructions())
+        }
+        None => system_prompt,
+    };
+    let mut messages = vec![
+        Message::new(Role::System, system_prompt),
+        Message::new(Role::User, diff_text),
+    ];
+    for attempt in 1..=ATTEMPTS {
+        let message = send(open_ai, messages.clone(), ResponseFormat::Text)?;
+        let Some(rule) = rule else {
+            return Ok(message);
+        };
+        match rule.check(message.trim()) {
+            Ok(()) => return Ok(message),
+            Err(problem) if attempt == ATTEMPTS => {
+                return Err(Error::default().wrap(Oops::CommitError).because(
+                    format!(
+                        "After {ATTEMPTS} attempts, {problem}:\n\n{}",
+                        message.trim()
+                    ),
+                ))
+            }
+            Err(problem) => {
+                debug!("Rewriting the commit message, since {problem}");
+                messages.push(Message::new(Role::Assistant, message));
+                messages.push(Message::new(
+                    Role::User,
+                    format!("That message is wrong; {problem}. Rewrite it."),
+                ));
+            }
+        }
+    }
+    unreachable!("the last attempt returns")
+}
+
+fn verify_message(
+    open_ai: &OpenAI,
+    diff_text: &str,
+    path: &Path,
+    rule: Option<&Rule>,
+) -> Result<(), Error> {
+    let raw = fs::read_to_string(path).map_err(|e| {
+        Error::default()
+            .wrap(Oops::CommitError)
+            .because(format!("Could not read the commit message {path:?}: {e}"))
+    })?;
+    let message = strip_comments(&raw);
+    if message.is_empty() {
+        // git aborts commits with empty messages on its own.
+        return Ok(());
+    }
+    if let Some(problem) = rule.and_then(|r| r.check(&message).err()) {
+        return Err(Error::default()
+            .wrap(Oops::CommitError)
+            .because(format!("The commit message is wrong; {problem}")));
+    }
+    let content = send(
+        open_ai,
+        vec![
+            Message::new(Role::System, constants::COMMIT_VERIFY_PROMPT.into()),
+            Message::new(Role::User, diff_text.into()),
+            Message::new(Role::User, format!("Commit message:\n\n{message}")),
+        ],
+        ResponseFormat::JsonSchema {
+            json_schema: get_json_schema(),
+        },
+    )?;
+    let verdict: Verdict = serde_json::from_str(&content).map_err(|e| {
+        debug!("Bad response content: {content}");
+        Error::default()
+            .wrap(Oops::CommitError)
+            .because(format!("Could not deserialize verdict: {e}"))
+    })?;
+    if verdict.ok {
+        Ok(())
+    } else {
+        Err(Error::default().wrap(Oops::CommitError).because(format!(
+            "The commit message does not describe the change: {}",
+            verdict.reason
+        )))
+    }
+}
+
+/// Remove the lines which git ignores from a commit message; comments, and
+/// everything below the scissors line of `git commit --verbose`.
+fn strip_comments(message: &str) -> String {
+    message
+        .lines()
+        .take_while(|l| !l.starts_with("# ------------------------ >8"))
+        .filter(|l| !l.starts_with('#'))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+fn send(
+    open_ai: &OpenAI,
+    messages: Vec<Message>,
+    response_format: ResponseFormat,
+) -> Result<String, Error> {
+    let payload = CompletionPayload::new(
+        open_ai,
+        messages,
+        PayloadOpts {
+            response_format,
+            ..Default::default()
+        },
+    );
+    let response = chat(open_ai, &payload).map_err(|e| {
+        e.wrap(Oops::CommitError)
+            .because("Error while sending the staged changes to OpenAI".into())
+    })?;
+    match response.choices[0].message.parse()? {
+        Content::Normal(c) => Ok(c.to_string()),
+        Content::Refusal(r) => Err(Error::default()
+            .wrap(Oops::OpenAIRefusal)
+            .wrap(Oops::CommitError)
+            .because(format!("OpenAI refused: {r}"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_comments() {
+        let message = "Fix the bug\n\nDetails.\n# Please enter the commit message\n# ------------------------ >8 ------------------------\ndiff --git a/x b/x\n";
+        assert_eq!(strip_comments(message), "Fix the bug\n\nDetails.");
+    }
+
+    #[test]
+    fn test_check_conventional() {
+        let rule = Rule::new(Convention::Conventional, None).unwrap();
+        assert!(rule.check("fix(db): close files").is_ok());
+        assert!(rule.check("feat!: drop v1\n\nDetails.").is_ok());
+        assert!(rule.check("Close files").is_err());
+        assert!(rule.check("fix: close files\nDetails.").is_err());
+    }
+
+    #[test]
+    fn test_check_gitmoji() {
+        let rule = Rule::new(Convention::Gitmoji, None).unwrap();
+        assert!(rule.check("🐛 Close files").is_ok());
+        assert!(rule.check("♻️ Split the parser").is_ok());
+        assert!(rule.check(":bug: Close files").is_ok());
+        assert!(rule.check("Close files 🐛").is_err());
+    }
+
+    #[test]
+    fn test_check_custom_template() {
+        assert!(Rule::new(Convention::CustomTemplate, None).is_err());
+        let rule =
+            Rule::new(Convention::CustomTemplate, Some(r"^\[[A-Z]+-\d+\] "))
+                .unwrap();
+        assert!(rule.check("[YAP-12] Close files").is_ok());
+        assert!(rule.check("Close files").is_err());
+    }
+}
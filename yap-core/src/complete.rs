@@ -0,0 +1,569 @@
+//! Write completion for prompts to `STDIN` to `STDOUT`.
+//!
+//! With `--batch`, `STDIN` is read as JSONL instead; see [complete_batch].
+
+use crate::{
+    cache,
+    config::{ConfigFile, Settings},
+    constants, ctx,
+    err::{Error, Oops},
+    filter, lang,
+    openai::{
+        chat_async, CompletionPayload, CompletionResponse, Content, Message,
+        OpenAI, PayloadOpts, Role,
+    },
+    pool, repomap,
+    serve::Daemon,
+};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::{
+    fs,
+    io::{self, IsTerminal, Read},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Options for `yap complete`; see `yap complete --help`.
+#[derive(Debug, Default)]
+pub struct Opts {
+    /// Always send the request, ignoring the response cache.
+    pub no_cache: bool,
+    /// Request this many candidate completions.
+    pub n: Option<u8>,
+    /// Print candidates as a JSON array of messages.
+    pub json: bool,
+    /// The language of the input, by name or extension (e.g, `rust` or
+    /// `rs`).
+    pub lang: Option<String>,
+    /// The file which the input comes from. If `lang` is not set, the
+    /// language is guessed from its extension.
+    pub filename: Option<PathBuf>,
+    /// A file containing the code which follows the input. The completion
+    /// fills in the middle; see [fill_in_middle].
+    pub suffix_file: Option<PathBuf>,
+    /// Attach the [crate::repomap] before the input.
+    pub repo_map: bool,
+    /// Whether to strip markdown fences, and any prose around them, from
+    /// completions; see [filter::strip_fences]. By default, fences are
+    /// stripped when the language is known.
+    pub strip_fences: Option<bool>,
+    /// A file containing the prompt. `STDIN`, if it isn't a terminal, is
+    /// sent before the prompt as context.
+    pub prompt_file: Option<PathBuf>,
+}
+
+/// Entrypoint for `yap complete`
+///
+/// Read into `STDIN`, and print completion to `STDOUT`. Load the system
+/// prompt from ~/.config/yap/complete_system_prompt.txt` if available,
+/// or else use the default prompt from
+/// [crate::constants::DEFAULT_COMPLETION_PROMPT]. Responses are cached (see
+/// [crate::cache]) unless `no_cache` is set.
+///
+/// If the input contains a [constants::CURSOR] marker, or `suffix_file` is
+/// given, the completion fills in the code at the marker; see
+/// [fill_in_middle].
+///
+/// If the input's language or filename is known, the system prompt mentions
+/// it, and completions are adjusted to suit the language; see [postprocess].
+/// Since the completion is then expected to be code, markdown fences are
+/// stripped unless `strip_fences` says otherwise. Finally, completions are
+/// run through the configured [crate::filter]s.
+///
+/// If `prompt_file` is given, its contents are completed instead, and
+/// `STDIN` is sent ahead of them as context, so that long prompts can be
+/// reused without a heredoc.
+///
+/// If `n` is more than 1, each candidate is printed beneath a delimiter, or
+/// all candidates are printed as a JSON array if `json` is set.
+pub fn complete(open_ai: &OpenAI, opts: &Opts) -> Result<(), Error> {
+    let input = read_input(opts)?;
+    let response = completions(open_ai, opts, input)?;
+    if opts.json {
+        let messages: Vec<&Message> =
+            response.choices.iter().map(|c| &c.message).collect();
+        let out = serde_json::to_string_pretty(&messages).map_err(|e| {
+            Error::default()
+                .wrap(Oops::CompletionError)
+                .because(format!("could not serialize candidates: {e}"))
+        })?;
+        println!("{out}");
+        return Ok(());
+    }
+    let candidates = response
+        .choices
+        .iter()
+        .map(|c| c.message.parse())
+        .collect::<Result<Vec<_>, Error>>()?;
+    print_candidates(&candidates)
+}
+
+/// Like [complete], but the completion is requested from `yap daemon`.
+/// `opts.json` is not supported.
+pub fn forward(daemon: &mut Daemon, opts: &Opts) -> Result<(), Error> {
+    let input = read_input(opts)?;
+    let response: Completions = daemon.call(
+        "complete",
+        json!({
+            "prompt": input,
+            "n": opts.n,
+            "no_cache": opts.no_cache,
+            "lang": opts.lang,
+            "filename": opts.filename,
+            "suffix_file": opts.suffix_file,
+            "repo_map": opts.repo_map,
+            "strip_fences": opts.strip_fences,
+            "prompt_file": opts.prompt_file,
+        }),
+    )?;
+    let candidates: Vec<Content> = response
+        .completions
+        .iter()
+        .map(|c| Content::Normal(c))
+        .chain(response.refusals.iter().map(|r| Content::Refusal(r)))
+        .collect();
+    print_candidates(&candidates)
+}
+
+/// The result of the daemon's `complete` method.
+#[derive(Deserialize)]
+struct Completions {
+    completions: Vec<String>,
+    #[serde(default)]
+    refusals: Vec<String>,
+}
+
+/// `STDIN`, which is optional with a prompt file.
+fn read_input(opts: &Opts) -> Result<String, Error> {
+    let mut input = String::new();
+    if opts.prompt_file.is_none() || !io::stdin().is_terminal() {
+        io::stdin().read_to_string(&mut input).map_err(|e| {
+            Error::default()
+                .wrap(Oops::CompletionError)
+                .wrap(Oops::StdinReadError)
+                .because(e.kind().to_string())
+        })?;
+    }
+    Ok(input)
+}
+
+/// The candidate completions of `input`, adjusted and filtered as described
+/// for [complete]. Used by [crate::serve], too.
+pub fn completions(
+    open_ai: &OpenAI,
+    opts: &Opts,
+    input: String,
+) -> Result<CompletionResponse, Error> {
+    let suffix = match &opts.suffix_file {
+        Some(path) => Some(fs::read_to_string(path).map_err(|e| {
+            Error::default()
+                .wrap(Oops::CompletionError)
+                .because(format!("Could not read suffix file {path:?}: {e}"))
+        })?),
+        None => None,
+    };
+    let (input, fill) = fill_in_middle(input, suffix)?;
+
+    let language = opts
+        .lang
+        .as_deref()
+        .and_then(lang::by_name)
+        .or_else(|| opts.filename.as_deref().and_then(lang::by_path));
+    let mut system_prompt = load_system_prompt()?;
+    if let Some(hint) = describe_input(
+        language.map(|l| l.name).or(opts.lang.as_deref()),
+        opts.filename.as_deref(),
+    ) {
+        system_prompt.push_str("\n\n");
+        system_prompt.push_str(&hint);
+    }
+    if fill {
+        system_prompt.push_str("\n\n");
+        system_prompt.push_str(constants::FILL_IN_MIDDLE_INSTRUCTIONS);
+    }
+    let use_cache = !opts.no_cache;
+    let mut context = ctx::messages()?;
+    if opts.repo_map {
+        context.insert(0, repomap::message()?);
+    }
+    let input =
+        match &opts.prompt_file {
+            Some(path) => {
+                if !input.trim().is_empty() {
+                    context.push(Message::new(Role::User, input));
+                }
+                fs::read_to_string(path).map_err(|e| {
+                    Error::default().wrap(Oops::CompletionError).because(
+                        format!("Could not read prompt file {path:?}: {e}"),
+                    )
+                })?
+            }
+            None => input,
+        };
+    let mut response = pool::block_on(send(
+        open_ai,
+        &system_prompt,
+        &context,
+        input,
+        opts.n,
+        use_cache,
+    ))?;
+    let strip = opts
+        .strip_fences
+        .unwrap_or(language.is_some() || opts.lang.is_some());
+    for choice in response.choices.iter_mut() {
+        if let Some(content) = &choice.message.content {
+            let content = postprocess(language, content, strip);
+            choice.message.content = Some(filter::apply("complete", &content)?);
+        }
+    }
+    Ok(response)
+}
+
+/// Print each candidate, beneath a delimiter if there are several.
+/// Refusals are printed to `STDERR`, and are an error if every candidate
+/// was refused.
+fn print_candidates(candidates: &[Content]) -> Result<(), Error> {
+    let count = candidates.len();
+    let mut refusals = 0;
+    for (i, candidate) in candidates.iter().enumerate() {
+        if count > 1 {
+            println!("{}", candidate_delimiter(i, count));
+        }
+        match candidate {
+            Content::Normal(c) => println!("{}", c),
+            Content::Refusal(r) => {
+                eprintln!("{}", r);
+                refusals += 1;
+            }
+        };
+    }
+    if refusals == count {
+        return Err(Error::default()
+            .wrap(Oops::OpenAIRefusal)
+            .wrap(Oops::CompletionError)
+            .because("The model refused to complete the input".into()));
+    }
+    Ok(())
+}
+
+/// Prepare `input` for a fill-in-the-middle completion. If `suffix` is given,
+/// it is joined to `input` with a [constants::CURSOR] marker. Otherwise,
+/// `input` may already contain one marker. Returns the input, and whether it
+/// has a marker.
+fn fill_in_middle(
+    input: String,
+    suffix: Option<String>,
+) -> Result<(String, bool), Error> {
+    let markers = input.matches(constants::CURSOR).count();
+    match (suffix, markers) {
+        (Some(_), 1..) => Err(Error::default().wrap(Oops::CompletionError).because(
+            format!("The input contains {} while a suffix file is given; use one or the other", constants::CURSOR)
+        )),
+        (Some(suffix), 0) => {
+            Ok((format!("{input}{}{suffix}", constants::CURSOR), true))
+        }
+        (None, 0) => Ok((input, false)),
+        (None, 1) => Ok((input, true)),
+        (None, _) => Err(Error::default()
+            .wrap(Oops::CompletionError)
+            .because(format!(
+                "The input contains {markers} {} markers, but only one is allowed",
+                constants::CURSOR
+            ))),
+    }
+}
+
+/// A note for the system prompt about where the input comes from.
+fn describe_input(
+    language: Option<&str>,
+    filename: Option<&Path>,
+) -> Option<String> {
+    match (language, filename) {
+        (Some(language), Some(filename)) => Some(format!(
+            "The input is {language} code from the file `{}`. Continue it in {language}.",
+            filename.display()
+        )),
+        (Some(language), None) => Some(format!(
+            "The input is {language} code. Continue it in {language}."
+        )),
+        (None, Some(filename)) => Some(format!(
+            "The input comes from the file `{}`.",
+            filename.display()
+        )),
+        (None, None) => None,
+    }
+}
+
+/// Adjust a completion to suit `language`. Models tend to indent with
+/// spaces, so code in [lang::Language::tab_indented] languages is
+/// re-indented with tabs, which Makefiles need and `gofmt` expects. If
+/// `strip` is set, fences are removed first; see [filter::strip_fences].
+fn postprocess(
+    language: Option<&lang::Language>,
+    text: &str,
+    strip: bool,
+) -> String {
+    // Fences are part of the code in markdown.
+    let text = if strip && language.map(|l| l.name) != Some("Markdown") {
+        filter::strip_fences(text)
+    } else {
+        text.to_string()
+    };
+    match language {
+        Some(language) if language.tab_indented => text
+            .split('\n')
+            .map(|line| {
+                let spaces = line.len() - line.trim_start_matches(' ').len();
+                format!(
+                    "{}{}{}",
+                    "\t".repeat(spaces / 4),
+                    " ".repeat(spaces % 4),
+                    &line[spaces..]
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => text,
+    }
+}
+
+pub fn load_system_prompt() -> Result<String, Error> {
+    Ok(ConfigFile::CompleteSystemPrompt
+        .load()
+        .map_err(|e| {
+            e.wrap(Oops::CompletionError)
+                .because("could not get system prompt for completion".into())
+        })?
+        .unwrap_or(constants::DEFAULT_COMPLETION_PROMPT.into()))
+}
+
+/// The payload for completing `input`. `context` is sent between the system
+/// prompt and `input`; i.e, from [crate::ctx::messages].
+pub fn payload(
+    open_ai: &OpenAI,
+    system_prompt: &str,
+    context: &[Message],
+    input: String,
+    n: Option<u8>,
+) -> CompletionPayload {
+    let mut messages = vec![Message::new(Role::System, system_prompt.into())];
+    messages.extend_from_slice(context);
+    messages.push(Message::new(Role::User, input));
+    CompletionPayload::new(
+        open_ai,
+        messages,
+        PayloadOpts {
+            n,
+            ..Default::default()
+        },
+    )
+}
+
+/// Send one prompt, or get its response from [crate::cache].
+pub async fn send(
+    open_ai: &OpenAI,
+    system_prompt: &str,
+    context: &[Message],
+    input: String,
+    n: Option<u8>,
+    use_cache: bool,
+) -> Result<CompletionResponse, Error> {
+    let payload = payload(open_ai, system_prompt, context, input, n);
+    let ttl = Duration::from_secs(Settings::load()?.cache_ttl);
+    // A dry run always shows the request, even if it was cached, mock
+    // responses are never mixed up with real ones, and every response is
+    // recorded.
+    let use_cache = use_cache
+        && !ttl.is_zero()
+        && !open_ai.dry_run
+        && !open_ai.is_mock()
+        && !open_ai.is_recording();
+    if use_cache {
+        if let Some(response) = cache::get(&payload, ttl)? {
+            return Ok(response);
+        }
+    }
+    let response = chat_async(open_ai, &payload).await?;
+    if use_cache {
+        cache::put(&payload, &response)?;
+    }
+    Ok(response)
+}
+
+/// Entrypoint for `yap complete --batch`.
+///
+/// Each line of `STDIN` is a prompt; either a JSON string, or a JSON object
+/// with a `prompt` field. Prompts are completed concurrently (see
+/// [crate::pool]), and one JSON object is printed per input line, in input
+/// order. For objects, the output is the input object (so fields like `id`
+/// are passed through) plus a `completion` field, or `completions` if `n` is
+/// more than 1. Prompts which fail get an `error` field instead, and don't
+/// stop the rest of the batch.
+pub fn complete_batch(
+    open_ai: &OpenAI,
+    no_cache: bool,
+    n: Option<u8>,
+) -> Result<(), Error> {
+    let mut input = String::new();
+    io::stdin().read_to_string(&mut input).map_err(|e| {
+        Error::default()
+            .wrap(Oops::CompletionError)
+            .wrap(Oops::StdinReadError)
+            .because(e.kind().to_string())
+    })?;
+    let system_prompt = load_system_prompt()?;
+    let lines: Vec<&str> =
+        input.lines().filter(|l| !l.trim().is_empty()).collect();
+    let system_prompt = &system_prompt;
+    let results = pool::block_on(pool::map(
+        &lines,
+        open_ai.max_concurrency,
+        |line| async move {
+            let (mut record, prompt) = match parse_batch_line(line) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    let mut record = Map::new();
+                    record.insert(
+                        "input".into(),
+                        Value::String(line.to_string()),
+                    );
+                    record.insert("error".into(), Value::String(e.summary()));
+                    return Value::Object(record);
+                }
+            };
+            let response =
+                send(open_ai, system_prompt, &[], prompt, n, !no_cache).await;
+            add_result(&mut record, response, n);
+            Value::Object(record)
+        },
+    ));
+    for result in results {
+        println!("{result}");
+    }
+    Ok(())
+}
+
+/// Add the `completion`, `completions`, or `error` field to a batch output
+/// record.
+pub fn add_result(
+    record: &mut Map<String, Value>,
+    response: Result<CompletionResponse, Error>,
+    n: Option<u8>,
+) {
+    match response {
+        Ok(response) => {
+            let texts: Vec<Value> = response
+                .choices
+                .iter()
+                .map(|c| json!(c.message.content))
+                .collect();
+            if n.unwrap_or(1) > 1 {
+                record.insert("completions".into(), Value::Array(texts));
+            } else {
+                record.insert(
+                    "completion".into(),
+                    texts.into_iter().next().unwrap_or(Value::Null),
+                );
+            }
+        }
+        Err(e) => {
+            record.insert("error".into(), Value::String(e.summary()));
+        }
+    }
+}
+
+/// Parse one line of batch input into the output record and the prompt.
+pub fn parse_batch_line(
+    line: &str,
+) -> Result<(Map<String, Value>, String), Error> {
+    let bad_line = |why: &str| {
+        Error::default()
+            .wrap(Oops::CompletionError)
+            .because(format!("invalid batch line: {why}"))
+    };
+    match serde_json::from_str(line).map_err(|e| bad_line(&e.to_string()))? {
+        Value::String(prompt) => {
+            let mut record = Map::new();
+            record.insert("prompt".into(), Value::String(prompt.clone()));
+            Ok((record, prompt))
+        }
+        Value::Object(record) => match record.get("prompt") {
+            Some(Value::String(prompt)) => {
+                let prompt = prompt.clone();
+                Ok((record, prompt))
+            }
+            _ => Err(bad_line("objects need a string `prompt` field")),
+        },
+        _ => Err(bad_line("expected a JSON string or object")),
+    }
+}
+
+/// A line which separates candidates when more than one is requested.
+pub fn candidate_delimiter(index: usize, count: usize) -> String {
+    format!("===== candidate {} of {count} =====", index + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fill_in_middle() {
+        let (input, fill) =
+            fill_in_middle("a(".into(), Some(")".into())).unwrap();
+        assert_eq!(input, "a(<CURSOR>)");
+        assert!(fill);
+        assert!(fill_in_middle("a(<CURSOR>)".into(), None).unwrap().1);
+        assert!(!fill_in_middle("a(".into(), None).unwrap().1);
+        assert!(fill_in_middle("<CURSOR>".into(), Some("".into())).is_err());
+        assert!(fill_in_middle("<CURSOR><CURSOR>".into(), None).is_err());
+    }
+
+    #[test]
+    fn test_describe_input() {
+        assert_eq!(
+            describe_input(Some("Rust"), None).unwrap(),
+            "The input is Rust code. Continue it in Rust."
+        );
+        assert!(describe_input(None, Some(Path::new("a.xyz")))
+            .unwrap()
+            .contains("`a.xyz`"));
+        assert!(describe_input(None, None).is_none());
+    }
+
+    #[test]
+    fn test_postprocess() {
+        let go = lang::by_name("go");
+        assert_eq!(
+            postprocess(go, "if x {\n        y()\n      z\n}", false),
+            "if x {\n\t\ty()\n\t  z\n}"
+        );
+        let rust = lang::by_name("rust");
+        assert_eq!(postprocess(rust, "    x", true), "    x");
+        let fenced = "Sure!\n```md\n# Title\n```";
+        assert_eq!(postprocess(rust, fenced, true), "# Title");
+        let markdown = lang::by_name("markdown");
+        assert_eq!(postprocess(markdown, fenced, true), fenced);
+    }
+
+    #[test]
+    fn test_parse_batch_line() {
+        let (record, prompt) = parse_batch_line(r#""hello""#).unwrap();
+        assert_eq!(prompt, "hello");
+        assert_eq!(Value::Object(record), json!({"prompt": "hello"}));
+
+        let (record, prompt) =
+            parse_batch_line(r#"{"id": 7, "prompt": "hi"}"#).unwrap();
+        assert_eq!(prompt, "hi");
+        assert_eq!(record["id"], 7);
+
+        assert!(parse_batch_line(r#"{"id": 7}"#)
+            .unwrap_err()
+            .has(&Oops::CompletionError));
+        assert!(parse_batch_line("not json")
+            .unwrap_err()
+            .has(&Oops::CompletionError));
+    }
+}
@@ -0,0 +1,346 @@
+//! Yap configuration files are loaded from the `$XDG_CONFIG_HOME/yap`
+//! directory. To figure out exactly where this is on your system, try;
+//!
+//! ```bash
+//! echo "Put yap configs in this folder: $XDG_CONFIG_HOME/yap"
+//! ```
+//!
+//! Configuration files supported by `yap` are as follows;
+//!
+//! - `chat_system_prompt.txt`: specify the system prompt provided to the LLM at
+//!   the start of each chat. This prompt is used for any new chats.
+//! - `complete_system_prompt.txt`: specify the system prompt for `yap
+//!   complete`. This prompt is sent with every invocation of `yap complete`.
+//! - `annotate_system_prompt.txt`: specify the system prompt for `yap
+//!   annotate`. This prompt is sent with every invocation of `yap annotate`.
+//! - `refactor_system_prompt.txt`: specify the system prompt for `yap
+//!   refactor`.
+//! - `changelog_system_prompt.txt`: specify the system prompt for `yap
+//!   changelog`.
+//! - `review_system_prompt.txt`: specify the system prompt for `yap review`.
+//! - `commit_system_prompt.txt`: specify the system prompt which `yap commit`
+//!   uses to write commit messages.
+//! - `ask_system_prompt.txt`: specify the system prompt for `yap ask`.
+//! - `config.json`: general settings, described by [Settings]. Every field is
+//!   optional. For example;
+//!
+//! ```json
+//! {
+//!   "context_strategy": "drop-oldest",
+//!   "cache_ttl": 3600,
+//!   "model": "gpt-4o-mini",
+//!   "command_models": {
+//!     "annotate": "gpt-4o"
+//!   },
+//!   "api_key_command": "pass show openai",
+//!   "max_concurrency": 8,
+//!   "headers": {
+//!     "Helicone-Auth": "Bearer sk-helicone-..."
+//!   }
+//! }
+//! ```
+
+use crate::{
+    confirm, context,
+    err::{Error, Oops},
+    filter, logfile,
+    openai::Model,
+    sandbox,
+};
+use log::debug;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    env::{self, VarError},
+    fs::{create_dir_all, read_to_string},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+/// Get the yap configuration directory. Recursively creates the directory
+/// via [create_dir_all] if it does not exist.
+///
+/// Returns errors if `$XDG_CONFIG_HOME` is missing or not unicode.
+pub fn get_or_create_yap_cfg_dir() -> Result<Box<PathBuf>, Error> {
+    let dir = env::var("XDG_CONFIG_HOME").map_err(|e| match e {
+        VarError::NotUnicode(_) => Error::default()
+            .wrap(Oops::XdgConfigError)
+            .because("$XDG_CONFIG_HOME is not a unicode string".into()),
+        VarError::NotPresent => Error::default()
+            .wrap(Oops::XdgConfigError)
+            .because("$XDG_CONFIG_HOME is not defined.".into()),
+    })?;
+    let dir = PathBuf::from(dir).join("yap");
+    if dir.exists() {
+        Ok(Box::new(dir))
+    } else {
+        create_dir_all(&dir).map_err(|e| {
+            Error::default().wrap(Oops::XdgConfigError).because(format!(
+                "OS error while creating {}/yap: {:?}",
+                dir.to_string_lossy(),
+                e
+            ))
+        })?;
+        Ok(Box::new(dir))
+    }
+}
+
+#[allow(clippy::enum_variant_names)]
+pub enum ConfigFile {
+    CompleteSystemPrompt,
+    ChatSystemPrompt,
+    AnnotateSystemPrompt,
+    RefactorSystemPrompt,
+    ChangelogSystemPrompt,
+    ReviewSystemPrompt,
+    CommitSystemPrompt,
+    AskSystemPrompt,
+    Settings,
+}
+
+impl ConfigFile {
+    fn filename(&self) -> &'static str {
+        match self {
+            Self::ChatSystemPrompt => "chat_system_prompt.txt",
+            Self::CompleteSystemPrompt => "complete_system_prompt.txt",
+            Self::AnnotateSystemPrompt => "annotate_system_prompt.txt",
+            Self::RefactorSystemPrompt => "refactor_system_prompt.txt",
+            Self::ChangelogSystemPrompt => "changelog_system_prompt.txt",
+            Self::ReviewSystemPrompt => "review_system_prompt.txt",
+            Self::CommitSystemPrompt => "commit_system_prompt.txt",
+            Self::AskSystemPrompt => "ask_system_prompt.txt",
+            Self::Settings => "config.json",
+        }
+    }
+    pub fn load(&self) -> Result<Option<String>, Error> {
+        let dir = get_or_create_yap_cfg_dir().map_err(|e| {
+            e.wrap(Oops::XdgConfigError).because(
+                "Error while getting system prompt for completion".into(),
+            )
+        })?;
+        let prompt_path = dir.join(self.filename());
+        if !prompt_path.exists() {
+            debug!("config file {} does not exist", self.filename());
+            return Ok(None);
+        }
+        let prompt = read_to_string(&prompt_path).map_err(|e| {
+            Error::default().wrap(Oops::XdgConfigError).because(format!(
+                "Could not read_to_string({}) due to an OS error: {:?}",
+                prompt_path.to_string_lossy(),
+                e
+            ))
+        })?;
+
+        debug!("Loaded config file {}", self.filename());
+
+        Ok(Some(prompt))
+    }
+}
+
+/// Settings from `config.json`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// What to do when a chat outgrows the model's context window; one of
+    /// `summarize` (the default), `drop-oldest`, or `error`. See
+    /// [context::Strategy].
+    pub context_strategy: context::Strategy,
+    /// How long, in seconds, `yap complete` responses are cached. Set to `0`
+    /// to disable caching. See [crate::cache].
+    pub cache_ttl: u64,
+    /// The model to use when `yap --model` is not passed.
+    pub model: Option<Model>,
+    /// Per-subcommand models, keyed by subcommand name (e.g. `annotate`),
+    /// which take precedence over `model`.
+    pub command_models: HashMap<String, Model>,
+    /// A file containing the OpenAI API key. Like the options below, this is
+    /// only used if `$OPENAI_API_KEY` is unset.
+    pub api_key_file: Option<PathBuf>,
+    /// A shell command which prints the OpenAI API key.
+    pub api_key_command: Option<String>,
+    /// The name of an OS keychain service which holds the OpenAI API key.
+    /// The key is read with `secret-tool` on Linux, or `security` on macOS.
+    pub api_key_keychain: Option<String>,
+    /// The maximum number of requests which `yap` sends at once, e.g. when
+    /// annotating a large file in chunks, or many files. See [crate::pool].
+    pub max_concurrency: usize,
+    /// A file containing a passphrase, which enables encryption of chat
+    /// files. `$YAP_PASSPHRASE` takes precedence. See [crate::crypt].
+    pub encryption_key_file: Option<PathBuf>,
+    /// Between `-2.0` and `2.0`. Positive values discourage the model from
+    /// repeating tokens in proportion to how often they have appeared so far.
+    /// Not sent to reasoning models, which don't support it.
+    pub frequency_penalty: Option<f32>,
+    /// Between `-2.0` and `2.0`. Positive values discourage the model from
+    /// repeating any token which has appeared at all. Not sent to reasoning
+    /// models.
+    pub presence_penalty: Option<f32>,
+    /// A JSON file mapping token IDs to a bias between `-100` and `100`,
+    /// which is added to the token's logits; e.g. `{"50256": -100}`. Not sent
+    /// to reasoning models.
+    pub logit_bias_file: Option<PathBuf>,
+    /// Extra HTTP headers sent with every request to OpenAI, e.g. for an LLM
+    /// gateway or observability proxy (`{"Helicone-Auth": "Bearer ..."}`).
+    /// These are set after `Authorization`, so they may replace it.
+    pub headers: BTreeMap<String, String>,
+    /// How `yap annotate` writes comments in files with each extension (or
+    /// file name, like `Dockerfile`), overriding the built-in styles; e.g.
+    /// `{"njk": {"prefix": "{# ", "suffix": " #}"}}`.
+    pub comment_styles: HashMap<String, CommentStyle>,
+    /// Filters which post-process the output of each subcommand, keyed by
+    /// subcommand name; e.g. `{"complete": ["strip_fences", "trim"]}`. See
+    /// [crate::filter].
+    pub output_filters: HashMap<String, Vec<filter::Filter>>,
+    /// A shell command which receives each chat completion payload as JSON
+    /// on `STDIN` before it is sent, e.g. for logging or to enforce a policy.
+    /// If it fails, the request is not sent.
+    pub pre_request: Option<String>,
+    /// A shell command which receives each chat completion response as
+    /// JSON on `STDIN`; e.g. `notify-send yap done`.
+    pub post_response: Option<String>,
+    /// Restrictions for commands which the LLM runs, i.e, with `yap agent
+    /// --command`. See [crate::sandbox].
+    pub sandbox: sandbox::Sandbox,
+    /// Which operations need confirmation, and whether `--yes` may skip it.
+    /// See [crate::confirm].
+    pub confirm: confirm::Policy,
+    /// Also write logs to `~/.local/state/yap/logs/yap.log`. See
+    /// [crate::logfile].
+    pub log_file: logfile::LogFile,
+}
+
+/// The prefix and suffix of a one-line comment.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommentStyle {
+    pub prefix: String,
+    #[serde(default)]
+    pub suffix: String,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            context_strategy: context::Strategy::default(),
+            cache_ttl: 60 * 60 * 24,
+            model: None,
+            command_models: HashMap::new(),
+            api_key_file: None,
+            api_key_command: None,
+            api_key_keychain: None,
+            max_concurrency: 4,
+            encryption_key_file: None,
+            frequency_penalty: None,
+            presence_penalty: None,
+            logit_bias_file: None,
+            headers: BTreeMap::new(),
+            comment_styles: HashMap::new(),
+            output_filters: HashMap::new(),
+            pre_request: None,
+            post_response: None,
+            sandbox: sandbox::Sandbox::default(),
+            confirm: confirm::Policy::default(),
+            log_file: logfile::LogFile::default(),
+        }
+    }
+}
+
+/// Set by [Settings::keep_loaded].
+static LOADED: OnceLock<Settings> = OnceLock::new();
+
+impl Settings {
+    /// Load `config.json`, or the default settings if it does not exist.
+    /// After [Settings::keep_loaded], the file is not read again.
+    pub fn load() -> Result<Self, Error> {
+        if let Some(settings) = LOADED.get() {
+            return Ok(settings.clone());
+        }
+        let Some(text) = ConfigFile::Settings.load()? else {
+            return Ok(Self::default());
+        };
+        let settings: Self = serde_json::from_str(&text).map_err(|e| {
+            Error::default()
+                .wrap(Oops::XdgConfigError)
+                .because(format!("config.json is invalid: {e}"))
+        })?;
+        settings.validate()?;
+        Ok(settings)
+    }
+    /// Load `config.json` once, for the rest of the process; for long-lived
+    /// processes like `yap daemon`, which would otherwise read it for every
+    /// request. Changes to `config.json` take effect after a restart.
+    pub fn keep_loaded() -> Result<(), Error> {
+        let settings = Self::load()?;
+        let _ = LOADED.set(settings);
+        Ok(())
+    }
+    fn validate(&self) -> Result<(), Error> {
+        let penalties = [
+            ("frequency_penalty", self.frequency_penalty),
+            ("presence_penalty", self.presence_penalty),
+        ];
+        for (name, penalty) in penalties {
+            if penalty.is_some_and(|p| !(-2.0..=2.0).contains(&p)) {
+                return Err(Error::default()
+                    .wrap(Oops::XdgConfigError)
+                    .because(format!("{name} must be between -2.0 and 2.0")));
+            }
+        }
+        if self.sandbox.timeout == 0 {
+            return Err(Error::default()
+                .wrap(Oops::XdgConfigError)
+                .because("sandbox.timeout must be at least 1 second".into()));
+        }
+        Ok(())
+    }
+    /// The configured comment style for `path`, by its file name or its
+    /// extension. Extensions are matched without regard to case or a
+    /// leading dot.
+    pub fn comment_style(&self, path: &Path) -> Option<&CommentStyle> {
+        let name = path.file_name()?.to_str()?;
+        if let Some(style) = self.comment_styles.get(name) {
+            return Some(style);
+        }
+        let extension = path.extension()?.to_str()?;
+        self.comment_styles.iter().find_map(|(key, style)| {
+            key.trim_start_matches('.')
+                .eq_ignore_ascii_case(extension)
+                .then_some(style)
+        })
+    }
+    /// The configured model for `command`, if any.
+    pub fn model_for(&self, command: &str) -> Option<Model> {
+        self.command_models
+            .get(command)
+            .or(self.model.as_ref())
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_for() {
+        let settings: Settings = serde_json::from_str(
+            r#"{"model": "gpt4o-mini", "command_models": {"annotate": "gpt-4o"}}"#,
+        )
+        .unwrap();
+        assert!(matches!(settings.model_for("annotate"), Some(Model::Gpt4o)));
+        assert!(matches!(
+            settings.model_for("complete"),
+            Some(Model::Gpt4oMini)
+        ));
+        assert!(Settings::default().model_for("complete").is_none());
+    }
+
+    #[test]
+    fn test_validate() {
+        let settings = |json| serde_json::from_str::<Settings>(json).unwrap();
+        assert!(settings(r#"{"frequency_penalty": 0.5}"#).validate().is_ok());
+        assert!(settings(r#"{"presence_penalty": -2.5}"#)
+            .validate()
+            .is_err());
+    }
+}
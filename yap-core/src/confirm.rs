@@ -0,0 +1,146 @@
+//! Which operations `yap` asks about before doing them, configured with
+//! `confirm` in `config.json` (see [crate::config]); i.e,
+//!
+//! ```json
+//! {
+//!   "confirm": {
+//!     "file_writes": true,
+//!     "shell": true,
+//!     "patches": true,
+//!     "strict": true
+//!   }
+//! }
+//! ```
+//!
+//! - `file_writes`: changes by `yap refactor` and `yap undo`, edits by
+//!   `yap agent`, and config files added by `yap import`. On by default.
+//! - `shell`: commands run by `yap agent`. On by default.
+//! - `patches`: patches written by `yap apply` (except with `--interactive`,
+//!   where each hunk is reviewed anyway). Off by default.
+//! - `strict`: never confirm automatically; ask before every operation above,
+//!   regardless of the other settings, and ignore `--yes`. Operations fail
+//!   when `STDIN` is not a terminal. Off by default.
+//!
+//! Otherwise, `--yes` confirms every operation, for use in scripts.
+
+use crate::{
+    config::Settings,
+    err::{Error, Oops},
+    term,
+};
+use serde::Deserialize;
+use std::fmt::Display;
+
+/// The `confirm` section of `config.json`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    pub file_writes: bool,
+    pub shell: bool,
+    pub patches: bool,
+    pub strict: bool,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            file_writes: true,
+            shell: true,
+            patches: false,
+            strict: false,
+        }
+    }
+}
+
+/// A kind of operation which may need confirmation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    FileWrite,
+    Shell,
+    Patch,
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::FileWrite => write!(f, "file changes"),
+            Self::Shell => write!(f, "commands"),
+            Self::Patch => write!(f, "patches"),
+        }
+    }
+}
+
+/// What to do about an operation, before anybody is asked.
+#[derive(Debug, PartialEq, Eq)]
+enum Verdict {
+    Allow,
+    Ask,
+}
+
+impl Policy {
+    fn verdict(&self, operation: Operation, yes: bool) -> Verdict {
+        let required = match operation {
+            Operation::FileWrite => self.file_writes,
+            Operation::Shell => self.shell,
+            Operation::Patch => self.patches,
+        };
+        if self.strict || (required && !yes) {
+            Verdict::Ask
+        } else {
+            Verdict::Allow
+        }
+    }
+}
+
+/// Whether `operation` may go ahead. Depending on the [Policy] and `yes`
+/// (i.e, `--yes`), the user may be asked `question`. Fails if the user must
+/// be asked, but `STDIN` is not a terminal.
+pub fn confirm(
+    operation: Operation,
+    question: &str,
+    yes: bool,
+) -> Result<bool, Error> {
+    let policy = Settings::load()?.confirm;
+    if policy.verdict(operation, yes) == Verdict::Allow {
+        return Ok(true);
+    }
+    match term::ask(question)?.as_deref() {
+        Some("y" | "Y" | "yes") => Ok(true),
+        Some(_) => Ok(false),
+        None if policy.strict => Err(Error::default()
+            .wrap(Oops::ConfirmError)
+            .because(format!(
+                "STDIN is not a terminal, so {operation} cannot be confirmed. The confirmation policy is strict, so --yes is ignored."
+            ))),
+        None => Err(Error::default()
+            .wrap(Oops::ConfirmError)
+            .because(format!(
+                "STDIN is not a terminal, so {operation} cannot be confirmed. Pass --yes to allow them without confirmation."
+            ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verdict() {
+        let policy = Policy::default();
+        assert_eq!(policy.verdict(Operation::FileWrite, false), Verdict::Ask);
+        assert_eq!(policy.verdict(Operation::FileWrite, true), Verdict::Allow);
+        assert_eq!(policy.verdict(Operation::Patch, false), Verdict::Allow);
+        let strict = Policy {
+            strict: true,
+            ..Default::default()
+        };
+        assert_eq!(strict.verdict(Operation::Shell, true), Verdict::Ask);
+        assert_eq!(strict.verdict(Operation::Patch, true), Verdict::Ask);
+        let policy: Policy =
+            serde_json::from_str(r#"{"shell": false, "patches": true}"#)
+                .unwrap();
+        assert_eq!(policy.verdict(Operation::Shell, false), Verdict::Allow);
+        assert_eq!(policy.verdict(Operation::Patch, false), Verdict::Ask);
+        assert!(policy.file_writes);
+    }
+}
@@ -0,0 +1,316 @@
+//! Keep conversations within the model's context window.
+//!
+//! What happens when a chat outgrows the context window is determined by a
+//! [Strategy], which can be set via `context_strategy` in `config.json` (see
+//! [crate::config]) or `yap chat --context-strategy`. By default, once a chat
+//! grows beyond [COMPACT_AT] of the model's context window, the oldest
+//! messages are summarized into a single "summary so far" system message. The
+//! summary is persisted in the chat file via [crate::db::Summary], alongside
+//! the original messages, so `yap recap` still shows the whole conversation.
+//!
+//! Messages pinned with `yap pin` (see [crate::pin]) are always sent in full,
+//! right after the system prompt, even once they have been summarized or
+//! dropped.
+
+use crate::{
+    constants,
+    db::{Chat, Summary},
+    err::{Error, Oops},
+    openai::{self, CompletionPayload, Content, Message, PayloadOpts, Role},
+};
+use log::debug;
+use serde::Deserialize;
+
+/// Compact once the conversation exceeds this fraction of the context
+/// window.
+const COMPACT_AT: f64 = 0.75;
+
+/// After compaction, the messages which were not summarized should fit into
+/// this fraction of the context window.
+const COMPACT_TO: f64 = 0.5;
+
+/// What to do when a chat outgrows the model's context window.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    /// Summarize the oldest messages, and persist the summary.
+    #[default]
+    Summarize,
+    /// Leave the oldest messages out of the request. Nothing is deleted
+    /// from the chat history.
+    DropOldest,
+    /// Refuse to send the chat once it no longer fits in the context window.
+    Error,
+}
+
+/// Get the messages to send to the LLM for `chat`, applying `strategy` if
+/// the chat has outgrown the context window.
+pub fn prepare(
+    open_ai: &openai::OpenAI,
+    chat: &mut Chat,
+    strategy: Strategy,
+) -> Result<Vec<Message>, Error> {
+    let window = open_ai.model.context_window() as f64;
+    let messages = to_send(chat);
+    let size = estimate_tokens(&messages);
+    let limit = match strategy {
+        Strategy::Error => window,
+        Strategy::Summarize | Strategy::DropOldest => window * COMPACT_AT,
+    };
+    if (size as f64) <= limit {
+        return Ok(messages);
+    }
+    debug!("Chat is ~{size} tokens; applying {strategy:?}");
+
+    match strategy {
+        Strategy::Error => Err(Error::default()
+            .wrap(Oops::ContextWindowError)
+            .because(format!(
+                "This chat is ~{size} tokens, which exceeds the {window} token context window of {:?}. Start a new chat, or choose a different context strategy.",
+                open_ai.model
+            ))),
+        Strategy::DropOldest => Ok(drop_oldest(chat, window)),
+        Strategy::Summarize => {
+            compact(open_ai, chat, window)?;
+            Ok(to_send(chat))
+        }
+    }
+}
+
+/// Summarize older messages into [Chat::summary].
+fn compact(
+    open_ai: &openai::OpenAI,
+    chat: &mut Chat,
+    window: f64,
+) -> Result<(), Error> {
+    let start = chat
+        .summary
+        .as_ref()
+        .map_or(head(&chat.messages), |s| s.covers);
+    let covers = compact_until(&chat.messages, start, budget(chat, window));
+    if covers == start {
+        return Err(Error::default().wrap(Oops::ContextWindowError).because(
+            "The latest message alone is too large for the context window"
+                .into(),
+        ));
+    }
+    let content = summarize(
+        open_ai,
+        chat.summary.as_ref(),
+        &chat.messages[start..covers],
+    )
+    .map_err(|e| {
+        e.wrap(Oops::ContextWindowError)
+            .because("Failed to summarize older messages".into())
+    })?;
+    chat.summary = Some(Summary { content, covers });
+    Ok(())
+}
+
+/// The messages to send once the oldest have been left out to fit within
+/// `window`. Like [to_send], a summary from an earlier compaction stands in
+/// for the messages it covers, so only messages after it are dropped.
+fn drop_oldest(chat: &Chat, window: f64) -> Vec<Message> {
+    let start = chat
+        .summary
+        .as_ref()
+        .map_or(head(&chat.messages), |s| s.covers);
+    let summary = chat.summary.as_ref().map(|s| summary_message(&s.content));
+    let budget = budget(chat, window)
+        - summary.as_ref().map_or(0, Message::estimate_tokens) as f64;
+    let keep_from = compact_until(&chat.messages, start, budget);
+    let head = head(&chat.messages).min(start);
+    let mut messages = with_pinned(&chat.messages, head, keep_from);
+    messages.extend(summary);
+    messages.extend_from_slice(&chat.messages[keep_from..]);
+    messages
+}
+
+/// The number of leading system messages, which are never summarized.
+fn head(messages: &[Message]) -> usize {
+    messages
+        .iter()
+        .take_while(|m| matches!(m.role, Role::System))
+        .count()
+}
+
+/// The tokens left for unpinned messages after compaction, since pinned
+/// messages are always sent.
+fn budget(chat: &Chat, window: f64) -> f64 {
+    let pinned = chat
+        .messages
+        .iter()
+        .filter(|m| m.pinned)
+        .map(Message::estimate_tokens)
+        .sum::<usize>();
+    window * COMPACT_TO - pinned as f64
+}
+
+/// `messages[..head]`, followed by the pinned messages in
+/// `messages[head..omitted]`, which would otherwise be left out.
+fn with_pinned(
+    messages: &[Message],
+    head: usize,
+    omitted: usize,
+) -> Vec<Message> {
+    let mut kept = messages[..head].to_vec();
+    kept.extend(messages[head..omitted].iter().filter(|m| m.pinned).cloned());
+    kept
+}
+
+fn estimate_tokens(messages: &[Message]) -> usize {
+    messages.iter().map(Message::estimate_tokens).sum()
+}
+
+/// Starting from `start`, find the index up to which messages must be
+/// summarized so that the rest fit within `budget` tokens. The most recent
+/// message is never summarized.
+fn compact_until(messages: &[Message], start: usize, budget: f64) -> usize {
+    let mut covers = start;
+    let mut remaining = estimate_tokens(&messages[start..]);
+    while covers + 1 < messages.len() && remaining as f64 > budget {
+        remaining -= messages[covers].estimate_tokens();
+        covers += 1;
+    }
+    covers
+}
+
+/// The messages to send to the LLM; leading system messages, pinned messages
+/// which have been summarized, the summary (if any), and all messages which
+/// have not been summarized.
+pub(crate) fn to_send(chat: &Chat) -> Vec<Message> {
+    match &chat.summary {
+        None => chat.messages.clone(),
+        Some(summary) => {
+            let head = head(&chat.messages).min(summary.covers);
+            let mut messages =
+                with_pinned(&chat.messages, head, summary.covers);
+            messages.push(summary_message(&summary.content));
+            messages.extend_from_slice(&chat.messages[summary.covers..]);
+            messages
+        }
+    }
+}
+
+fn summary_message(content: &str) -> Message {
+    Message::new(
+        Role::System,
+        format!("Summary of the conversation so far:\n\n{content}"),
+    )
+}
+
+fn summarize(
+    open_ai: &openai::OpenAI,
+    previous: Option<&Summary>,
+    messages: &[Message],
+) -> Result<String, Error> {
+    let mut request = vec![Message::new(
+        Role::System,
+        constants::COMPACTION_PROMPT.into(),
+    )];
+    if let Some(previous) = previous {
+        request.push(summary_message(&previous.content));
+    }
+    let transcript = messages
+        .iter()
+        .filter_map(|m| {
+            m.content.as_ref().map(|c| format!("[{}]: {c}", m.role))
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    request.push(Message::new(Role::User, transcript));
+    let response = openai::chat(
+        open_ai,
+        &CompletionPayload::new(open_ai, request, PayloadOpts::default()),
+    )?;
+    match response.choices[0].message.parse()? {
+        Content::Normal(summary) => Ok(summary.to_string()),
+        Content::Refusal(r) => Err(Error::default()
+            .wrap(Oops::OpenAIRefusal)
+            .wrap(Oops::ContextWindowError)
+            .because(format!("OpenAI refused to summarize the chat: {r}"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chat() -> Chat {
+        Chat {
+            messages: vec![
+                Message::new(Role::System, "be nice".into()),
+                Message::new(Role::User, "a".repeat(400)),
+                Message::new(Role::Assistant, "b".repeat(400)),
+                Message::new(Role::User, "c".repeat(40)),
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_compact_until() {
+        let messages = chat().messages;
+        // Each long message is ~104 tokens, and the last is ~14 tokens.
+        assert_eq!(compact_until(&messages, 1, 150.0), 2);
+        assert_eq!(compact_until(&messages, 1, 20.0), 3);
+        assert_eq!(compact_until(&messages, 1, 1.0), 3);
+    }
+
+    #[test]
+    fn test_to_send_with_summary() {
+        let mut chat = chat();
+        chat.summary = Some(Summary {
+            content: "they said a and b".into(),
+            covers: 3,
+        });
+        let messages = to_send(&chat);
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0].content.as_deref(), Some("be nice"));
+        assert!(messages[1]
+            .content
+            .as_ref()
+            .unwrap()
+            .ends_with("they said a and b"));
+        assert_eq!(messages[2].content, chat.messages[3].content);
+    }
+
+    #[test]
+    fn test_to_send_keeps_pinned() {
+        let mut chat = chat();
+        chat.messages[1].pinned = true;
+        chat.summary = Some(Summary {
+            content: "they said a and b".into(),
+            covers: 3,
+        });
+        let messages = to_send(&chat);
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[1].content, chat.messages[1].content);
+        assert!(messages[2].content.as_ref().unwrap().starts_with("Summary"));
+    }
+
+    #[test]
+    fn test_drop_oldest_keeps_summary() {
+        let mut chat = chat();
+        chat.summary = Some(Summary {
+            content: "they said a".into(),
+            covers: 2,
+        });
+        // Only the messages after the summary are candidates for dropping.
+        let messages = drop_oldest(&chat, 100.0);
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0].content.as_deref(), Some("be nice"));
+        assert!(messages[1]
+            .content
+            .as_ref()
+            .unwrap()
+            .ends_with("they said a"));
+        assert_eq!(messages[2].content, chat.messages[3].content);
+
+        chat.summary = None;
+        let messages = drop_oldest(&chat, 100.0);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].content, chat.messages[3].content);
+    }
+}
@@ -0,0 +1,189 @@
+//! Optional at-rest encryption of chat files.
+//!
+//! Encryption is enabled by setting `$YAP_PASSPHRASE`, or by pointing
+//! `encryption_key_file` in `config.json` at a file holding a passphrase.
+//! Chats are then encrypted with ChaCha20-Poly1305 whenever they are saved,
+//! using a key derived from the passphrase with PBKDF2. Plaintext chats
+//! (i.e, from before encryption was enabled) can still be read, and are
+//! encrypted the next time they are saved.
+//!
+//! An encrypted file is [MAGIC], followed by the PBKDF2 salt, the nonce, and
+//! the ciphertext.
+
+use crate::{
+    config::Settings,
+    db,
+    err::{Error, Oops},
+};
+use ring::{
+    aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, NONCE_LEN},
+    pbkdf2,
+    rand::{SecureRandom, SystemRandom},
+};
+use std::{env, fs, num::NonZeroU32, sync::Mutex};
+
+const MAGIC: &[u8] = b"YAPENC1\n";
+const SALT_LEN: usize = 16;
+const ITERATIONS: NonZeroU32 = NonZeroU32::new(100_000).unwrap();
+
+/// Keys which have already been derived, by passphrase and salt, since key
+/// derivation is deliberately slow and `yap chatlog` reads every chat.
+type KeyCache = Vec<(Vec<u8>, [u8; SALT_LEN], LessSafeKey)>;
+static KEYS: Mutex<KeyCache> = Mutex::new(Vec::new());
+
+fn oops(why: String) -> Error {
+    Error::default().wrap(Oops::CryptError).because(why)
+}
+
+/// The passphrase from `$YAP_PASSPHRASE` or `encryption_key_file`, if
+/// encryption is enabled.
+fn passphrase() -> Result<Option<Vec<u8>>, Error> {
+    if let Ok(passphrase) = env::var("YAP_PASSPHRASE") {
+        return Ok(Some(passphrase.into_bytes()));
+    }
+    let Some(path) = Settings::load()?.encryption_key_file else {
+        return Ok(None);
+    };
+    let contents = fs::read_to_string(&path).map_err(|e| {
+        oops(format!("Could not read encryption_key_file {path:?}: {e}"))
+    })?;
+    let passphrase = contents.trim();
+    if passphrase.is_empty() {
+        return Err(oops(format!("encryption_key_file {path:?} is empty")));
+    }
+    Ok(Some(passphrase.as_bytes().to_vec()))
+}
+
+/// The salt for new files, which is generated once and kept in the state
+/// directory, so that the key is only derived once per process.
+fn salt() -> Result<[u8; SALT_LEN], Error> {
+    let path = db::get_or_create_persistence_dir()?.join("encryption_salt");
+    if let Ok(bytes) = fs::read(&path) {
+        if let Ok(salt) = bytes.try_into() {
+            return Ok(salt);
+        }
+    }
+    let mut salt = [0u8; SALT_LEN];
+    fill_random(&mut salt)?;
+    fs::write(&path, salt)
+        .map_err(|e| oops(format!("Could not write {path:?}: {e}")))?;
+    Ok(salt)
+}
+
+fn fill_random(buf: &mut [u8]) -> Result<(), Error> {
+    SystemRandom::new()
+        .fill(buf)
+        .map_err(|_| oops("Could not generate random bytes".into()))
+}
+
+/// Run `f` with the key for `passphrase` and `salt`.
+fn with_key<T>(
+    passphrase: &[u8],
+    salt: [u8; SALT_LEN],
+    f: impl FnOnce(&LessSafeKey) -> T,
+) -> T {
+    let mut keys = KEYS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((_, _, key)) =
+        keys.iter().find(|(p, s, _)| p == passphrase && *s == salt)
+    {
+        return f(key);
+    }
+    let mut key = [0u8; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        ITERATIONS,
+        &salt,
+        passphrase,
+        &mut key,
+    );
+    let key = LessSafeKey::new(
+        UnboundKey::new(&aead::CHACHA20_POLY1305, &key)
+            .expect("ChaCha20-Poly1305 keys are 32 bytes"),
+    );
+    let result = f(&key);
+    keys.push((passphrase.to_vec(), salt, key));
+    result
+}
+
+/// Encrypt `data` if encryption is enabled.
+pub fn seal(data: Vec<u8>) -> Result<Vec<u8>, Error> {
+    match passphrase()? {
+        Some(passphrase) => encrypt(&passphrase, salt()?, data),
+        None => Ok(data),
+    }
+}
+
+/// Decrypt `data` if it is encrypted.
+pub fn open(data: Vec<u8>) -> Result<Vec<u8>, Error> {
+    if !data.starts_with(MAGIC) {
+        return Ok(data);
+    }
+    let passphrase = passphrase()?.ok_or_else(|| {
+        oops("This chat is encrypted; set $YAP_PASSPHRASE or encryption_key_file in config.json".into())
+    })?;
+    decrypt(&passphrase, data)
+}
+
+fn encrypt(
+    passphrase: &[u8],
+    salt: [u8; SALT_LEN],
+    mut data: Vec<u8>,
+) -> Result<Vec<u8>, Error> {
+    let mut nonce = [0u8; NONCE_LEN];
+    fill_random(&mut nonce)?;
+    with_key(passphrase, salt, |key| {
+        key.seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(MAGIC),
+            &mut data,
+        )
+    })
+    .map_err(|_| oops("Encryption failed".into()))?;
+    Ok([MAGIC, &salt, &nonce, &data].concat())
+}
+
+fn decrypt(passphrase: &[u8], data: Vec<u8>) -> Result<Vec<u8>, Error> {
+    let header = MAGIC.len() + SALT_LEN + NONCE_LEN;
+    if data.len() < header {
+        return Err(oops("The encrypted file is truncated".into()));
+    }
+    let salt: [u8; SALT_LEN] = data[MAGIC.len()..MAGIC.len() + SALT_LEN]
+        .try_into()
+        .expect("the salt is SALT_LEN bytes");
+    let nonce: [u8; NONCE_LEN] = data[MAGIC.len() + SALT_LEN..header]
+        .try_into()
+        .expect("the nonce is NONCE_LEN bytes");
+    let mut ciphertext = data[header..].to_vec();
+    let len = with_key(passphrase, salt, |key| {
+        key.open_in_place(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(MAGIC),
+            &mut ciphertext,
+        )
+        .map(|plaintext| plaintext.len())
+    })
+    .map_err(|_| {
+        oops("Decryption failed; the passphrase may be wrong, or the file may be corrupt".into())
+    })?;
+    ciphertext.truncate(len);
+    Ok(ciphertext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let salt = [7; SALT_LEN];
+        let sealed =
+            encrypt(b"hunter2", salt, br#"{"messages":[]}"#.to_vec()).unwrap();
+        assert!(sealed.starts_with(MAGIC));
+        assert!(!sealed.windows(8).any(|w| w == b"messages"));
+        assert_eq!(
+            decrypt(b"hunter2", sealed.clone()).unwrap(),
+            br#"{"messages":[]}"#
+        );
+        assert!(decrypt(b"wrong", sealed).is_err());
+    }
+}
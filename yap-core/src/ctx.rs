@@ -0,0 +1,191 @@
+//! Maintain a persistent, per-project set of context files with `yap ctx`.
+//!
+//! Files added with `yap ctx add` are attached to every `yap chat` and `yap
+//! complete` request made from the same project; i.e, the same git
+//! repository, or the same directory outside of a git repository. The files
+//! are read when each request is sent, so the LLM always sees their current
+//! contents, and they are not saved into chat history. Directories are
+//! expanded with [files::expand].
+//!
+//! This is not to be confused with [crate::context], which decides how much
+//! of a chat's history is sent to the LLM.
+//!
+//! Each project's files are kept in `~/.local/state/yap/contexts`, keyed by
+//! [files::project_id].
+
+use crate::{
+    db,
+    err::{Error, Oops},
+    files,
+    openai::{Message, Role},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Context {
+    root: PathBuf,
+    /// Paths relative to `root`.
+    files: Vec<PathBuf>,
+}
+
+fn oops(why: String) -> Error {
+    Error::default().wrap(Oops::CtxError).because(why)
+}
+
+fn root() -> Result<PathBuf, Error> {
+    let root = files::project_root();
+    root.canonicalize()
+        .map_err(|e| oops(format!("Could not resolve {root:?}: {e}")))
+}
+
+fn path_for(root: &Path) -> Result<PathBuf, Error> {
+    let dir = db::get_or_create_persistence_dir()?.join("contexts");
+    fs::create_dir_all(&dir)
+        .map_err(|e| oops(format!("Could not create {dir:?}: {e}")))?;
+    Ok(dir.join(format!("{}.json", files::project_id(root))))
+}
+
+fn load() -> Result<Context, Error> {
+    let root = root()?;
+    let path = path_for(&root)?;
+    match fs::read_to_string(&path) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .map_err(|e| oops(format!("{path:?} is invalid: {e}"))),
+        Err(_) => Ok(Context {
+            root,
+            files: Vec::new(),
+        }),
+    }
+}
+
+fn save(context: &Context) -> Result<(), Error> {
+    let path = path_for(&context.root)?;
+    let contents = serde_json::to_string_pretty(context).map_err(|e| {
+        oops(format!("Could not serialize the context files: {e}"))
+    })?;
+    fs::write(&path, contents)
+        .map_err(|e| oops(format!("Could not write {path:?}: {e}")))
+}
+
+/// `path`, relative to `root`. `path` may be relative to the current
+/// directory, and need not exist, so that missing files can be removed.
+fn relative(root: &Path, path: &Path) -> Result<PathBuf, Error> {
+    let absolute = match path.canonicalize() {
+        Ok(absolute) => absolute,
+        Err(_) => env::current_dir()
+            .map_err(|e| {
+                oops(format!("Could not get the current directory: {e}"))
+            })?
+            .join(path),
+    };
+    absolute
+        .strip_prefix(root)
+        .map(Path::to_path_buf)
+        .map_err(|_| oops(format!("{path:?} is outside of {root:?}")))
+}
+
+/// Entrypoint for `yap ctx add`.
+pub fn add(paths: &[PathBuf]) -> Result<(), Error> {
+    let mut context = load()?;
+    for path in paths {
+        if !path.exists() {
+            return Err(oops(format!("{path:?} does not exist")));
+        }
+        let path = relative(&context.root, path)?;
+        if !context.files.contains(&path) {
+            context.files.push(path);
+        }
+    }
+    save(&context)
+}
+
+/// Entrypoint for `yap ctx remove`.
+pub fn remove(paths: &[PathBuf]) -> Result<(), Error> {
+    let mut context = load()?;
+    for path in paths {
+        let path = relative(&context.root, path)?;
+        if !context.files.contains(&path) {
+            return Err(oops(format!("{path:?} is not a context file")));
+        }
+        context.files.retain(|f| *f != path);
+    }
+    save(&context)
+}
+
+/// Entrypoint for `yap ctx list`.
+pub fn list() -> Result<(), Error> {
+    let context = load()?;
+    for file in &context.files {
+        if context.root.join(file).exists() {
+            println!("{}", file.display());
+        } else {
+            println!("{} (missing)", file.display());
+        }
+    }
+    Ok(())
+}
+
+/// Entrypoint for `yap ctx clear`.
+pub fn clear() -> Result<(), Error> {
+    let path = path_for(&root()?)?;
+    if path.exists() {
+        fs::remove_file(&path)
+            .map_err(|e| oops(format!("Could not remove {path:?}: {e}")))?;
+    }
+    Ok(())
+}
+
+/// One user message for each of the current project's context files. Files
+/// which no longer exist are skipped with a warning.
+pub fn messages() -> Result<Vec<Message>, Error> {
+    let context = load()?;
+    let mut paths = Vec::new();
+    for file in &context.files {
+        let path = context.root.join(file);
+        if path.exists() {
+            paths.push(path);
+        } else {
+            log::warn!("Context file {file:?} no longer exists");
+        }
+    }
+    let mut messages = Vec::new();
+    for path in files::expand(&paths)? {
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| oops(format!("Could not read {path:?}: {e}")))?;
+        let name = path.strip_prefix(&context.root).unwrap_or(&path);
+        messages.push(Message::new(
+            Role::User,
+            format!("File: {}\n```\n{contents}\n```", name.display()),
+        ));
+    }
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative() {
+        let root = std::env::temp_dir()
+            .join(format!("yap-test-ctx-{}", std::process::id()));
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/db.rs"), "").unwrap();
+        let root = root.canonicalize().unwrap();
+
+        assert_eq!(
+            relative(&root, &root.join("src/db.rs")).unwrap(),
+            PathBuf::from("src/db.rs")
+        );
+        assert_eq!(
+            relative(&root, &root.join("src/gone.rs")).unwrap(),
+            PathBuf::from("src/gone.rs")
+        );
+        assert!(relative(&root, Path::new("/elsewhere/db.rs")).is_err());
+        fs::remove_dir_all(root).unwrap();
+    }
+}
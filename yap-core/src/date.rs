@@ -0,0 +1,151 @@
+//! Formatting for Unix timestamps. Times are always shown in UTC.
+
+/// Format a Unix timestamp as a UTC `YYYY-MM-DD` date.
+pub fn date(timestamp: u64) -> String {
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = (timestamp / 86_400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Format a Unix timestamp as a UTC `YYYY-MM-DD HH:MM` date and time.
+pub fn datetime(timestamp: u64) -> String {
+    let minutes = timestamp % 86_400 / 60;
+    format!(
+        "{} {:02}:{:02}",
+        date(timestamp),
+        minutes / 60,
+        minutes % 60
+    )
+}
+
+/// Parse a `YYYY-MM-DD` date as the Unix timestamp of midnight UTC at the
+/// start of that day.
+pub fn parse(date: &str) -> Result<u64, String> {
+    let invalid = || format!("{date:?} is not a YYYY-MM-DD date");
+    let mut parts = date.trim().splitn(3, '-');
+    let mut next = || -> Result<i64, String> {
+        parts
+            .next()
+            .and_then(|p| p.parse().ok())
+            .ok_or_else(invalid)
+    };
+    let (year, month, day) = (next()?, next()?, next()?);
+    if !(1970..=9999).contains(&year)
+        || !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+    {
+        return Err(invalid());
+    }
+    // https://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    let timestamp = (days * 86_400) as u64;
+    // Catch days which don't exist, i.e, February 30th.
+    if self::date(timestamp) != format!("{year:04}-{month:02}-{day:02}") {
+        return Err(invalid());
+    }
+    Ok(timestamp)
+}
+
+/// The Unix timestamp of midnight at the start of the local day which
+/// `now` falls on. Where the local time zone is unknown, days start at
+/// midnight UTC.
+pub fn local_midnight(now: u64) -> u64 {
+    let offset = utc_offset(now);
+    let local = now as i64 + offset;
+    (local - local.rem_euclid(86_400) - offset).max(0) as u64
+}
+
+/// How many seconds the local time zone is ahead of UTC at `timestamp`.
+#[cfg(unix)]
+fn utc_offset(timestamp: u64) -> i64 {
+    let time = timestamp as libc::time_t;
+    let mut tm = std::mem::MaybeUninit::<libc::tm>::uninit();
+    // SAFETY: `localtime_r` initializes `tm` when it succeeds.
+    let tm = unsafe {
+        if libc::localtime_r(&time, tm.as_mut_ptr()).is_null() {
+            return 0;
+        }
+        tm.assume_init()
+    };
+    tm.tm_gmtoff as i64
+}
+
+#[cfg(not(unix))]
+fn utc_offset(_timestamp: u64) -> i64 {
+    0
+}
+
+/// How long before `now` the `timestamp` was, i.e, `2h ago`. Anything more
+/// than four weeks ago is shown as a [date] instead.
+pub fn ago(timestamp: u64, now: u64) -> String {
+    let secs = now.saturating_sub(timestamp);
+    match secs {
+        0..60 => "just now".into(),
+        60..3_600 => format!("{}m ago", secs / 60),
+        3_600..86_400 => format!("{}h ago", secs / 3_600),
+        86_400..604_800 => format!("{}d ago", secs / 86_400),
+        604_800..2_419_200 => format!("{}w ago", secs / 604_800),
+        _ => date(timestamp),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_date() {
+        assert_eq!(date(0), "1970-01-01");
+        assert_eq!(date(951_782_400), "2000-02-29");
+        assert_eq!(date(1_735_689_599), "2024-12-31");
+    }
+
+    #[test]
+    fn test_datetime() {
+        assert_eq!(datetime(0), "1970-01-01 00:00");
+        assert_eq!(datetime(1_735_689_599), "2024-12-31 23:59");
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("1970-01-01"), Ok(0));
+        assert_eq!(parse("2000-02-29"), Ok(951_782_400));
+        assert_eq!(date(parse("2024-12-31").unwrap()), "2024-12-31");
+        assert!(parse("2023-02-29").is_err());
+        assert!(parse("2024-13-01").is_err());
+        assert!(parse("yesterday").is_err());
+    }
+
+    #[test]
+    fn test_local_midnight() {
+        let now = 1_735_689_599;
+        let midnight = local_midnight(now);
+        assert!(midnight <= now && now - midnight < 86_400 + 3_600);
+        assert_eq!(local_midnight(midnight), midnight);
+    }
+
+    #[test]
+    fn test_ago() {
+        let now = 1_735_689_599;
+        assert_eq!(ago(now - 5, now), "just now");
+        assert_eq!(ago(now - 7_200, now), "2h ago");
+        assert_eq!(ago(now - 3 * 86_400, now), "3d ago");
+        assert_eq!(ago(now - 5 * 86_400 * 7, now), "2024-11-26");
+        // Clock skew.
+        assert_eq!(ago(now + 60, now), "just now");
+    }
+}
@@ -0,0 +1,568 @@
+//! `yap` persists data into `$HOME/.local/state/yap`
+
+use crate::{
+    crypt,
+    err::{Error, Oops},
+    files,
+    openai::{Message, Model, Role},
+};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::{self, create_dir_all, Metadata},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+pub fn get_or_create_persistence_dir() -> Result<PathBuf, Error> {
+    let dir = env::var("HOME")
+        .map_err(|e| match e {
+            env::VarError::NotPresent => Error::default()
+                .wrap(Oops::DbError)
+                .because("$HOME is not present in the environment".into()),
+            env::VarError::NotUnicode(_) => Error::default()
+                .wrap(Oops::DbError)
+                .because("$HOME is not a unicode string".into()),
+        })
+        .map(PathBuf::from)?
+        .join(".local")
+        .join("state")
+        .join("yap");
+    if !dir.exists() {
+        create_dir_all(&dir).map_err(|e| {
+            Error::default().wrap(Oops::DbError).because(format!(
+                "Failed to create ~/.local/state/yap directory: {e}"
+            ))
+        })?;
+    }
+    Ok(dir)
+}
+
+fn get_or_create_chat_directory() -> Result<PathBuf, Error> {
+    let dir = get_or_create_persistence_dir()?;
+    let chat_file_dir = dir.join("chats");
+    if !chat_file_dir.exists() {
+        create_dir_all(&chat_file_dir).map_err(|e| {
+            Error::default()
+                .wrap(Oops::DbError)
+                .because(format!("Failed to create chat subdirectory: {e}"))
+        })?;
+    }
+    Ok(chat_file_dir)
+}
+
+/// A conversation, as persisted in the chat directory.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Chat {
+    /// Every message in the conversation, from first to last. Messages are
+    /// never removed by compaction; see [Chat::summary].
+    pub messages: Vec<Message>,
+    /// A summary of older messages, produced by [crate::context] when the
+    /// conversation outgrows the model's context window.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<Summary>,
+    /// The model which the chat is pinned to. Chats from before models were
+    /// pinned are pinned to whichever model they are next resumed with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<Model>,
+    /// Labels added with `yap chat --tag`, for filtering `yap chatlog`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Absolute paths of files added with `yap pin --file`, which are sent
+    /// with every message; see [crate::pin].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pinned_files: Vec<PathBuf>,
+}
+
+impl Chat {
+    /// Keep only the first `len` messages. A summary which covers messages
+    /// at or beyond `len` is dropped, since it would describe messages which
+    /// are gone; the chat is summarized again if it still needs to be.
+    pub fn truncate(&mut self, len: usize) {
+        self.messages.truncate(len);
+        if self.summary.as_ref().is_some_and(|s| len <= s.covers) {
+            self.summary = None;
+        }
+    }
+}
+
+/// A summary of `messages[..covers]`, which stands in for those messages
+/// when the conversation is sent to the LLM.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Summary {
+    pub content: String,
+    pub covers: usize,
+}
+
+/// Chat files used to be a bare array of messages. Both shapes are accepted
+/// when reading, but chats are always written as a [Chat].
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ChatFile {
+    Legacy(Vec<Message>),
+    Chat(Chat),
+}
+
+impl From<ChatFile> for Chat {
+    fn from(file: ChatFile) -> Self {
+        match file {
+            ChatFile::Chat(chat) => chat,
+            ChatFile::Legacy(messages) => Chat {
+                messages,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+pub fn get_chat(id: &Uuid) -> Result<Chat, Error> {
+    let chat_file_dir = get_or_create_chat_directory().map_err(|e| {
+        e.wrap(Oops::DbError).because("during `get_chat`".into())
+    })?;
+    let chat_file_path = chat_file_dir.join(format!("{id}.json"));
+
+    if !chat_file_path.exists() {
+        return Ok(Chat::default());
+    }
+
+    let contents = fs::read(&chat_file_path)
+        .map_err(|e| {
+            Error::default().wrap(Oops::DbNotFound).because(format!(
+                "Could not open chat file at {:?}: {e}",
+                chat_file_dir
+            ))
+        })
+        .and_then(crypt::open)?;
+
+    let chat: ChatFile = serde_json::from_slice(&contents).map_err(|e| {
+        Error::default().wrap(Oops::DbError).because(format!(
+            "Failed to deserialize chat file at {:?}: {e}",
+            chat_file_dir
+        ))
+    })?;
+
+    Ok(chat.into())
+}
+
+pub fn save_chat(id: &Uuid, chat: &Chat) -> Result<(), Error> {
+    let chat_file_path = get_or_create_chat_directory()
+        .map_err(|e| {
+            e.wrap(Oops::DbError).because("during `save_chat`".into())
+        })?
+        .join(format!("{id}.json"));
+
+    let contents = serde_json::to_vec(chat).map_err(|e| {
+        Error::default().wrap(Oops::DbError).because(format!(
+            "Failed to serialize chat to file at {:?}: {e}",
+            chat_file_path
+        ))
+    })?;
+
+    fs::write(&chat_file_path, crypt::seal(contents)?).map_err(|e| {
+        Error::default().wrap(Oops::DbError).because(format!(
+            "Could not open or create chat file at {:?}: {e}",
+            chat_file_path
+        ))
+    })?;
+
+    // The index heals itself when it is next read, so a failure here
+    // shouldn't fail the save.
+    let update = fs::metadata(&chat_file_path)
+        .map_err(|e| {
+            Error::default()
+                .wrap(Oops::DbError)
+                .because(format!("Could not stat {chat_file_path:?}: {e}"))
+        })
+        .and_then(|metadata| {
+            let mut index = ChatIndex::load()?;
+            index.insert(*id, IndexEntry::new(chat, &metadata));
+            index.save()
+        });
+    if let Err(e) = update {
+        warn!("Could not update the chat index: {e:?}");
+    }
+
+    Ok(())
+}
+
+/// What `yap chatlog` shows about one chat.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IndexEntry {
+    /// The chat file's modified time when this entry was written, in
+    /// milliseconds since the Unix epoch. If the file has changed since,
+    /// the entry is stale.
+    modified_ms: u64,
+    /// The first line of the first message that the user sent.
+    pub title: Option<String>,
+    /// The first line of the most recent message that the user sent, or of
+    /// the first message if the user hasn't sent any.
+    pub last_message: Option<String>,
+    pub message_count: usize,
+    pub tags: Vec<String>,
+    pub model: Option<Model>,
+    /// When the first message was written, in seconds since the Unix
+    /// epoch. Not recorded in older chats.
+    #[serde(default)]
+    pub started: Option<u64>,
+}
+
+impl IndexEntry {
+    fn new(chat: &Chat, metadata: &Metadata) -> Self {
+        let first_line = |m: &Message| {
+            m.content
+                .as_ref()
+                .and_then(|c| c.lines().next())
+                .map(String::from)
+        };
+        let is_user =
+            |m: &&Message| matches!(m.role, Role::User) && m.content.is_some();
+        Self {
+            modified_ms: modified_ms(metadata),
+            title: chat.messages.iter().find(is_user).and_then(first_line),
+            last_message: chat
+                .messages
+                .iter()
+                .rev()
+                .find(is_user)
+                .or(chat.messages.first())
+                .and_then(first_line),
+            message_count: chat.messages.len(),
+            tags: chat.tags.clone(),
+            model: chat.model.clone(),
+            started: chat.messages.iter().find_map(|m| m.created),
+        }
+    }
+}
+
+fn modified_ms(metadata: &Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// An [IndexEntry] for each chat, kept in `~/.local/state/yap/chat_index.json`
+/// so that `yap chatlog` doesn't need to load every chat. It is updated by
+/// [save_chat]; chats which were changed some other way (i.e, by `yap
+/// import`, or by hand) are noticed by their modified time, and re-indexed
+/// when they are next listed. Like chats, the index is encrypted if
+/// [crypt] is enabled.
+#[derive(Debug, Default)]
+pub struct ChatIndex {
+    entries: BTreeMap<Uuid, IndexEntry>,
+    changed: bool,
+}
+
+fn get_chat_index_path() -> Result<PathBuf, Error> {
+    Ok(get_or_create_persistence_dir()?.join("chat_index.json"))
+}
+
+impl ChatIndex {
+    /// Load the index. A missing or unreadable index is treated as empty,
+    /// since it can always be rebuilt.
+    pub fn load() -> Result<Self, Error> {
+        let path = get_chat_index_path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let entries = fs::read(&path)
+            .map_err(|e| {
+                Error::default()
+                    .wrap(Oops::DbError)
+                    .because(format!("Could not read {path:?}: {e}"))
+            })
+            .and_then(crypt::open)
+            .and_then(|contents| {
+                serde_json::from_slice(&contents).map_err(|e| {
+                    Error::default()
+                        .wrap(Oops::DbError)
+                        .because(format!("{path:?} is invalid: {e}"))
+                })
+            });
+        match entries {
+            Ok(entries) => Ok(Self {
+                entries,
+                changed: false,
+            }),
+            Err(e) => {
+                warn!("Rebuilding the chat index: {e:?}");
+                Ok(Self {
+                    entries: BTreeMap::new(),
+                    changed: true,
+                })
+            }
+        }
+    }
+    fn insert(&mut self, id: Uuid, entry: IndexEntry) {
+        self.entries.insert(id, entry);
+        self.changed = true;
+    }
+    /// The entry for `convo`, which is re-indexed if it has changed since
+    /// it was indexed.
+    pub fn get(&mut self, convo: &Conversation) -> Result<&IndexEntry, Error> {
+        let id = convo.uuid()?;
+        let fresh = self
+            .entries
+            .get(&id)
+            .is_some_and(|e| e.modified_ms == modified_ms(&convo.metadata));
+        if !fresh {
+            debug!("Indexing chat {id}");
+            let entry = IndexEntry::new(&get_chat(&id)?, &convo.metadata);
+            self.insert(id, entry);
+        }
+        Ok(&self.entries[&id])
+    }
+    /// Forget chats which aren't in `conversations`; i.e, deleted chats.
+    pub fn retain(&mut self, conversations: &[Conversation]) {
+        let ids: Vec<Uuid> =
+            conversations.iter().filter_map(|c| c.uuid().ok()).collect();
+        let before = self.entries.len();
+        self.entries.retain(|id, _| ids.contains(id));
+        self.changed |= self.entries.len() != before;
+    }
+    /// Write the index, if anything has changed.
+    pub fn save(self) -> Result<(), Error> {
+        if !self.changed {
+            return Ok(());
+        }
+        let path = get_chat_index_path()?;
+        let contents = serde_json::to_vec(&self.entries).map_err(|e| {
+            Error::default()
+                .wrap(Oops::DbError)
+                .because(format!("Could not serialize the chat index: {e}"))
+        })?;
+        fs::write(&path, crypt::seal(contents)?).map_err(|e| {
+            Error::default()
+                .wrap(Oops::DbError)
+                .because(format!("Could not write {path:?}: {e}"))
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Conversation {
+    metadata: Metadata,
+    pub path: PathBuf,
+}
+
+impl Conversation {
+    pub fn accessed(&self) -> Result<SystemTime, Error> {
+        self.metadata.accessed()
+            .map_err(|e| Error::default().wrap(Oops::OsError).because(format!(
+                "Could not get last accessed time from file metadata related to {:?}: {}",
+                self.path,
+                e
+            )))
+    }
+    pub fn modified(&self) -> Result<SystemTime, Error> {
+        self.metadata.modified().map_err(|e| {
+            Error::default().wrap(Oops::OsError).because(format!(
+                "Could not get modified time from file metadata related to {:?}: {e}",
+                self.path
+            ))
+        })
+    }
+    /// Not every filesystem records when files were created.
+    pub fn created(&self) -> Option<SystemTime> {
+        self.metadata.created().ok()
+    }
+    pub fn uuid(&self) -> Result<Uuid, Error> {
+        parse_uuid(&self.path)
+    }
+}
+
+fn parse_uuid(path: &PathBuf) -> Result<Uuid, Error> {
+    let name = path
+        .file_name()
+        .ok_or(Error::default().wrap(Oops::DbError).because(format!(
+            "conversation path has no filename ({:?})",
+            path
+        )))?
+        .to_str()
+        .ok_or(Error::default().wrap(Oops::DbError).because(format!(
+            "for path {:?}, cannot convert filename into string",
+            path
+        )))?;
+    let mut parts = name.split(".");
+    let uuid_str = parts.next().ok_or(
+        Error::default()
+            .wrap(Oops::DbError)
+            .because(format!("cannot find first part of {name}")),
+    )?;
+    let extension = parts.next().ok_or(
+        Error::default()
+            .wrap(Oops::DbError)
+            .because(format!("cannot find second part of {name}",)),
+    )?;
+    if extension != "json" {
+        return Err(Error::default().wrap(Oops::DbError).because(format!(
+            "file extension {} != json; for file {:?}",
+            extension, path
+        )));
+    };
+    if parts.enumerate().fold(0, |_, (i, _)| i) != 0 {
+        return Err(Error::default().wrap(Oops::DbError).because(format!(
+            "file name has more parts than expected: {name}"
+        )));
+    };
+    Uuid::parse_str(uuid_str).map_err(|e| {
+        Error::default()
+            .wrap(Oops::DbError)
+            .because(format!("cannot parse UUID from file {path:?}: {e}"))
+    })
+}
+
+pub fn list_conversations() -> Result<Vec<Conversation>, Error> {
+    get_or_create_chat_directory().map_err(|e| {
+        e.wrap(Oops::DbError).because("during `list_conversations`: {e}".into())
+    })?
+    .read_dir()
+        .map_err(|e| {
+            Error::default()
+                .wrap(Oops::DbError)
+                .because(format!("could not read chat dir: {e}"))
+        })
+        .map(|files| {
+            #[allow(clippy::manual_try_fold)]
+            files.fold(Ok(Vec::new()), |acc, file| {
+                match (acc, file) {
+                    (Ok(mut convos), Ok(file)) => {
+                            file.metadata()
+                                .map_err(|e|
+                                    Error::default()
+                                        .wrap(Oops::DbError)
+                                        .because(
+                                            format!(
+                                                "could not read metadata for file {file:?}: {e}"
+                                            )
+                                        )
+                                )
+                            .map(|metadata| {
+                                convos.push(Conversation {
+                                    metadata,
+                                    path: file.path()
+                                });
+                            })?;
+                        Ok(convos)
+                    },
+                    (_, Err(e)) => {
+                        Err(
+                            Error::default()
+                                .wrap(Oops::DbError)
+                                .because(
+                                    format!(
+                                        "read_dir error encountered: {e}"
+                                    )
+                                )
+                        )
+                    },
+                    (Err(e), _) => Err(e)
+                }
+            })
+        })?
+}
+
+fn get_active_chat_path() -> Result<PathBuf, Error> {
+    let dir = get_or_create_persistence_dir()?;
+    Ok(dir.join("active_chat"))
+}
+
+/// The active chat for the current project (see [files::project_root]),
+/// which takes precedence over the global active chat. This way, chatting in
+/// one project doesn't change the active chat in another.
+fn get_project_chat_path() -> Result<PathBuf, Error> {
+    let dir = get_or_create_persistence_dir()?.join("active_chats");
+    create_dir_all(&dir).map_err(|e| {
+        Error::default()
+            .wrap(Oops::DbError)
+            .because(format!("could not create {dir:?}: {e}"))
+    })?;
+    Ok(dir.join(files::project_id(&files::project_root())))
+}
+
+/// The active chat for the current project, or else the chat which was most
+/// recently made active anywhere.
+pub fn get_active_chat() -> Result<Option<Uuid>, Error> {
+    let project_chat_path = get_project_chat_path()?;
+    if project_chat_path.exists() {
+        return read_chat_id(&project_chat_path).map(Some);
+    }
+    let active_chat_path = get_active_chat_path()?;
+    if !active_chat_path.exists() {
+        return Ok(None);
+    }
+    read_chat_id(&active_chat_path).map(Some)
+}
+
+fn read_chat_id(active_chat_path: &Path) -> Result<Uuid, Error> {
+    let contents = std::fs::read_to_string(active_chat_path).map_err(|e| {
+        Error::default().wrap(Oops::DbError).because(format!(
+            "could not read active chat: {active_chat_path:?}: {e}"
+        ))
+    })?;
+    Uuid::parse_str(contents.trim()).map_err(|e| {
+        debug!("found bad file contents: {contents}");
+        Error::default()
+            .wrap(Oops::DbError)
+            .because(format!("active chat is not a uuid ({e})"))
+    })
+}
+
+/// Make `uuid` the active chat, both for the current project and globally.
+pub fn set_chat_id(uuid: &Uuid) -> Result<(), Error> {
+    for active_chat_path in [get_project_chat_path()?, get_active_chat_path()?]
+    {
+        std::fs::write(&active_chat_path, uuid.to_string()).map_err(|e| {
+            Error::default()
+                .wrap(Oops::DbError)
+                .because(format!(
+                        "could not write new chat ID {uuid} to chat path {active_chat_path:?}: {e}"
+                ))
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_parse_uuid() {
+        let uuid =
+            Uuid::parse_str("4a016e25-60f4-4355-8165-97abff7be79b").unwrap();
+        let path =
+            PathBuf::from(format!("/home/foo/.local/state/yap/{}.json", uuid));
+        let result = parse_uuid(&path).unwrap();
+        assert_eq!(result, uuid);
+    }
+    #[test]
+    fn test_read_legacy_chat_file() {
+        let legacy = r#"[{"role":"user","content":"hi","refusal":null}]"#;
+        let chat: Chat =
+            serde_json::from_str::<ChatFile>(legacy).unwrap().into();
+        assert_eq!(chat.messages.len(), 1);
+        assert!(chat.summary.is_none());
+    }
+    #[test]
+    fn test_index_entry() {
+        let chat = Chat {
+            messages: vec![
+                Message::new(Role::System, "be brief".into()),
+                Message::new(Role::User, "first\nquestion".into()),
+                Message::new(Role::Assistant, "answer".into()),
+                Message::new(Role::User, "second".into()),
+            ],
+            ..Default::default()
+        };
+        let metadata = fs::metadata(env!("CARGO_MANIFEST_DIR")).unwrap();
+        let entry = IndexEntry::new(&chat, &metadata);
+        assert_eq!(entry.title.as_deref(), Some("first"));
+        assert_eq!(entry.last_message.as_deref(), Some("second"));
+        assert_eq!(entry.message_count, 4);
+        assert_eq!(entry.modified_ms, modified_ms(&metadata));
+    }
+}
@@ -0,0 +1,385 @@
+//! A small parser for unified diffs, like the ones produced by `git diff`.
+
+use crate::err::{Error, Oops};
+use std::{iter::Peekable, path::PathBuf, process::Command, str::Lines};
+
+/// All of the hunks in a diff which touch one file.
+#[derive(Debug)]
+pub struct FileDiff {
+    /// The path of the file after the change (the `+++` side of the diff).
+    pub path: PathBuf,
+    pub hunks: Vec<Hunk>,
+}
+
+#[derive(Debug)]
+pub struct Hunk {
+    /// 1-based; `0` if unknown, or if the hunk adds lines to an empty file.
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub lines: Vec<Line>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Line {
+    Context(String),
+    Added(String),
+    Removed(String),
+}
+
+impl Hunk {
+    /// Lines which exist before the change (context and removed lines).
+    pub fn old_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                Line::Context(l) | Line::Removed(l) => Some(l.as_str()),
+                Line::Added(_) => None,
+            })
+            .collect()
+    }
+
+    /// Lines which exist after the change (context and added lines), paired
+    /// with their 1-based line number in the new file. The boolean is `true`
+    /// for added lines.
+    pub fn new_lines(&self) -> Vec<(usize, &str, bool)> {
+        let mut lineno = self.new_start;
+        let mut out = Vec::with_capacity(self.new_len);
+        for line in &self.lines {
+            match line {
+                Line::Context(l) => {
+                    out.push((lineno, l.as_str(), false));
+                    lineno += 1;
+                }
+                Line::Added(l) => {
+                    out.push((lineno, l.as_str(), true));
+                    lineno += 1;
+                }
+                Line::Removed(_) => {}
+            }
+        }
+        out
+    }
+}
+
+/// Run `git diff` with `args` in the current directory, and return its
+/// output.
+pub fn git_diff(args: &[&str]) -> Result<String, Error> {
+    let output = Command::new("git")
+        .args(["diff", "--no-color", "--no-ext-diff"])
+        .args(args)
+        .output()
+        .map_err(|e| {
+            Error::default()
+                .wrap(Oops::CommandError)
+                .because(format!("could not run `git diff`: {e}"))
+        })?;
+    if !output.status.success() {
+        return Err(Error::default().wrap(Oops::CommandError).because(
+            format!(
+                "`git diff` failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ));
+    }
+    String::from_utf8(output.stdout).map_err(|e| {
+        Error::default()
+            .wrap(Oops::StringError)
+            .because(format!("`git diff` output is not utf-8: {e}"))
+    })
+}
+
+/// Parse a unified diff. Files which are deleted by the diff are omitted,
+/// since there is nothing left to talk about.
+pub fn parse(diff: &str) -> Result<Vec<FileDiff>, Error> {
+    parse_inner(diff, false)
+}
+
+/// Parse a unified diff written by an LLM. LLMs are bad at counting, so the
+/// line counts and positions in hunk headers are treated as hints. Hunks end
+/// at the next header instead of after the number of lines given in the
+/// header, and headers without any numbers (`@@ ... @@`) are accepted.
+pub fn parse_lenient(diff: &str) -> Result<Vec<FileDiff>, Error> {
+    parse_inner(diff, true)
+}
+
+fn parse_inner(diff: &str, lenient: bool) -> Result<Vec<FileDiff>, Error> {
+    let mut files: Vec<FileDiff> = Vec::new();
+    let mut deleted = false;
+    let mut lines = diff.lines().peekable();
+    while let Some(line) = lines.next() {
+        if line.starts_with("diff --git ") {
+            deleted = false;
+        } else if line.starts_with("--- ") {
+            continue;
+        } else if let Some(path) = line.strip_prefix("+++ ") {
+            let path = path.split('\t').next().unwrap_or(path).trim();
+            if path == "/dev/null" {
+                deleted = true;
+                continue;
+            }
+            deleted = false;
+            let path = path.strip_prefix("b/").unwrap_or(path);
+            files.push(FileDiff {
+                path: PathBuf::from(path),
+                hunks: Vec::new(),
+            });
+        } else if line.starts_with("@@") {
+            let mut hunk = match parse_hunk_header(line) {
+                Ok(hunk) => hunk,
+                Err(_) if lenient => Hunk {
+                    old_start: 0,
+                    old_len: 0,
+                    new_start: 0,
+                    new_len: 0,
+                    lines: Vec::new(),
+                },
+                Err(e) => return Err(e),
+            };
+            let (mut old_seen, mut new_seen) = (0, 0);
+            loop {
+                if !lenient
+                    && old_seen >= hunk.old_len
+                    && new_seen >= hunk.new_len
+                {
+                    break;
+                }
+                if lenient && is_hunk_boundary(&lines) {
+                    break;
+                }
+                let Some(body) = lines.next() else {
+                    return Err(Error::default()
+                        .wrap(Oops::DiffError)
+                        .because(format!(
+                            "diff ended in the middle of hunk {line:?}"
+                        )));
+                };
+                if let Some(l) = body.strip_prefix('+') {
+                    hunk.lines.push(Line::Added(l.into()));
+                    new_seen += 1;
+                } else if let Some(l) = body.strip_prefix('-') {
+                    hunk.lines.push(Line::Removed(l.into()));
+                    old_seen += 1;
+                } else if body.starts_with('\\') {
+                    // "\ No newline at end of file"
+                } else {
+                    let l = body.strip_prefix(' ').unwrap_or(body);
+                    hunk.lines.push(Line::Context(l.into()));
+                    old_seen += 1;
+                    new_seen += 1;
+                }
+            }
+            while lines.peek().is_some_and(|l| l.starts_with('\\')) {
+                lines.next();
+            }
+            if lenient {
+                hunk.old_len = old_seen;
+                hunk.new_len = new_seen;
+            }
+            if deleted {
+                continue;
+            }
+            match files.last_mut() {
+                Some(file) => file.hunks.push(hunk),
+                None => {
+                    return Err(Error::default().wrap(Oops::DiffError).because(
+                        format!("hunk {line:?} does not belong to any file"),
+                    ))
+                }
+            }
+        }
+    }
+    files.retain(|f| !f.hunks.is_empty());
+    Ok(files)
+}
+
+/// Whether a change is too trivial to be worth sending to the LLM; that is,
+/// it is empty, or it only changes whitespace (including blank lines).
+pub fn is_trivial(file_diffs: &[FileDiff]) -> bool {
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+    for line in file_diffs
+        .iter()
+        .flat_map(|f| &f.hunks)
+        .flat_map(|h| &h.lines)
+    {
+        match line {
+            Line::Removed(l) if !l.trim().is_empty() => removed.push(l.trim()),
+            Line::Added(l) if !l.trim().is_empty() => added.push(l.trim()),
+            _ => {}
+        }
+    }
+    removed.sort_unstable();
+    added.sort_unstable();
+    removed == added
+}
+
+/// In lenient mode, whether the next line ends the current hunk. A `--- `
+/// line only starts a new file when a `+++ ` line follows it; otherwise, it
+/// removes a line which begins with `-- `, like a SQL comment.
+fn is_hunk_boundary(rest: &Peekable<Lines>) -> bool {
+    let mut rest = rest.clone();
+    let Some(line) = rest.next() else {
+        return true;
+    };
+    line.starts_with("@@")
+        || line.starts_with("diff ")
+        || line.starts_with("```")
+        || (line.starts_with("--- ")
+            && rest.next().is_some_and(|l| l.starts_with("+++ ")))
+}
+
+/// Parse `@@ -old_start,old_len +new_start,new_len @@`, where lengths are
+/// optional and default to 1.
+fn parse_hunk_header(line: &str) -> Result<Hunk, Error> {
+    let bad_header = || {
+        Error::default()
+            .wrap(Oops::DiffError)
+            .because(format!("malformed hunk header: {line:?}"))
+    };
+    let mut parts = line.split_whitespace().skip(1);
+    let old = parts
+        .next()
+        .and_then(|p| p.strip_prefix('-'))
+        .ok_or_else(bad_header)?;
+    let new = parts
+        .next()
+        .and_then(|p| p.strip_prefix('+'))
+        .ok_or_else(bad_header)?;
+    let range = |r: &str| -> Result<(usize, usize), Error> {
+        let mut nums = r.split(',');
+        let start = nums
+            .next()
+            .and_then(|n| n.parse().ok())
+            .ok_or_else(bad_header)?;
+        let len = match nums.next() {
+            Some(n) => n.parse().map_err(|_| bad_header())?,
+            None => 1,
+        };
+        Ok((start, len))
+    };
+    let (old_start, old_len) = range(old)?;
+    let (new_start, new_len) = range(new)?;
+    Ok(Hunk {
+        old_start,
+        old_len,
+        new_start,
+        new_len,
+        lines: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIFF: &str = "diff --git a/src/foo.rs b/src/foo.rs
+index 1111111..2222222 100644
+--- a/src/foo.rs
++++ b/src/foo.rs
+@@ -1,3 +1,4 @@
+ fn main() {
+-    println!(\"hi\");
++    let name = \"world\";
++    println!(\"hello, {name}\");
+ }
+@@ -10 +11 @@ fn other() {
+-    a
++    b
+diff --git a/gone.txt b/gone.txt
+deleted file mode 100644
+--- a/gone.txt
++++ /dev/null
+@@ -1 +0,0 @@
+-bye
+";
+
+    #[test]
+    fn test_parse() {
+        let files = parse(DIFF).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, PathBuf::from("src/foo.rs"));
+        assert_eq!(files[0].hunks.len(), 2);
+
+        let hunk = &files[0].hunks[0];
+        assert_eq!((hunk.old_start, hunk.old_len), (1, 3));
+        assert_eq!((hunk.new_start, hunk.new_len), (1, 4));
+        assert_eq!(
+            hunk.new_lines(),
+            vec![
+                (1, "fn main() {", false),
+                (2, "    let name = \"world\";", true),
+                (3, "    println!(\"hello, {name}\");", true),
+                (4, "}", false),
+            ]
+        );
+        let hunk = &files[0].hunks[1];
+        assert_eq!(hunk.new_lines(), vec![(11, "    b", true)]);
+    }
+
+    #[test]
+    fn test_parse_malformed_header() {
+        assert!(parse("+++ b/x\n@@ nonsense @@\n").is_err());
+    }
+
+    #[test]
+    fn test_is_trivial() {
+        assert!(is_trivial(&[]));
+        let reindent = "+++ b/x\n@@ -1,2 +1,3 @@\n-if x {\n+  if x {\n+\n }\n";
+        assert!(is_trivial(&parse(reindent).unwrap()));
+        assert!(!is_trivial(&parse(DIFF).unwrap()));
+    }
+
+    #[test]
+    fn test_parse_lenient() {
+        // The header claims one line of context, but there are three, and
+        // the second hunk has no numbers at all.
+        let diff = "--- a/x.rs
++++ b/x.rs
+@@ -1,1 +1,1 @@
+ one
+-two
++deux
+ three
+@@ ... @@
+ five
++six
+";
+        assert!(parse(diff).is_err());
+        let files = parse_lenient(diff).unwrap();
+        let hunks = &files[0].hunks;
+        assert_eq!(hunks.len(), 2);
+        assert_eq!(hunks[0].old_lines(), vec!["one", "two", "three"]);
+        assert_eq!(hunks[0].old_len, 3);
+        assert_eq!(hunks[1].old_start, 0);
+        assert_eq!(hunks[1].new_lines().len(), 2);
+    }
+
+    #[test]
+    fn test_parse_lenient_removed_comment() {
+        let diff = "--- a/schema.sql
++++ b/schema.sql
+@@ ... @@
+ CREATE TABLE t (
+--- legacy column
+-    old INT,
++++ counter
++    n INT
+ );
+--- a/other.sql
++++ b/other.sql
+@@ ... @@
+-x
++y
+";
+        let files = parse_lenient(diff).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(
+            files[0].hunks[0].old_lines(),
+            vec!["CREATE TABLE t (", "-- legacy column", "    old INT,", ");"]
+        );
+        assert_eq!(files[0].hunks[0].new_len, 4);
+        assert_eq!(files[1].path, PathBuf::from("other.sql"));
+    }
+}
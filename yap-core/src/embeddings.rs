@@ -0,0 +1,112 @@
+//! Cache embeddings in `~/.local/state/yap/embeddings`, so that unchanged
+//! text is only ever embedded once.
+//!
+//! Embeddings are keyed by a SHA-256 hash of the embedding model and the
+//! text, and stored as little-endian `f32`s. Unlike [crate::cache], entries
+//! never expire, because an embedding doesn't change for the same model and
+//! text. Feel free to delete the directory at any time.
+
+use crate::{
+    db,
+    err::{Error, Oops},
+    openai::{embeddings_api, OpenAI},
+};
+use sha2::{Digest, Sha256};
+use std::{fs, path::PathBuf};
+
+fn oops(why: String) -> Error {
+    Error::default().wrap(Oops::EmbeddingError).because(why)
+}
+
+/// The cache key for `text`.
+pub fn key(text: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(embeddings_api::EMBEDDING_MODEL);
+    hasher.update([0]);
+    hasher.update(text);
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+fn path(key: &str) -> Result<PathBuf, Error> {
+    // Entries are spread over subdirectories, since big repositories have
+    // many thousands of chunks.
+    Ok(db::get_or_create_persistence_dir()?
+        .join("embeddings")
+        .join(&key[..2])
+        .join(key))
+}
+
+/// The cached embedding for `key`, if there is one.
+pub fn get(key: &str) -> Result<Option<Vec<f32>>, Error> {
+    let Ok(bytes) = fs::read(path(key)?) else {
+        return Ok(None);
+    };
+    Ok(Some(
+        bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+    ))
+}
+
+fn put(key: &str, embedding: &[f32]) -> Result<(), Error> {
+    let path = path(key)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| oops(format!("Could not create {parent:?}: {e}")))?;
+    }
+    let bytes: Vec<u8> =
+        embedding.iter().flat_map(|f| f.to_le_bytes()).collect();
+    fs::write(&path, bytes)
+        .map_err(|e| oops(format!("Could not write {path:?}: {e}")))
+}
+
+/// Embed each of `texts`, using the cache where possible. Returns the
+/// embeddings in order, and the number of texts which weren't cached.
+pub fn embed(
+    open_ai: &OpenAI,
+    texts: &[String],
+) -> Result<(Vec<Vec<f32>>, usize), Error> {
+    let keys: Vec<String> = texts.iter().map(|t| key(t)).collect();
+    let mut embeddings = Vec::with_capacity(texts.len());
+    let mut missing = Vec::new();
+    for (i, key) in keys.iter().enumerate() {
+        let cached = get(key)?;
+        if cached.is_none() {
+            missing.push(i);
+        }
+        embeddings.push(cached);
+    }
+    if !missing.is_empty() {
+        let inputs: Vec<String> =
+            missing.iter().map(|i| texts[*i].clone()).collect();
+        let fresh = embeddings_api::embed(open_ai, &inputs)?;
+        for (i, embedding) in missing.iter().zip(fresh) {
+            put(&keys[*i], &embedding)?;
+            embeddings[*i] = Some(embedding);
+        }
+    }
+    Ok((
+        embeddings
+            .into_iter()
+            .map(|e| e.expect("every embedding was cached or fetched"))
+            .collect(),
+        missing.len(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key() {
+        assert_eq!(key("fn main() {}"), key("fn main() {}"));
+        assert_ne!(key("fn main() {}"), key("fn main() { }"));
+        assert_eq!(key("").len(), 64);
+    }
+}
@@ -0,0 +1,423 @@
+//! Error handling for `yap`
+
+use log::{debug, error};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Oops {
+    OpenAIKeyMissing,
+    OpenAIChatResponse,
+    OpenAIChatDeserialization,
+    OpenAIBadFinishReason,
+    OpenAIEmptyChoices,
+    OpenAIContentAndRefusal,
+    OpenAIEmptyContent,
+    OpenAIPoverty,
+    OpenAIRefusal,
+    OpenAIUnauthorized,
+    /// Not really an error; see [crate::openai::OpenAI::dry_run].
+    DryRun,
+    StdinReadError,
+    StatsError,
+    XdgConfigError,
+    DbError,
+    DbNotFound,
+    CompletionError,
+    ChatError,
+    ChangelogError,
+    ContextWindowError,
+    AnnotateError,
+    AgentError,
+    AskError,
+    BackupError,
+    BatchError,
+    ApplyError,
+    ArchiveError,
+    CacheError,
+    CommitError,
+    ConfirmError,
+    CryptError,
+    CtxError,
+    DiffError,
+    EmbeddingError,
+    FilesError,
+    FilterError,
+    FinetuneError,
+    GitHubError,
+    GitLabError,
+    HttpTransportError,
+    HttpStatusError,
+    HttpMetaError,
+    CommandError,
+    StringError,
+    MockError,
+    OsError,
+    SandboxError,
+    SayError,
+    ServeError,
+    SimilarError,
+    SymbolError,
+    TmuxError,
+    TranscribeError,
+    WatchError,
+    WorkflowError,
+    PickerError,
+    PinError,
+    #[allow(unused)]
+    Placeholder,
+    RecapError,
+    RequestHookError,
+    RefactorError,
+    RepoMapError,
+    ReviewError,
+    /// The review found problems at or above `--fail-on`; see
+    /// [crate::review].
+    ReviewFailed,
+    RulesError,
+    HookError,
+    ImagineError,
+    IndexError,
+    MemoryError,
+    ModelsError,
+}
+
+impl Oops {
+    /// In some cases, there might only be one possible explanation for an
+    /// error type, in which case we can centralize those explanations here
+    /// instead of needing to use [Error::because] all over the place.
+    pub fn explain(&self) -> Option<&'static str> {
+        match self {
+            Self::OpenAIEmptyChoices => {
+                Some("OpenAI did not provide any response choices.")
+            }
+            Self::OpenAIKeyMissing => {
+                Some("set $OPENAI_API_KEY in your environment, or set one of api_key_file, api_key_command, or api_key_keychain in config.json")
+            },
+            Self::OpenAIContentAndRefusal => {
+                Some("OpenAI message contained `content` and `refusal`. This should never happen.")
+            },
+            Self::OpenAIEmptyContent => {
+                Some("OpenAI messages contains neither `content` nor `refusal`. This should never happen.")
+            },
+            Self::HttpTransportError => {
+                Some("A HTTP transport error occurred. Double-check your internet connection. Enable debug logging for more details.")
+            },
+            Self::OpenAIRefusal => Some("The model refused the request."),
+            Self::DryRun => Some("This is a dry run; nothing was sent."),
+            Self::OpenAIUnauthorized => {
+                Some("OpenAI rejected the API key. Check that it is correct, and that it has not been revoked.")
+            },
+            _ => None,
+        }
+    }
+    /// The exit code for this class of error, if it has one. These are
+    /// documented in the "Exit Codes" section of the crate docs, so they
+    /// must not change.
+    pub fn exit_code(&self) -> Option<i32> {
+        match self {
+            Self::DryRun => Some(0),
+            Self::OpenAIKeyMissing | Self::OpenAIUnauthorized => Some(2),
+            Self::HttpTransportError => Some(3),
+            Self::OpenAIRefusal => Some(4),
+            Self::ContextWindowError => Some(5),
+            Self::OpenAIPoverty => Some(6),
+            Self::HttpStatusError => Some(7),
+            Self::ReviewFailed => Some(8),
+            _ => None,
+        }
+    }
+    /// An [Oops] whose [Self::exit_code] is `code`, to stand in for an
+    /// error which happened in another process; i.e, in `yap daemon`.
+    pub fn from_exit_code(code: i64) -> Option<Self> {
+        match code {
+            2 => Some(Self::OpenAIUnauthorized),
+            3 => Some(Self::HttpTransportError),
+            4 => Some(Self::OpenAIRefusal),
+            5 => Some(Self::ContextWindowError),
+            6 => Some(Self::OpenAIPoverty),
+            7 => Some(Self::HttpStatusError),
+            8 => Some(Self::ReviewFailed),
+            _ => None,
+        }
+    }
+}
+
+/// One entry on the error stack. Each [Oopsie] is the
+/// [std::error::Error::source] of the one wrapped around it.
+#[derive(Debug)]
+pub struct Oopsie {
+    variant: Oops,
+    ctx: Option<String>,
+    source: Option<Box<Oopsie>>,
+}
+
+impl Oopsie {
+    pub fn variant(&self) -> &Oops {
+        &self.variant
+    }
+    pub fn ctx(&self) -> Option<&str> {
+        self.ctx.as_deref()
+    }
+    /// [Self::ctx], or else the [Oops::explain]-ation.
+    fn details(&self) -> Option<&str> {
+        self.ctx().or(self.variant.explain())
+    }
+}
+
+impl std::fmt::Display for Oopsie {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.details() {
+            Some(details) => write!(f, "{:?}: {details}", self.variant),
+            None => write!(f, "{:?}", self.variant),
+        }
+    }
+}
+
+impl std::error::Error for Oopsie {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source
+            .as_deref()
+            .map(|s| s as &(dyn std::error::Error + 'static))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Error {
+    /// The most recent of a series of unfortunate events; the earlier ones
+    /// are chained behind it through [Oopsie::source].
+    last: Option<Oopsie>,
+}
+
+/// An adequate and simple error framework. Start by creating an error;
+///
+/// ```
+/// use yap_core::err::{Error, Oops};
+///
+/// // Start by making a new error, and identify what went wrong.
+/// let e = Error::default().wrap(Oops::OpenAIKeyMissing);
+/// // Then, as it flows up the stack, add context. Optionally, say why.
+/// let e = e.wrap(Oops::OpenAIChatResponse).because(format!(
+///     "In function {}, we encountered {}",
+///     "bad_stuff", "some other error type"
+/// ));
+/// assert!(e.has(&Oops::OpenAIKeyMissing));
+/// ```
+///
+/// As errors flow up through a call stack, receivers can call [Self::wrap]
+/// and/or [Self::because] to add context to the error.
+impl Error {
+    /// Append an error-type to the stack.
+    pub fn wrap(mut self, oops: Oops) -> Self {
+        self.last = Some(Oopsie {
+            variant: oops,
+            ctx: None,
+            source: self.last.take().map(Box::new),
+        });
+        self
+    }
+    /// Add a description by mutating the most recent entry on the error stack.
+    /// We expect this to be called immediately after a call to [Self::wrap]
+    /// to enhance the error-type with details from the context where the
+    /// error happened.
+    pub fn because(mut self, ctx: String) -> Self {
+        if let Some(last) = self.last.as_mut() {
+            last.ctx = Some(ctx);
+        }
+        self
+    }
+    /// The error stack, from first to last.
+    pub fn oopsies(&self) -> Vec<&Oopsie> {
+        let mut oopsies = Vec::new();
+        let mut next = self.last.as_ref();
+        while let Some(oopsie) = next {
+            oopsies.push(oopsie);
+            next = oopsie.source.as_deref();
+        }
+        oopsies.reverse();
+        oopsies
+    }
+    /// Whether `oops` is anywhere on the error stack.
+    pub fn has(&self, oops: &Oops) -> bool {
+        self.oopsies().iter().any(|o| o.variant == *oops)
+    }
+    /// The process exit code for this error, from the first (i.e, the
+    /// root-cause) [Oops] which has one, or else `1`.
+    pub fn exit_code(&self) -> i32 {
+        self.oopsies()
+            .iter()
+            .find_map(|o| o.variant().exit_code())
+            .unwrap_or(1)
+    }
+    pub fn display(&self) {
+        if self.last.is_none() {
+            return;
+        }
+        eprintln!("{}", self);
+    }
+    /// The whole error stack on one line, for machine-readable output.
+    pub fn summary(&self) -> String {
+        self.oopsies()
+            .iter()
+            .map(|o| o.to_string())
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+    /// For a request which got no response; see [crate::http].
+    pub fn wrap_http(self, e: reqwest::Error) -> Error {
+        debug!("transport error: {e:?}");
+        self.wrap(Oops::HttpTransportError)
+    }
+    /// For an unsuccessful response from `url`, whose `body` has been read.
+    pub fn wrap_http_status(
+        self,
+        status_code: u16,
+        url: &str,
+        body: Option<&str>,
+    ) -> Error {
+        error!("Received HTTP error ({status_code})");
+        let openai = is_openai(url);
+        if openai && (status_code == 401 || status_code == 403) {
+            return self.wrap(Oops::OpenAIUnauthorized);
+        }
+        if openai && status_code == 429 {
+            return self
+                .wrap(Oops::OpenAIPoverty)
+                .because(
+                    "429 responses from OpenAI typically indicate that you don't have any credits".into()
+                );
+        }
+        if let Some(body) = body {
+            debug!("BEGIN response body\n{body}\nEND response body");
+        }
+        if openai && status_code == 400 {
+            if let Some(message) = body.and_then(context_length_exceeded) {
+                return self.wrap(Oops::ContextWindowError).because(message);
+            }
+        }
+        self.wrap(Oops::HttpStatusError).because(
+            format!(
+            "Received unsuccessful HTTP response {status_code}. Enable debug logging for more details.")
+        )
+    }
+}
+
+/// Whether `url` is OpenAI's API, rather than (i.e) GitHub's, whose URLs
+/// may mention OpenAI too.
+fn is_openai(url: &str) -> bool {
+    reqwest::Url::parse(url)
+        .is_ok_and(|url| url.host_str() == Some("api.openai.com"))
+}
+
+/// OpenAI's message, if `body` is an error because the prompt did not fit
+/// in the model's context window.
+fn context_length_exceeded(body: &str) -> Option<String> {
+    let body: serde_json::Value = serde_json::from_str(body).ok()?;
+    let error = &body["error"];
+    (error["code"] == "context_length_exceeded")
+        .then(|| error["message"].as_str().unwrap_or_default().to_string())
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Oops! One or more errors occurred;")?;
+        let alt = "details not available";
+        for (indent, item) in self.oopsies().iter().enumerate() {
+            let indent = "  ".repeat(indent + 1);
+            let er_code = &item.variant;
+            let details = item.details().unwrap_or(alt);
+            writeln!(f, "{indent}{er_code:?} :: {details}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The error's own message is the whole stack, as shown by [Error::display];
+/// its [std::error::Error::source] is the second-to-last [Oopsie], so that
+/// tools which walk the chain see each of the earlier events in turn.
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.last.as_ref().and_then(|l| l.source())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(Error::default().wrap(Oops::ChatError).exit_code(), 1);
+        assert_eq!(Error::default().wrap(Oops::ReviewFailed).exit_code(), 8);
+        assert_eq!(
+            Error::default()
+                .wrap(Oops::OpenAIRefusal)
+                .wrap(Oops::ChatError)
+                .exit_code(),
+            4
+        );
+        // The root cause wins.
+        assert_eq!(
+            Error::default()
+                .wrap(Oops::HttpTransportError)
+                .wrap(Oops::ContextWindowError)
+                .exit_code(),
+            3
+        );
+        for code in 2..=8 {
+            let oops = Oops::from_exit_code(code).unwrap();
+            assert_eq!(oops.exit_code(), Some(code as i32));
+        }
+        assert!(Oops::from_exit_code(1).is_none());
+    }
+
+    #[test]
+    fn test_context_length_exceeded() {
+        let body = r#"{"error": {"message": "This model's maximum context length is 128000 tokens.", "type": "invalid_request_error", "param": "messages", "code": "context_length_exceeded"}}"#;
+        let url = "https://api.openai.com/v1/chat/completions";
+        let e = Error::default().wrap_http_status(400, url, Some(body));
+        assert!(e.has(&Oops::ContextWindowError));
+        assert_eq!(e.exit_code(), 5);
+        assert!(e.summary().contains("maximum context length"));
+        let e = Error::default().wrap_http_status(400, url, Some("{}"));
+        assert_eq!(e.exit_code(), 7);
+    }
+
+    #[test]
+    fn test_http_status_host() {
+        let openai = "https://api.openai.com/v1/models";
+        let e = Error::default().wrap_http_status(401, openai, None);
+        assert!(e.has(&Oops::OpenAIUnauthorized));
+        let e = Error::default().wrap_http_status(429, openai, None);
+        assert_eq!(e.exit_code(), 6);
+
+        let github =
+            "https://api.github.com/repos/openai/openai-python/pulls/1";
+        let e = Error::default().wrap_http_status(401, github, None);
+        assert!(!e.has(&Oops::OpenAIUnauthorized));
+        let e = Error::default().wrap_http_status(429, github, None);
+        assert_eq!(e.exit_code(), 7);
+    }
+
+    #[test]
+    fn test_source_chain() {
+        use std::error::Error as _;
+        let e = Error::default()
+            .wrap(Oops::HttpTransportError)
+            .wrap(Oops::OpenAIChatResponse)
+            .because("no dice".into())
+            .wrap(Oops::ChatError);
+        assert!(e.has(&Oops::OpenAIChatResponse));
+        assert!(!e.has(&Oops::OpenAIRefusal));
+        let source = e.source().unwrap();
+        assert_eq!(source.to_string(), "OpenAIChatResponse: no dice");
+        let root = source.source().unwrap();
+        assert_eq!(
+            root.downcast_ref::<Oopsie>().unwrap().variant(),
+            &Oops::HttpTransportError
+        );
+        assert!(root.source().is_none());
+        assert_eq!(
+            e.summary(),
+            "HttpTransportError: A HTTP transport error occurred. Double-check your internet connection. Enable debug logging for more details.; OpenAIChatResponse: no dice; ChatError"
+        );
+    }
+}
@@ -0,0 +1,237 @@
+//! Gather files to send to the LLM as context.
+//!
+//! `.gitignore` files are respected, as well as `.yapignore` files for
+//! exclusions which only matter to `yap`, like test fixtures and vendored
+//! code. `.yapignore` files use the same syntax as `.gitignore` files.
+
+use crate::err::{Error, Oops};
+use ignore::{gitignore::Gitignore, overrides::OverrideBuilder, WalkBuilder};
+use sha2::{Digest, Sha256};
+use std::{
+    cell::RefCell,
+    env, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+pub const IGNORE_FILE: &str = ".yapignore";
+
+thread_local! {
+    /// The directory set by [in_dir], if any.
+    static DIR: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+/// Run `f` as though `yap` had been run in `dir`. Unlike
+/// [env::set_current_dir], this only applies to the current thread, so that
+/// `yap serve` can answer requests from many directories at once; [cwd],
+/// [resolve], and [project_root] answer for `dir` until `f` returns.
+pub fn in_dir<T>(dir: &Path, f: impl FnOnce() -> T) -> T {
+    let outer = DIR.with(|d| d.replace(Some(dir.to_path_buf())));
+    let result = f();
+    DIR.with(|d| d.replace(outer));
+    result
+}
+
+/// The directory which `yap` is running in; see [in_dir].
+pub fn cwd() -> io::Result<PathBuf> {
+    match DIR.with(|d| d.borrow().clone()) {
+        Some(dir) => Ok(dir),
+        None => env::current_dir(),
+    }
+}
+
+/// A [Command] to run `program` in [cwd].
+pub fn command(program: &str) -> Command {
+    let mut command = Command::new(program);
+    if let Some(dir) = DIR.with(|d| d.borrow().clone()) {
+        command.current_dir(dir);
+    }
+    command
+}
+
+/// `path`, relative to [cwd] if it is a relative path.
+pub fn resolve(path: &Path) -> PathBuf {
+    match cwd() {
+        Ok(cwd) if path.is_relative() => cwd.join(path),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Expand `paths` into a list of files. Directories are walked recursively,
+/// skipping hidden and ignored files. Files which are named explicitly are
+/// always included.
+pub fn expand(paths: &[PathBuf]) -> Result<Vec<PathBuf>, Error> {
+    let mut files = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+        for entry in WalkBuilder::new(path)
+            .add_custom_ignore_filename(IGNORE_FILE)
+            .sort_by_file_path(|a, b| a.cmp(b))
+            .build()
+        {
+            let entry = entry.map_err(|e| {
+                Error::default()
+                    .wrap(Oops::FilesError)
+                    .because(format!("Could not list files in {path:?}: {e}"))
+            })?;
+            if entry.file_type().is_some_and(|t| t.is_file()) {
+                files.push(entry.into_path());
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Walk `dir` like [expand], keeping only files which match at least one of
+/// the `include` globs (if there are any), and none of the `exclude` globs.
+/// Globs use `.gitignore` syntax, relative to `dir`; i.e, `*.rs` matches in
+/// any directory, but `tests/*` only matches `dir/tests`.
+pub fn walk(
+    dir: &Path,
+    include: &[String],
+    exclude: &[String],
+) -> Result<Vec<PathBuf>, Error> {
+    let oops =
+        |why: String| Error::default().wrap(Oops::FilesError).because(why);
+    let mut overrides = OverrideBuilder::new(dir);
+    let globs = include
+        .iter()
+        .cloned()
+        .chain(exclude.iter().map(|glob| format!("!{glob}")));
+    for glob in globs {
+        overrides
+            .add(&glob)
+            .map_err(|e| oops(format!("Bad glob {glob:?}: {e}")))?;
+    }
+    let overrides = overrides
+        .build()
+        .map_err(|e| oops(format!("Bad globs: {e}")))?;
+    let mut files = Vec::new();
+    for entry in WalkBuilder::new(dir)
+        .add_custom_ignore_filename(IGNORE_FILE)
+        .overrides(overrides)
+        .sort_by_file_path(|a, b| a.cmp(b))
+        .build()
+    {
+        let entry = entry.map_err(|e| {
+            oops(format!("Could not list files in {dir:?}: {e}"))
+        })?;
+        if entry.file_type().is_some_and(|t| t.is_file()) {
+            files.push(entry.into_path());
+        }
+    }
+    Ok(files)
+}
+
+/// The root of the git repository, or the current directory outside of a
+/// git repository; see [cwd].
+pub fn project_root() -> PathBuf {
+    let cwd = cwd().unwrap_or_else(|_| PathBuf::from("."));
+    let root = command("git")
+        .args(["rev-parse", "--show-toplevel"])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .map(|s| PathBuf::from(s.trim()))
+        .unwrap_or(cwd);
+    root.canonicalize().unwrap_or(root)
+}
+
+/// A stable identifier for the project at `root`, for naming per-project
+/// state; i.e, `~/.local/state/yap/contexts/<id>.json`.
+pub fn project_id(root: &Path) -> String {
+    Sha256::digest(root.as_os_str().as_encoded_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Matches paths against the `.yapignore` at the [project_root]. This is for
+/// paths which don't come from [expand], like the files in a diff.
+pub struct Ignores(Gitignore);
+
+impl Ignores {
+    pub fn load() -> Self {
+        Self::at(&project_root())
+    }
+    fn at(root: &Path) -> Self {
+        let (gitignore, e) = Gitignore::new(root.join(IGNORE_FILE));
+        if let Some(e) = e {
+            log::warn!("Problem with {IGNORE_FILE}: {e}");
+        }
+        Self(gitignore)
+    }
+    /// Whether `path`, relative to the root, is ignored.
+    pub fn ignores(&self, path: &Path) -> bool {
+        self.0.matched_path_or_any_parents(path, false).is_ignore()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_ignores() {
+        let root = std::env::temp_dir()
+            .join(format!("yap-test-ignores-{}", std::process::id()));
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join(IGNORE_FILE), "fixtures/\n*.snap\n").unwrap();
+        fs::write(root.join("src/lib.rs"), "").unwrap();
+        fs::write(root.join("src/lib.snap"), "").unwrap();
+
+        let ignores = Ignores::at(&root);
+        assert!(ignores.ignores(Path::new("fixtures/big.json")));
+        assert!(ignores.ignores(Path::new("src/lib.snap")));
+        assert!(!ignores.ignores(Path::new("src/lib.rs")));
+
+        let files = expand(std::slice::from_ref(&root)).unwrap();
+        assert_eq!(files, vec![root.join("src/lib.rs")]);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_in_dir() {
+        let dir = std::env::temp_dir()
+            .join(format!("yap-test-in-dir-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        let here = env::current_dir().unwrap();
+        let (inner, resolved, root) = in_dir(&dir, || {
+            (cwd().unwrap(), resolve(Path::new("a.rs")), project_root())
+        });
+        assert_eq!(inner, dir);
+        assert_eq!(resolved, dir.join("a.rs"));
+        assert_eq!(root, dir);
+        assert_eq!(resolve(Path::new("/a.rs")), Path::new("/a.rs"));
+        // The process, and other threads, stay where they were.
+        assert_eq!(env::current_dir().unwrap(), here);
+        assert_eq!(cwd().unwrap(), here);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_walk() {
+        let root = std::env::temp_dir()
+            .join(format!("yap-test-walk-{}", std::process::id()));
+        fs::create_dir_all(root.join("src/tests")).unwrap();
+        fs::create_dir_all(root.join("tests")).unwrap();
+        for file in ["src/lib.rs", "src/lib.py", "src/tests/a.rs", "tests/b.rs"]
+        {
+            fs::write(root.join(file), "").unwrap();
+        }
+
+        let files = walk(&root, &["*.rs".into()], &["tests/*".into()]).unwrap();
+        assert_eq!(
+            files,
+            vec![root.join("src/lib.rs"), root.join("src/tests/a.rs")]
+        );
+        assert_eq!(walk(&root, &[], &["*.rs".into()]).unwrap().len(), 1);
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,171 @@
+//! Post-processing for LLM output, configured per subcommand with
+//! `output_filters` in `config.json` (see [crate::config]); i.e,
+//!
+//! ```json
+//! {
+//!   "output_filters": {
+//!     "complete": ["strip_fences", {"command": "rustfmt --emit stdout"}],
+//!     "commit": ["trim"]
+//!   }
+//! }
+//! ```
+//!
+//! Filters run in order, after `yap`'s own post-processing, and before the
+//! output is printed or written to a file. They apply to `ask`, `changelog`,
+//! `chat` (except with `--stream`), `commit`, `complete`, and the output of
+//! each step of `run`, and to the new contents of each file which `refactor`
+//! changes. Chats are saved as the LLM wrote them.
+
+use crate::{
+    config::Settings,
+    err::{Error, Oops},
+    files,
+};
+use serde::Deserialize;
+use std::{io::Write, process::Stdio, thread};
+
+/// One step of an output filter.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Filter {
+    /// Keep only the contents of markdown fences; see [strip_fences].
+    StripFences,
+    /// Remove trailing whitespace from each line, and blank lines from the
+    /// start and end.
+    Trim,
+    /// Pipe the output through a shell command, and keep what it prints.
+    Command(String),
+}
+
+fn oops(why: String) -> Error {
+    Error::default().wrap(Oops::FilterError).because(why)
+}
+
+/// Run the filters configured for `command` on `text`.
+pub fn apply(command: &str, text: &str) -> Result<String, Error> {
+    let settings = Settings::load()?;
+    let filters = settings
+        .output_filters
+        .get(command)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let mut text = text.to_string();
+    for filter in filters {
+        text = run(filter, &text)?;
+    }
+    Ok(text)
+}
+
+fn run(filter: &Filter, text: &str) -> Result<String, Error> {
+    Ok(match filter {
+        Filter::StripFences => strip_fences(text),
+        Filter::Trim => trim(text),
+        Filter::Command(command) => pipe(command, text)?,
+    })
+}
+
+/// Models often wrap code in markdown fences despite the system prompt,
+/// with some prose before or after. If `text` has fenced blocks, only their
+/// contents are kept, so that the output can be inserted into a file as-is.
+/// Text without fences is returned unchanged.
+pub fn strip_fences(text: &str) -> String {
+    let mut code = Vec::new();
+    let mut in_block = false;
+    let mut fenced = false;
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_block = !in_block;
+            fenced = true;
+        } else if in_block {
+            code.push(line);
+        }
+    }
+    if fenced {
+        code.join("\n")
+    } else {
+        text.to_string()
+    }
+}
+
+fn trim(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().map(str::trim_end).collect();
+    let start = lines.iter().position(|l| !l.is_empty());
+    let end = lines.iter().rposition(|l| !l.is_empty());
+    match (start, end) {
+        (Some(start), Some(end)) => lines[start..=end].join("\n"),
+        _ => String::new(),
+    }
+}
+
+/// Run `command` with `sh -c`, with `text` as its `STDIN`.
+fn pipe(command: &str, text: &str) -> Result<String, Error> {
+    let mut child = files::command("sh")
+        .args(["-c", command])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| oops(format!("Could not run {command:?}: {e}")))?;
+    let mut stdin = child.stdin.take().expect("STDIN is piped");
+    let input = text.to_string();
+    // Write from another thread, so that a command which prints as it reads
+    // can't fill its `STDOUT` pipe while we are blocked on its `STDIN`.
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child
+        .wait_with_output()
+        .map_err(|e| oops(format!("Could not run {command:?}: {e}")))?;
+    // A command may exit without reading all of its input.
+    let _ = writer.join();
+    if !output.status.success() {
+        return Err(oops(format!(
+            "{command:?} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    String::from_utf8(output.stdout)
+        .map_err(|e| oops(format!("{command:?} printed invalid utf-8: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filters() {
+        let filters: Vec<Filter> = serde_json::from_str(
+            r#"["strip_fences", "trim", {"command": "tr a-z A-Z"}]"#,
+        )
+        .unwrap();
+        assert_eq!(filters[2], Filter::Command("tr a-z A-Z".into()));
+        let mut text = "Sure!\n```rust\n\nfn a() {}  \n```\nDone.".to_string();
+        for filter in &filters {
+            text = run(filter, &text).unwrap();
+        }
+        assert_eq!(text, "FN A() {}");
+        assert!(run(&Filter::Command("exit 1".into()), "x").is_err());
+        assert!(serde_json::from_str::<Filter>(r#""rustfmt""#).is_err());
+    }
+
+    #[test]
+    fn test_strip_fences() {
+        assert_eq!(
+            strip_fences(
+                "Here is the code:\n```rust\nfn a() {}\n\nfn b() {}\n```\n\
+                Let me know if you need anything else!"
+            ),
+            "fn a() {}\n\nfn b() {}"
+        );
+        assert_eq!(
+            strip_fences("```\nlet x = 1;\n```\nand\n```\nlet y = 2;\n```"),
+            "let x = 1;\nlet y = 2;"
+        );
+        assert_eq!(strip_fences("```\nunclosed"), "unclosed");
+        assert_eq!(strip_fences("x + 1"), "x + 1");
+    }
+
+    #[test]
+    fn test_trim() {
+        assert_eq!(trim("\n  \n  a  \n\nb\t\n\n"), "  a\n\nb");
+        assert_eq!(trim(" \n "), "");
+    }
+}
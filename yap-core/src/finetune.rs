@@ -0,0 +1,246 @@
+//! Fine-tune a model on your own chats, with `yap finetune`; i.e, to teach
+//! a cheaper model the answers you liked from a bigger one.
+//!
+//! - `yap finetune prepare` writes chats as a JSONL training file; the
+//!   active chat, or chats picked with `--chat` or `--tag`. Refusals and
+//!   truncated replies are left out. Review the file before submitting it;
+//!   OpenAI needs at least 10 examples.
+//! - `yap finetune submit <file>` uploads the file, and starts fine-tuning
+//!   the model chosen with `yap --model` (or `command_models.finetune` in
+//!   `config.json`).
+//! - `yap finetune status` shows the job's progress, and keeps polling until
+//!   it finishes with `--wait`.
+//!
+//! Once the job succeeds, its `ft:` model ID can be passed to `yap --model`,
+//! or used in `config.json`, like any other model. The most recently
+//! submitted job is remembered in `~/.local/state/yap/finetunes`.
+
+use crate::{
+    date, db,
+    err::{Error, Oops},
+    openai::{finetune_api, Message, Model, OpenAI, Role},
+};
+use serde_json::{json, Value};
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+use uuid::Uuid;
+
+/// How often `yap finetune status --wait` checks on the job.
+const POLL: Duration = Duration::from_secs(30);
+
+/// The number of events shown by `yap finetune status`.
+const EVENTS: usize = 5;
+
+fn oops(why: String) -> Error {
+    Error::default().wrap(Oops::FinetuneError).because(why)
+}
+
+fn get_or_create_finetune_dir() -> Result<PathBuf, Error> {
+    let dir = db::get_or_create_persistence_dir()?.join("finetunes");
+    fs::create_dir_all(&dir).map_err(|e| {
+        oops(format!("Failed to create fine-tune directory: {e}"))
+    })?;
+    Ok(dir)
+}
+
+/// The given job ID, or else the most recently submitted one.
+fn resolve_id(id: Option<&str>) -> Result<String, Error> {
+    if let Some(id) = id {
+        return Ok(id.to_string());
+    }
+    let path = get_or_create_finetune_dir()?.join("latest");
+    fs::read_to_string(&path)
+        .map(|id| id.trim().to_string())
+        .map_err(|_| {
+            oops("No job ID was given, and no job has been submitted".into())
+        })
+}
+
+/// Entrypoint for `yap finetune prepare`. Writes the training file to
+/// `output`, or else to `STDOUT`.
+pub fn prepare(
+    chats: &[Uuid],
+    tags: &[String],
+    output: Option<&Path>,
+) -> Result<(), Error> {
+    let mut ids: Vec<Uuid> = chats.to_vec();
+    if !tags.is_empty() {
+        for convo in db::list_conversations()? {
+            let id = convo.uuid()?;
+            let chat = db::get_chat(&id)?;
+            if tags.iter().all(|t| chat.tags.contains(t)) {
+                ids.push(id);
+            }
+        }
+    }
+    if chats.is_empty() && tags.is_empty() {
+        ids.push(db::get_active_chat()?.ok_or_else(|| {
+            oops("No chat is active; pass --chat or --tag".into())
+        })?);
+    }
+    let mut seen = HashSet::new();
+    ids.retain(|id| seen.insert(*id));
+
+    let mut jsonl = String::new();
+    let mut count = 0;
+    for id in &ids {
+        match example(&db::get_chat(id)?.messages) {
+            Some(example) => {
+                jsonl.push_str(&example.to_string());
+                jsonl.push('\n');
+                count += 1;
+            }
+            None => eprintln!("Skipping chat {id}, which has no replies"),
+        }
+    }
+    if count == 0 {
+        return Err(oops("None of the chats have any replies".into()));
+    }
+    match output {
+        Some(path) => {
+            fs::write(path, jsonl)
+                .map_err(|e| oops(format!("Could not write {path:?}: {e}")))?;
+            eprintln!("Wrote {count} examples to {}", path.display());
+        }
+        None => print!("{jsonl}"),
+    }
+    Ok(())
+}
+
+/// One training example, in OpenAI's chat format. `None` if there is no
+/// complete reply to learn from.
+fn example(messages: &[Message]) -> Option<Value> {
+    let messages: Vec<Value> = messages
+        .iter()
+        .filter(|m| !m.truncated)
+        .filter_map(|m| {
+            let role = match m.role {
+                Role::System | Role::Developer => "system",
+                Role::User => "user",
+                Role::Assistant => "assistant",
+            };
+            Some(json!({"role": role, "content": m.content.as_deref()?}))
+        })
+        .collect();
+    messages
+        .iter()
+        .any(|m| m["role"] == "assistant")
+        .then(|| json!({"messages": messages}))
+}
+
+/// The model ID to fine-tune for `model`. OpenAI only fine-tunes dated
+/// snapshots, so aliases are pinned to the snapshot they pointed to when
+/// fine-tuning was released.
+fn base_model(model: &Model) -> Result<String, Error> {
+    match model {
+        Model::Gpt4oMini => Ok("gpt-4o-mini-2024-07-18".into()),
+        Model::Gpt4o => Ok("gpt-4o-2024-08-06".into()),
+        // Fine-tuned models can be trained further.
+        Model::FineTuned(id) => Ok(id.to_string()),
+        model => Err(oops(format!(
+            "{model} can't be fine-tuned; pass --model gpt-4o-mini or gpt-4o"
+        ))),
+    }
+}
+
+/// Entrypoint for `yap finetune submit`. Prints the job ID.
+pub fn submit(
+    open_ai: &OpenAI,
+    file: &Path,
+    suffix: Option<&str>,
+) -> Result<(), Error> {
+    let model = base_model(&open_ai.model)?;
+    let jsonl = fs::read_to_string(file)
+        .map_err(|e| oops(format!("Could not read {file:?}: {e}")))?;
+    let file_id = finetune_api::upload_training_file(open_ai, &jsonl)?;
+    let job = finetune_api::create_job(open_ai, &file_id, &model, suffix)?;
+    fs::write(get_or_create_finetune_dir()?.join("latest"), &job.id)
+        .map_err(|e| oops(format!("Could not save job {}: {e}", job.id)))?;
+    println!("{}", job.id);
+    Ok(())
+}
+
+/// Entrypoint for `yap finetune status`. With `wait`, new events are
+/// printed until the job finishes.
+pub fn status(
+    open_ai: &OpenAI,
+    id: Option<&str>,
+    wait: bool,
+) -> Result<(), Error> {
+    let id = resolve_id(id)?;
+    let mut shown = HashSet::new();
+    loop {
+        let job = finetune_api::get_job(open_ai, &id)?;
+        for event in finetune_api::get_events(open_ai, &id, EVENTS)? {
+            if shown.insert(event.id) {
+                println!(
+                    "{}  {}",
+                    date::datetime(event.created_at),
+                    event.message
+                );
+            }
+        }
+        if !wait || job.is_finished() {
+            print_job(&job);
+            return Ok(());
+        }
+        thread::sleep(POLL);
+    }
+}
+
+fn print_job(job: &finetune_api::Job) {
+    let tokens = job
+        .trained_tokens
+        .map_or(String::new(), |t| format!(", {t} tokens trained"));
+    println!("{}: {} ({}{tokens})", job.id, job.status, job.model);
+    if let Some(message) = job.error.as_ref().and_then(|e| e.message.as_ref()) {
+        println!("{message}");
+    }
+    if let Some(model) = &job.fine_tuned_model {
+        println!("Use it with `yap --model {model}`");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_example() {
+        let mut truncated = Message::new(Role::Assistant, "Hel".into());
+        truncated.truncated = true;
+        let messages = vec![
+            Message::new(Role::Developer, "be brief".into()),
+            Message::new(Role::User, "hi".into()),
+            Message::new(Role::Assistant, "Hello!".into()),
+            Message::new(Role::User, "again".into()),
+            truncated,
+        ];
+        let example = example(&messages).unwrap();
+        let messages = example["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 4);
+        assert_eq!(
+            messages[0],
+            json!({"role": "system", "content": "be brief"})
+        );
+
+        let unanswered = [Message::new(Role::User, "hi".into())];
+        assert!(super::example(&unanswered).is_none());
+    }
+
+    #[test]
+    fn test_base_model() {
+        assert_eq!(
+            base_model(&Model::Gpt4oMini).unwrap(),
+            "gpt-4o-mini-2024-07-18"
+        );
+        let id = "ft:gpt-4o-mini-2024-07-18:acme::abc";
+        assert_eq!(base_model(&Model::FineTuned(id.into())).unwrap(), id);
+        assert!(base_model(&Model::O3Mini).is_err());
+    }
+}
@@ -0,0 +1,194 @@
+//! A small client for the GitHub REST API, for reviewing pull requests with
+//! `yap review --github owner/repo#123`.
+//!
+//! The token is read from `$GITHUB_TOKEN` (or else `$GH_TOKEN`), and needs
+//! read access to pull requests, or write access to post reviews. Set
+//! `$GITHUB_API_URL` to use GitHub Enterprise; it is already set inside of
+//! GitHub Actions.
+
+use crate::{
+    err::{Error, Oops},
+    http::{Body, Request},
+    review::Comment,
+};
+use log::debug;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{env, fmt::Display, str::FromStr};
+
+const DEFAULT_API: &str = "https://api.github.com";
+
+fn oops(why: String) -> Error {
+    Error::default().wrap(Oops::GitHubError).because(why)
+}
+
+/// A pull request, written as `owner/repo#123`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PullRequest {
+    pub owner: String,
+    pub repo: String,
+    pub number: u64,
+}
+
+impl FromStr for PullRequest {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let usage =
+            || format!("{s:?} is not a pull request like owner/repo#123");
+        let (path, number) = s.split_once('#').ok_or_else(usage)?;
+        let (owner, repo) = path.split_once('/').ok_or_else(usage)?;
+        if owner.is_empty() || repo.is_empty() || repo.contains('/') {
+            return Err(usage());
+        }
+        Ok(Self {
+            owner: owner.into(),
+            repo: repo.into(),
+            number: number.parse().map_err(|_| usage())?,
+        })
+    }
+}
+
+impl Display for PullRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}#{}", self.owner, self.repo, self.number)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Pull {
+    head: Head,
+}
+
+#[derive(Debug, Deserialize)]
+struct Head {
+    sha: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Review {
+    pub html_url: String,
+}
+
+fn token() -> Result<String, Error> {
+    env::var("GITHUB_TOKEN")
+        .or_else(|_| env::var("GH_TOKEN"))
+        .map_err(|_| oops("Set $GITHUB_TOKEN to review pull requests".into()))
+}
+
+fn url(pr: &PullRequest, rest: &str) -> String {
+    let api = env::var("GITHUB_API_URL").unwrap_or(DEFAULT_API.into());
+    format!(
+        "{}/repos/{}/{}/pulls/{}{rest}",
+        api.trim_end_matches('/'),
+        pr.owner,
+        pr.repo,
+        pr.number
+    )
+}
+
+fn request(method: &str, url: &str, accept: &str) -> Result<Request, Error> {
+    Ok(Request::new(method, url)
+        .set("Authorization", &format!("Bearer {}", token()?))
+        .set("Accept", accept)
+        .set("X-GitHub-Api-Version", "2022-11-28"))
+}
+
+fn parse<T: for<'de> Deserialize<'de>>(body: &str) -> Result<T, Error> {
+    serde_json::from_str(body).map_err(|e| {
+        debug!("Bad response body: {body}");
+        oops(format!("Could not deserialize the response: {e}"))
+    })
+}
+
+/// The pull request's changes, as a unified diff.
+pub fn diff(pr: &PullRequest) -> Result<String, Error> {
+    request("GET", &url(pr, ""), "application/vnd.github.diff")?
+        .call(Body::Empty)
+        .map_err(|e| {
+            e.wrap(Oops::GitHubError)
+                .because(format!("Could not fetch the diff of {pr}"))
+        })
+}
+
+/// Start a pending review of the pull request's latest commit, which only
+/// the token's owner can see until they submit it on GitHub. `comments`
+/// must be on lines in the diff.
+pub fn create_review(
+    pr: &PullRequest,
+    body: &str,
+    comments: &[Comment],
+) -> Result<Review, Error> {
+    let response = request("GET", &url(pr, ""), "application/vnd.github+json")?
+        .call(Body::Empty)
+        .map_err(|e| {
+            e.wrap(Oops::GitHubError)
+                .because(format!("Could not fetch {pr}"))
+        })?;
+    let pull: Pull = parse(&response)?;
+    let payload = review_payload(&pull.head.sha, body, comments);
+    let response =
+        request("POST", &url(pr, "/reviews"), "application/vnd.github+json")?
+            .call(Body::Json(&payload))
+            .map_err(|e| {
+                e.wrap(Oops::GitHubError)
+                    .because(format!("Could not create a review of {pr}"))
+            })?;
+    parse(&response)
+}
+
+/// Omitting `event` leaves the review pending.
+fn review_payload(commit_id: &str, body: &str, comments: &[Comment]) -> Value {
+    let comments: Vec<Value> = comments
+        .iter()
+        .map(|c| {
+            json!({
+                "path": c.path.to_string_lossy().replace('\\', "/"),
+                "line": c.line,
+                "side": "RIGHT",
+                "body": c.body,
+            })
+        })
+        .collect();
+    json!({
+        "commit_id": commit_id,
+        "body": body,
+        "comments": comments,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_parse_pull_request() {
+        let pr: PullRequest = "jdevries3133/yap#123".parse().unwrap();
+        assert_eq!(pr.owner, "jdevries3133");
+        assert_eq!(pr.repo, "yap");
+        assert_eq!(pr.number, 123);
+        assert_eq!(pr.to_string(), "jdevries3133/yap#123");
+        assert!("yap#123".parse::<PullRequest>().is_err());
+        assert!("a/b/c#1".parse::<PullRequest>().is_err());
+        assert!("a/b#x".parse::<PullRequest>().is_err());
+    }
+
+    #[test]
+    fn test_review_payload() {
+        let payload = review_payload(
+            "abc",
+            "Reviewed by yap",
+            &[Comment {
+                path: PathBuf::from("src/main.rs"),
+                line: 7,
+                old_line: None,
+                body: "unwrap".into(),
+            }],
+        );
+        assert_eq!(payload["commit_id"], "abc");
+        assert!(payload.get("event").is_none());
+        assert_eq!(payload["comments"][0]["path"], "src/main.rs");
+        assert_eq!(payload["comments"][0]["side"], "RIGHT");
+    }
+}
@@ -0,0 +1,276 @@
+//! A small client for the GitLab REST API, for reviewing merge requests with
+//! `yap review --gitlab group/project!123`.
+//!
+//! The token is read from `$GITLAB_TOKEN`, and needs the `api` scope to post
+//! discussions (or `read_api`, to only review). The instance is read from
+//! `$GITLAB_URL`, or else `$CI_SERVER_URL`, which is set inside of GitLab
+//! CI; it defaults to <https://gitlab.com>.
+
+use crate::{
+    err::{Error, Oops},
+    http::{Body, Request},
+    review::Comment,
+};
+use log::debug;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{env, fmt::Display, str::FromStr};
+
+const DEFAULT_URL: &str = "https://gitlab.com";
+
+/// How many files are fetched per page of the diff.
+const PER_PAGE: usize = 100;
+
+fn oops(why: String) -> Error {
+    Error::default().wrap(Oops::GitLabError).because(why)
+}
+
+/// A merge request, written as `group/project!123`. Projects may be nested
+/// in subgroups; i.e, `group/subgroup/project!123`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MergeRequest {
+    pub project: String,
+    pub iid: u64,
+}
+
+impl FromStr for MergeRequest {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let usage =
+            || format!("{s:?} is not a merge request like group/project!123");
+        let (project, iid) = s.rsplit_once('!').ok_or_else(usage)?;
+        if !project.contains('/')
+            || project.split('/').any(|part| part.is_empty())
+        {
+            return Err(usage());
+        }
+        Ok(Self {
+            project: project.into(),
+            iid: iid.parse().map_err(|_| usage())?,
+        })
+    }
+}
+
+impl Display for MergeRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}!{}", self.project, self.iid)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Mr {
+    web_url: String,
+    diff_refs: DiffRefs,
+}
+
+/// The commits which positions in the diff are relative to.
+#[derive(Debug, Deserialize)]
+struct DiffRefs {
+    base_sha: String,
+    start_sha: String,
+    head_sha: String,
+}
+
+/// One file in the merge request's diff.
+#[derive(Debug, Deserialize)]
+struct FileChange {
+    old_path: String,
+    new_path: String,
+    /// The file's hunks, without a `---`/`+++` header.
+    diff: String,
+    new_file: bool,
+    deleted_file: bool,
+}
+
+fn token() -> Result<String, Error> {
+    env::var("GITLAB_TOKEN")
+        .map_err(|_| oops("Set $GITLAB_TOKEN to review merge requests".into()))
+}
+
+fn url(mr: &MergeRequest, rest: &str) -> String {
+    let base = env::var("GITLAB_URL")
+        .or_else(|_| env::var("CI_SERVER_URL"))
+        .unwrap_or(DEFAULT_URL.into());
+    // Project paths are passed as one URL-encoded path segment.
+    format!(
+        "{}/api/v4/projects/{}/merge_requests/{}{rest}",
+        base.trim_end_matches('/'),
+        mr.project.replace('/', "%2F"),
+        mr.iid
+    )
+}
+
+fn request(method: &str, url: &str) -> Result<Request, Error> {
+    Ok(Request::new(method, url).set("PRIVATE-TOKEN", &token()?))
+}
+
+fn parse<T: for<'de> Deserialize<'de>>(body: &str) -> Result<T, Error> {
+    serde_json::from_str(body).map_err(|e| {
+        debug!("Bad response body: {body}");
+        oops(format!("Could not deserialize the response: {e}"))
+    })
+}
+
+fn get_mr(mr: &MergeRequest) -> Result<Mr, Error> {
+    let response =
+        request("GET", &url(mr, ""))?
+            .call(Body::Empty)
+            .map_err(|e| {
+                e.wrap(Oops::GitLabError)
+                    .because(format!("Could not fetch {mr}"))
+            })?;
+    parse(&response)
+}
+
+/// The merge request's changes, as a unified diff.
+pub fn diff(mr: &MergeRequest) -> Result<String, Error> {
+    let mut changes = Vec::new();
+    for page in 1.. {
+        let page_url =
+            url(mr, &format!("/diffs?page={page}&per_page={PER_PAGE}"));
+        let response =
+            request("GET", &page_url)?.call(Body::Empty).map_err(|e| {
+                e.wrap(Oops::GitLabError)
+                    .because(format!("Could not fetch the diff of {mr}"))
+            })?;
+        let files: Vec<FileChange> = parse(&response)?;
+        let done = files.len() < PER_PAGE;
+        changes.extend(files);
+        if done {
+            break;
+        }
+    }
+    Ok(unified_diff(&changes))
+}
+
+/// Join GitLab's per-file diffs into one unified diff. Files without hunks
+/// (i.e, binary files) are left out.
+fn unified_diff(changes: &[FileChange]) -> String {
+    let mut out = String::new();
+    for change in changes.iter().filter(|c| !c.diff.is_empty()) {
+        let old = if change.new_file {
+            "/dev/null".to_string()
+        } else {
+            format!("a/{}", change.old_path)
+        };
+        let new = if change.deleted_file {
+            "/dev/null".to_string()
+        } else {
+            format!("b/{}", change.new_path)
+        };
+        out.push_str(&format!(
+            "diff --git a/{} b/{}\n--- {old}\n+++ {new}\n{}",
+            change.old_path, change.new_path, change.diff
+        ));
+        if !change.diff.ends_with('\n') {
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Start a discussion on the line of each of `comments`, and then one more
+/// with `body`. Returns the merge request's URL.
+pub fn create_discussions(
+    mr: &MergeRequest,
+    body: &str,
+    comments: &[Comment],
+) -> Result<String, Error> {
+    let info = get_mr(mr)?;
+    let mut payloads: Vec<Value> = comments
+        .iter()
+        .map(|c| discussion_payload(&info.diff_refs, c))
+        .collect();
+    payloads.push(json!({ "body": body }));
+    for payload in payloads {
+        request("POST", &url(mr, "/discussions"))?
+            .call(Body::Json(&payload))
+            .map_err(|e| {
+                e.wrap(Oops::GitLabError)
+                    .because(format!("Could not start a discussion on {mr}"))
+            })?;
+    }
+    Ok(info.web_url)
+}
+
+/// GitLab anchors added lines by their new line number, and unchanged lines
+/// by both their old and new line numbers.
+fn discussion_payload(refs: &DiffRefs, comment: &Comment) -> Value {
+    let path = comment.path.to_string_lossy().replace('\\', "/");
+    let mut position = json!({
+        "position_type": "text",
+        "base_sha": refs.base_sha,
+        "start_sha": refs.start_sha,
+        "head_sha": refs.head_sha,
+        "old_path": path,
+        "new_path": path,
+        "new_line": comment.line,
+    });
+    if let Some(old_line) = comment.old_line {
+        position["old_line"] = json!(old_line);
+    }
+    json!({ "body": comment.body, "position": position })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_parse_merge_request() {
+        let mr: MergeRequest = "group/sub/project!42".parse().unwrap();
+        assert_eq!(mr.project, "group/sub/project");
+        assert_eq!(mr.iid, 42);
+        assert_eq!(mr.to_string(), "group/sub/project!42");
+        assert!("project!42".parse::<MergeRequest>().is_err());
+        assert!("group//project!42".parse::<MergeRequest>().is_err());
+        assert!("group/project#42".parse::<MergeRequest>().is_err());
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let change = |path: &str, diff: &str, new_file| FileChange {
+            old_path: path.into(),
+            new_path: path.into(),
+            diff: diff.into(),
+            new_file,
+            deleted_file: false,
+        };
+        let diff = unified_diff(&[
+            change("a.rs", "@@ -1 +1 @@\n-a\n+b", false),
+            change("new.rs", "@@ -0,0 +1 @@\n+c\n", true),
+            change("logo.png", "", false),
+        ]);
+        assert_eq!(
+            diff,
+            "diff --git a/a.rs b/a.rs\n--- a/a.rs\n+++ b/a.rs\n\
+            @@ -1 +1 @@\n-a\n+b\n\
+            diff --git a/new.rs b/new.rs\n--- /dev/null\n+++ b/new.rs\n\
+            @@ -0,0 +1 @@\n+c\n"
+        );
+        assert_eq!(crate::diff::parse(&diff).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_discussion_payload() {
+        let refs = DiffRefs {
+            base_sha: "base".into(),
+            start_sha: "start".into(),
+            head_sha: "head".into(),
+        };
+        let comment = |old_line| Comment {
+            path: PathBuf::from("src/main.rs"),
+            line: 7,
+            old_line,
+            body: "unwrap".into(),
+        };
+        let added = discussion_payload(&refs, &comment(None));
+        assert_eq!(added["position"]["new_line"], 7);
+        assert!(added["position"].get("old_line").is_none());
+        let unchanged = discussion_payload(&refs, &comment(Some(5)));
+        assert_eq!(unchanged["position"]["old_line"], 5);
+        assert_eq!(unchanged["position"]["head_sha"], "head");
+    }
+}
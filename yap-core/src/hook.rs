@@ -0,0 +1,111 @@
+//! Install git hooks which run `yap`.
+//!
+//! `yap hook install` writes a `pre-commit` hook which runs `yap review
+//! --diff-cached` (see [crate::review]), and a `commit-msg` hook which runs
+//! `yap commit --verify` (see [crate::commit]). Both commands skip the LLM
+//! when the staged change is empty or whitespace-only, so trivial commits
+//! stay fast.
+//!
+//! Existing hooks which were not written by `yap` are left alone unless
+//! `--force` is passed.
+
+use crate::err::{Error, Oops};
+use std::{fs, path::PathBuf, process::Command};
+
+/// Identifies hooks written by `yap`, so that they can be safely replaced.
+const MARKER: &str = "# Installed by `yap hook install`.";
+
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum Hook {
+    /// Review staged changes with `yap review --diff-cached`.
+    PreCommit,
+    /// Check commit messages with `yap commit --verify`.
+    CommitMsg,
+}
+
+impl Hook {
+    fn filename(&self) -> &'static str {
+        match self {
+            Self::PreCommit => "pre-commit",
+            Self::CommitMsg => "commit-msg",
+        }
+    }
+    fn script(&self) -> String {
+        let command = match self {
+            Self::PreCommit => "yap review --diff-cached",
+            Self::CommitMsg => "yap commit --verify \"$1\"",
+        };
+        format!("#!/bin/sh\n{MARKER}\nexec {command}\n")
+    }
+}
+
+/// Entrypoint for `yap hook install`. Installs `only`, or else every [Hook].
+pub fn install(only: Option<Hook>, force: bool) -> Result<(), Error> {
+    let hooks = match only {
+        Some(hook) => vec![hook],
+        None => vec![Hook::PreCommit, Hook::CommitMsg],
+    };
+    let dir = hooks_dir()?;
+    fs::create_dir_all(&dir).map_err(|e| {
+        Error::default()
+            .wrap(Oops::HookError)
+            .because(format!("Could not create {dir:?}: {e}"))
+    })?;
+    for hook in hooks {
+        let path = dir.join(hook.filename());
+        if let Ok(existing) = fs::read_to_string(&path) {
+            if !existing.contains(MARKER) && !force {
+                return Err(Error::default().wrap(Oops::HookError).because(
+                    format!(
+                        "{path:?} already exists. Pass --force to replace it."
+                    ),
+                ));
+            }
+        }
+        fs::write(&path, hook.script()).map_err(|e| {
+            Error::default()
+                .wrap(Oops::HookError)
+                .because(format!("Could not write {path:?}: {e}"))
+        })?;
+        make_executable(&path)?;
+        println!("Installed {}", path.display());
+    }
+    Ok(())
+}
+
+/// Ask git where hooks live, which respects `core.hooksPath`.
+fn hooks_dir() -> Result<PathBuf, Error> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--git-path", "hooks"])
+        .output()
+        .map_err(|e| {
+            Error::default()
+                .wrap(Oops::CommandError)
+                .because(format!("could not run `git rev-parse`: {e}"))
+        })?;
+    if !output.status.success() {
+        return Err(Error::default().wrap(Oops::HookError).because(format!(
+            "Could not find the git hooks directory; are you in a git repository? {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(PathBuf::from(
+        String::from_utf8_lossy(&output.stdout).trim(),
+    ))
+}
+
+#[cfg(unix)]
+fn make_executable(path: &std::path::Path) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755)).map_err(|e| {
+        Error::default()
+            .wrap(Oops::HookError)
+            .because(format!("Could not make {path:?} executable: {e}"))
+    })
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &std::path::Path) -> Result<(), Error> {
+    Ok(())
+}
@@ -0,0 +1,131 @@
+//! The HTTP client which every request goes through; to OpenAI (see
+//! [crate::openai]), and to GitHub and GitLab for `yap review`.
+//!
+//! Requests are async, and run on the runtime in [crate::pool]. A
+//! [Request] is built up front, so that it can be captured with
+//! `--debug-http` before it is sent.
+
+use crate::{
+    err::{Error, Oops},
+    pool,
+};
+use serde_json::Value;
+use std::sync::OnceLock;
+
+/// What is sent with a request.
+pub enum Body<'a> {
+    Empty,
+    Json(&'a Value),
+    Text(&'a str),
+    Bytes(&'a [u8]),
+}
+
+/// A request which has not been sent yet.
+pub struct Request {
+    method: String,
+    url: String,
+    /// Names are lowercase, as they are sent with HTTP/2.
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    pub fn new(method: &str, url: &str) -> Self {
+        Self {
+            method: method.to_string(),
+            url: url.to_string(),
+            headers: Vec::new(),
+        }
+    }
+
+    /// Set the header `name` to `value`, replacing any earlier value.
+    pub fn set(mut self, name: &str, value: &str) -> Self {
+        let name = name.to_lowercase();
+        self.headers.retain(|(n, _)| *n != name);
+        self.headers.push((name, value.to_string()));
+        self
+    }
+
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        let name = name.to_lowercase();
+        self.headers
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Each header's name and value, in the order they were set.
+    pub fn headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    /// Send the request with `body`. The response may be unsuccessful; only
+    /// requests which got no response at all are errors.
+    pub async fn send(
+        self,
+        body: Body<'_>,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let method = reqwest::Method::from_bytes(self.method.as_bytes())
+            .expect("requests are made with standard methods");
+        let mut builder = client().request(method, &self.url);
+        if matches!(body, Body::Json(_))
+            && self.header("content-type").is_none()
+        {
+            builder = builder.header("content-type", "application/json");
+        }
+        for (name, value) in &self.headers {
+            builder = builder.header(name, value);
+        }
+        match body {
+            Body::Empty => builder,
+            Body::Json(value) => builder.body(value.to_string()),
+            Body::Text(text) => builder.body(text.to_string()),
+            Body::Bytes(bytes) => builder.body(bytes.to_vec()),
+        }
+        .send()
+        .await
+    }
+
+    /// Send the request with `body`, blocking until the whole response has
+    /// been read. Unsuccessful responses are errors; see
+    /// [Error::wrap_http_status].
+    pub fn call(self, body: Body<'_>) -> Result<String, Error> {
+        pool::block_on(async {
+            let response = self
+                .send(body)
+                .await
+                .map_err(|e| Error::default().wrap_http(e))?;
+            let status = response.status().as_u16();
+            let url = response.url().to_string();
+            let text = response.text().await.map_err(|e| {
+                Error::default()
+                    .wrap(Oops::HttpMetaError)
+                    .because(format!("Could not read the response: {e}"))
+            })?;
+            if status >= 400 {
+                return Err(Error::default().wrap_http_status(
+                    status,
+                    &url,
+                    Some(&text),
+                ));
+            }
+            Ok(text)
+        })
+    }
+}
+
+/// The client shared by every request in the process, so that requests
+/// reuse connections instead of paying for a TLS handshake each time; i.e,
+/// when `yap annotate --dir` or `yap index` send many requests, or in `yap
+/// daemon`.
+pub fn client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(reqwest::Client::new)
+}
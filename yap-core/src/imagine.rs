@@ -0,0 +1,66 @@
+//! Generate images with `yap imagine`; i.e, to sketch a diagram, or make a
+//! quick placeholder asset.
+
+use crate::{
+    err::{Error, Oops},
+    openai::{images_api, OpenAI},
+};
+use std::{
+    fs::OpenOptions,
+    io::{self, ErrorKind, Read, Write},
+    path::Path,
+};
+
+fn oops(why: String) -> Error {
+    Error::default().wrap(Oops::ImagineError).because(why)
+}
+
+/// Entrypoint for `yap imagine`. Draws `prompt` (or else the prompt on
+/// `STDIN`), and saves it to `output` as a PNG. Existing files are never
+/// overwritten. The prompt which the model actually drew, which it rewrites
+/// for itself, is printed to `STDERR`.
+pub fn imagine(
+    open_ai: &OpenAI,
+    prompt: &[String],
+    size: images_api::Size,
+    output: &Path,
+) -> Result<(), Error> {
+    let prompt = if prompt.is_empty() {
+        let mut prompt = String::new();
+        io::stdin().read_to_string(&mut prompt).map_err(|e| {
+            Error::default()
+                .wrap(Oops::ImagineError)
+                .wrap(Oops::StdinReadError)
+                .because(e.kind().to_string())
+        })?;
+        prompt
+    } else {
+        prompt.join(" ")
+    };
+    if prompt.trim().is_empty() {
+        return Err(oops("The prompt is empty!".into()));
+    }
+    if output.extension().is_none_or(|e| e != "png") {
+        return Err(oops(format!("{output:?} must be a .png file")));
+    }
+    // Check before spending money on the image.
+    if output.exists() {
+        return Err(oops(format!("{output:?} already exists")));
+    }
+    let image = images_api::generate(open_ai, prompt.trim(), size)?;
+    if let Some(revised_prompt) = image.revised_prompt {
+        eprintln!("{revised_prompt}");
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(output)
+        .map_err(|e| match e.kind() {
+            ErrorKind::AlreadyExists => {
+                oops(format!("{output:?} already exists"))
+            }
+            _ => oops(format!("Could not create {output:?}: {e}")),
+        })?;
+    file.write_all(&image.png)
+        .map_err(|e| oops(format!("Could not write {output:?}: {e}")))
+}
@@ -0,0 +1,204 @@
+//! Build an embedding index of the project with `yap index`.
+//!
+//! Each file is split into chunks; one per top-level function or type where
+//! [syntax] can parse the file, or else fixed windows of lines. Chunks are
+//! embedded through [embeddings], so re-running `yap index` only embeds the
+//! chunks which changed since the last run.
+//!
+//! The index lists each chunk's location and [embeddings::key], and is kept
+//! in `~/.local/state/yap/indexes`, keyed by [files::project_id]. The
+//! embeddings themselves stay in the cache.
+
+use crate::{
+    db, embeddings,
+    err::{Error, Oops},
+    files,
+    openai::{embeddings_api::EMBEDDING_MODEL, estimate_tokens, OpenAI},
+    syntax,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
+
+/// The size of chunks in files which can't be parsed.
+const WINDOW: usize = 40;
+
+/// Larger units are split into windows, since embedding models only accept
+/// so much text, and very long chunks don't embed very precisely.
+const MAX_CHUNK_TOKENS: usize = 2000;
+
+/// Larger files (i.e, lockfiles and generated code) are not indexed.
+const MAX_FILE_BYTES: u64 = 512 * 1024;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Index {
+    pub root: PathBuf,
+    pub model: String,
+    pub chunks: Vec<Chunk>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Chunk {
+    /// Relative to [Index::root].
+    pub file: PathBuf,
+    pub line_start: usize,
+    pub line_end: usize,
+    /// The chunk's key in [embeddings].
+    pub key: String,
+}
+
+fn oops(why: String) -> Error {
+    Error::default().wrap(Oops::IndexError).because(why)
+}
+
+fn root() -> Result<PathBuf, Error> {
+    let root = files::project_root();
+    root.canonicalize()
+        .map_err(|e| oops(format!("Could not resolve {root:?}: {e}")))
+}
+
+fn path_for(root: &Path) -> Result<PathBuf, Error> {
+    let dir = db::get_or_create_persistence_dir()?.join("indexes");
+    fs::create_dir_all(&dir)
+        .map_err(|e| oops(format!("Could not create {dir:?}: {e}")))?;
+    Ok(dir.join(format!("{}.json", files::project_id(root))))
+}
+
+/// The text which is embedded for lines `lines` of `file`. The file's name
+/// is included, since it says a lot about what the code is for.
+fn text(
+    file: &Path,
+    source_lines: &[&str],
+    lines: &RangeInclusive<usize>,
+) -> String {
+    format!(
+        "File: {}\n{}",
+        file.display(),
+        source_lines[lines.start() - 1..*lines.end()].join("\n")
+    )
+}
+
+/// The line ranges to embed in `source`.
+fn ranges(path: &Path, source: &str) -> Vec<RangeInclusive<usize>> {
+    let lines: Vec<&str> = source.lines().collect();
+    let units: Vec<RangeInclusive<usize>> = syntax::units(path, source)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|u| u.depth == 0)
+        .map(|u| *u.lines.start()..=(*u.lines.end()).min(lines.len()))
+        .collect();
+    let mut ranges = Vec::new();
+    if units.is_empty() {
+        ranges.extend(windows(1..=lines.len()));
+    }
+    for unit in units {
+        let text = lines[unit.start() - 1..*unit.end()].join("\n");
+        if estimate_tokens(&text) > MAX_CHUNK_TOKENS {
+            ranges.extend(windows(unit));
+        } else {
+            ranges.push(unit);
+        }
+    }
+    ranges
+        .into_iter()
+        .filter(|r| {
+            lines[r.start() - 1..*r.end()]
+                .iter()
+                .any(|l| !l.trim().is_empty())
+        })
+        .collect()
+}
+
+/// The index of the project around the current directory.
+pub fn load() -> Result<Index, Error> {
+    let path = path_for(&root()?)?;
+    let contents = fs::read_to_string(&path).map_err(|_| {
+        oops("This project has not been indexed; run `yap index` first".into())
+    })?;
+    serde_json::from_str(&contents)
+        .map_err(|e| oops(format!("{path:?} is invalid: {e}")))
+}
+
+/// Split `range` into [WINDOW]s.
+fn windows(range: RangeInclusive<usize>) -> Vec<RangeInclusive<usize>> {
+    let mut windows = Vec::new();
+    let mut start = *range.start();
+    while start <= *range.end() {
+        let end = (start + WINDOW - 1).min(*range.end());
+        windows.push(start..=end);
+        start = end + 1;
+    }
+    windows
+}
+
+/// Entrypoint for `yap index`.
+pub fn index(open_ai: &OpenAI) -> Result<(), Error> {
+    let root = root()?;
+    let paths = files::expand(std::slice::from_ref(&root))?;
+    let mut chunks = Vec::new();
+    let mut texts = Vec::new();
+    let mut file_count = 0;
+    for path in &paths {
+        if fs::metadata(path).is_ok_and(|m| m.len() > MAX_FILE_BYTES) {
+            continue;
+        }
+        // Binary files are skipped.
+        let Ok(source) = fs::read_to_string(path) else {
+            continue;
+        };
+        let file = path.strip_prefix(&root).unwrap_or(path).to_path_buf();
+        let lines: Vec<&str> = source.lines().collect();
+        let ranges = ranges(path, &source);
+        if !ranges.is_empty() {
+            file_count += 1;
+        }
+        for range in ranges {
+            let text = text(&file, &lines, &range);
+            chunks.push(Chunk {
+                file: file.clone(),
+                line_start: *range.start(),
+                line_end: *range.end(),
+                key: embeddings::key(&text),
+            });
+            texts.push(text);
+        }
+    }
+    let (_, embedded) = embeddings::embed(open_ai, &texts)?;
+    let index = Index {
+        root: root.clone(),
+        model: EMBEDDING_MODEL.to_string(),
+        chunks,
+    };
+    let path = path_for(&root)?;
+    let contents = serde_json::to_string(&index)
+        .map_err(|e| oops(format!("Could not serialize the index: {e}")))?;
+    fs::write(&path, contents)
+        .map_err(|e| oops(format!("Could not write {path:?}: {e}")))?;
+    eprintln!(
+        "Indexed {} chunks from {file_count} files; {embedded} chunks were new or changed.",
+        index.chunks.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ranges() {
+        let source = "use std::fs;\n\nfn a() {}\n\nfn b() {\n    todo!()\n}\n";
+        assert_eq!(ranges(Path::new("x.rs"), source), vec![3..=3, 5..=7]);
+
+        let source =
+            (1..=100).map(|i| format!("line {i}\n")).collect::<String>();
+        assert_eq!(
+            ranges(Path::new("x.txt"), &source),
+            vec![1..=40, 41..=80, 81..=100]
+        );
+        assert!(ranges(Path::new("x.txt"), "\n\n").is_empty());
+    }
+}
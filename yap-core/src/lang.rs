@@ -0,0 +1,119 @@
+//! Programming languages that `yap` knows about, so that prompts and output
+//! can be tailored to them.
+
+use std::path::Path;
+
+#[derive(Debug, PartialEq)]
+pub struct Language {
+    pub name: &'static str,
+    /// File extensions, without the leading dot. The first is the most
+    /// common.
+    pub extensions: &'static [&'static str],
+    /// Whether code is indented with tabs by convention (or by necessity,
+    /// in the case of Makefiles).
+    pub tab_indented: bool,
+    /// The prefix and suffix of a one-line comment; i.e, `("# ", "")`.
+    pub comment: (&'static str, &'static str),
+}
+
+const fn lang(
+    name: &'static str,
+    extensions: &'static [&'static str],
+) -> Language {
+    Language {
+        name,
+        extensions,
+        tab_indented: false,
+        comment: ("// ", ""),
+    }
+}
+
+impl Language {
+    const fn comment(self, prefix: &'static str, suffix: &'static str) -> Self {
+        Self {
+            comment: (prefix, suffix),
+            ..self
+        }
+    }
+}
+
+const LANGUAGES: &[Language] = &[
+    lang("Rust", &["rs"]),
+    lang("Python", &["py", "pyi"]).comment("# ", ""),
+    lang("JavaScript", &["js", "mjs", "cjs", "jsx"]),
+    lang("TypeScript", &["ts", "tsx", "mts", "cts"]),
+    Language {
+        name: "Go",
+        extensions: &["go"],
+        tab_indented: true,
+        comment: ("// ", ""),
+    },
+    lang("C", &["c", "h"]),
+    lang("C++", &["cpp", "cc", "cxx", "hpp", "hh"]),
+    lang("C#", &["cs"]),
+    lang("Java", &["java"]),
+    lang("Kotlin", &["kt", "kts"]),
+    lang("Swift", &["swift"]),
+    lang("Ruby", &["rb"]).comment("# ", ""),
+    lang("PHP", &["php"]),
+    lang("Lua", &["lua"]).comment("-- ", ""),
+    lang("Haskell", &["hs"]).comment("-- ", ""),
+    lang("Elixir", &["ex", "exs"]).comment("# ", ""),
+    lang("Shell", &["sh", "bash", "zsh"]).comment("# ", ""),
+    lang("SQL", &["sql"]).comment("-- ", ""),
+    lang("HTML", &["html", "htm"]).comment("<!-- ", " -->"),
+    lang("CSS", &["css", "scss"]).comment("/* ", " */"),
+    lang("Markdown", &["md", "markdown"]).comment("<!-- ", " -->"),
+    lang("JSON", &["json"]),
+    lang("YAML", &["yaml", "yml"]).comment("# ", ""),
+    lang("TOML", &["toml"]).comment("# ", ""),
+    lang("Nix", &["nix"]).comment("# ", ""),
+    Language {
+        name: "Makefile",
+        extensions: &["mk"],
+        tab_indented: true,
+        comment: ("# ", ""),
+    },
+];
+
+/// Look up a language by name or extension, ignoring case; e.g, `rust` or
+/// `rs`.
+pub fn by_name(name: &str) -> Option<&'static Language> {
+    let name = name.trim_start_matches('.');
+    LANGUAGES.iter().find(|l| {
+        l.name.eq_ignore_ascii_case(name)
+            || l.extensions.iter().any(|e| e.eq_ignore_ascii_case(name))
+    })
+}
+
+/// Guess the language of `path` from its extension (or its name, for
+/// Makefiles).
+pub fn by_path(path: &Path) -> Option<&'static Language> {
+    let file_name = path.file_name()?.to_str()?;
+    if ["Makefile", "makefile", "GNUmakefile"].contains(&file_name) {
+        return by_name("Makefile");
+    }
+    let extension = path.extension()?.to_str()?;
+    LANGUAGES.iter().find(|l| {
+        l.extensions
+            .iter()
+            .any(|e| e.eq_ignore_ascii_case(extension))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(by_name("rust").unwrap().name, "Rust");
+        assert_eq!(by_name("RS").unwrap().name, "Rust");
+        assert_eq!(by_name(".py").unwrap().name, "Python");
+        assert!(by_name("klingon").is_none());
+        assert_eq!(by_path(Path::new("src/main.rs")).unwrap().name, "Rust");
+        assert!(by_path(Path::new("Makefile")).unwrap().tab_indented);
+        assert!(by_path(Path::new("README")).is_none());
+        assert_eq!(by_path(Path::new("a.html")).unwrap().comment.1, " -->");
+    }
+}
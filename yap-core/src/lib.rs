@@ -0,0 +1,127 @@
+//! The logic behind [`yap`](https://github.com/jdevries3133/yap), as a
+//! library, so that other Rust tools and editor plugins can embed it rather
+//! than shelling out to the `yap` binary. The binary is a thin command-line
+//! interface over this crate; each `yap` subcommand has a module of the same
+//! name here, with a public entrypoint.
+//!
+//! The pieces most useful on their own are;
+//!
+//! - [openai]: the API client. [openai::OpenAI::from_env] reads the API key
+//!   and `config.json` like `yap` does; [openai::chat] and
+//!   [openai::chat_stream] send a [openai::CompletionPayload].
+//! - [annotate]: the annotation engine. [annotate::annotations] returns
+//!   annotations for a range of lines as JSON, without touching the file.
+//! - [db]: chat history, and other state under `~/.local/state/yap`.
+//! - Prompt assembly: the default system prompts in [constants], the
+//!   project's context files in [ctx], team conventions in [rules], and
+//!   trimming to the model's context window in [context].
+//! - [config]: `config.json`, and the other files in `$XDG_CONFIG_HOME/yap`.
+//! - [serve]: the JSON-RPC protocol which `yap serve` speaks, for editor
+//!   plugins which would rather talk to a long-lived process.
+//!
+//! Every fallible function returns an [err::Error].
+//!
+//! The `clap` feature derives `clap::ValueEnum` for the enums which `yap`
+//! takes as arguments (i.e, [openai::Provider] and [context::Strategy]), for
+//! tools which want the same flags. It is off by default.
+//!
+//! ```no_run
+//! use std::path::Path;
+//! use yap_core::{
+//!     annotate,
+//!     openai::{
+//!         self, ClientOpts, CompletionPayload, Message, OpenAI, PayloadOpts,
+//!         Role,
+//!     },
+//! };
+//!
+//! # fn main() -> Result<(), yap_core::err::Error> {
+//! let open_ai = OpenAI::from_env("my-tool", &ClientOpts::default())?;
+//! let payload = CompletionPayload::new(
+//!     &open_ai,
+//!     vec![Message::new(Role::User, "Say hello".into())],
+//!     PayloadOpts::default(),
+//! );
+//! let response = openai::chat(&open_ai, &payload)?;
+//! println!("{:?}", response.choices[0].message.content);
+//!
+//! let annotations = annotate::annotations(
+//!     &open_ai,
+//!     None,
+//!     Path::new("src/lib.rs"),
+//!     1,
+//!     None,
+//!     false,
+//! )?;
+//! println!("{annotations}");
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! With `YAP_MOCK=1`, requests are answered from fixture files instead; see
+//! [openai::Provider].
+
+// The module docs describe their internals, too; those links resolve with
+// `cargo doc --document-private-items`.
+#![allow(rustdoc::private_intra_doc_links)]
+
+pub mod agent;
+pub mod annotate;
+pub mod apply;
+pub mod archive;
+pub mod ask;
+pub mod backup;
+pub mod batch;
+mod cache;
+pub mod changelog;
+pub mod chat;
+pub mod chatlog;
+pub mod commit;
+pub mod complete;
+pub mod config;
+pub mod confirm;
+pub mod constants;
+pub mod context;
+pub mod crypt;
+pub mod ctx;
+pub mod date;
+pub mod db;
+mod diff;
+mod embeddings;
+pub mod err;
+mod files;
+pub mod filter;
+pub mod finetune;
+pub mod github;
+pub mod gitlab;
+pub mod hook;
+mod http;
+pub mod imagine;
+pub mod index;
+mod lang;
+pub mod logfile;
+mod markdown;
+pub mod memory;
+pub mod models;
+pub mod openai;
+mod picker;
+pub mod pin;
+mod pool;
+pub mod recap;
+pub mod refactor;
+pub mod repomap;
+pub mod review;
+pub mod rules;
+pub mod sandbox;
+pub mod say;
+pub mod serve;
+pub mod similar;
+mod spinner;
+mod symbols;
+pub mod syntax;
+mod term;
+mod tmux;
+pub mod transcribe;
+pub mod usage;
+pub mod watch;
+pub mod workflow;
@@ -0,0 +1,249 @@
+//! Write logs to `~/.local/state/yap/logs/yap.log` as well as `STDERR`, so
+//! that intermittent failures (i.e, under an editor plugin, which hides
+//! `STDERR`) can be diagnosed after the fact. This is configured with
+//! `log_file` in `config.json` (see [crate::config]); i.e,
+//!
+//! ```json
+//! {
+//!   "log_file": {
+//!     "level": "debug",
+//!     "max_bytes": 1048576,
+//!     "keep": 3
+//!   }
+//! }
+//! ```
+//!
+//! - `level`: one of `error`, `warn`, `info`, `debug`, or `trace`. File
+//!   logging is off unless this is set. Records from other crates are only
+//!   written at `warn` and above, so that HTTP internals stay out of the log.
+//! - `max_bytes`: once the log would grow beyond this size, it is rotated to
+//!   `yap.log.1`, which is rotated to `yap.log.2`, and so on. Defaults to 1
+//!   MiB.
+//! - `keep`: how many rotated logs are kept. Defaults to 3.
+//!
+//! `RUST_LOG` still controls what is printed to `STDERR`, independently.
+
+use crate::{config::Settings, date, db};
+use log::{LevelFilter, Log, Metadata, Record};
+use serde::Deserialize;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The `log_file` section of `config.json`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogFile {
+    pub level: Option<LevelFilter>,
+    pub max_bytes: u64,
+    pub keep: usize,
+}
+
+impl Default for LogFile {
+    fn default() -> Self {
+        Self {
+            level: None,
+            max_bytes: 1024 * 1024,
+            keep: 3,
+        }
+    }
+}
+
+/// Appends to the log file, rotating it when it grows too large.
+struct Writer {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_bytes: u64,
+    keep: usize,
+}
+
+impl Writer {
+    fn open(
+        path: PathBuf,
+        max_bytes: u64,
+        keep: usize,
+    ) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file,
+            size,
+            max_bytes,
+            keep,
+        })
+    }
+
+    fn write(&mut self, line: &str) -> std::io::Result<()> {
+        let len = line.len() as u64;
+        if self.size > 0 && self.size + len > self.max_bytes {
+            rotate(&self.path, self.keep)?;
+            *self = Self::open(self.path.clone(), self.max_bytes, self.keep)?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += len;
+        Ok(())
+    }
+}
+
+/// Shift `yap.log.N` to `yap.log.N+1` (dropping the oldest), and `yap.log`
+/// to `yap.log.1`. With `keep` set to 0, the log is simply removed.
+fn rotate(path: &Path, keep: usize) -> std::io::Result<()> {
+    let numbered = |n: usize| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{n}"));
+        PathBuf::from(name)
+    };
+    if keep == 0 {
+        return fs::remove_file(path);
+    }
+    // Another `yap` may have rotated the log already, so missing files are
+    // not an error.
+    let _ = fs::remove_file(numbered(keep));
+    for n in (1..keep).rev() {
+        let _ = fs::rename(numbered(n), numbered(n + 1));
+    }
+    match fs::rename(path, numbered(1)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Sends each record to `env_logger`, and to the log file if it is enabled.
+struct Logger {
+    stderr: env_logger::Logger,
+    file: Option<(LevelFilter, Mutex<Writer>)>,
+}
+
+impl Logger {
+    fn file_enabled(&self, metadata: &Metadata) -> bool {
+        let Some((level, _)) = &self.file else {
+            return false;
+        };
+        let ours = metadata.target().split("::").next() == Some("yap");
+        metadata.level() <= *level
+            && (ours || metadata.level() <= log::Level::Warn)
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.stderr.enabled(metadata) || self.file_enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.stderr.matches(record) {
+            self.stderr.log(record);
+        }
+        if !self.file_enabled(record.metadata()) {
+            return;
+        }
+        if let Some((_, writer)) = &self.file {
+            let line = format_line(record, now());
+            if let Ok(mut writer) = writer.lock() {
+                // There is nowhere to report a failure to log.
+                let _ = writer.write(&line);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        self.stderr.flush();
+        if let Some((_, writer)) = &self.file {
+            if let Ok(mut writer) = writer.lock() {
+                let _ = writer.file.flush();
+            }
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// One line of the log file; the time in UTC, the process ID (to tell apart
+/// concurrent runs), the level, the module, and the message.
+fn format_line(record: &Record, timestamp: u64) -> String {
+    let seconds = timestamp % 86_400;
+    format!(
+        "{} {:02}:{:02}:{:02} [{}] {:<5} {}: {}\n",
+        date::date(timestamp),
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        std::process::id(),
+        record.level(),
+        record.target(),
+        record.args()
+    )
+}
+
+fn open_writer(config: &LogFile) -> Option<Writer> {
+    let dir = db::get_or_create_persistence_dir().ok()?.join("logs");
+    fs::create_dir_all(&dir).ok()?;
+    Writer::open(dir.join("yap.log"), config.max_bytes, config.keep).ok()
+}
+
+/// Set up logging to `STDERR` (configured by `RUST_LOG`, as usual), and to the
+/// log file if `log_file.level` is set. Problems with the log file (or with
+/// `config.json`, which will be reported later) leave only `STDERR` logging.
+pub fn init() {
+    let stderr = env_logger::Builder::from_default_env().build();
+    let config = Settings::load().map(|s| s.log_file).unwrap_or_default();
+    let file = config.level.and_then(|level| {
+        open_writer(&config).map(|writer| (level, Mutex::new(writer)))
+    });
+    let max_level = match &file {
+        Some((level, _)) => stderr.filter().max(*level),
+        None => stderr.filter(),
+    };
+    if log::set_boxed_logger(Box::new(Logger { stderr, file })).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotation() {
+        let dir = std::env::temp_dir()
+            .join(format!("yap-test-logfile-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("yap.log");
+        let mut writer = Writer::open(path.clone(), 10, 2).unwrap();
+        for line in ["one\n", "two\n", "three\n", "four\n", "five\n"] {
+            writer.write(line).unwrap();
+        }
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("yap.log"), "four\nfive\n");
+        assert_eq!(read("yap.log.1"), "three\n");
+        assert_eq!(read("yap.log.2"), "one\ntwo\n");
+        assert!(!dir.join("yap.log.3").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_format_line() {
+        let line = format_line(
+            &Record::builder()
+                .level(log::Level::Warn)
+                .target("yap::openai")
+                .args(format_args!("slow response"))
+                .build(),
+            1_733_225_696,
+        );
+        assert!(line.starts_with("2024-12-03 11:34:56 ["));
+        assert!(line.ends_with("] WARN  yap::openai: slow response\n"));
+    }
+}
@@ -0,0 +1,282 @@
+//! Minimal markdown styling for terminals, using ANSI escape codes.
+//!
+//! This is not a complete markdown renderer. It handles the subset of
+//! markdown which LLMs tend to produce in chat responses; headers, bold text,
+//! inline code, and fenced code blocks (with rudimentary syntax
+//! highlighting). Everything else is passed through as-is.
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const UNDERLINE: &str = "\x1b[4m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const MAGENTA: &str = "\x1b[35m";
+const CYAN: &str = "\x1b[36m";
+
+const KEYWORDS: &[&str] = &[
+    "as",
+    "async",
+    "await",
+    "break",
+    "case",
+    "class",
+    "const",
+    "continue",
+    "def",
+    "default",
+    "do",
+    "elif",
+    "else",
+    "enum",
+    "export",
+    "extends",
+    "false",
+    "fi",
+    "fn",
+    "for",
+    "from",
+    "func",
+    "function",
+    "if",
+    "impl",
+    "import",
+    "in",
+    "interface",
+    "let",
+    "loop",
+    "match",
+    "mod",
+    "mut",
+    "new",
+    "None",
+    "null",
+    "package",
+    "pub",
+    "return",
+    "self",
+    "Self",
+    "static",
+    "struct",
+    "switch",
+    "then",
+    "this",
+    "trait",
+    "true",
+    "True",
+    "False",
+    "type",
+    "use",
+    "var",
+    "where",
+    "while",
+    "with",
+    "yield",
+];
+
+/// Render `text` with ANSI styling.
+pub fn render(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut fence: Option<String> = None;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if let Some(lang) = trimmed.strip_prefix("```") {
+            fence = match fence {
+                Some(_) => None,
+                None => Some(lang.trim().to_lowercase()),
+            };
+            out.push_str(DIM);
+            out.push_str(line);
+            out.push_str(RESET);
+        } else if let Some(lang) = &fence {
+            out.push_str(&highlight(line, lang));
+        } else if trimmed.starts_with('#') {
+            out.push_str(BOLD);
+            out.push_str(UNDERLINE);
+            out.push_str(line);
+            out.push_str(RESET);
+        } else {
+            out.push_str(&render_inline(line));
+        }
+        out.push('\n');
+    }
+    if !text.ends_with('\n') {
+        out.pop();
+    }
+    out
+}
+
+/// Style `**bold**` and `` `code` `` spans within a line.
+fn render_inline(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+    loop {
+        let bold = rest.find("**");
+        let code = rest.find('`');
+        let (start, delim, style) = match (bold, code) {
+            (Some(b), Some(c)) if c < b => (c, "`", CYAN),
+            (Some(b), _) => (b, "**", BOLD),
+            (None, Some(c)) => (c, "`", CYAN),
+            (None, None) => break,
+        };
+        let after = &rest[start + delim.len()..];
+        match after.find(delim) {
+            Some(end) => {
+                out.push_str(&rest[..start]);
+                out.push_str(style);
+                out.push_str(&after[..end]);
+                out.push_str(RESET);
+                rest = &after[end + delim.len()..];
+            }
+            None => break,
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Rudimentary syntax highlighting for one line of code; keywords, strings,
+/// numbers, and line comments. In Rust, `'` only starts a char literal; it
+/// is left alone in lifetimes and labels, i.e, `&'a str`.
+fn highlight(line: &str, lang: &str) -> String {
+    let rust = matches!(lang, "rust" | "rs");
+    let comment = match lang {
+        "sh" | "bash" | "zsh" | "shell" | "python" | "py" | "ruby" | "rb"
+        | "yaml" | "yml" | "toml" | "perl" | "r" | "make" | "makefile"
+        | "dockerfile" => "#",
+        "sql" | "lua" | "haskell" | "hs" => "--",
+        _ => "//",
+    };
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if line[i..].starts_with(comment) {
+            out.push_str(DIM);
+            out.push_str(&line[i..]);
+            out.push_str(RESET);
+            return out;
+        } else if c == '\'' && rust {
+            match char_literal_len(&line[i..]) {
+                Some(len) => {
+                    while chars.next_if(|&(j, _)| j < i + len).is_some() {}
+                    out.push_str(GREEN);
+                    out.push_str(&line[i..i + len]);
+                    out.push_str(RESET);
+                }
+                // A lifetime or label, which may share a keyword's name,
+                // i.e, `'static`.
+                None => {
+                    let mut end = i + 1;
+                    while let Some((j, d)) =
+                        chars.next_if(|&(_, d)| d.is_alphanumeric() || d == '_')
+                    {
+                        end = j + d.len_utf8();
+                    }
+                    out.push_str(&line[i..end]);
+                }
+            }
+        } else if c == '"' || c == '\'' {
+            let mut end = line.len();
+            let mut escaped = false;
+            for (j, d) in chars.by_ref() {
+                if escaped {
+                    escaped = false;
+                } else if d == '\\' {
+                    escaped = true;
+                } else if d == c {
+                    end = j + d.len_utf8();
+                    break;
+                }
+            }
+            out.push_str(GREEN);
+            out.push_str(&line[i..end]);
+            out.push_str(RESET);
+        } else if c.is_alphanumeric() || c == '_' {
+            let mut end = i + c.len_utf8();
+            while let Some(&(j, d)) = chars.peek() {
+                if d.is_alphanumeric() || d == '_' {
+                    end = j + d.len_utf8();
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            let word = &line[i..end];
+            if KEYWORDS.contains(&word) {
+                out.push_str(MAGENTA);
+                out.push_str(word);
+                out.push_str(RESET);
+            } else if c.is_ascii_digit() {
+                out.push_str(YELLOW);
+                out.push_str(word);
+                out.push_str(RESET);
+            } else {
+                out.push_str(word);
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// The length of the Rust char literal at the start of `code`, if there is
+/// one; i.e, `'a'`, `'\''`, or `'\u{1F600}'`.
+fn char_literal_len(code: &str) -> Option<usize> {
+    let body = code.strip_prefix('\'')?;
+    // The index of the closing quote within `body`.
+    let end = match body.strip_prefix('\\') {
+        Some(escape) => escape
+            .char_indices()
+            .skip(1)
+            .find(|&(_, c)| c == '\'')
+            .map(|(j, _)| j + 1)
+            .filter(|&end| end <= "\\u{10FFFF}".len())?,
+        None => body.chars().next().filter(|&c| c != '\'')?.len_utf8(),
+    };
+    body[end..].starts_with('\'').then_some(end + 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_inline() {
+        assert_eq!(
+            render_inline("use **bold** and `code`"),
+            format!("use {BOLD}bold{RESET} and {CYAN}code{RESET}")
+        );
+        assert_eq!(render_inline("a * b ** c"), "a * b ** c");
+    }
+
+    #[test]
+    fn test_render_code_block() {
+        let text = "# Title\n```rust\nlet x = \"hi\"; // note\n```\n";
+        let expected = format!(
+            "{BOLD}{UNDERLINE}# Title{RESET}\n\
+            {DIM}```rust{RESET}\n\
+            {MAGENTA}let{RESET} x = {GREEN}\"hi\"{RESET}; {DIM}// note{RESET}\n\
+            {DIM}```{RESET}\n"
+        );
+        assert_eq!(render(text), expected);
+    }
+
+    #[test]
+    fn test_highlight_rust_quotes() {
+        assert_eq!(
+            highlight("fn f<'a>(s: &'a str) -> char {", "rust"),
+            format!("{MAGENTA}fn{RESET} f<'a>(s: &'a str) -> char {{")
+        );
+        assert_eq!(
+            highlight("'outer: loop { c == '\\'' }", "rust"),
+            format!(
+                "'outer: {MAGENTA}loop{RESET} {{ c == {GREEN}'\\''{RESET} }}"
+            )
+        );
+        assert_eq!(highlight("&'static str", "rs"), "&'static str".to_string());
+        assert_eq!(char_literal_len("'é' "), Some(4));
+        assert_eq!(char_literal_len("'\\u{1F600}'"), Some(11));
+        assert_eq!(char_literal_len("'a, 'b"), None);
+    }
+}
@@ -0,0 +1,143 @@
+//! Remember facts about you and your preferences with `yap memory`; i.e,
+//! "I prefer thiserror over anyhow."
+//!
+//! Memories are kept in `~/.local/state/yap/memory.json`, and are shared by
+//! every project. When a new chat begins, they are added to it as a system
+//! message after the system prompt, so they are sent with every message in
+//! that chat. Chats which already began are not changed by `yap memory add`
+//! or `yap memory rm`.
+
+use crate::{
+    db,
+    err::{Error, Oops},
+    openai::{Message, Role},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Memory {
+    text: String,
+    /// Seconds since the Unix epoch.
+    created: u64,
+}
+
+fn oops(why: String) -> Error {
+    Error::default().wrap(Oops::MemoryError).because(why)
+}
+
+fn path() -> Result<PathBuf, Error> {
+    Ok(db::get_or_create_persistence_dir()?.join("memory.json"))
+}
+
+fn load() -> Result<Vec<Memory>, Error> {
+    let path = path()?;
+    match fs::read_to_string(&path) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .map_err(|e| oops(format!("{path:?} is invalid: {e}"))),
+        Err(_) => Ok(Vec::new()),
+    }
+}
+
+fn save(memories: &[Memory]) -> Result<(), Error> {
+    let path = path()?;
+    let contents = serde_json::to_string_pretty(memories)
+        .map_err(|e| oops(format!("Could not serialize memories: {e}")))?;
+    fs::write(&path, contents)
+        .map_err(|e| oops(format!("Could not write {path:?}: {e}")))
+}
+
+/// Entrypoint for `yap memory add`.
+pub fn add(text: &[String]) -> Result<(), Error> {
+    let text = text.join(" ");
+    if text.trim().is_empty() {
+        return Err(oops("There is nothing to remember".into()));
+    }
+    let mut memories = load()?;
+    memories.push(Memory {
+        text: text.trim().to_string(),
+        created: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+    });
+    save(&memories)?;
+    eprintln!("Remembered as memory {}.", memories.len());
+    Ok(())
+}
+
+/// Entrypoint for `yap memory list`. Memories are numbered from 1, for
+/// `yap memory rm`.
+pub fn list() -> Result<(), Error> {
+    for (i, memory) in load()?.iter().enumerate() {
+        println!("{:>3}  {}", i + 1, memory.text);
+    }
+    Ok(())
+}
+
+/// Entrypoint for `yap memory rm`. `numbers` count from 1, as printed by
+/// `yap memory list`.
+pub fn remove(numbers: &[usize]) -> Result<(), Error> {
+    let mut memories = load()?;
+    for &n in numbers {
+        if n == 0 || n > memories.len() {
+            return Err(oops(format!(
+                "There is no memory {n}; see `yap memory list`"
+            )));
+        }
+    }
+    let mut i = 0;
+    memories.retain(|_| {
+        i += 1;
+        !numbers.contains(&i)
+    });
+    save(&memories)
+}
+
+/// The system message which carries the memories into a new chat, or `None`
+/// if there aren't any.
+pub fn message() -> Result<Option<Message>, Error> {
+    Ok(render(&load()?).map(|m| Message::new(Role::System, m)))
+}
+
+fn render(memories: &[Memory]) -> Option<String> {
+    if memories.is_empty() {
+        return None;
+    }
+    let mut message =
+        "The user asked you to remember the following:\n".to_string();
+    for memory in memories {
+        message.push_str(&format!("\n- {}", memory.text));
+    }
+    Some(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        assert_eq!(render(&[]), None);
+        let memories = [
+            Memory {
+                text: "I prefer thiserror over anyhow".into(),
+                created: 0,
+            },
+            Memory {
+                text: "I use nix".into(),
+                created: 0,
+            },
+        ];
+        assert_eq!(
+            render(&memories).unwrap(),
+            "The user asked you to remember the following:\n\n\
+            - I prefer thiserror over anyhow\n\
+            - I use nix"
+        );
+    }
+}
@@ -0,0 +1,129 @@
+//! List the models which your API key can use, with `yap models`.
+//!
+//! Each model is listed with its creation date and owner (fine-tuned models
+//! are owned by your organization), and annotated with what it's for, as
+//! far as `yap` knows. Models which `yap --model` accepts are marked with
+//! their context window.
+//! OpenAI adds models faster than `yap` learns about them, so models which
+//! `yap` doesn't recognize are listed without notes.
+
+use crate::{
+    date,
+    err::Error,
+    openai::{
+        audio_api, embeddings_api, images_api, models_api, Model, OpenAI,
+    },
+    term,
+};
+
+/// Entrypoint for `yap models`. With `chat_only`, only models which `yap
+/// --model` accepts are listed.
+pub fn models(open_ai: &OpenAI, chat_only: bool) -> Result<(), Error> {
+    let mut models = models_api::list(open_ai)?;
+    models.sort_by(|a, b| a.id.cmp(&b.id));
+    if chat_only {
+        models.retain(|m| yap_model(&m.id).is_some());
+    }
+    if models.is_empty() {
+        println!("No models are available.");
+        return Ok(());
+    }
+    let width = models.iter().map(|m| m.id.len()).max().unwrap_or(0);
+    let owner_width =
+        models.iter().map(|m| m.owned_by.len()).max().unwrap_or(0);
+    let mut out = String::new();
+    for model in &models {
+        let notes = notes(&model.id);
+        let line = format!(
+            "{:<width$}  {}  {:<owner_width$}  {}",
+            model.id,
+            date::date(model.created),
+            model.owned_by,
+            notes.join(", ")
+        );
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    term::page(&out)
+}
+
+/// The [Model] named `id`, if `yap --model` accepts it.
+fn yap_model(id: &str) -> Option<Model> {
+    id.parse().ok()
+}
+
+/// What `yap` knows about the model `id`.
+fn notes(id: &str) -> Vec<String> {
+    if let Some(model) = yap_model(id) {
+        let mut notes = vec![
+            "chat".to_string(),
+            format!("{}k context", model.context_window() / 1000),
+        ];
+        if model.is_reasoning() {
+            notes.push("reasoning".into());
+        }
+        notes.push(format!("yap --model {model}"));
+        return notes;
+    }
+    let note = match id {
+        _ if id == embeddings_api::EMBEDDING_MODEL => "embeddings (yap index)",
+        _ if id == audio_api::TRANSCRIPTION_MODEL => {
+            "transcription (yap transcribe)"
+        }
+        _ if id == audio_api::SPEECH_MODEL => "speech (yap say)",
+        _ if id == images_api::IMAGE_MODEL => "images (yap imagine)",
+        _ if id.starts_with("text-embedding") => "embeddings",
+        _ if id.starts_with("whisper") || id.contains("transcribe") => {
+            "transcription"
+        }
+        _ if id.starts_with("tts") || id.contains("-tts") => "speech",
+        _ if id.starts_with("dall-e") || id.starts_with("gpt-image") => {
+            "images"
+        }
+        _ if id.contains("moderation") => "moderation",
+        _ if id.contains("realtime") => "realtime",
+        _ if id.contains("audio") => "audio",
+        _ if id.starts_with("gpt-")
+            || id.starts_with("chatgpt-")
+            || id.starts_with('o') && id[1..].starts_with(char::is_numeric) =>
+        {
+            "chat"
+        }
+        _ => return Vec::new(),
+    };
+    vec![note.to_string()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notes() {
+        assert_eq!(
+            notes("gpt-4o-mini"),
+            vec!["chat", "128k context", "yap --model gpt-4o-mini"]
+        );
+        assert_eq!(
+            notes("o3-mini"),
+            vec!["chat", "200k context", "reasoning", "yap --model o3-mini"]
+        );
+        assert_eq!(
+            notes("text-embedding-3-small"),
+            vec!["embeddings (yap index)"]
+        );
+        assert_eq!(notes("text-embedding-ada-002"), vec!["embeddings"]);
+        assert_eq!(notes("gpt-4.1"), vec!["chat"]);
+        assert_eq!(notes("o4-mini"), vec!["chat"]);
+        assert_eq!(notes("omni-moderation-latest"), vec!["moderation"]);
+        assert_eq!(
+            notes("ft:gpt-4o-mini-2024-07-18:acme::abc"),
+            vec![
+                "chat",
+                "128k context",
+                "yap --model ft:gpt-4o-mini-2024-07-18:acme::abc"
+            ]
+        );
+        assert!(notes("babbage-002").is_empty());
+    }
+}
@@ -0,0 +1,164 @@
+//! <https://platform.openai.com/docs/api-reference/audio>
+
+use super::{preview, Body, OpenAI};
+use crate::err::{Error, Oops};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::path::Path;
+
+const TRANSCRIPTIONS_URL: &str =
+    "https://api.openai.com/v1/audio/transcriptions";
+const SPEECH_URL: &str = "https://api.openai.com/v1/audio/speech";
+
+/// Audio is always transcribed with this model, regardless of `yap --model`.
+pub const TRANSCRIPTION_MODEL: &str = "whisper-1";
+
+/// Speech is always generated with this model, regardless of `yap --model`.
+pub const SPEECH_MODEL: &str = "tts-1";
+
+/// The most characters of text which can be spoken in one request.
+pub const MAX_SPEECH_CHARS: usize = 4096;
+
+/// The sample rate of [SpeechFormat::Pcm] audio, which is always 16-bit,
+/// mono, and little-endian.
+pub const PCM_SAMPLE_RATE: u32 = 24_000;
+
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum Voice {
+    #[default]
+    Alloy,
+    Ash,
+    Coral,
+    Echo,
+    Fable,
+    Onyx,
+    Nova,
+    Sage,
+    Shimmer,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpeechFormat {
+    Mp3,
+    /// Raw samples, without a header; see [PCM_SAMPLE_RATE].
+    Pcm,
+}
+
+/// OpenAI rejects larger uploads.
+pub const MAX_UPLOAD_BYTES: usize = 25 * 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+struct Transcription {
+    text: String,
+}
+
+/// The MIME type of an audio file which OpenAI accepts, by extension.
+pub fn audio_mime_type(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    Some(match extension.as_str() {
+        "flac" => "audio/flac",
+        "m4a" => "audio/mp4",
+        "mp3" | "mpga" | "mpeg" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        "ogg" | "oga" => "audio/ogg",
+        "wav" => "audio/wav",
+        "webm" => "audio/webm",
+        _ => return None,
+    })
+}
+
+/// Transcribe `audio`, the contents of the file `path`.
+pub fn transcribe(
+    open_ai: &OpenAI,
+    path: &Path,
+    audio: &[u8],
+) -> Result<String, Error> {
+    let oops =
+        |why: String| Error::default().wrap(Oops::TranscribeError).because(why);
+    let mime_type = audio_mime_type(path).ok_or_else(|| {
+        oops(format!(
+            "{path:?} is not a supported audio file; use flac, m4a, mp3, mp4, ogg, wav, or webm"
+        ))
+    })?;
+    if open_ai.dry_run {
+        // The audio itself is not worth printing.
+        return Err(preview(
+            TRANSCRIPTIONS_URL,
+            &json!({
+                "model": TRANSCRIPTION_MODEL,
+                "file": path,
+                "bytes": audio.len(),
+            }),
+        ));
+    }
+    debug!("Transcribing {} bytes of {mime_type}", audio.len());
+    let file_name = path
+        .file_name()
+        .map_or("audio".into(), |n| n.to_string_lossy());
+    // This multipart body is simple enough to build by hand.
+    let boundary = format!("yap-{}", uuid::Uuid::new_v4());
+    let mut body = format!(
+        "--{boundary}\r\n\
+        Content-Disposition: form-data; name=\"model\"\r\n\r\n\
+        {TRANSCRIPTION_MODEL}\r\n\
+        --{boundary}\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"{file_name}\"\r\n\
+        Content-Type: {mime_type}\r\n\r\n"
+    )
+    .into_bytes();
+    body.extend_from_slice(audio);
+    body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+    let response = open_ai
+        .send_text(
+            open_ai.request("POST", TRANSCRIPTIONS_URL).set(
+                "Content-Type",
+                &format!("multipart/form-data; boundary={boundary}"),
+            ),
+            Body::Bytes(&body),
+        )
+        .map_err(|e| e.wrap(Oops::TranscribeError))?;
+    let transcription: Transcription = serde_json::from_str(&response)
+        .map_err(|e| {
+            oops(format!("Could not deserialize the transcription: {e}"))
+        })?;
+    Ok(transcription.text)
+}
+
+/// Speak `input`, which must be at most [MAX_SPEECH_CHARS] long.
+pub fn speech(
+    open_ai: &OpenAI,
+    input: &str,
+    voice: Voice,
+    format: SpeechFormat,
+) -> Result<Vec<u8>, Error> {
+    let payload = json!({
+        "model": SPEECH_MODEL,
+        "input": input,
+        "voice": voice,
+        "response_format": format,
+    });
+    if open_ai.dry_run {
+        return Err(preview(SPEECH_URL, &payload));
+    }
+    debug!("Speaking {} characters", input.chars().count());
+    open_ai
+        .send(open_ai.request("POST", SPEECH_URL), Body::Json(&payload))
+        .map_err(|e| e.wrap(Oops::SayError))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audio_mime_type() {
+        assert_eq!(audio_mime_type(Path::new("memo.MP3")), Some("audio/mpeg"));
+        assert_eq!(audio_mime_type(Path::new("a/b.m4a")), Some("audio/mp4"));
+        assert_eq!(audio_mime_type(Path::new("notes.txt")), None);
+        assert_eq!(audio_mime_type(Path::new("wav")), None);
+    }
+}
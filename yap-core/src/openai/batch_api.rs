@@ -0,0 +1,111 @@
+//! <https://platform.openai.com/docs/api-reference/batch>
+
+use super::{jsonl_upload, preview, Body, OpenAI};
+use crate::err::{Error, Oops};
+use log::debug;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+
+const API: &str = "https://api.openai.com/v1";
+
+#[derive(Debug, Deserialize)]
+pub struct Batch {
+    pub id: String,
+    /// e.g. `validating`, `in_progress`, `completed`, `failed`, `expired`.
+    pub status: String,
+    pub output_file_id: Option<String>,
+    pub error_file_id: Option<String>,
+    pub request_counts: Option<RequestCounts>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RequestCounts {
+    pub total: u64,
+    pub completed: u64,
+    pub failed: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct File {
+    id: String,
+}
+
+fn parse<T: DeserializeOwned>(body: &str) -> Result<T, Error> {
+    serde_json::from_str(body).map_err(|e| {
+        debug!("Bad response body: {body}");
+        Error::default()
+            .wrap(Oops::BatchError)
+            .because(format!("Could not deserialize the response: {e}"))
+    })
+}
+
+/// Upload `jsonl` as a batch input file, returning its file ID.
+pub fn upload_batch_file(
+    open_ai: &OpenAI,
+    jsonl: &str,
+) -> Result<String, Error> {
+    if open_ai.dry_run {
+        // The requests in the file are what's interesting, so they are
+        // shown instead of the multipart body.
+        let requests = jsonl
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<serde_json::Value>, _>>()
+            .map_err(|e| {
+                Error::default()
+                    .wrap(Oops::BatchError)
+                    .because(format!("Invalid batch input file: {e}"))
+            })?;
+        return Err(preview(&format!("{API}/files"), &requests));
+    }
+    let (content_type, body) = jsonl_upload("batch", jsonl);
+    let response = open_ai
+        .send_text(
+            open_ai
+                .request("POST", &format!("{API}/files"))
+                .set("Content-Type", &content_type),
+            Body::Text(&body),
+        )
+        .map_err(|e| e.wrap(Oops::BatchError))?;
+    Ok(parse::<File>(&response)?.id)
+}
+
+/// Start a batch of chat completions from an uploaded input file.
+pub fn create_batch(
+    open_ai: &OpenAI,
+    input_file_id: &str,
+) -> Result<Batch, Error> {
+    let response = open_ai
+        .send_text(
+            open_ai.request("POST", &format!("{API}/batches")),
+            Body::Json(&json!({
+                "input_file_id": input_file_id,
+                "endpoint": "/v1/chat/completions",
+                "completion_window": "24h",
+            })),
+        )
+        .map_err(|e| e.wrap(Oops::BatchError))?;
+    parse(&response)
+}
+
+pub fn get_batch(open_ai: &OpenAI, id: &str) -> Result<Batch, Error> {
+    let response = open_ai
+        .send_text(
+            open_ai.request("GET", &format!("{API}/batches/{id}")),
+            Body::Empty,
+        )
+        .map_err(|e| e.wrap(Oops::BatchError))?;
+    parse(&response)
+}
+
+pub fn get_file_content(open_ai: &OpenAI, id: &str) -> Result<String, Error> {
+    open_ai
+        .send_text(
+            open_ai.request("GET", &format!("{API}/files/{id}/content")),
+            Body::Empty,
+        )
+        .map_err(|e| {
+            e.wrap(Oops::BatchError)
+                .because(format!("Could not read file {id}"))
+        })
+}
@@ -0,0 +1,1015 @@
+//! <https://platform.openai.com/docs/api-reference/chat>
+
+use super::{
+    debug_http::Capture, preview, run_hook, Body, Hook, LogitBias, OpenAI,
+    Request, Role,
+};
+use crate::{
+    err::{Error, Oops},
+    pool,
+    spinner::Spinner,
+    usage,
+};
+use log::debug;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use std::{
+    io::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const CHAT_URL: &str = "https://api.openai.com/v1/chat/completions";
+
+/// Models are named as OpenAI names them (i.e, in `config.json`), but the
+/// shorter names `gpt4o-mini` and `gpt4o` are also accepted. Fine-tuned
+/// models are named by their `ft:` IDs.
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub enum Model {
+    #[default]
+    Gpt4oMini,
+    Gpt4o,
+    O1,
+    O1Mini,
+    O3Mini,
+    /// A fine-tuned model, like `ft:gpt-4o-mini-2024-07-18:my-org::abc123`;
+    /// see [crate::finetune].
+    FineTuned(Arc<str>),
+}
+
+/// The names which `yap --model` accepts, besides fine-tuned model IDs.
+const MODEL_NAMES: [&str; 5] =
+    ["gpt-4o-mini", "gpt-4o", "o1", "o1-mini", "o3-mini"];
+
+impl std::fmt::Display for Model {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Gpt4oMini => write!(f, "gpt-4o-mini"),
+            Self::Gpt4o => write!(f, "gpt-4o"),
+            Self::O1 => write!(f, "o1"),
+            Self::O1Mini => write!(f, "o1-mini"),
+            Self::O3Mini => write!(f, "o3-mini"),
+            Self::FineTuned(id) => write!(f, "{id}"),
+        }
+    }
+}
+
+impl std::str::FromStr for Model {
+    type Err = String;
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "gpt-4o-mini" | "gpt4o-mini" => Ok(Self::Gpt4oMini),
+            "gpt-4o" | "gpt4o" => Ok(Self::Gpt4o),
+            "o1" => Ok(Self::O1),
+            "o1-mini" => Ok(Self::O1Mini),
+            "o3-mini" => Ok(Self::O3Mini),
+            id if id.starts_with("ft:") && id.len() > 3 => {
+                Ok(Self::FineTuned(id.into()))
+            }
+            _ => Err(format!(
+                "unknown model {name:?}; expected one of {}, or a fine-tuned model ID starting with `ft:`",
+                MODEL_NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+impl Serialize for Model {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Model {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl Model {
+    /// The model which a fine-tuned model was trained from, going by its ID
+    /// (`ft:<base>:<org>:<suffix>:<id>`). Unfamiliar base models are
+    /// treated like `gpt-4o`.
+    fn base(&self) -> Self {
+        let Self::FineTuned(id) = self else {
+            return self.clone();
+        };
+        let base = id.trim_start_matches("ft:");
+        [
+            ("gpt-4o-mini", Self::Gpt4oMini),
+            ("o1-mini", Self::O1Mini),
+            ("o3-mini", Self::O3Mini),
+            ("o1", Self::O1),
+        ]
+        .into_iter()
+        .find(|(prefix, _)| base.starts_with(prefix))
+        .map_or(Self::Gpt4o, |(_, model)| model)
+    }
+    /// The maximum number of tokens that the model will accept, including
+    /// both the prompt and the response.
+    pub fn context_window(&self) -> usize {
+        match self.base() {
+            Self::O1 | Self::O3Mini => 200_000,
+            _ => 128_000,
+        }
+    }
+    /// US dollars per million prompt and completion tokens, as listed on
+    /// <https://openai.com/api/pricing>. Fine-tuned models cost more to use
+    /// than their base models; reasoning models can't be fine-tuned.
+    pub fn price(&self) -> (f64, f64) {
+        match (self, self.base()) {
+            (Self::FineTuned(_), Self::Gpt4oMini) => (0.30, 1.20),
+            (Self::FineTuned(_), _) => (3.75, 15.00),
+            (_, Self::Gpt4oMini) => (0.15, 0.60),
+            (_, Self::O1) => (15.00, 60.00),
+            (_, Self::O1Mini | Self::O3Mini) => (1.10, 4.40),
+            _ => (2.50, 10.00),
+        }
+    }
+    /// The cost of `usage`, in US dollars; see [Self::price].
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        let (prompt, completion) = self.price();
+        (usage.prompt_tokens as f64 * prompt
+            + usage.completion_tokens as f64 * completion)
+            / 1_000_000.
+    }
+    /// Reasoning models (the o1/o3 family) reject `system` messages and
+    /// sampling parameters.
+    pub fn is_reasoning(&self) -> bool {
+        matches!(self.base(), Self::O1 | Self::O1Mini | Self::O3Mini)
+    }
+    /// The role which system prompts should be sent with. Most reasoning
+    /// models accept `developer` messages in place of `system` messages, but
+    /// `o1-mini` only accepts `user` and `assistant` messages.
+    fn system_role(&self) -> Role {
+        match self.base() {
+            Self::O1 | Self::O3Mini => Role::Developer,
+            Self::O1Mini => Role::User,
+            _ => Role::System,
+        }
+    }
+    /// Whether the model accepts a `reasoning_effort` parameter; `o1-mini`
+    /// rejects it, like the models which don't reason at all.
+    fn supports_reasoning_effort(&self) -> bool {
+        matches!(self.base(), Self::O1 | Self::O3Mini)
+    }
+    /// Whether the model accepts a `json_schema` [ResponseFormat]. `o1-mini`
+    /// does not support structured outputs; see [CompletionPayload::new].
+    fn supports_json_schema(&self) -> bool {
+        !matches!(self.base(), Self::O1Mini)
+    }
+}
+
+/// How hard a reasoning model should think before responding. Ignored for
+/// other models.
+#[derive(Copy, Clone, Debug, Serialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum ReasoningEffort {
+    Low,
+    Medium,
+    High,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CompletionPayload {
+    pub messages: Vec<Message>,
+    #[serde(skip_serializing_if = "ResponseFormat::is_text")]
+    pub response_format: ResponseFormat,
+    model: Model,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reasoning_effort: Option<ReasoningEffort>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    n: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    logit_bias: Option<LogitBias>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+}
+
+#[derive(Debug, Serialize)]
+struct StreamOptions {
+    /// Ask for token counts in the final chunk of a stream.
+    include_usage: bool,
+}
+
+#[derive(Default, Debug, Serialize)]
+#[serde(tag = "type")]
+pub enum ResponseFormat {
+    #[default]
+    #[serde(rename(serialize = "text"))]
+    Text,
+    #[serde(rename(serialize = "json_schema"))]
+    JsonSchema { json_schema: Value },
+}
+
+impl ResponseFormat {
+    /// `text` is the default, so there's no need to send it. Omitting it
+    /// also keeps us compatible with models which don't support
+    /// `response_format` at all.
+    fn is_text(&self) -> bool {
+        matches!(self, Self::Text)
+    }
+}
+
+#[derive(Default)]
+pub struct PayloadOpts {
+    pub response_format: ResponseFormat,
+    /// Ask OpenAI to sample deterministically. If unset, the seed passed via
+    /// `yap --seed` (if any) is used.
+    pub seed: Option<i64>,
+    /// Only sent to reasoning models which accept it. If unset, the effort
+    /// passed via `yap --reasoning-effort` (if any) is used.
+    pub reasoning_effort: Option<ReasoningEffort>,
+    /// The number of candidate responses to generate; each is a separate
+    /// [Choice].
+    pub n: Option<u8>,
+    /// Ask for the response to be streamed; see [chat_stream].
+    pub stream: bool,
+    /// Sample more (or less) randomly, from 0 to 2. Not sent to reasoning
+    /// models.
+    pub temperature: Option<f32>,
+    /// Discourage repetition; see [crate::config::Settings]. Like
+    /// [Self::logit_bias], these are not sent to reasoning models, and fall
+    /// back to `config.json` if unset.
+    pub frequency_penalty: Option<f32>,
+    pub presence_penalty: Option<f32>,
+    /// Make particular tokens more or less likely.
+    pub logit_bias: Option<LogitBias>,
+}
+
+impl CompletionPayload {
+    pub fn new(
+        open_ai: &OpenAI,
+        messages: Vec<Message>,
+        opts: PayloadOpts,
+    ) -> Self {
+        let model = &open_ai.model;
+        // Reasoning models reject sampling parameters.
+        let sampling = !model.is_reasoning();
+        let mut messages = if model.is_reasoning() {
+            let role = model.system_role();
+            messages
+                .into_iter()
+                .map(|mut m| {
+                    if matches!(m.role, Role::System) {
+                        m.role = role.clone();
+                    }
+                    m
+                })
+                .collect()
+        } else {
+            messages
+        };
+        let reasoning_effort = opts
+            .reasoning_effort
+            .or(open_ai.reasoning_effort)
+            .filter(|_| model.supports_reasoning_effort());
+        // Models without structured outputs are asked for the schema in
+        // words instead, which they usually (but not always) follow.
+        let response_format = match opts.response_format {
+            ResponseFormat::JsonSchema { json_schema }
+                if !model.supports_json_schema() =>
+            {
+                messages.push(Message::new(
+                    Role::User,
+                    format!(
+                        "Respond with only a JSON object, without a code fence, which matches this JSON schema:\n\n{}",
+                        json_schema["schema"]
+                    ),
+                ));
+                ResponseFormat::Text
+            }
+            response_format => response_format,
+        };
+        // `truncated`, `created`, `pinned`, `model`, and `usage` are our own
+        // bookkeeping; OpenAI doesn't need to see them.
+        let messages = messages
+            .into_iter()
+            .map(|m| Message {
+                truncated: false,
+                created: None,
+                pinned: false,
+                model: None,
+                usage: None,
+                ..m
+            })
+            .collect();
+        CompletionPayload {
+            messages,
+            model: model.clone(),
+            response_format,
+            seed: opts.seed.or(open_ai.seed),
+            reasoning_effort,
+            n: opts.n,
+            temperature: opts.temperature.filter(|_| sampling),
+            frequency_penalty: opts
+                .frequency_penalty
+                .or(open_ai.frequency_penalty)
+                .filter(|_| sampling),
+            presence_penalty: opts
+                .presence_penalty
+                .or(open_ai.presence_penalty)
+                .filter(|_| sampling),
+            logit_bias: opts
+                .logit_bias
+                .or_else(|| open_ai.logit_bias.clone())
+                .filter(|_| sampling),
+            stream: opts.stream,
+            stream_options: opts.stream.then_some(StreamOptions {
+                include_usage: true,
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+pub struct Message {
+    pub role: Role,
+    pub content: Option<String>,
+    refusal: Option<String>,
+    /// Set if the response was cut off before it was complete, e.g. because
+    /// the user interrupted a streaming response.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    /// When the message was written, in seconds since the Unix epoch. Not
+    /// recorded in older chats.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<u64>,
+    /// Set by `yap pin`; pinned messages are never summarized or dropped by
+    /// [crate::context].
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    /// The model which wrote the reply, and the tokens its request used,
+    /// for `yap recap --stats`. Not recorded for other messages, or in
+    /// older chats.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<Model>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+}
+
+pub enum Content<'a> {
+    Normal(&'a str),
+    Refusal(&'a str),
+}
+
+impl Message {
+    pub fn new(role: Role, content: String) -> Self {
+        Self {
+            role,
+            content: Some(content),
+            refusal: None,
+            truncated: false,
+            created: unix_now(),
+            pinned: false,
+            model: None,
+            usage: None,
+        }
+    }
+    /// A rough estimate of the number of tokens in this message, including a
+    /// bit of overhead for the message itself. See [estimate_tokens].
+    pub fn estimate_tokens(&self) -> usize {
+        let text = self.content.as_ref().or(self.refusal.as_ref());
+        4 + text.map_or(0, |t| estimate_tokens(t))
+    }
+    pub fn parse(&self) -> Result<Content<'_>, Error> {
+        match (self.content.as_ref(), self.refusal.as_ref()) {
+            (Some(_), Some(_)) => {
+                Err(Error::default().wrap(Oops::OpenAIContentAndRefusal))
+            }
+            (Some(content), None) => Ok(Content::Normal(content)),
+            (None, Some(refusal)) => Ok(Content::Refusal(refusal)),
+            (None, None) => {
+                Err(Error::default().wrap(Oops::OpenAIEmptyContent))
+            }
+        }
+    }
+}
+
+/// A rough estimate of the number of tokens in `text`. We don't have a
+/// tokenizer, so we assume about 4 bytes per token.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.len().div_ceil(4)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompletionResponse {
+    pub choices: Vec<Choice>,
+    /// Identifies the backend configuration which produced the response.
+    /// Responses with the same seed are only reproducible while this stays
+    /// the same.
+    #[serde(default)]
+    pub system_fingerprint: Option<String>,
+    #[serde(default)]
+    pub usage: Option<Usage>,
+}
+
+/// Token counts for a request, as reported by OpenAI.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl CompletionResponse {
+    pub fn validate(mut self) -> Result<Self, Error> {
+        if self.choices.is_empty() {
+            return Err(Error::default().wrap(Oops::OpenAIEmptyChoices));
+        };
+        if self.choices.iter().all(|Choice { finish_reason, .. }| {
+            *finish_reason != FinishReason::Stop
+        }) {
+            return Err(Error::default()
+                .wrap(Oops::OpenAIBadFinishReason)
+                .because(format!(
+                    r#"Finish reason was "{:?}" instead of "stop""#,
+                    self.choices[0].finish_reason
+                )));
+        };
+        for choice in self.choices.iter_mut() {
+            if choice.message.created.is_none() {
+                choice.message.created = unix_now();
+            }
+        }
+
+        Ok(self)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Choice {
+    pub message: Message,
+    pub finish_reason: FinishReason,
+}
+
+#[derive(Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FinishReason {
+    Length,
+    Stop,
+}
+
+/// `messages` as they are shown by `yap --show-prompt`; each message's role
+/// and estimated token count, followed by its content.
+fn describe_prompt(messages: &[Message]) -> String {
+    let mut out = String::new();
+    let mut total = 0;
+    for message in messages {
+        let tokens = message.estimate_tokens();
+        total += tokens;
+        out.push_str(&format!("--- {} (~{tokens} tokens) ---\n", message.role));
+        if let Some(text) =
+            message.content.as_ref().or(message.refusal.as_ref())
+        {
+            out.push_str(text);
+            if !text.ends_with('\n') {
+                out.push('\n');
+            }
+        }
+    }
+    out.push_str(&format!("--- ~{total} tokens in total ---"));
+    out
+}
+
+/// Print the prompt to `STDERR` if [OpenAI::show_prompt] is set.
+fn show_prompt(open_ai: &OpenAI, payload: &CompletionPayload) {
+    if open_ai.show_prompt {
+        // Concurrent requests (i.e, from `yap annotate`) are printed whole.
+        let mut stderr = std::io::stderr().lock();
+        let _ = writeln!(stderr, "{}", describe_prompt(&payload.messages));
+    }
+}
+
+/// Send `payload`, blocking until the response arrives; see [chat_async].
+pub fn chat(
+    open_ai: &OpenAI,
+    payload: &CompletionPayload,
+) -> Result<CompletionResponse, Error> {
+    pool::block_on(chat_async(open_ai, payload))
+}
+
+/// Send `payload`, and wait for the whole response. Batch operations run
+/// many of these at once; see [crate::pool].
+pub async fn chat_async(
+    open_ai: &OpenAI,
+    payload: &CompletionPayload,
+) -> Result<CompletionResponse, Error> {
+    debug!("Sending chat completion payload: {payload:?}");
+    show_prompt(open_ai, payload);
+    if open_ai.dry_run {
+        return Err(preview(CHAT_URL, payload));
+    }
+    run_hook(open_ai, Hook::PreRequest, payload)?;
+    let start = Instant::now();
+    let spinner = Spinner::start(format!("Waiting for {}", open_ai.model));
+    let response = async {
+        let body = serde_json::to_value(payload).map_err(|e| {
+            Error::default()
+                .wrap(Oops::OpenAIChatResponse)
+                .because(format!("Could not serialize payload: {e}"))
+        })?;
+        let str = open_ai
+            .fetch_text(
+                open_ai
+                    .request("POST", CHAT_URL)
+                    .set("Content-Type", "application/json"),
+                Body::Json(&body),
+            )
+            .await
+            .map_err(|e| e.wrap(Oops::OpenAIChatResponse))?;
+        serde_json::from_str::<CompletionResponse>(&str).map_err(|e| {
+            Error::default()
+                .wrap(Oops::OpenAIChatDeserialization)
+                .because(format!("{e}"))
+        })
+    }
+    .await;
+    drop(spinner);
+    let response = response?;
+    run_hook(open_ai, Hook::PostResponse, &response)?;
+    response.validate().map(|mut response| {
+        usage::record(
+            open_ai,
+            response.usage.unwrap_or_default(),
+            start.elapsed(),
+        );
+        if payload.seed.is_some() {
+            if let Some(fingerprint) = &response.system_fingerprint {
+                eprintln!("system_fingerprint: {fingerprint}");
+            }
+        }
+        // With `n`, the usage covers every candidate, so each candidate is
+        // charged with the whole request.
+        for choice in response.choices.iter_mut() {
+            choice.message.model = Some(open_ai.model.clone());
+            choice.message.usage = response.usage;
+        }
+        response
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamChunk {
+    choices: Vec<StreamChoice>,
+    /// Only present in the final chunk.
+    #[serde(default)]
+    usage: Option<Usage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamChoice {
+    delta: Delta,
+}
+
+#[derive(Debug, Deserialize)]
+struct Delta {
+    content: Option<String>,
+    refusal: Option<String>,
+}
+
+/// Like [chat], but the response is streamed; `on_delta` is called with each
+/// piece of content as it arrives. If `interrupted` becomes `true` (i.e, from
+/// a Ctrl-C handler), the request is abandoned and the partial message is
+/// returned with [Message::truncated] set.
+///
+/// The request runs as a background task (see [crate::pool]), so that an
+/// interruption takes effect immediately, even while waiting on the network.
+pub fn chat_stream(
+    open_ai: &OpenAI,
+    payload: &CompletionPayload,
+    interrupted: &AtomicBool,
+    mut on_delta: impl FnMut(&str),
+) -> Result<Message, Error> {
+    debug!("Sending streaming chat completion payload: {payload:?}");
+    show_prompt(open_ai, payload);
+    if open_ai.dry_run {
+        return Err(preview(CHAT_URL, payload));
+    }
+    run_hook(open_ai, Hook::PreRequest, payload)?;
+    let start = Instant::now();
+    let request = open_ai
+        .request("POST", CHAT_URL)
+        .set("Content-Type", "application/json");
+    let body = serde_json::to_value(payload).map_err(|e| {
+        Error::default()
+            .wrap(Oops::OpenAIChatResponse)
+            .because(format!("Could not serialize payload: {e}"))
+    })?;
+    let capture = open_ai.capture(&request, &Body::Json(&body));
+    let capturing = capture.is_some();
+    let (tx, rx) = mpsc::channel::<Result<String, Error>>();
+    let reading = pool::runtime().spawn(read_stream(
+        open_ai.clone(),
+        request,
+        body,
+        capture,
+        tx,
+    ));
+
+    let (mut content, mut refusal) = (String::new(), String::new());
+    let mut done = false;
+    let mut tokens = None;
+    while !interrupted.load(Ordering::SeqCst) {
+        let line = match rx.recv_timeout(Duration::from_millis(50)) {
+            Ok(line) => line?,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+            continue;
+        };
+        if data == "[DONE]" {
+            done = true;
+            break;
+        }
+        let chunk: StreamChunk = serde_json::from_str(data).map_err(|e| {
+            Error::default()
+                .wrap(Oops::OpenAIChatDeserialization)
+                .because(format!("{e}"))
+        })?;
+        tokens = chunk.usage.or(tokens);
+        for choice in chunk.choices {
+            if let Some(c) = choice.delta.content {
+                on_delta(&c);
+                content.push_str(&c);
+            }
+            if let Some(r) = choice.delta.refusal {
+                refusal.push_str(&r);
+            }
+        }
+    }
+
+    // Let the capture be saved before `yap` exits. Once the stream is done,
+    // the response ends promptly.
+    if done && capturing {
+        let _ = pool::block_on(reading);
+    }
+    // If the stream was interrupted, OpenAI's token counts never arrive.
+    usage::record(open_ai, tokens.unwrap_or_default(), start.elapsed());
+    let has_refusal = !refusal.is_empty();
+    let message = Message {
+        role: Role::Assistant,
+        content: (!has_refusal || !content.is_empty()).then_some(content),
+        refusal: has_refusal.then_some(refusal),
+        truncated: !done,
+        created: unix_now(),
+        pinned: false,
+        model: Some(open_ai.model.clone()),
+        usage: tokens,
+    };
+    // There is no response object for a stream, so the hook gets the
+    // message instead.
+    run_hook(open_ai, Hook::PostResponse, &message)?;
+    Ok(message)
+}
+
+/// Send the streaming `request` with `body`, and send each line of the
+/// response to `tx`, until the stream ends or the receiver hangs up. The
+/// whole stream is saved to `capture`, if it is given.
+async fn read_stream(
+    open_ai: OpenAI,
+    request: Request,
+    body: Value,
+    mut capture: Option<Capture>,
+    tx: Sender<Result<String, Error>>,
+) {
+    let _permit = open_ai.permit().await;
+    let response = open_ai.open(request, Body::Json(&body), &mut capture).await;
+    let (status, mut response) = match response {
+        Ok(response) => response,
+        Err(e) => {
+            let _ = tx.send(Err(e.wrap(Oops::OpenAIChatResponse)));
+            return;
+        }
+    };
+    // The whole stream, for `--debug-http` and `YAP_RECORD`.
+    let mut raw = Vec::new();
+    // The bytes received since the last newline.
+    let mut pending = Vec::new();
+    loop {
+        let chunk = match response.chunk().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(e) => {
+                let _ = tx.send(Err(e.wrap(Oops::OpenAIChatResponse)));
+                break;
+            }
+        };
+        if capture.is_some() {
+            raw.extend_from_slice(&chunk);
+        }
+        pending.extend_from_slice(&chunk);
+        let Some(end) = pending.iter().rposition(|b| *b == b'\n') else {
+            continue;
+        };
+        let lines: Vec<u8> = pending.drain(..=end).collect();
+        // The receiver hangs up if the user interrupts the stream.
+        if !send_lines(&tx, &lines) {
+            break;
+        }
+    }
+    send_lines(&tx, &pending);
+    if let Some(capture) = capture {
+        capture.response(status, &raw);
+    }
+}
+
+/// Send each line of `bytes` to `tx`, returning whether the receiver is
+/// still listening.
+fn send_lines(tx: &Sender<Result<String, Error>>, bytes: &[u8]) -> bool {
+    let text = match std::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) => {
+            let _ = tx.send(Err(Error::default()
+                .wrap(Oops::OpenAIChatResponse)
+                .because(format!("Error while reading the stream: {e}"))));
+            return false;
+        }
+    };
+    text.lines()
+        .all(|line| tx.send(Ok(line.to_string())).is_ok())
+}
+
+/// Seconds since the Unix epoch.
+fn unix_now() -> Option<u64> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open_ai(model: Model) -> OpenAI {
+        OpenAI {
+            auth_header: String::new(),
+            headers: Default::default(),
+            pre_request: None,
+            post_response: None,
+            permits: std::sync::Arc::new(tokio::sync::Semaphore::new(1)),
+            model,
+            explicit_model: false,
+            seed: None,
+            reasoning_effort: Some(ReasoningEffort::High),
+            command: "test",
+            max_concurrency: 1,
+            dry_run: false,
+            show_prompt: false,
+            debug_http: None,
+            mock: None,
+            record: None,
+            frequency_penalty: Some(0.5),
+            presence_penalty: None,
+            logit_bias: None,
+        }
+    }
+
+    #[test]
+    fn test_request_headers() {
+        let open_ai = OpenAI {
+            auth_header: "Bearer sk-test".into(),
+            headers: [("Helicone-Auth".into(), "Bearer sk-helicone".into())]
+                .into(),
+            ..open_ai(Model::Gpt4o)
+        };
+        let request = open_ai.request("POST", CHAT_URL);
+        assert_eq!(request.header("Authorization"), Some("Bearer sk-test"));
+        assert_eq!(request.header("Helicone-Auth"), Some("Bearer sk-helicone"));
+    }
+
+    #[test]
+    fn test_sampling_payload() {
+        let messages = vec![Message::new(Role::User, "hi".into())];
+        let opts = || PayloadOpts {
+            presence_penalty: Some(1.0),
+            logit_bias: Some(LogitBias::from([("50256".into(), -100.0)])),
+            ..Default::default()
+        };
+        let payload = CompletionPayload::new(
+            &open_ai(Model::Gpt4o),
+            messages.clone(),
+            opts(),
+        );
+        let json = serde_json::to_value(&payload).unwrap();
+        assert_eq!(json["frequency_penalty"], 0.5);
+        assert_eq!(json["presence_penalty"], 1.0);
+        assert_eq!(json["logit_bias"]["50256"], -100.0);
+
+        let payload =
+            CompletionPayload::new(&open_ai(Model::O3Mini), messages, opts());
+        let json = serde_json::to_value(&payload).unwrap();
+        assert!(json.get("frequency_penalty").is_none());
+        assert!(json.get("presence_penalty").is_none());
+        assert!(json.get("logit_bias").is_none());
+    }
+
+    #[test]
+    fn test_describe_prompt() {
+        let messages = vec![
+            Message::new(Role::System, "be brief".into()),
+            Message::new(Role::User, "hi\n".into()),
+        ];
+        assert_eq!(
+            describe_prompt(&messages),
+            "--- system (~6 tokens) ---\nbe brief\n--- user (~5 tokens) ---\nhi\n--- ~11 tokens in total ---"
+        );
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let open_ai = OpenAI {
+            dry_run: true,
+            ..open_ai(Model::Gpt4oMini)
+        };
+        let payload = CompletionPayload::new(
+            &open_ai,
+            vec![Message::new(Role::User, "hi".into())],
+            PayloadOpts::default(),
+        );
+        let e = chat(&open_ai, &payload).unwrap_err();
+        assert!(e.has(&Oops::DryRun));
+        assert_eq!(e.exit_code(), 0);
+    }
+
+    #[test]
+    fn test_pre_request_hook() {
+        let open_ai = OpenAI {
+            pre_request: Some(
+                r#"grep -q '"content":"hi"' && test "$YAP_HOOK" = pre_request && exit 9"#
+                    .into(),
+            ),
+            ..open_ai(Model::Gpt4oMini)
+        };
+        let payload = CompletionPayload::new(
+            &open_ai,
+            vec![Message::new(Role::User, "hi".into())],
+            PayloadOpts::default(),
+        );
+        // The hook vetoes the request before anything is sent.
+        let e = chat(&open_ai, &payload).unwrap_err();
+        assert!(e.has(&Oops::RequestHookError));
+        assert!(e.to_string().contains("exit status: 9"));
+    }
+
+    #[test]
+    fn test_mock_chat() {
+        let open_ai = OpenAI {
+            mock: Some(super::super::mock::Mock::new(
+                "/nonexistent/yap-mock".into(),
+            )),
+            ..open_ai(Model::Gpt4oMini)
+        };
+        let messages = vec![Message::new(Role::User, "hi".into())];
+        let payload = CompletionPayload::new(
+            &open_ai,
+            messages.clone(),
+            PayloadOpts::default(),
+        );
+        let response = chat(&open_ai, &payload).unwrap();
+        let reply = response.choices[0].message.content.clone().unwrap();
+        assert!(reply.contains("mock"));
+
+        let payload = CompletionPayload::new(
+            &open_ai,
+            messages,
+            PayloadOpts {
+                stream: true,
+                ..Default::default()
+            },
+        );
+        let mut streamed = String::new();
+        let message =
+            chat_stream(&open_ai, &payload, &AtomicBool::new(false), |d| {
+                streamed.push_str(d)
+            })
+            .unwrap();
+        assert_eq!(message.content, Some(reply.clone()));
+        assert_eq!(streamed, reply);
+        assert!(!message.truncated);
+    }
+
+    #[test]
+    fn test_replay_stream() {
+        let open_ai = OpenAI::replaying(
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("fixtures/chat-stream"),
+        );
+        let payload = CompletionPayload::new(
+            &open_ai,
+            vec![Message::new(Role::User, "hello".into())],
+            PayloadOpts {
+                stream: true,
+                ..Default::default()
+            },
+        );
+        let mut deltas = Vec::new();
+        let message =
+            chat_stream(&open_ai, &payload, &AtomicBool::new(false), |d| {
+                deltas.push(d.to_string())
+            })
+            .unwrap();
+        assert_eq!(
+            message.content.as_deref(),
+            Some("Hello! How can I help you today?")
+        );
+        assert_eq!(deltas.len(), 10);
+        assert!(!message.truncated);
+        assert!(message.refusal.is_none());
+        let usage = message.usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (9, 10));
+        // Only one request was recorded.
+        let e =
+            chat_stream(&open_ai, &payload, &AtomicBool::new(false), |_| {})
+                .unwrap_err();
+        assert!(e.has(&Oops::MockError));
+    }
+
+    #[test]
+    fn test_reasoning_payload() {
+        let messages = vec![
+            Message::new(Role::System, "be brief".into()),
+            Message::new(Role::User, "hi".into()),
+        ];
+        let payload = serde_json::to_value(CompletionPayload::new(
+            &open_ai(Model::O3Mini),
+            messages.clone(),
+            PayloadOpts::default(),
+        ))
+        .unwrap();
+        assert_eq!(payload["model"], "o3-mini");
+        assert_eq!(payload["messages"][0]["role"], "developer");
+        assert_eq!(payload["reasoning_effort"], "high");
+        assert!(payload.get("response_format").is_none());
+
+        let payload = serde_json::to_value(CompletionPayload::new(
+            &open_ai(Model::O1Mini),
+            messages.clone(),
+            PayloadOpts {
+                response_format: ResponseFormat::JsonSchema {
+                    json_schema: serde_json::json!({
+                        "name": "answer",
+                        "schema": {"type": "object"}
+                    }),
+                },
+                ..Default::default()
+            },
+        ))
+        .unwrap();
+        assert_eq!(payload["messages"][0]["role"], "user");
+        assert!(payload.get("reasoning_effort").is_none());
+        assert!(payload.get("response_format").is_none());
+        let last = payload["messages"][2]["content"].as_str().unwrap();
+        assert!(last.ends_with(r#"{"type":"object"}"#));
+
+        let payload = serde_json::to_value(CompletionPayload::new(
+            &open_ai(Model::Gpt4o),
+            messages,
+            PayloadOpts::default(),
+        ))
+        .unwrap();
+        assert_eq!(payload["messages"][0]["role"], "system");
+        assert!(payload.get("reasoning_effort").is_none());
+    }
+
+    #[test]
+    fn test_parse_model() {
+        assert_eq!("gpt4o".parse(), Ok(Model::Gpt4o));
+        assert_eq!("o3-mini".parse(), Ok(Model::O3Mini));
+        assert!("gpt-5".parse::<Model>().is_err());
+        assert!("ft:".parse::<Model>().is_err());
+
+        let id = "ft:o1-mini-2024-09-12:acme::abc123";
+        let model: Model = serde_json::from_str(&format!("{id:?}")).unwrap();
+        assert_eq!(model, Model::FineTuned(id.into()));
+        assert_eq!(serde_json::to_value(&model).unwrap(), id);
+        assert!(model.is_reasoning());
+        assert!(matches!(model.system_role(), Role::User));
+        let model: Model = "ft:gpt-4o-2024-08-06:acme::x".parse().unwrap();
+        assert!(!model.is_reasoning());
+        assert_eq!(model.context_window(), 128_000);
+    }
+}
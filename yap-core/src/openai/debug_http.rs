@@ -0,0 +1,213 @@
+//! With `yap --debug-http <dir>`, each request to OpenAI and its response are
+//! written to a JSON file in `<dir>`, so that a response which `yap` can't
+//! deserialize can be inspected, and reported. Files are named like
+//! `1733225696-4242-0001-chat-completions.json`; the time, the process ID,
+//! and the request's number within the process, so they sort in order.
+//!
+//! The response body is saved exactly as it was received, as a string.
+//! Credentials are scrubbed: the values of `Authorization` and of any other
+//! header which looks like it holds a secret (i.e, from `headers` in
+//! `config.json`) are replaced with `[REDACTED]`, everywhere they appear.
+//!
+//! Recording fixtures with `YAP_RECORD` saves the same files under other
+//! names; see [super::mock].
+
+use super::{Body, Request};
+use log::warn;
+use serde_json::{json, Value};
+use std::{
+    fs,
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Counts requests within this process.
+static SEQUENCE: AtomicUsize = AtomicUsize::new(1);
+
+/// A request which is being captured, waiting for its response.
+pub struct Capture {
+    paths: Vec<PathBuf>,
+    record: Value,
+    secrets: Vec<String>,
+}
+
+/// Headers whose values are never saved.
+fn is_secret(header: &str) -> bool {
+    let header = header.to_lowercase();
+    ["auth", "key", "token", "secret", "cookie", "password"]
+        .iter()
+        .any(|s| header.contains(s))
+}
+
+/// The last part of `url`'s path, for the file name; i.e, `chat-completions`.
+pub fn slug(url: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let path = path.split_once("/v1/").map_or(path, |(_, rest)| rest);
+    let slug: String = path
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    slug.trim_matches('-').chars().take(60).collect()
+}
+
+/// Replace each of `secrets` in `text`.
+fn scrub(text: &str, secrets: &[String]) -> String {
+    secrets
+        .iter()
+        .filter(|s| !s.is_empty())
+        .fold(text.to_string(), |text, s| {
+            text.replace(s.as_str(), REDACTED)
+        })
+}
+
+fn body_text(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => format!("[{} bytes of binary data]", bytes.len()),
+    }
+}
+
+/// The name of the file for a request to `url` in the `--debug-http`
+/// directory.
+pub fn file_name(url: &str) -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    format!(
+        "{timestamp}-{}-{:04}-{}.json",
+        std::process::id(),
+        SEQUENCE.fetch_add(1, Ordering::SeqCst),
+        slug(url)
+    )
+}
+
+impl Capture {
+    /// Begin capturing `request`, to be saved to each of `paths`. There is
+    /// nothing to capture if `paths` is empty.
+    pub fn start(
+        paths: Vec<PathBuf>,
+        request: &Request,
+        body: &Body,
+    ) -> Option<Self> {
+        if paths.is_empty() {
+            return None;
+        }
+        let mut secrets = Vec::new();
+        let mut headers = serde_json::Map::new();
+        for (name, value) in request.headers() {
+            if is_secret(name) {
+                secrets.push(value.to_string());
+                // The key alone, in case it is echoed without `Bearer`.
+                if let Some(token) = value.strip_prefix("Bearer ") {
+                    secrets.push(token.to_string());
+                }
+                headers.insert(name.to_string(), json!(REDACTED));
+            } else {
+                headers.insert(name.to_string(), json!(value));
+            }
+        }
+        // Longer secrets first, so that `Bearer <key>` is scrubbed whole.
+        secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+        let body = match body {
+            Body::Empty => Value::Null,
+            Body::Json(value) => (*value).clone(),
+            Body::Text(text) => json!(text),
+            Body::Bytes(bytes) => json!(body_text(bytes)),
+        };
+        Some(Self {
+            paths,
+            record: json!({
+                "method": request.method(),
+                "url": request.url(),
+                "request_headers": headers,
+                "request_body": body,
+            }),
+            secrets,
+        })
+    }
+
+    /// Save the capture, with the response's `status` and `body`.
+    pub fn response(mut self, status: u16, body: &[u8]) {
+        self.record["status"] = json!(status);
+        self.record["response_body"] = json!(body_text(body));
+        self.save();
+    }
+
+    /// Save the capture of a request which failed without a response.
+    pub fn error(mut self, error: &str) {
+        self.record["error"] = json!(error);
+        self.save();
+    }
+
+    fn save(self) {
+        let text = match serde_json::to_string_pretty(&self.record) {
+            Ok(text) => scrub(&text, &self.secrets),
+            Err(e) => {
+                warn!("Could not serialize the capture of a request: {e}");
+                return;
+            }
+        };
+        for path in &self.paths {
+            let written = path
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| fs::write(path, &text));
+            // Capturing is for debugging; it should never break the request.
+            if let Err(e) = written {
+                warn!("Could not write {path:?}: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slug() {
+        assert_eq!(
+            slug("https://api.openai.com/v1/chat/completions"),
+            "chat-completions"
+        );
+        assert_eq!(
+            slug("https://api.openai.com/v1/fine_tuning/jobs/ft-1/events?limit=5"),
+            "fine-tuning-jobs-ft-1-events"
+        );
+    }
+
+    #[test]
+    fn test_capture_is_scrubbed() {
+        let dir = std::env::temp_dir()
+            .join(format!("yap-test-debug-http-{}", std::process::id()));
+        let request =
+            Request::new("POST", "https://api.openai.com/v1/chat/completions")
+                .set("Authorization", "Bearer sk-secret")
+                .set("Helicone-Auth", "Bearer sk-helicone")
+                .set("Content-Type", "application/json");
+        let payload = json!({"messages": [{"role": "user", "content": "hi"}]});
+        let path = dir.join(file_name(request.url()));
+        let capture =
+            Capture::start(vec![path.clone()], &request, &Body::Json(&payload))
+                .unwrap();
+        capture.response(200, br#"{"echo": "sk-secret"}"#);
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(!text.contains("sk-secret"));
+        assert!(!text.contains("sk-helicone"));
+        let record: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(record["request_headers"]["authorization"], REDACTED);
+        assert_eq!(
+            record["request_headers"]["content-type"],
+            "application/json"
+        );
+        assert_eq!(record["request_body"], payload);
+        assert_eq!(record["status"], 200);
+        assert_eq!(record["response_body"], r#"{"echo": "[REDACTED]"}"#);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
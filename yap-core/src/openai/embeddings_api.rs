@@ -0,0 +1,91 @@
+//! <https://platform.openai.com/docs/api-reference/embeddings>
+
+use super::{preview, Body, OpenAI};
+use crate::{
+    err::{Error, Oops},
+    pool,
+};
+use log::debug;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// Embeddings are always made with this model, regardless of `yap --model`,
+/// since embeddings from different models can't be compared.
+pub const EMBEDDING_MODEL: &str = "text-embedding-3-small";
+
+const EMBEDDINGS_URL: &str = "https://api.openai.com/v1/embeddings";
+
+/// The number of inputs sent in each request.
+const BATCH_SIZE: usize = 256;
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<Embedding>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Embedding {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+/// Embed each of `inputs`, returning the embeddings in the same order.
+/// Batches of inputs are sent concurrently; see [crate::pool].
+pub fn embed(
+    open_ai: &OpenAI,
+    inputs: &[String],
+) -> Result<Vec<Vec<f32>>, Error> {
+    let batches: Vec<&[String]> = inputs.chunks(BATCH_SIZE).collect();
+    if open_ai.dry_run {
+        if let Some(batch) = batches.first() {
+            return Err(preview(EMBEDDINGS_URL, &payload(batch)));
+        }
+    }
+    let mut embeddings = Vec::with_capacity(inputs.len());
+    let results =
+        pool::block_on(pool::map(&batches, open_ai.max_concurrency, |batch| {
+            embed_batch(open_ai, batch)
+        }));
+    for result in results {
+        embeddings.extend(result?);
+    }
+    Ok(embeddings)
+}
+
+fn payload(batch: &[String]) -> Value {
+    json!({
+        "model": EMBEDDING_MODEL,
+        "input": batch,
+    })
+}
+
+async fn embed_batch(
+    open_ai: &OpenAI,
+    batch: &[String],
+) -> Result<Vec<Vec<f32>>, Error> {
+    debug!("Embedding {} inputs", batch.len());
+    let response = open_ai
+        .fetch_text(
+            open_ai.request("POST", EMBEDDINGS_URL),
+            Body::Json(&payload(batch)),
+        )
+        .await
+        .map_err(|e| e.wrap(Oops::EmbeddingError))?;
+    let mut response: EmbeddingResponse = serde_json::from_str(&response)
+        .map_err(|e| {
+            Error::default()
+                .wrap(Oops::EmbeddingError)
+                .because(format!("Could not deserialize the response: {e}"))
+        })?;
+    if response.data.len() != batch.len() {
+        return Err(Error::default().wrap(Oops::EmbeddingError).because(
+            format!(
+                "Expected {} embeddings, but got {}",
+                batch.len(),
+                response.data.len()
+            ),
+        ));
+    }
+    response.data.sort_by_key(|e| e.index);
+    Ok(response.data.into_iter().map(|e| e.embedding).collect())
+}
@@ -0,0 +1,143 @@
+//! <https://platform.openai.com/docs/api-reference/fine-tuning>
+
+use super::{jsonl_upload, preview, Body, OpenAI};
+use crate::err::{Error, Oops};
+use log::debug;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+
+const API: &str = "https://api.openai.com/v1";
+
+#[derive(Debug, Deserialize)]
+pub struct Job {
+    pub id: String,
+    /// The base model.
+    pub model: String,
+    /// e.g. `validating_files`, `queued`, `running`, `succeeded`, `failed`,
+    /// `cancelled`.
+    pub status: String,
+    /// The ID of the new model, once the job has succeeded.
+    pub fine_tuned_model: Option<String>,
+    pub trained_tokens: Option<u64>,
+    pub error: Option<JobError>,
+}
+
+impl Job {
+    /// Whether the job will never change again.
+    pub fn is_finished(&self) -> bool {
+        matches!(self.status.as_str(), "succeeded" | "failed" | "cancelled")
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JobError {
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Event {
+    pub id: String,
+    /// Seconds since the Unix epoch.
+    pub created_at: u64,
+    pub message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct List<T> {
+    data: Vec<T>,
+}
+
+#[derive(Debug, Deserialize)]
+struct File {
+    id: String,
+}
+
+fn parse<T: DeserializeOwned>(body: &str) -> Result<T, Error> {
+    serde_json::from_str(body).map_err(|e| {
+        debug!("Bad response body: {body}");
+        Error::default()
+            .wrap(Oops::FinetuneError)
+            .because(format!("Could not deserialize the response: {e}"))
+    })
+}
+
+/// Upload `jsonl` as a training file, returning its file ID.
+pub fn upload_training_file(
+    open_ai: &OpenAI,
+    jsonl: &str,
+) -> Result<String, Error> {
+    if open_ai.dry_run {
+        // The examples are what's interesting, so they are shown instead of
+        // the multipart body.
+        let examples = jsonl
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<serde_json::Value>, _>>()
+            .map_err(|e| {
+                Error::default()
+                    .wrap(Oops::FinetuneError)
+                    .because(format!("Invalid training file: {e}"))
+            })?;
+        return Err(preview(&format!("{API}/files"), &examples));
+    }
+    let (content_type, body) = jsonl_upload("fine-tune", jsonl);
+    let response = open_ai
+        .send_text(
+            open_ai
+                .request("POST", &format!("{API}/files"))
+                .set("Content-Type", &content_type),
+            Body::Text(&body),
+        )
+        .map_err(|e| e.wrap(Oops::FinetuneError))?;
+    Ok(parse::<File>(&response)?.id)
+}
+
+/// Start fine-tuning `model` on an uploaded training file. The new model's
+/// ID will include `suffix`, if it is given.
+pub fn create_job(
+    open_ai: &OpenAI,
+    training_file: &str,
+    model: &str,
+    suffix: Option<&str>,
+) -> Result<Job, Error> {
+    let mut payload = json!({
+        "training_file": training_file,
+        "model": model,
+    });
+    if let Some(suffix) = suffix {
+        payload["suffix"] = json!(suffix);
+    }
+    let response = open_ai
+        .send_text(
+            open_ai.request("POST", &format!("{API}/fine_tuning/jobs")),
+            Body::Json(&payload),
+        )
+        .map_err(|e| e.wrap(Oops::FinetuneError))?;
+    parse(&response)
+}
+
+pub fn get_job(open_ai: &OpenAI, id: &str) -> Result<Job, Error> {
+    let response = open_ai
+        .send_text(
+            open_ai.request("GET", &format!("{API}/fine_tuning/jobs/{id}")),
+            Body::Empty,
+        )
+        .map_err(|e| e.wrap(Oops::FinetuneError))?;
+    parse(&response)
+}
+
+/// The most recent `limit` events of a job, oldest first.
+pub fn get_events(
+    open_ai: &OpenAI,
+    id: &str,
+    limit: usize,
+) -> Result<Vec<Event>, Error> {
+    let url = format!("{API}/fine_tuning/jobs/{id}/events?limit={limit}");
+    let response = open_ai
+        .send_text(open_ai.request("GET", &url), Body::Empty)
+        .map_err(|e| e.wrap(Oops::FinetuneError))?;
+    let mut events = parse::<List<Event>>(&response)?.data;
+    // OpenAI lists the newest events first.
+    events.reverse();
+    Ok(events)
+}
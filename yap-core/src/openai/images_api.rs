@@ -0,0 +1,97 @@
+//! <https://platform.openai.com/docs/api-reference/images>
+
+use super::{preview, Body, OpenAI};
+use crate::err::{Error, Oops};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+const GENERATIONS_URL: &str = "https://api.openai.com/v1/images/generations";
+
+/// Images are always generated with this model, regardless of `yap --model`.
+pub const IMAGE_MODEL: &str = "dall-e-3";
+
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum Size {
+    #[default]
+    #[serde(rename = "1024x1024")]
+    Square,
+    #[serde(rename = "1792x1024")]
+    Landscape,
+    #[serde(rename = "1024x1792")]
+    Portrait,
+}
+
+#[derive(Debug, Deserialize)]
+struct GenerationResponse {
+    data: Vec<GeneratedImage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GeneratedImage {
+    b64_json: String,
+    /// The model rewrites prompts before drawing them.
+    revised_prompt: Option<String>,
+}
+
+pub struct Image {
+    /// The image, as a PNG.
+    pub png: Vec<u8>,
+    pub revised_prompt: Option<String>,
+}
+
+/// Draw `prompt`.
+pub fn generate(
+    open_ai: &OpenAI,
+    prompt: &str,
+    size: Size,
+) -> Result<Image, Error> {
+    let oops =
+        |why: String| Error::default().wrap(Oops::ImagineError).because(why);
+    let payload = json!({
+        "model": IMAGE_MODEL,
+        "prompt": prompt,
+        "size": size,
+        "response_format": "b64_json",
+    });
+    if open_ai.dry_run {
+        return Err(preview(GENERATIONS_URL, &payload));
+    }
+    debug!("Generating a {size:?} image");
+    // The base64 encoded image is large, but `send_text` has no limit.
+    let response = open_ai
+        .send_text(
+            open_ai.request("POST", GENERATIONS_URL),
+            Body::Json(&payload),
+        )
+        .map_err(|e| e.wrap(Oops::ImagineError))?;
+    let response: GenerationResponse = serde_json::from_str(&response)
+        .map_err(|e| {
+            oops(format!("Could not deserialize the response: {e}"))
+        })?;
+    let image = response
+        .data
+        .into_iter()
+        .next()
+        .ok_or_else(|| oops("The response has no images".into()))?;
+    let png = STANDARD
+        .decode(image.b64_json)
+        .map_err(|e| oops(format!("The image is not valid base64: {e}")))?;
+    Ok(Image {
+        png,
+        revised_prompt: image.revised_prompt,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_size() {
+        assert_eq!(json!(Size::Landscape), json!("1792x1024"));
+        assert_eq!(json!(Size::default()), json!("1024x1024"));
+    }
+}
@@ -0,0 +1,346 @@
+//! With `yap --provider mock` (or `YAP_MOCK=1`), requests are answered from
+//! fixture files instead of OpenAI, so that `yap chat`, `yap annotate`, `yap
+//! complete`, etc. can be tested (or demonstrated) offline, without an API
+//! key.
+//!
+//! Fixtures are read from `$YAP_MOCK_DIR`, or else `$XDG_CONFIG_HOME/yap/mock`.
+//! Each is named for the endpoint it answers, as with `--debug-http` (see
+//! [super::debug_http]); i.e, `chat-completions.json`. To script a sequence
+//! of responses, number the fixtures; the third request to an endpoint is
+//! answered by `chat-completions.3.json` if it exists, and by
+//! `chat-completions.json` otherwise.
+//!
+//! A fixture is a JSON object with one of;
+//!
+//! - `content`: for chat completions, the content of the reply. If it is not
+//!   a string, it is serialized; i.e, for a structured response like those of
+//!   `yap annotate`.
+//! - `response`: the JSON response body.
+//! - `response_body`: the response body as a string, exactly as it is sent.
+//!
+//! and optionally `status` (200 by default). Files saved by `--debug-http`
+//! have `status` and `response_body`, so they can be used as fixtures as-is.
+//!
+//! ```json
+//! {
+//!   "content": "Hello from the mock!"
+//! }
+//! ```
+//!
+//! Streamed chat completions are streamed from a `content` or `response`
+//! fixture in one chunk. Without any fixture, chat completions get a canned
+//! reply, and other requests fail.
+//!
+//! # Record and replay
+//!
+//! With `YAP_RECORD=<dir>`, requests are sent to OpenAI as usual, and each
+//! response is also saved to `<dir>` as a numbered fixture, scrubbed as with
+//! `--debug-http`. Responses are not taken from `yap complete`'s cache while
+//! recording. With `YAP_REPLAY=<dir>`, those fixtures are served instead of
+//! OpenAI, in the same order; this is the mock provider, except that a
+//! missing fixture is an error rather than a canned reply. i.e,
+//!
+//! ```bash
+//! YAP_RECORD=fixtures/greeting yap chat --new hello
+//! YAP_REPLAY=fixtures/greeting yap chat --new hello
+//! ```
+//!
+//! Numbering starts over with each run of `yap`, so one directory holds the
+//! requests of one command.
+
+use super::{debug_http::slug, Body};
+use crate::{
+    config::get_or_create_yap_cfg_dir,
+    err::{Error, Oops},
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+/// The reply to chat completions when there is no fixture.
+const CANNED_CONTENT: &str = "This is a mock response from yap.";
+
+/// Counts requests to each endpoint, for numbered fixtures.
+#[derive(Clone, Debug, Default)]
+struct Counter(Arc<Mutex<HashMap<String, usize>>>);
+
+impl Counter {
+    /// The number of this request to the endpoint named `slug`, from 1.
+    fn next(&self, slug: &str) -> usize {
+        let mut counts = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let count = counts.entry(slug.to_string()).or_default();
+        *count += 1;
+        *count
+    }
+}
+
+/// Answers requests from fixture files.
+#[derive(Clone, Debug)]
+pub struct Mock {
+    dir: PathBuf,
+    /// Whether a missing fixture is an error, when replaying.
+    strict: bool,
+    counts: Counter,
+}
+
+/// Saves responses as fixtures, with `YAP_RECORD`.
+#[derive(Clone, Debug)]
+pub struct Recorder {
+    dir: PathBuf,
+    counts: Counter,
+}
+
+impl Recorder {
+    pub fn from_env() -> Option<Self> {
+        env::var_os("YAP_RECORD").map(|dir| Self {
+            dir: PathBuf::from(dir),
+            counts: Default::default(),
+        })
+    }
+
+    /// Where to save the fixture for the next request to `url`.
+    pub fn path(&self, url: &str) -> PathBuf {
+        let slug = slug(url);
+        let n = self.counts.next(&slug);
+        self.dir.join(format!("{slug}.{n}.json"))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Fixture {
+    #[serde(default = "ok")]
+    status: u16,
+    content: Option<Value>,
+    response: Option<Value>,
+    response_body: Option<String>,
+}
+
+fn ok() -> u16 {
+    200
+}
+
+fn oops(why: String) -> Error {
+    Error::default().wrap(Oops::MockError).because(why)
+}
+
+/// Whether `YAP_MOCK` or `YAP_REPLAY` asks for the mock provider.
+pub fn enabled_by_env() -> bool {
+    env::var("YAP_MOCK").is_ok_and(|v| !matches!(v.as_str(), "" | "0"))
+        || env::var_os("YAP_REPLAY").is_some()
+}
+
+/// A chat completion response, with one choice whose content is `content`.
+fn completion(content: &str) -> Value {
+    json!({
+        "choices": [{
+            "message": {"role": "assistant", "content": content},
+            "finish_reason": "stop",
+        }],
+        "usage": {"prompt_tokens": 0, "completion_tokens": 0},
+    })
+}
+
+/// A chat completion `response` as a stream of server-sent events.
+fn stream(response: &Value) -> String {
+    let message = &response["choices"][0]["message"];
+    let delta = json!({
+        "content": message["content"],
+        "refusal": message["refusal"],
+    });
+    let usage = response.get("usage").cloned().unwrap_or(Value::Null);
+    format!(
+        "data: {}\n\ndata: {}\n\ndata: [DONE]\n\n",
+        json!({"choices": [{"delta": delta}]}),
+        json!({"choices": [], "usage": usage}),
+    )
+}
+
+impl Mock {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            strict: false,
+            counts: Default::default(),
+        }
+    }
+
+    /// A mock which only serves the fixtures in `dir`.
+    pub fn replay(dir: PathBuf) -> Self {
+        Self {
+            strict: true,
+            ..Self::new(dir)
+        }
+    }
+
+    /// A mock replaying `$YAP_REPLAY`, or else reading fixtures from
+    /// `$YAP_MOCK_DIR`, or else `$XDG_CONFIG_HOME/yap/mock`.
+    pub fn from_env() -> Result<Self, Error> {
+        if let Some(dir) = env::var_os("YAP_REPLAY") {
+            return Ok(Self::replay(PathBuf::from(dir)));
+        }
+        let dir = match env::var_os("YAP_MOCK_DIR") {
+            Some(dir) => PathBuf::from(dir),
+            None => get_or_create_yap_cfg_dir()?.join("mock"),
+        };
+        Ok(Self::new(dir))
+    }
+
+    /// The fixture for the `n`th request to the endpoint named `slug`, if
+    /// there is one.
+    fn fixture(&self, slug: &str, n: usize) -> Result<Option<Fixture>, Error> {
+        let candidates = [
+            self.dir.join(format!("{slug}.{n}.json")),
+            self.dir.join(format!("{slug}.json")),
+        ];
+        let Some(path) = candidates.iter().find(|p| p.exists()) else {
+            return Ok(None);
+        };
+        let text = fs::read_to_string(path)
+            .map_err(|e| oops(format!("Could not read {path:?}: {e}")))?;
+        serde_json::from_str(&text)
+            .map(Some)
+            .map_err(|e| oops(format!("Invalid fixture {path:?}: {e}")))
+    }
+
+    /// The status and body of the response to a request to `url`.
+    pub fn respond(
+        &self,
+        url: &str,
+        body: &Body,
+    ) -> Result<(u16, Vec<u8>), Error> {
+        let slug = slug(url);
+        let n = self.counts.next(&slug);
+        let is_chat = slug == "chat-completions";
+        let fixture = match self.fixture(&slug, n)? {
+            Some(fixture) => fixture,
+            None if self.strict => {
+                return Err(oops(format!(
+                    "Request {n} to {url} was not recorded in {:?}; expected {slug}.{n}.json",
+                    self.dir
+                )))
+            }
+            None if is_chat => Fixture {
+                status: ok(),
+                content: Some(json!(CANNED_CONTENT)),
+                response: None,
+                response_body: None,
+            },
+            None => {
+                return Err(oops(format!(
+                "There is no fixture for {url} in {:?}; expected {slug}.json",
+                self.dir
+            )))
+            }
+        };
+        let status = fixture.status;
+        let response = match fixture {
+            Fixture {
+                response_body: Some(text),
+                ..
+            } => return Ok((status, text.into_bytes())),
+            Fixture {
+                response: Some(response),
+                ..
+            } => response,
+            Fixture {
+                content: Some(Value::String(content)),
+                ..
+            } if is_chat => completion(&content),
+            Fixture {
+                content: Some(content),
+                ..
+            } if is_chat => completion(&content.to_string()),
+            _ => {
+                return Err(oops(format!(
+                    "The fixture for {url} needs a response or response_body"
+                )))
+            }
+        };
+        let streaming =
+            matches!(body, Body::Json(payload) if payload["stream"] == true);
+        let text = if streaming && is_chat {
+            stream(&response)
+        } else {
+            response.to_string()
+        };
+        Ok((status, text.into_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://api.openai.com/v1/chat/completions";
+
+    fn fixtures(name: &str, files: &[(&str, &str)]) -> Mock {
+        let dir = env::temp_dir()
+            .join(format!("yap-test-mock-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (file, text) in files {
+            fs::write(dir.join(file), text).unwrap();
+        }
+        Mock::new(dir)
+    }
+
+    fn respond(mock: &Mock, url: &str, body: &Body) -> (u16, String) {
+        let (status, bytes) = mock.respond(url, body).unwrap();
+        (status, String::from_utf8(bytes).unwrap())
+    }
+
+    #[test]
+    fn test_scripted_responses() {
+        let mock = fixtures(
+            "scripted",
+            &[
+                ("chat-completions.json", r#"{"content": "again"}"#),
+                ("chat-completions.2.json", r#"{"content": {"ok": true}}"#),
+                (
+                    "models.json",
+                    r#"{"status": 500, "response_body": "oh no"}"#,
+                ),
+            ],
+        );
+        let content = |(_, text): (u16, String)| {
+            let response: Value = serde_json::from_str(&text).unwrap();
+            response["choices"][0]["message"]["content"].clone()
+        };
+        assert_eq!(content(respond(&mock, URL, &Body::Empty)), "again");
+        assert_eq!(
+            content(respond(&mock, URL, &Body::Empty)),
+            r#"{"ok":true}"#
+        );
+        assert_eq!(content(respond(&mock, URL, &Body::Empty)), "again");
+        assert_eq!(
+            respond(&mock, "https://api.openai.com/v1/models", &Body::Empty),
+            (500, "oh no".into())
+        );
+        assert!(mock
+            .respond("https://api.openai.com/v1/batches", &Body::Empty)
+            .is_err());
+        fs::remove_dir_all(&mock.dir).unwrap();
+    }
+
+    #[test]
+    fn test_canned_stream() {
+        let mock = fixtures("stream", &[]);
+        let payload = json!({"stream": true});
+        let (status, text) = respond(&mock, URL, &Body::Json(&payload));
+        assert_eq!(status, 200);
+        let events: Vec<&str> = text
+            .lines()
+            .filter_map(|l| l.strip_prefix("data: "))
+            .collect();
+        assert_eq!(events.len(), 3);
+        let chunk: Value = serde_json::from_str(events[0]).unwrap();
+        assert_eq!(chunk["choices"][0]["delta"]["content"], CANNED_CONTENT);
+        assert_eq!(events[2], "[DONE]");
+        fs::remove_dir_all(&mock.dir).unwrap();
+    }
+}
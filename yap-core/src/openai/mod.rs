@@ -0,0 +1,610 @@
+//! `yap`'s interface to OpenAI
+
+pub mod audio_api;
+pub mod batch_api;
+mod chat_api;
+mod debug_http;
+pub mod embeddings_api;
+pub mod finetune_api;
+pub mod images_api;
+mod mock;
+pub mod models_api;
+
+use crate::{
+    config::Settings,
+    err::{Error, Oops},
+    files,
+    http::{Body, Request},
+    pool,
+};
+use debug_http::Capture;
+use mock::{Mock, Recorder};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    default::Default,
+    env,
+    fmt::Display,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::Arc,
+};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// Where requests are sent.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum Provider {
+    #[default]
+    #[cfg_attr(feature = "clap", value(name = "openai"))]
+    OpenAI,
+    /// Answer from fixture files, offline; see [mock].
+    Mock,
+}
+
+#[derive(Clone)]
+pub struct OpenAI {
+    auth_header: String,
+    pub model: Model,
+    /// Whether the model was chosen with `yap --model`, rather than by
+    /// `config.json` or the default.
+    pub explicit_model: bool,
+    /// The default seed for requests; see [PayloadOpts::seed].
+    pub seed: Option<i64>,
+    /// The default reasoning effort for requests; see
+    /// [PayloadOpts::reasoning_effort].
+    pub reasoning_effort: Option<ReasoningEffort>,
+    /// The `yap` subcommand making requests, which is recorded in the usage
+    /// log; see [crate::usage].
+    pub command: &'static str,
+    /// The maximum number of requests to send at once; see [crate::pool].
+    pub max_concurrency: usize,
+    /// Print requests instead of sending them; see [preview].
+    pub dry_run: bool,
+    /// Print each prompt to `STDERR` before it is sent.
+    pub show_prompt: bool,
+    /// Save each request and response to this directory; see [debug_http].
+    pub debug_http: Option<PathBuf>,
+    /// Answers requests instead of OpenAI, with `--provider mock`.
+    mock: Option<Mock>,
+    /// Saves responses as fixtures, with `YAP_RECORD`; see [mock].
+    record: Option<Recorder>,
+    /// The defaults for [PayloadOpts::frequency_penalty] and
+    /// [PayloadOpts::presence_penalty], from `config.json`.
+    pub frequency_penalty: Option<f32>,
+    pub presence_penalty: Option<f32>,
+    /// The default for [PayloadOpts::logit_bias], from `logit_bias_file` in
+    /// `config.json`.
+    pub logit_bias: Option<LogitBias>,
+    /// Extra headers for every request, from `config.json`.
+    headers: BTreeMap<String, String>,
+    /// Shell commands which are run before each chat completion request, and
+    /// after each response; see [run_hook].
+    pre_request: Option<String>,
+    post_response: Option<String>,
+    /// Caps the requests in flight at `max_concurrency`, across every copy
+    /// of this client; see [crate::pool].
+    permits: Arc<Semaphore>,
+}
+
+/// How [OpenAI::from_env] sets up a client; i.e, from `yap`'s global
+/// options. The default is a client set up only by the environment and
+/// `config.json`.
+#[derive(Clone, Debug, Default)]
+pub struct ClientOpts {
+    /// From `yap --model`; takes precedence over `config.json`.
+    pub model: Option<Model>,
+    /// See [OpenAI::seed].
+    pub seed: Option<i64>,
+    /// See [OpenAI::reasoning_effort].
+    pub reasoning_effort: Option<ReasoningEffort>,
+    /// See [OpenAI::dry_run]. The API key is not needed for a dry run.
+    pub dry_run: bool,
+    /// See [OpenAI::show_prompt].
+    pub show_prompt: bool,
+    /// See [OpenAI::debug_http].
+    pub debug_http: Option<PathBuf>,
+    /// From `yap --provider`, or else `YAP_MOCK`. The API key is not needed
+    /// for the mock provider.
+    pub provider: Option<Provider>,
+}
+
+impl OpenAI {
+    /// A client for the `yap` subcommand named `command`, which picks its
+    /// model from `config.json` unless `opts` sets one.
+    pub fn from_env(
+        command: &'static str,
+        opts: &ClientOpts,
+    ) -> Result<Self, Error> {
+        let ClientOpts {
+            model: preferred_model,
+            seed,
+            reasoning_effort,
+            dry_run,
+            show_prompt,
+            debug_http,
+            provider,
+        } = opts.clone();
+        let mock = match provider {
+            Some(Provider::Mock) => Some(Mock::from_env()?),
+            None if mock::enabled_by_env() => Some(Mock::from_env()?),
+            _ => None,
+        };
+        let api_key = match resolve_api_key() {
+            Err(_) if dry_run || mock.is_some() => String::new(),
+            key => key?,
+        };
+        let settings = Settings::load()?;
+        let logit_bias = match &settings.logit_bias_file {
+            Some(path) => Some(load_logit_bias(path)?),
+            None => None,
+        };
+        Ok(Self {
+            auth_header: format!("Bearer {api_key}"),
+            explicit_model: preferred_model.is_some(),
+            model: preferred_model
+                .or(settings.model_for(command))
+                .unwrap_or_default(),
+            seed,
+            reasoning_effort,
+            command,
+            max_concurrency: settings.max_concurrency,
+            dry_run,
+            show_prompt,
+            debug_http,
+            mock,
+            record: Recorder::from_env(),
+            frequency_penalty: settings.frequency_penalty,
+            presence_penalty: settings.presence_penalty,
+            logit_bias,
+            headers: settings.headers,
+            pre_request: settings.pre_request,
+            post_response: settings.post_response,
+            permits: Arc::new(Semaphore::new(settings.max_concurrency.max(1))),
+        })
+    }
+
+    /// A request to `url`, with the `Authorization` header and any extra
+    /// headers from `config.json`.
+    fn request(&self, method: &str, url: &str) -> Request {
+        let request =
+            Request::new(method, url).set("Authorization", &self.auth_header);
+        self.headers
+            .iter()
+            .fold(request, |request, (name, value)| request.set(name, value))
+    }
+
+    /// A client which replays the fixtures in `dir` (i.e, under `fixtures/`
+    /// in this repository), for regression tests; see [mock].
+    #[cfg(test)]
+    pub fn replaying(dir: PathBuf) -> Self {
+        Self {
+            auth_header: String::new(),
+            model: Model::default(),
+            explicit_model: false,
+            seed: None,
+            reasoning_effort: None,
+            command: "test",
+            max_concurrency: 1,
+            dry_run: false,
+            show_prompt: false,
+            debug_http: None,
+            mock: Some(Mock::replay(dir)),
+            record: None,
+            frequency_penalty: None,
+            presence_penalty: None,
+            logit_bias: None,
+            headers: Default::default(),
+            pre_request: None,
+            post_response: None,
+            permits: Arc::new(Semaphore::new(1)),
+        }
+    }
+
+    /// Whether requests are answered by the mock provider; see [mock].
+    pub fn is_mock(&self) -> bool {
+        self.mock.is_some()
+    }
+
+    /// Whether responses are being recorded as fixtures; see [mock].
+    pub fn is_recording(&self) -> bool {
+        self.record.is_some()
+    }
+
+    /// Begin capturing `request`, for `--debug-http` and `YAP_RECORD`.
+    fn capture(&self, request: &Request, body: &Body) -> Option<Capture> {
+        let url = request.url();
+        let paths = self
+            .debug_http
+            .iter()
+            .map(|dir| dir.join(debug_http::file_name(url)))
+            .chain(self.record.iter().map(|r| r.path(url)))
+            .collect();
+        Capture::start(paths, request, body)
+    }
+
+    /// Wait until fewer than `max_concurrency` requests are in flight. The
+    /// request counts as in flight until the permit is dropped.
+    async fn permit(&self) -> SemaphorePermit<'_> {
+        self.permits
+            .acquire()
+            .await
+            .expect("the semaphore is never closed")
+    }
+
+    /// Send `request` with `body`, and read the whole response. Unsuccessful
+    /// responses are errors, as with [Error::wrap_http_status]. With
+    /// `--debug-http`, the request and response are saved; see [debug_http].
+    async fn fetch(
+        &self,
+        request: Request,
+        body: Body<'_>,
+    ) -> Result<Vec<u8>, Error> {
+        let _permit = self.permit().await;
+        let mut capture = self.capture(&request, &body);
+        let (status, mut response) =
+            self.open(request, body, &mut capture).await?;
+        let mut bytes = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            bytes.extend_from_slice(&chunk);
+        }
+        if let Some(capture) = capture {
+            capture.response(status, &bytes);
+        }
+        Ok(bytes)
+    }
+
+    /// Like [OpenAI::fetch], for responses which are text; i.e, JSON.
+    async fn fetch_text(
+        &self,
+        request: Request,
+        body: Body<'_>,
+    ) -> Result<String, Error> {
+        String::from_utf8(self.fetch(request, body).await?).map_err(|e| {
+            Error::default()
+                .wrap(Oops::HttpMetaError)
+                .because(format!("The response is not valid utf-8: {e}"))
+        })
+    }
+
+    /// Like [OpenAI::fetch], but blocks; for requests which are sent one at
+    /// a time.
+    fn send(&self, request: Request, body: Body) -> Result<Vec<u8>, Error> {
+        pool::block_on(self.fetch(request, body))
+    }
+
+    /// Like [OpenAI::fetch_text], but blocks.
+    fn send_text(&self, request: Request, body: Body) -> Result<String, Error> {
+        pool::block_on(self.fetch_text(request, body))
+    }
+
+    /// Send `request` with `body` (or answer it with the mock provider),
+    /// returning the response's status and its body, which is read as it
+    /// arrives. Unsuccessful responses are errors, and are saved to
+    /// `capture` if it is given.
+    async fn open(
+        &self,
+        request: Request,
+        body: Body<'_>,
+        capture: &mut Option<Capture>,
+    ) -> Result<(u16, Incoming), Error> {
+        if let Some(mock) = &self.mock {
+            let (status, bytes) = mock.respond(request.url(), &body)?;
+            if status >= 400 {
+                return Err(failed(status, request.url(), &bytes, capture));
+            }
+            return Ok((status, Incoming::Mock(Some(bytes))));
+        }
+        let response = request.send(body).await.map_err(|e| {
+            if let Some(capture) = capture.take() {
+                capture.error(&e.to_string());
+            }
+            Error::default().wrap_http(e)
+        })?;
+        let status = response.status().as_u16();
+        if status >= 400 {
+            let url = response.url().to_string();
+            let bytes = response.bytes().await.unwrap_or_default();
+            return Err(failed(status, &url, &bytes, capture));
+        }
+        Ok((status, Incoming::Http(response)))
+    }
+
+    /// A copy of this client which uses `model`.
+    pub fn with_model(&self, model: Model) -> Self {
+        Self {
+            model,
+            ..self.clone()
+        }
+    }
+}
+
+/// The body of a response, which is read as it arrives.
+enum Incoming {
+    Mock(Option<Vec<u8>>),
+    Http(reqwest::Response),
+}
+
+impl Incoming {
+    /// The next piece of the body, or `None` once all of it has been read.
+    async fn chunk(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        match self {
+            Self::Mock(bytes) => Ok(bytes.take()),
+            Self::Http(response) => response
+                .chunk()
+                .await
+                .map(|chunk| chunk.map(|c| c.to_vec()))
+                .map_err(|e| {
+                    Error::default()
+                        .wrap(Oops::HttpMetaError)
+                        .because(format!("Could not read the response: {e}"))
+                }),
+        }
+    }
+}
+
+/// The error for an unsuccessful response from `url`, after saving it to
+/// `capture`, if it is given.
+fn failed(
+    status: u16,
+    url: &str,
+    body: &[u8],
+    capture: &mut Option<Capture>,
+) -> Error {
+    if let Some(capture) = capture.take() {
+        capture.response(status, body);
+    }
+    let text = String::from_utf8_lossy(body);
+    Error::default().wrap_http_status(status, url, Some(&text))
+}
+
+/// Shell commands from `config.json` which are run around each chat
+/// completion request.
+#[derive(Clone, Copy, Debug)]
+enum Hook {
+    PreRequest,
+    PostResponse,
+}
+
+impl Display for Hook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::PreRequest => write!(f, "pre_request"),
+            Self::PostResponse => write!(f, "post_response"),
+        }
+    }
+}
+
+/// Run the `hook` command from `config.json`, if it is set, with `body` as
+/// JSON on its `STDIN`. The hook's `STDOUT` goes to
+/// `STDERR`, so that it can't mix with `yap`'s output. `$YAP_HOOK`,
+/// `$YAP_COMMAND`, and `$YAP_MODEL` describe the request. If the hook fails,
+/// so does the command; a `pre_request` hook can veto requests this way.
+fn run_hook(
+    open_ai: &OpenAI,
+    hook: Hook,
+    body: &impl Serialize,
+) -> Result<(), Error> {
+    let command = match hook {
+        Hook::PreRequest => &open_ai.pre_request,
+        Hook::PostResponse => &open_ai.post_response,
+    };
+    let Some(command) = command else {
+        return Ok(());
+    };
+    let oops = |why: String| {
+        Error::default().wrap(Oops::RequestHookError).because(why)
+    };
+    let body = serde_json::to_string(body)
+        .map_err(|e| oops(format!("Could not serialize {hook} input: {e}")))?;
+    let mut child = files::command("sh")
+        .args(["-c", command])
+        .env("YAP_HOOK", hook.to_string())
+        .env("YAP_COMMAND", open_ai.command)
+        .env("YAP_MODEL", open_ai.model.to_string())
+        .stdin(Stdio::piped())
+        .stdout(std::io::stderr())
+        .spawn()
+        .map_err(|e| oops(format!("Could not run {hook} {command:?}: {e}")))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // The hook may not read its input.
+        if let Err(e) = stdin.write_all(body.as_bytes()) {
+            if e.kind() != std::io::ErrorKind::BrokenPipe {
+                return Err(oops(format!("Could not write to {hook}: {e}")));
+            }
+        }
+    }
+    let status = child
+        .wait()
+        .map_err(|e| oops(format!("Could not run {hook} {command:?}: {e}")))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(oops(format!("{hook} {command:?} failed with {status}")))
+    }
+}
+
+/// Token IDs (as strings, which is how OpenAI expects them) mapped to a
+/// bias between `-100` and `100`. Sorted, so that payloads are serialized
+/// consistently; see [crate::cache].
+pub type LogitBias = BTreeMap<String, f32>;
+
+fn load_logit_bias(path: &Path) -> Result<LogitBias, Error> {
+    let text = fs::read_to_string(path).map_err(|e| {
+        Error::default()
+            .wrap(Oops::XdgConfigError)
+            .because(format!("Could not read logit_bias_file {path:?}: {e}"))
+    })?;
+    parse_logit_bias(&text).map_err(|why| {
+        Error::default()
+            .wrap(Oops::XdgConfigError)
+            .because(format!("logit_bias_file {path:?} is invalid: {why}"))
+    })
+}
+
+fn parse_logit_bias(text: &str) -> Result<LogitBias, String> {
+    let bias: LogitBias =
+        serde_json::from_str(text).map_err(|e| e.to_string())?;
+    for (token, value) in &bias {
+        if token.parse::<u32>().is_err() {
+            return Err(format!("{token:?} is not a token ID"));
+        }
+        if !(-100.0..=100.0).contains(value) {
+            return Err(format!(
+                "the bias for {token} must be between -100 and 100"
+            ));
+        }
+    }
+    Ok(bias)
+}
+
+/// A multipart body which uploads `jsonl` to the files API for `purpose`;
+/// i.e, `batch` or `fine-tune`. Returns the `Content-Type` and the body.
+fn jsonl_upload(purpose: &str, jsonl: &str) -> (String, String) {
+    // This multipart body is simple enough to build by hand.
+    let boundary = format!("yap-{}", uuid::Uuid::new_v4());
+    let body = format!(
+        "--{boundary}\r\n\
+        Content-Disposition: form-data; name=\"purpose\"\r\n\r\n\
+        {purpose}\r\n\
+        --{boundary}\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"{purpose}.jsonl\"\r\n\
+        Content-Type: application/jsonl\r\n\r\n\
+        {jsonl}\r\n\
+        --{boundary}--\r\n"
+    );
+    (format!("multipart/form-data; boundary={boundary}"), body)
+}
+
+/// Pretty-print the request which would be sent to `url` for `yap
+/// --dry-run`, and return an [Oops::DryRun] error to stop the command before
+/// it sends anything, or changes any state.
+fn preview(url: &str, payload: &impl Serialize) -> Error {
+    let body = match serde_json::to_string_pretty(payload) {
+        Ok(body) => body,
+        Err(e) => {
+            return Error::default()
+                .wrap(Oops::DryRun)
+                .because(format!("Could not serialize payload: {e}"))
+        }
+    };
+    // Concurrent requests (i.e, from `yap annotate`) are printed whole.
+    let mut stdout = std::io::stdout().lock();
+    let _ = writeln!(stdout, "POST {url}\n{body}");
+    Error::default().wrap(Oops::DryRun)
+}
+
+/// Find the API key. `$OPENAI_API_KEY` is used if it is set. Otherwise,
+/// `config.json` may name a file containing the key (`api_key_file`), a
+/// shell command which prints the key (`api_key_command`, e.g. `pass show
+/// openai`), or an OS keychain service holding the key (`api_key_keychain`),
+/// which are tried in that order.
+fn resolve_api_key() -> Result<String, Error> {
+    if let Ok(key) = env::var("OPENAI_API_KEY") {
+        return Ok(key);
+    }
+    let settings = Settings::load()?;
+    if let Some(path) = &settings.api_key_file {
+        let key = fs::read_to_string(path).map_err(|e| {
+            Error::default()
+                .wrap(Oops::OpenAIKeyMissing)
+                .because(format!("Could not read api_key_file {path:?}: {e}"))
+        })?;
+        return non_empty(key, "api_key_file");
+    }
+    if let Some(command) = &settings.api_key_command {
+        return non_empty(run(&["sh", "-c", command])?, "api_key_command");
+    }
+    if let Some(service) = &settings.api_key_keychain {
+        let key = if cfg!(target_os = "macos") {
+            run(&["security", "find-generic-password", "-s", service, "-w"])
+        } else {
+            run(&["secret-tool", "lookup", "service", service])
+        }?;
+        return non_empty(key, "api_key_keychain");
+    }
+    Err(Error::default().wrap(Oops::OpenAIKeyMissing))
+}
+
+/// Run `args`, and return its `STDOUT`.
+fn run(args: &[&str]) -> Result<String, Error> {
+    let output =
+        Command::new(args[0])
+            .args(&args[1..])
+            .output()
+            .map_err(|e| {
+                Error::default()
+                    .wrap(Oops::OpenAIKeyMissing)
+                    .because(format!("Could not run {:?}: {e}", args[0]))
+            })?;
+    if !output.status.success() {
+        return Err(Error::default().wrap(Oops::OpenAIKeyMissing).because(
+            format!(
+                "{:?} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ));
+    }
+    String::from_utf8(output.stdout).map_err(|e| {
+        Error::default()
+            .wrap(Oops::OpenAIKeyMissing)
+            .because(format!("{:?} printed invalid utf-8: {e}", args[0]))
+    })
+}
+
+fn non_empty(key: String, source: &str) -> Result<String, Error> {
+    let key = key.trim();
+    if key.is_empty() {
+        Err(Error::default()
+            .wrap(Oops::OpenAIKeyMissing)
+            .because(format!("The API key from {source} is empty")))
+    } else {
+        Ok(key.to_string())
+    }
+}
+
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    /// Replaces `system` for reasoning models.
+    Developer,
+    #[default]
+    User,
+    Assistant,
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::User => write!(f, "user"),
+            Role::System => write!(f, "system"),
+            Role::Developer => write!(f, "developer"),
+            Role::Assistant => write!(f, "llm"),
+        }
+    }
+}
+
+pub use chat_api::{
+    chat, chat_async, chat_stream, estimate_tokens, CompletionPayload,
+    CompletionResponse, Content, Message, Model, PayloadOpts, ReasoningEffort,
+    ResponseFormat, Usage,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_logit_bias() {
+        let bias = parse_logit_bias(r#"{"50256": -100, "1234": 5.5}"#).unwrap();
+        assert_eq!(bias["50256"], -100.0);
+        assert_eq!(bias["1234"], 5.5);
+        assert!(parse_logit_bias(r#"{"hello": 1}"#).is_err());
+        assert!(parse_logit_bias(r#"{"1": 101}"#).is_err());
+        assert!(parse_logit_bias("[]").is_err());
+    }
+}
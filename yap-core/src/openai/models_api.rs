@@ -0,0 +1,33 @@
+//! <https://platform.openai.com/docs/api-reference/models>
+
+use super::{Body, OpenAI};
+use crate::err::{Error, Oops};
+use serde::Deserialize;
+
+const MODELS_URL: &str = "https://api.openai.com/v1/models";
+
+#[derive(Debug, Deserialize)]
+struct ModelList {
+    data: Vec<ModelInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ModelInfo {
+    pub id: String,
+    /// Seconds since the Unix epoch.
+    pub created: u64,
+    pub owned_by: String,
+}
+
+/// Every model which the API key can use.
+pub fn list(open_ai: &OpenAI) -> Result<Vec<ModelInfo>, Error> {
+    let response = open_ai
+        .send_text(open_ai.request("GET", MODELS_URL), Body::Empty)
+        .map_err(|e| e.wrap(Oops::ModelsError))?;
+    let list: ModelList = serde_json::from_str(&response).map_err(|e| {
+        Error::default()
+            .wrap(Oops::ModelsError)
+            .because(format!("Could not deserialize the model list: {e}"))
+    })?;
+    Ok(list.data)
+}
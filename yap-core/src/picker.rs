@@ -0,0 +1,277 @@
+//! A minimal fuzzy-search list for the terminal, which powers
+//! `yap chatlog --pick`.
+//!
+//! Type to filter the list, move with the arrow keys (or Ctrl-N / Ctrl-P),
+//! press Enter to choose an item, or Esc / Ctrl-C to cancel. The list is
+//! drawn on `STDERR`, so that `STDOUT` can still be piped elsewhere.
+
+use crate::{
+    err::{Error, Oops},
+    term,
+};
+use std::io::{self, IsTerminal, Write};
+
+/// The most items shown at once.
+const MAX_ROWS: usize = 15;
+
+/// Whether every character of `query` appears in `candidate`, in order,
+/// ignoring case.
+pub fn fuzzy_match(query: &str, candidate: &str) -> bool {
+    let mut candidate = candidate.chars().flat_map(char::to_lowercase);
+    query
+        .chars()
+        .flat_map(char::to_lowercase)
+        .all(|q| candidate.any(|c| c == q))
+}
+
+enum Key {
+    Char(u8),
+    Backspace,
+    Up,
+    Down,
+    Enter,
+    Cancel,
+}
+
+/// Let the user choose one of `items`. Returns the index of the chosen item,
+/// or `None` if the user cancelled.
+pub fn pick(items: &[String]) -> Result<Option<usize>, Error> {
+    if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+        return Err(Error::default()
+            .wrap(Oops::PickerError)
+            .because("The picker needs a terminal".into()));
+    }
+    let _raw = raw::RawMode::enable()?;
+    let mut query: Vec<u8> = Vec::new();
+    let mut selected = 0;
+    let mut offset = 0;
+    loop {
+        let text = String::from_utf8_lossy(&query).to_string();
+        let matches: Vec<usize> = (0..items.len())
+            .filter(|i| fuzzy_match(&text, &items[*i]))
+            .collect();
+        selected = selected.min(matches.len().saturating_sub(1));
+        let rows = MAX_ROWS
+            .min(usize::from(term::lines()).saturating_sub(2))
+            .max(1);
+        if selected < offset {
+            offset = selected;
+        } else if selected >= offset + rows {
+            offset = selected + 1 - rows;
+        }
+        draw(items, &matches, &text, selected, offset, rows)?;
+        match raw::read_key()? {
+            Key::Char(c) => {
+                query.push(c);
+                selected = 0;
+                offset = 0;
+            }
+            Key::Backspace => {
+                // Remove a whole (possibly multi-byte) character.
+                while let Some(c) = query.pop() {
+                    if c & 0b1100_0000 != 0b1000_0000 {
+                        break;
+                    }
+                }
+            }
+            Key::Up => selected = selected.saturating_sub(1),
+            Key::Down => {
+                if selected + 1 < matches.len() {
+                    selected += 1
+                }
+            }
+            Key::Enter if !matches.is_empty() => {
+                clear()?;
+                return Ok(Some(matches[selected]));
+            }
+            Key::Enter => {}
+            Key::Cancel => {
+                clear()?;
+                return Ok(None);
+            }
+        }
+    }
+}
+
+/// Draw the query line, followed by the visible matches, and then put the
+/// cursor back at the end of the query line.
+fn draw(
+    items: &[String],
+    matches: &[usize],
+    query: &str,
+    selected: usize,
+    offset: usize,
+    rows: usize,
+) -> Result<(), Error> {
+    let width = usize::from(term::cols()).saturating_sub(2);
+    let mut frame = format!("\r\x1b[J> {query}");
+    let visible = matches.iter().enumerate().skip(offset).take(rows);
+    let mut drawn = 0;
+    for (i, item) in visible {
+        let label: String = items[*item].chars().take(width).collect();
+        if i == selected {
+            frame.push_str(&format!("\r\n\x1b[7m  {label}\x1b[0m"));
+        } else {
+            frame.push_str(&format!("\r\n  {label}"));
+        }
+        drawn += 1;
+    }
+    if matches.is_empty() {
+        frame.push_str("\r\n  (no matches)");
+        drawn += 1;
+    }
+    frame.push_str(&format!(
+        "\x1b[{drawn}A\r\x1b[{}C",
+        query.chars().count() + 2
+    ));
+    write(&frame)
+}
+
+fn clear() -> Result<(), Error> {
+    write("\r\x1b[J")
+}
+
+fn write(text: &str) -> Result<(), Error> {
+    let mut stderr = io::stderr();
+    stderr
+        .write_all(text.as_bytes())
+        .and_then(|_| stderr.flush())
+        .map_err(|e| {
+            Error::default()
+                .wrap(Oops::PickerError)
+                .because(format!("Could not draw the picker: {e}"))
+        })
+}
+
+#[cfg(unix)]
+mod raw {
+    use super::Key;
+    use crate::err::{Error, Oops};
+    use std::mem::MaybeUninit;
+
+    /// Puts the terminal into raw mode, and restores the original mode when
+    /// dropped.
+    pub struct RawMode(libc::termios);
+
+    impl RawMode {
+        pub fn enable() -> Result<Self, Error> {
+            let mut original = MaybeUninit::<libc::termios>::uninit();
+            // SAFETY: `tcgetattr` initializes `original` when it succeeds.
+            let original = unsafe {
+                if libc::tcgetattr(libc::STDIN_FILENO, original.as_mut_ptr())
+                    != 0
+                {
+                    return Err(os_error("Could not read terminal settings"));
+                }
+                original.assume_init()
+            };
+            let mut raw = original;
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+            raw.c_iflag &= !(libc::ICRNL | libc::IXON);
+            // Reads give up after 100ms, so that a lone Esc can be told
+            // apart from the start of an arrow key.
+            raw.c_cc[libc::VMIN] = 0;
+            raw.c_cc[libc::VTIME] = 1;
+            // SAFETY: `raw` is a valid `termios`, copied from `original`.
+            if unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw)
+            } != 0
+            {
+                return Err(os_error("Could not enter raw mode"));
+            }
+            Ok(Self(original))
+        }
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            // SAFETY: `self.0` came from `tcgetattr`.
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.0);
+            }
+        }
+    }
+
+    fn os_error(what: &str) -> Error {
+        Error::default()
+            .wrap(Oops::PickerError)
+            .because(format!("{what}: {}", std::io::Error::last_os_error()))
+    }
+
+    /// Read one byte, or `None` if no input arrived in time.
+    fn read_byte() -> Result<Option<u8>, Error> {
+        let mut byte = 0u8;
+        // SAFETY: we read at most one byte into `byte`.
+        let n = unsafe {
+            libc::read(
+                libc::STDIN_FILENO,
+                &mut byte as *mut u8 as *mut libc::c_void,
+                1,
+            )
+        };
+        match n {
+            1 => Ok(Some(byte)),
+            0 => Ok(None),
+            _ => Err(os_error("Could not read from the terminal")),
+        }
+    }
+
+    /// Wait for the next keypress.
+    pub fn read_key() -> Result<Key, Error> {
+        loop {
+            let Some(byte) = read_byte()? else {
+                continue;
+            };
+            return Ok(match byte {
+                b'\r' | b'\n' => Key::Enter,
+                0x7f | 0x08 => Key::Backspace,
+                0x0e => Key::Down,
+                0x10 => Key::Up,
+                0x03 | 0x04 | 0x07 => Key::Cancel,
+                0x1b => match (read_byte()?, read_byte()?) {
+                    (Some(b'[' | b'O'), Some(b'A')) => Key::Up,
+                    (Some(b'[' | b'O'), Some(b'B')) => Key::Down,
+                    (None, _) => Key::Cancel,
+                    // Ignore other escape sequences.
+                    _ => continue,
+                },
+                c if c < 0x20 => continue,
+                c => Key::Char(c),
+            });
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod raw {
+    use super::Key;
+    use crate::err::{Error, Oops};
+
+    pub struct RawMode;
+
+    impl RawMode {
+        pub fn enable() -> Result<Self, Error> {
+            Err(Error::default()
+                .wrap(Oops::PickerError)
+                .because("The picker is not supported on this platform".into()))
+        }
+    }
+
+    pub fn read_key() -> Result<Key, Error> {
+        Ok(Key::Cancel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuzzy_match() {
+        assert!(fuzzy_match("", "anything"));
+        assert!(fuzzy_match("rst", "Rust traits"));
+        assert!(fuzzy_match("RT", "rust traits"));
+        assert!(!fuzzy_match("tsr", "rust"));
+        assert!(!fuzzy_match("rusty", "rust"));
+    }
+}
@@ -0,0 +1,166 @@
+//! Pin messages or files to a chat with `yap pin`, so that they are always
+//! sent; i.e, project conventions, or an API spec.
+//!
+//! When a chat outgrows the model's context window (see [crate::context]),
+//! pinned messages are sent in full instead of being summarized or dropped.
+//! Pinned files are read again for every message, like `yap ctx` files, but
+//! belong to one chat rather than to the project.
+//!
+//! `yap pin` with no arguments lists the messages in the active chat, so
+//! that they can be pinned by number; pinned messages are marked with `*`.
+
+use crate::{
+    db,
+    err::{Error, Oops},
+    files,
+    openai::{Message, Role},
+};
+use std::{fs, path::PathBuf};
+use uuid::Uuid;
+
+/// How much of each message `yap pin` shows.
+const PREVIEW_CHARS: usize = 60;
+
+fn oops(why: String) -> Error {
+    Error::default().wrap(Oops::PinError).because(why)
+}
+
+fn active_chat() -> Result<Uuid, Error> {
+    db::get_active_chat()?
+        .ok_or_else(|| oops("No chat is active; run `yap chat` first".into()))
+}
+
+/// Entrypoint for `yap pin`. Pins (or, if `remove` is set, unpins) the
+/// `messages` of the active chat, counting from 1, and `paths`. `last`
+/// refers to the last message in the chat. Without any messages or paths,
+/// the chat's messages and pinned files are listed instead.
+pub fn pin(
+    messages: &[usize],
+    last: bool,
+    paths: &[PathBuf],
+    remove: bool,
+) -> Result<(), Error> {
+    let id = active_chat()?;
+    let mut chat = db::get_chat(&id)?;
+    if messages.is_empty() && !last && paths.is_empty() {
+        print!("{}", list(&chat));
+        return Ok(());
+    }
+
+    let mut indices = Vec::new();
+    for &n in messages {
+        if n == 0 || n > chat.messages.len() {
+            return Err(oops(format!(
+                "There is no message {n}; the chat has {} messages",
+                chat.messages.len()
+            )));
+        }
+        indices.push(n - 1);
+    }
+    if last {
+        indices.push(
+            chat.messages
+                .len()
+                .checked_sub(1)
+                .ok_or_else(|| oops("The chat is empty".into()))?,
+        );
+    }
+    for i in indices {
+        chat.messages[i].pinned = !remove;
+    }
+
+    for path in paths {
+        if remove {
+            let absolute = path.canonicalize().unwrap_or(path.clone());
+            chat.pinned_files.retain(|p| *p != absolute && p != path);
+        } else {
+            let absolute = path
+                .canonicalize()
+                .map_err(|e| oops(format!("Could not find {path:?}: {e}")))?;
+            if !chat.pinned_files.contains(&absolute) {
+                chat.pinned_files.push(absolute);
+            }
+        }
+    }
+    db::save_chat(&id, &chat)
+}
+
+/// Each message, numbered from 1, with pinned messages marked by `*`,
+/// followed by the pinned files.
+fn list(chat: &db::Chat) -> String {
+    let mut out = String::new();
+    for (i, message) in chat.messages.iter().enumerate() {
+        let content = message.content.as_deref().unwrap_or_default();
+        let mut preview: String = content
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .chars()
+            .take(PREVIEW_CHARS + 1)
+            .collect();
+        if preview.chars().count() > PREVIEW_CHARS {
+            preview = preview.chars().take(PREVIEW_CHARS - 3).collect();
+            preview.push_str("...");
+        }
+        let mark = if message.pinned { '*' } else { ' ' };
+        out.push_str(&format!(
+            "{mark}{:>3} [{}] {preview}\n",
+            i + 1,
+            message.role
+        ));
+    }
+    for path in &chat.pinned_files {
+        out.push_str(&format!("*    {}\n", path.display()));
+    }
+    out
+}
+
+/// One user message for each of the chat's pinned files. Files which no
+/// longer exist are skipped with a warning.
+pub fn file_messages(chat: &db::Chat) -> Result<Vec<Message>, Error> {
+    let mut paths = Vec::new();
+    for path in &chat.pinned_files {
+        if path.exists() {
+            paths.push(path.clone());
+        } else {
+            log::warn!("Pinned file {path:?} no longer exists");
+        }
+    }
+    let cwd = files::cwd().unwrap_or_default();
+    let mut messages = Vec::new();
+    for path in files::expand(&paths)? {
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| oops(format!("Could not read {path:?}: {e}")))?;
+        let name = path.strip_prefix(&cwd).unwrap_or(&path);
+        messages.push(Message::new(
+            Role::User,
+            format!("File: {}\n```\n{contents}\n```", name.display()),
+        ));
+    }
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list() {
+        let mut pinned = Message::new(Role::User, "Use tabs.\nAlways.".into());
+        pinned.pinned = true;
+        let chat = db::Chat {
+            messages: vec![
+                pinned,
+                Message::new(Role::Assistant, "x".repeat(100)),
+            ],
+            pinned_files: vec![PathBuf::from("/spec.yaml")],
+            ..Default::default()
+        };
+        let list = list(&chat);
+        let lines: Vec<&str> = list.lines().collect();
+        assert_eq!(lines[0], "*  1 [user] Use tabs. Always.");
+        assert!(lines[1].starts_with("   2 [llm] xxx"));
+        assert!(lines[1].ends_with("x..."));
+        assert_eq!(lines[2], "*    /spec.yaml");
+    }
+}
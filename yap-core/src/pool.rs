@@ -0,0 +1,78 @@
+//! Run many requests to OpenAI at once.
+//!
+//! `yap` talks to OpenAI with an async HTTP client, on one runtime shared by
+//! the whole process; each request spends nearly all of its time waiting on
+//! the network, so many requests can be in flight at once without a thread
+//! for each. Batch operations build a future per item and run them with
+//! [map]. Everything else is synchronous, and enters the runtime with
+//! [block_on].
+//!
+//! The number of requests in flight is capped by `max_concurrency` in
+//! `config.json` (see [crate::config::Settings]), which keeps us clear of
+//! OpenAI's rate limits. [map] caps the futures it runs at once, and the
+//! client caps the requests it sends, so that nested batches (i.e, the
+//! chunks of each file in `yap annotate --dir`) don't multiply the limit.
+
+use futures::{stream, StreamExt};
+use std::{future::Future, sync::OnceLock};
+use tokio::runtime::{self, Runtime};
+
+/// The runtime which every request runs on.
+pub fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        runtime::Builder::new_multi_thread()
+            .enable_all()
+            .thread_name("yap-http")
+            .build()
+            .expect("can start the async runtime")
+    })
+}
+
+/// Run `future` to completion, blocking the current thread. This must not be
+/// called from within a future; await the future instead.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    runtime().block_on(future)
+}
+
+/// Call `f` on every item, running up to `limit` of the futures it returns
+/// at once. Results are returned in the same order as `items`.
+pub async fn map<'a, T, R, F, Fut>(items: &'a [T], limit: usize, f: F) -> Vec<R>
+where
+    F: FnMut(&'a T) -> Fut,
+    Fut: Future<Output = R>,
+{
+    stream::iter(items)
+        .map(f)
+        .buffered(limit.max(1))
+        .collect()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    #[test]
+    fn test_map_preserves_order_and_limit() {
+        let in_flight = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let items: Vec<u64> = (0..20).collect();
+        let results = block_on(map(&items, 3, |i| {
+            let (in_flight, peak) = (&in_flight, &peak);
+            async move {
+                let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(20 - i)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                i * 2
+            }
+        }));
+        assert_eq!(results, items.iter().map(|i| i * 2).collect::<Vec<_>>());
+        assert_eq!(peak.load(Ordering::SeqCst), 3);
+    }
+}
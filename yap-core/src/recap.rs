@@ -0,0 +1,208 @@
+//! Print your entire conversation so far.
+//!
+//! When `STDOUT` is a terminal and the recap is taller than the terminal, the
+//! recap is piped into `$PAGER` (or `less`, if `$PAGER` is unset).
+//!
+//! `yap recap --stats` prints a table of the messages instead, with the
+//! model, token counts, and cost of each reply, and the total cost of the
+//! conversation. Costs are estimated from [crate::openai::Model::price].
+//! Replies from before token counts were saved in the chat are not counted.
+
+use crate::{
+    date, db,
+    err::{Error, Oops},
+    openai::{Message, Model, Role},
+    term, usage,
+};
+
+/// How `yap recap` should render the conversation.
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum Format {
+    /// Messages prefixed with their role, separated by `===`.
+    #[default]
+    Plain,
+    /// Each message beneath a markdown heading naming its role.
+    Markdown,
+    /// The raw message array.
+    Json,
+}
+
+/// Load and print the recap, or the [stats] table if `stats` is set.
+pub fn recap(format: Format, stats: bool) -> Result<(), Error> {
+    let active_chat_id = db::get_active_chat()?.map_or_else(
+        || Err(Error::default().wrap(Oops::RecapError).because(
+            "Cannot recap; no chat is active! Hint: run `yap chat [prompt]` to get a new conversation started".to_string()
+        )), Ok)?;
+    let conversation_content = db::get_chat(&active_chat_id)?.messages;
+    if conversation_content.is_empty() {
+        println!("Chat is empty!");
+        Ok(())
+    } else {
+        let convo = match format {
+            _ if stats => render_stats(
+                &conversation_content,
+                term::output_width().unwrap_or(usize::MAX),
+            ),
+            Format::Plain => render_plain(&conversation_content),
+            Format::Markdown => render_markdown(&conversation_content),
+            Format::Json => serde_json::to_string_pretty(&conversation_content)
+                .map_err(|e| {
+                    Error::default()
+                        .wrap(Oops::RecapError)
+                        .because(format!("Could not serialize chat: {e}"))
+                })?,
+        };
+        term::page(&convo)
+    }
+}
+
+fn render_plain(messages: &[Message]) -> String {
+    messages
+        .iter()
+        .fold(Vec::new(), |mut acc, msg| {
+            if let Some(c) = &msg.content {
+                let mut prefixed_str =
+                    format!("[{}{}]: {}", msg.role, truncated(msg), c);
+                if prefixed_str.ends_with('\n') {
+                    prefixed_str.push('\n');
+                }
+                acc.push(prefixed_str)
+            }
+            acc
+        })
+        .join("\n===\n")
+}
+
+fn render_markdown(messages: &[Message]) -> String {
+    messages
+        .iter()
+        .fold(Vec::new(), |mut acc, msg| {
+            if let Some(c) = &msg.content {
+                acc.push(format!(
+                    "## {}{}\n\n{}",
+                    msg.role,
+                    truncated(msg),
+                    c.trim_end()
+                ))
+            }
+            acc
+        })
+        .join("\n\n")
+}
+
+/// One row per message, followed by the totals for the conversation.
+fn render_stats(messages: &[Message], width: usize) -> String {
+    let (mut prompt, mut completion, mut cost) = (0, 0, 0.);
+    let mut uncounted = 0;
+    let rows = messages
+        .iter()
+        .enumerate()
+        .map(|(i, msg)| {
+            let dash = || "-".to_string();
+            let (model, usage) = match (&msg.model, msg.usage) {
+                (Some(model), Some(usage)) => {
+                    prompt += usage.prompt_tokens;
+                    completion += usage.completion_tokens;
+                    cost += model.cost(&usage);
+                    (model.to_string(), Some((model, usage)))
+                }
+                _ => {
+                    if matches!(msg.role, Role::Assistant) {
+                        uncounted += 1;
+                    }
+                    (
+                        msg.model.as_ref().map_or_else(dash, Model::to_string),
+                        None,
+                    )
+                }
+            };
+            vec![
+                (i + 1).to_string(),
+                msg.role.to_string(),
+                msg.created.map_or_else(dash, date::datetime),
+                model,
+                format!("~{}", msg.estimate_tokens()),
+                usage.map_or_else(dash, |(_, u)| u.prompt_tokens.to_string()),
+                usage.map_or_else(dash, |(_, u)| {
+                    u.completion_tokens.to_string()
+                }),
+                usage.map_or_else(dash, |(m, u)| format!("${:.4}", m.cost(&u))),
+            ]
+        })
+        .collect();
+    let mut stats = usage::table(
+        &[
+            "#",
+            "Role",
+            "Time",
+            "Model",
+            "Size",
+            "Prompt",
+            "Completion",
+            "Cost",
+        ],
+        rows,
+        width,
+    );
+    stats.push_str(&format!(
+        "\n{prompt} prompt and {completion} completion tokens, ${cost:.4}\n"
+    ));
+    if uncounted > 0 {
+        stats.push_str(&format!(
+            "{uncounted} replies without token counts are not included\n"
+        ));
+    }
+    stats
+}
+
+fn truncated(msg: &Message) -> &'static str {
+    if msg.truncated {
+        " (truncated)"
+    } else {
+        ""
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::openai::{Model, Usage};
+
+    #[test]
+    fn test_render_markdown() {
+        let mut messages = vec![
+            Message::new(Role::User, "hi\n".into()),
+            Message::new(Role::Assistant, "hello!".into()),
+        ];
+        assert_eq!(
+            render_markdown(&messages),
+            "## user\n\nhi\n\n## llm\n\nhello!"
+        );
+        messages[1].truncated = true;
+        assert!(render_markdown(&messages).contains("## llm (truncated)"));
+    }
+
+    #[test]
+    fn test_render_stats() {
+        let mut reply = Message::new(Role::Assistant, "hello!".into());
+        reply.model = Some(Model::Gpt4o);
+        reply.usage = Some(Usage {
+            prompt_tokens: 1000,
+            completion_tokens: 100,
+        });
+        let messages = vec![
+            Message::new(Role::User, "hi".into()),
+            reply,
+            Message::new(Role::Assistant, "old reply".into()),
+        ];
+        let stats = render_stats(&messages, 120);
+        let lines: Vec<&str> = stats.lines().collect();
+        assert!(lines[3].contains("gpt-4o"));
+        assert!(lines[3].ends_with("$0.0035"));
+        assert!(
+            stats.contains("1000 prompt and 100 completion tokens, $0.0035")
+        );
+        assert!(stats.contains("1 replies without token counts"));
+    }
+}
@@ -0,0 +1,317 @@
+//! Ask an LLM for coordinated changes across several files.
+//!
+//! The LLM responds with a list of search/replace edits. Every edit is
+//! validated before anything is written; each `search` string must appear
+//! exactly once in its file. The full change set is previewed, and applied
+//! only after confirmation.
+
+use crate::{
+    backup,
+    config::ConfigFile,
+    confirm::{self, Operation},
+    constants,
+    err::{Error, Oops},
+    filter,
+    openai::{
+        chat, CompletionPayload, Content, Message, OpenAI, PayloadOpts,
+        ResponseFormat, Role,
+    },
+};
+use log::debug;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+fn get_json_schema() -> Value {
+    json!({
+      "name": "refactor_edits",
+      "schema": {
+        "type": "object",
+        "properties": {
+          "explanation": {
+            "type": "string",
+            "description": "A brief summary of the change set."
+          },
+          "edits": {
+            "type": "array",
+            "description": "Search/replace edits, applied in order.",
+            "items": {
+              "type": "object",
+              "properties": {
+                "file": {
+                  "type": "string",
+                  "description": "The path of the file to edit, exactly as provided."
+                },
+                "search": {
+                  "type": "string",
+                  "description": "Text which appears exactly once in the file. Empty to create a new file."
+                },
+                "replace": {
+                  "type": "string",
+                  "description": "Text to put in place of `search`."
+                }
+              },
+              "required": ["file", "search", "replace"],
+              "additionalProperties": false
+            }
+          }
+        },
+        "required": ["explanation", "edits"],
+        "additionalProperties": false
+      },
+      "strict": true
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct RefactorResponse {
+    explanation: String,
+    edits: Vec<Edit>,
+}
+
+/// Replace `search` with `replace` in `file`; see [plan].
+#[derive(Debug, Deserialize)]
+pub struct Edit {
+    pub file: PathBuf,
+    pub search: String,
+    pub replace: String,
+}
+
+/// Entrypoint for `yap refactor`. Changes are applied after the user
+/// confirms them, unless the [confirm::Policy] allows them, or `yes` is set.
+pub fn refactor(
+    open_ai: &OpenAI,
+    prompt: &str,
+    files: &[PathBuf],
+    yes: bool,
+) -> Result<(), Error> {
+    let mut originals = BTreeMap::new();
+    for file in files {
+        let contents = if file.exists() {
+            Some(fs::read_to_string(file).map_err(|e| {
+                Error::default()
+                    .wrap(Oops::RefactorError)
+                    .because(format!("Could not read {file:?}: {e}"))
+            })?)
+        } else {
+            None
+        };
+        originals.insert(file.clone(), contents);
+    }
+
+    let response = request_edits(open_ai, prompt, &originals)?;
+    let changes = plan(&originals, &response.edits)?;
+    if changes.is_empty() {
+        println!("{}\n\nNo changes were proposed.", response.explanation);
+        return Ok(());
+    }
+
+    println!("{}\n", response.explanation);
+    for edit in &response.edits {
+        print!("{}", preview(edit));
+    }
+
+    let confirmed = confirm::confirm(
+        Operation::FileWrite,
+        "Apply these changes? [y/N] ",
+        yes,
+    )
+    .map_err(|e| e.wrap(Oops::RefactorError))?;
+    if !confirmed {
+        eprintln!("No files were changed.");
+        return Ok(());
+    }
+
+    let changes = changes
+        .into_iter()
+        .map(|(file, contents)| {
+            Ok((file, filter::apply("refactor", &contents)?))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    for (file, contents) in changes {
+        backup::save("refactor", &file)
+            .map_err(|e| e.wrap(Oops::RefactorError))?;
+        // New files may be in directories which don't exist yet.
+        file.parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&file, contents))
+            .map_err(|e| {
+                Error::default()
+                    .wrap(Oops::RefactorError)
+                    .because(format!("Could not write {file:?}: {e}"))
+            })?;
+        println!("Updated {}", file.display());
+    }
+    Ok(())
+}
+
+fn request_edits(
+    open_ai: &OpenAI,
+    prompt: &str,
+    originals: &BTreeMap<PathBuf, Option<String>>,
+) -> Result<RefactorResponse, Error> {
+    let system_prompt = ConfigFile::RefactorSystemPrompt
+        .load()
+        .map_err(|e| {
+            e.wrap(Oops::RefactorError)
+                .because("Could not load the refactor system prompt".into())
+        })?
+        .unwrap_or(constants::DEFAULT_REFACTOR_PROMPT.into());
+    let mut messages = vec![Message::new(Role::System, system_prompt)];
+    for (file, contents) in originals {
+        let body = match contents {
+            Some(c) => format!("File: {}\n```\n{c}\n```", file.display()),
+            None => format!("File: {} (does not exist yet)", file.display()),
+        };
+        messages.push(Message::new(Role::User, body));
+    }
+    messages.push(Message::new(Role::User, prompt.into()));
+
+    let payload = CompletionPayload::new(
+        open_ai,
+        messages,
+        PayloadOpts {
+            response_format: ResponseFormat::JsonSchema {
+                json_schema: get_json_schema(),
+            },
+            ..Default::default()
+        },
+    );
+    let response = chat(open_ai, &payload).map_err(|e| {
+        e.wrap(Oops::RefactorError)
+            .because("Error while requesting edits from OpenAI".into())
+    })?;
+    let content = match response.choices[0].message.parse()? {
+        Content::Normal(c) => c,
+        Content::Refusal(r) => {
+            return Err(Error::default()
+                .wrap(Oops::OpenAIRefusal)
+                .wrap(Oops::RefactorError)
+                .because(format!("OpenAI refused to propose edits: {r}")))
+        }
+    };
+    serde_json::from_str(content).map_err(|e| {
+        debug!("Bad response content: {content}");
+        Error::default()
+            .wrap(Oops::RefactorError)
+            .because(format!("Could not deserialize edits: {e}"))
+    })
+}
+
+/// Apply `edits` in memory, returning the new contents of each changed file.
+/// Fails without changing anything if any edit is invalid.
+pub fn plan(
+    originals: &BTreeMap<PathBuf, Option<String>>,
+    edits: &[Edit],
+) -> Result<BTreeMap<PathBuf, String>, Error> {
+    let mut changes: BTreeMap<PathBuf, String> = BTreeMap::new();
+    let mut problems = Vec::new();
+    for (i, edit) in edits.iter().enumerate() {
+        let Some(original) = originals.get(&edit.file) else {
+            problems.push(format!(
+                "edit {} targets {:?}, which was not provided",
+                i + 1,
+                edit.file
+            ));
+            continue;
+        };
+        let current = changes
+            .get(&edit.file)
+            .cloned()
+            .or_else(|| original.clone());
+        match (current, edit.search.is_empty()) {
+            (None, true) => {
+                changes.insert(edit.file.clone(), edit.replace.clone());
+            }
+            (None, false) => problems.push(format!(
+                "edit {} searches {:?}, which does not exist",
+                i + 1,
+                edit.file
+            )),
+            (Some(_), true) => problems.push(format!(
+                "edit {} has an empty search string, but {:?} already exists",
+                i + 1,
+                edit.file
+            )),
+            (Some(current), false) => {
+                match current.matches(edit.search.as_str()).count() {
+                    1 => {
+                        changes.insert(
+                            edit.file.clone(),
+                            current.replacen(&edit.search, &edit.replace, 1),
+                        );
+                    }
+                    n => problems.push(format!(
+                        "edit {} search text appears {n} times in {:?}; expected exactly once",
+                        i + 1,
+                        edit.file
+                    )),
+                }
+            }
+        }
+    }
+    if problems.is_empty() {
+        Ok(changes)
+    } else {
+        Err(Error::default().wrap(Oops::RefactorError).because(format!(
+            "The proposed edits are invalid, so no files were changed.\n{}",
+            problems.join("\n")
+        )))
+    }
+}
+
+/// Show `edit` like a diff, for confirmation.
+pub fn preview(edit: &Edit) -> String {
+    let mut out = format!("--- {}\n", edit.file.display());
+    for line in edit.search.lines() {
+        out.push_str(&format!("-{line}\n"));
+    }
+    for line in edit.replace.lines() {
+        out.push_str(&format!("+{line}\n"));
+    }
+    out.push('\n');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn originals() -> BTreeMap<PathBuf, Option<String>> {
+        BTreeMap::from([
+            (PathBuf::from("a.rs"), Some("fn a() {}\nfn b() {}\n".into())),
+            (PathBuf::from("new.rs"), None),
+        ])
+    }
+
+    fn edit(file: &str, search: &str, replace: &str) -> Edit {
+        Edit {
+            file: file.into(),
+            search: search.into(),
+            replace: replace.into(),
+        }
+    }
+
+    #[test]
+    fn test_plan() {
+        let changes = plan(
+            &originals(),
+            &[
+                edit("a.rs", "fn a() {}", "fn alpha() {}"),
+                edit("a.rs", "fn b() {}", "fn beta() {}"),
+                edit("new.rs", "", "pub fn c() {}\n"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(changes[Path::new("a.rs")], "fn alpha() {}\nfn beta() {}\n");
+        assert_eq!(changes[Path::new("new.rs")], "pub fn c() {}\n");
+    }
+
+    #[test]
+    fn test_plan_rejects_ambiguous_and_unknown_edits() {
+        assert!(plan(&originals(), &[edit("a.rs", "fn", "func")]).is_err());
+        assert!(plan(&originals(), &[edit("b.rs", "", "x")]).is_err());
+        assert!(plan(&originals(), &[edit("a.rs", "", "x")]).is_err());
+    }
+}
@@ -0,0 +1,99 @@
+//! A compact map of the repository, listing each file along with the public
+//! functions and types declared in it (see [crate::symbols]).
+//!
+//! `yap map` prints the map, and `--repo-map` attaches it to `yap chat`,
+//! `yap complete`, and `yap ask` requests, so that the LLM knows what else
+//! is in the project without every file being sent in full. The map is cut
+//! short to fit in a budget of tokens.
+
+use crate::{
+    err::{Error, Oops},
+    files, lang,
+    openai::{Message, Role},
+    symbols,
+};
+use std::{fs, path::Path};
+
+/// The default budget, in tokens, for the map.
+pub const DEFAULT_MAX_TOKENS: usize = 2048;
+
+/// A rough estimate, which is close enough for English and code.
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Build the map of the project around the current directory; see
+/// [files::project_root].
+pub fn build(max_tokens: usize) -> Result<String, Error> {
+    let root = files::project_root();
+    let paths = files::expand(std::slice::from_ref(&root)).map_err(|e| {
+        e.wrap(Oops::RepoMapError)
+            .because("Could not list the project's files".into())
+    })?;
+    let mut sections = Vec::new();
+    for path in &paths {
+        let name = path.strip_prefix(&root).unwrap_or(path);
+        sections.push(section(name, path));
+    }
+    Ok(fit(&sections, max_tokens * CHARS_PER_TOKEN))
+}
+
+/// The file's name, followed by its symbols, indented.
+fn section(name: &Path, path: &Path) -> String {
+    let mut section = format!("{}\n", name.display());
+    let Some(language) = lang::by_path(path) else {
+        return section;
+    };
+    // Files which aren't text, like images, are still listed.
+    let Ok(source) = fs::read_to_string(path) else {
+        return section;
+    };
+    for symbol in symbols::outline(language, &source) {
+        section.push_str(&format!("  {}: {}\n", symbol.line, symbol.signature));
+    }
+    section
+}
+
+/// Join as many `sections` as fit in `max_chars`, noting how many were left
+/// out.
+fn fit(sections: &[String], max_chars: usize) -> String {
+    let mut map = String::new();
+    for (i, section) in sections.iter().enumerate() {
+        if map.len() + section.len() > max_chars {
+            map.push_str(&format!(
+                "... and {} more files\n",
+                sections.len() - i
+            ));
+            break;
+        }
+        map.push_str(section);
+    }
+    map
+}
+
+/// Entrypoint for `yap map`.
+pub fn print(max_tokens: usize) -> Result<(), Error> {
+    print!("{}", build(max_tokens)?);
+    Ok(())
+}
+
+/// The map as a message, to be sent after the system prompt.
+pub fn message() -> Result<Message, Error> {
+    Ok(Message::new(
+        Role::User,
+        format!(
+            "Here is a map of the files in this project, with the line number and signature of each public symbol:\n\n{}",
+            build(DEFAULT_MAX_TOKENS)?
+        ),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit() {
+        let sections = vec!["a.rs\n".to_string(), "b.rs\n".to_string()];
+        assert_eq!(fit(&sections, 100), "a.rs\nb.rs\n");
+        assert_eq!(fit(&sections, 7), "a.rs\n... and 1 more files\n");
+    }
+}
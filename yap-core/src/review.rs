@@ -0,0 +1,468 @@
+//! Review a change before it is committed.
+//!
+//! `yap review` sends the output of `git diff` (or `git diff --cached`, with
+//! `--diff-cached`) to the LLM, and prints its findings. Each finding has a
+//! [Severity]; if any finding is an [Severity::Error] (or, with `--fail-on`,
+//! at least as severe as the given [Severity]), `yap review` exits with code
+//! 8, so it can be used as a `pre-commit` hook (see [crate::hook]), and so
+//! that CI can tell a failed review from a review which could not run.
+//! Changes which only touch whitespace are not sent to the LLM at all. The
+//! project's `.yaprules.json` is followed, if it has one; see
+//! [crate::rules].
+//!
+//! `yap review` can also be a CI step; i.e, `yap review --ci --base
+//! origin/main --fail-on warning` reviews the changes since the branch
+//! forked from `origin/main`, and writes a JSON report (see [Gate]) for the
+//! CI system to keep as an artifact.
+//!
+//! `yap review --github owner/repo#123` reviews a pull request instead, and
+//! `--post` leaves the findings as a pending review on it; see
+//! [crate::github]. `yap review --gitlab group/project!123` does the same for
+//! GitLab merge requests, posting discussions instead; see [crate::gitlab].
+
+use crate::{
+    config::ConfigFile,
+    constants,
+    diff::{self, git_diff, FileDiff, Line},
+    err::{Error, Oops},
+    github, gitlab,
+    openai::{
+        chat, CompletionPayload, Content, Message, OpenAI, PayloadOpts,
+        ResponseFormat, Role,
+    },
+    rules,
+};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+};
+
+fn get_json_schema() -> Value {
+    json!({
+      "name": "review_findings",
+      "schema": {
+        "type": "object",
+        "properties": {
+          "findings": {
+            "type": "array",
+            "description": "Problems found in the change. Empty if the change looks good.",
+            "items": {
+              "type": "object",
+              "properties": {
+                "file": {
+                  "type": "string",
+                  "description": "The path of the changed file."
+                },
+                "line": {
+                  "type": "integer",
+                  "description": "The line number in the changed file."
+                },
+                "severity": {
+                  "type": "string",
+                  "enum": ["info", "warning", "error"],
+                  "description": "`error` for problems which must be fixed before committing."
+                },
+                "message": {
+                  "type": "string",
+                  "description": "A description of the problem."
+                }
+              },
+              "required": ["file", "line", "severity", "message"],
+              "additionalProperties": false
+            }
+          }
+        },
+        "required": ["findings"],
+        "additionalProperties": false
+      },
+      "strict": true
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct ReviewResponse {
+    findings: Vec<Finding>,
+}
+
+#[derive(
+    Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Info => write!(f, "info"),
+            Self::Warning => write!(f, "warning"),
+            Self::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Finding {
+    file: PathBuf,
+    line: usize,
+    severity: Severity,
+    message: String,
+}
+
+/// Entrypoint for `yap review`. With `cached`, the staged changes are
+/// reviewed instead of the unstaged changes. With `base`, the changes
+/// committed since the branch forked from `base` are reviewed instead; i.e,
+/// a pull request's changes, in CI.
+pub fn review(
+    open_ai: &OpenAI,
+    cached: bool,
+    base: Option<&str>,
+    gate: Gate,
+) -> Result<(), Error> {
+    let range = base.map(|base| format!("{base}...HEAD"));
+    let args: Vec<&str> = match &range {
+        Some(range) => vec![range],
+        None if cached => vec!["--cached"],
+        None => vec![],
+    };
+    let diff_text = git_diff(&args).map_err(|e| {
+        e.wrap(Oops::ReviewError)
+            .because("Could not get a diff to review".into())
+    })?;
+    if parse_diff(&diff_text)?.is_none() {
+        return gate.write_report(&[]);
+    }
+    report(&get_findings(open_ai, &diff_text)?, gate)
+}
+
+/// Entrypoint for `yap review --github`. The pull request's diff is fetched
+/// from GitHub and reviewed like a local change. With `post`, the findings
+/// are also left as a pending review on the pull request (see
+/// [github::create_review]); findings on lines outside of the diff, which
+/// GitHub can't comment on, are listed in the review's body instead.
+pub fn review_github(
+    open_ai: &OpenAI,
+    pr: &github::PullRequest,
+    post: bool,
+    gate: Gate,
+) -> Result<(), Error> {
+    let diff_text = github::diff(pr).map_err(|e| e.wrap(Oops::ReviewError))?;
+    let Some(file_diffs) = parse_diff(&diff_text)? else {
+        return gate.write_report(&[]);
+    };
+    let findings = get_findings(open_ai, &diff_text)?;
+    if post && !findings.is_empty() {
+        let (body, comments) = to_comments(&file_diffs, &findings);
+        let review = github::create_review(pr, &body, &comments)
+            .map_err(|e| e.wrap(Oops::ReviewError))?;
+        eprintln!("Started a pending review of {pr}: {}", review.html_url);
+    }
+    report(&findings, gate)
+}
+
+/// Entrypoint for `yap review --gitlab`; like [review_github], but for a
+/// GitLab merge request. With `post`, each finding on a line of the diff
+/// starts a discussion on that line, and a summary (including any other
+/// findings) starts one more; see [gitlab::create_discussions].
+pub fn review_gitlab(
+    open_ai: &OpenAI,
+    mr: &gitlab::MergeRequest,
+    post: bool,
+    gate: Gate,
+) -> Result<(), Error> {
+    let diff_text = gitlab::diff(mr).map_err(|e| e.wrap(Oops::ReviewError))?;
+    let Some(file_diffs) = parse_diff(&diff_text)? else {
+        return gate.write_report(&[]);
+    };
+    let findings = get_findings(open_ai, &diff_text)?;
+    if post && !findings.is_empty() {
+        let (body, comments) = to_comments(&file_diffs, &findings);
+        let url = gitlab::create_discussions(mr, &body, &comments)
+            .map_err(|e| e.wrap(Oops::ReviewError))?;
+        eprintln!("Started discussions on {mr}: {url}");
+    }
+    report(&findings, gate)
+}
+
+/// Parse `diff_text`. `None` if the change is too trivial to review; see
+/// [diff::is_trivial].
+fn parse_diff(diff_text: &str) -> Result<Option<Vec<FileDiff>>, Error> {
+    let file_diffs = diff::parse(diff_text).map_err(|e| {
+        e.wrap(Oops::ReviewError)
+            .because("Could not parse the diff to review".into())
+    })?;
+    if diff::is_trivial(&file_diffs) {
+        eprintln!("Skipping review; the change is empty or whitespace-only.");
+        return Ok(None);
+    }
+    Ok(Some(file_diffs))
+}
+
+/// When `yap review` fails, and where it writes a report; i.e, for running
+/// it as a CI step with `--ci`.
+#[derive(Clone, Copy, Debug)]
+pub struct Gate<'a> {
+    /// Fail if any finding is at least this severe.
+    pub fail_on: Severity,
+    /// Write a JSON report of the review to this path; see [to_report].
+    pub report: Option<&'a Path>,
+}
+
+impl Gate<'_> {
+    fn write_report(&self, findings: &[Finding]) -> Result<(), Error> {
+        let Some(path) = self.report else {
+            return Ok(());
+        };
+        let report =
+            serde_json::to_string_pretty(&to_report(findings, self.fail_on))
+                .map_err(|e| {
+                    Error::default()
+                        .wrap(Oops::ReviewError)
+                        .because(format!("Could not serialize the report: {e}"))
+                })?;
+        fs::write(path, report).map_err(|e| {
+            Error::default()
+                .wrap(Oops::ReviewError)
+                .because(format!("Could not write the report to {path:?}: {e}"))
+        })
+    }
+}
+
+/// A machine-readable summary of the review, for CI artifacts.
+fn to_report(findings: &[Finding], fail_on: Severity) -> Value {
+    let count =
+        |severity| findings.iter().filter(|f| f.severity == severity).count();
+    json!({
+        "passed": !findings.iter().any(|f| f.severity >= fail_on),
+        "fail_on": fail_on,
+        "counts": {
+            "info": count(Severity::Info),
+            "warning": count(Severity::Warning),
+            "error": count(Severity::Error),
+        },
+        "findings": findings,
+    })
+}
+
+/// Write the report, print `findings`, and fail if any of them is at least
+/// as severe as [Gate::fail_on].
+fn report(findings: &[Finding], gate: Gate) -> Result<(), Error> {
+    gate.write_report(findings)?;
+    if findings.is_empty() {
+        eprintln!("No problems found.");
+        return Ok(());
+    }
+    for f in findings {
+        println!(
+            "{}:{}: {}: {}",
+            f.file.display(),
+            f.line,
+            f.severity,
+            f.message
+        );
+    }
+    let failures = findings
+        .iter()
+        .filter(|f| f.severity >= gate.fail_on)
+        .count();
+    if failures > 0 {
+        Err(Error::default().wrap(Oops::ReviewFailed).because(format!(
+            "The review found {failures} finding(s) at or above {}",
+            gate.fail_on
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// A finding which can be left as a comment on a line of the diff.
+#[derive(Debug)]
+pub struct Comment {
+    pub path: PathBuf,
+    /// The line's number after the change.
+    pub line: usize,
+    /// The line's number before the change, unless the change added it.
+    pub old_line: Option<usize>,
+    pub body: String,
+}
+
+/// Each line in `file_diffs` which can be commented on, mapped to its
+/// number before the change (if it existed).
+fn diff_lines(
+    file_diffs: &[FileDiff],
+) -> HashMap<(&Path, usize), Option<usize>> {
+    let mut lines = HashMap::new();
+    for d in file_diffs {
+        for hunk in &d.hunks {
+            let (mut old, mut new) = (hunk.old_start, hunk.new_start);
+            for line in &hunk.lines {
+                match line {
+                    Line::Context(_) => {
+                        lines.insert((d.path.as_path(), new), Some(old));
+                        old += 1;
+                        new += 1;
+                    }
+                    Line::Added(_) => {
+                        lines.insert((d.path.as_path(), new), None);
+                        new += 1;
+                    }
+                    Line::Removed(_) => old += 1,
+                }
+            }
+        }
+    }
+    lines
+}
+
+/// Split `findings` into a summary and line comments. Only lines which
+/// appear in `file_diffs` can be commented on; the rest are listed in the
+/// summary.
+fn to_comments(
+    file_diffs: &[FileDiff],
+    findings: &[Finding],
+) -> (String, Vec<Comment>) {
+    let lines = diff_lines(file_diffs);
+    let mut body = format!(
+        "yap found {} problem{}.\n",
+        findings.len(),
+        if findings.len() == 1 { "" } else { "s" }
+    );
+    let mut comments = Vec::new();
+    for f in findings {
+        let message = format!("**{}**: {}", f.severity, f.message);
+        match lines.get(&(f.file.as_path(), f.line)) {
+            Some(old_line) => comments.push(Comment {
+                path: f.file.clone(),
+                line: f.line,
+                old_line: *old_line,
+                body: message,
+            }),
+            None => body.push_str(&format!(
+                "\n- `{}:{}` {message}",
+                f.file.display(),
+                f.line
+            )),
+        }
+    }
+    (body, comments)
+}
+
+fn get_findings(
+    open_ai: &OpenAI,
+    diff_text: &str,
+) -> Result<Vec<Finding>, Error> {
+    let system_prompt = ConfigFile::ReviewSystemPrompt
+        .load()
+        .map_err(|e| {
+            e.wrap(Oops::ReviewError)
+                .because("Could not load the review system prompt".into())
+        })?
+        .unwrap_or(constants::DEFAULT_REVIEW_PROMPT.into());
+    let system_prompt = rules::with_rules(&system_prompt)
+        .map_err(|e| e.wrap(Oops::ReviewError))?;
+    let payload = CompletionPayload::new(
+        open_ai,
+        vec![
+            Message::new(Role::System, system_prompt),
+            Message::new(Role::User, diff_text.into()),
+        ],
+        PayloadOpts {
+            response_format: ResponseFormat::JsonSchema {
+                json_schema: get_json_schema(),
+            },
+            ..Default::default()
+        },
+    );
+    let response = chat(open_ai, &payload).map_err(|e| {
+        e.wrap(Oops::ReviewError)
+            .because("Error while requesting a review from OpenAI".into())
+    })?;
+    let content = match response.choices[0].message.parse()? {
+        Content::Normal(c) => c,
+        Content::Refusal(r) => {
+            return Err(Error::default()
+                .wrap(Oops::OpenAIRefusal)
+                .wrap(Oops::ReviewError)
+                .because(format!("OpenAI refused to review the change: {r}")))
+        }
+    };
+    let response: ReviewResponse =
+        serde_json::from_str(content).map_err(|e| {
+            debug!("Bad response content: {content}");
+            Error::default()
+                .wrap(Oops::ReviewError)
+                .because(format!("Could not deserialize review findings: {e}"))
+        })?;
+    Ok(response.findings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_report() {
+        let finding = |severity| Finding {
+            file: PathBuf::from("src/main.rs"),
+            line: 1,
+            severity,
+            message: "unwrap".into(),
+        };
+        let findings = [finding(Severity::Info), finding(Severity::Warning)];
+        let report = to_report(&findings, Severity::Error);
+        assert_eq!(report["passed"], true);
+        assert_eq!(report["fail_on"], "error");
+        assert_eq!(report["counts"]["warning"], 1);
+        assert_eq!(report["findings"][1]["severity"], "warning");
+        assert_eq!(to_report(&findings, Severity::Warning)["passed"], false);
+    }
+
+    #[test]
+    fn test_diff_lines() {
+        let file_diffs = diff::parse(
+            "--- a/a.rs\n+++ b/a.rs\n@@ -10,3 +10,3 @@\n x\n-y\n+z\n w\n",
+        )
+        .unwrap();
+        let lines = diff_lines(&file_diffs);
+        let path = Path::new("a.rs");
+        assert_eq!(lines[&(path, 10)], Some(10));
+        assert_eq!(lines[&(path, 11)], None);
+        assert_eq!(lines[&(path, 12)], Some(12));
+        assert!(!lines.contains_key(&(path, 13)));
+    }
+
+    #[test]
+    fn test_to_comments() {
+        let file_diffs = diff::parse(
+            "--- a/src/main.rs\n+++ b/src/main.rs\n@@ -1,2 +1,3 @@\n a\n+b\n c\n",
+        )
+        .unwrap();
+        let finding = |line, message: &str| Finding {
+            file: PathBuf::from("src/main.rs"),
+            line,
+            severity: Severity::Warning,
+            message: message.into(),
+        };
+        let (body, comments) = to_comments(
+            &file_diffs,
+            &[finding(2, "in the diff"), finding(40, "elsewhere")],
+        );
+        assert_eq!(comments.len(), 1);
+        assert_eq!(comments[0].line, 2);
+        assert_eq!(comments[0].old_line, None);
+        assert_eq!(comments[0].body, "**warning**: in the diff");
+        assert_eq!(
+            body,
+            "yap found 2 problems.\n\n- `src/main.rs:40` **warning**: elsewhere"
+        );
+    }
+}
@@ -0,0 +1,137 @@
+//! Team conventions for `yap review` and `yap annotate`, so that feedback
+//! matches the project's standards rather than the LLM's taste.
+//!
+//! Rules are read from `.yaprules.json` at the [files::project_root], so
+//! they can be committed alongside the code. Every field is optional:
+//!
+//! ```json
+//! {
+//!   "naming": ["Types are nouns; functions which return bool start with is_"],
+//!   "error_handling": ["Never unwrap outside of tests"],
+//!   "forbidden_apis": [
+//!     {"api": "std::process::exit", "reason": "return an Error instead"}
+//!   ],
+//!   "conventions": ["Public functions have doc comments"]
+//! }
+//! ```
+//!
+//! The rules are compiled into a section which is appended to the system
+//! prompt; see [with_rules].
+
+use crate::{
+    err::{Error, Oops},
+    files,
+};
+use serde::Deserialize;
+use std::{fmt::Write, fs, path::Path};
+
+const RULES_FILE: &str = ".yaprules.json";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Rules {
+    naming: Vec<String>,
+    error_handling: Vec<String>,
+    forbidden_apis: Vec<ForbiddenApi>,
+    conventions: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ForbiddenApi {
+    api: String,
+    reason: Option<String>,
+}
+
+fn oops(why: String) -> Error {
+    Error::default().wrap(Oops::RulesError).because(why)
+}
+
+/// The project's rules, compiled into a section for the system prompt, or
+/// `None` if the project has no rules.
+fn section() -> Result<Option<String>, Error> {
+    load(&files::project_root().join(RULES_FILE)).map(|r| r.and_then(compile))
+}
+
+/// `system_prompt`, followed by the project's rules, if it has any.
+pub fn with_rules(system_prompt: &str) -> Result<String, Error> {
+    Ok(match section()? {
+        Some(section) => format!("{}\n\n{section}", system_prompt.trim_end()),
+        None => system_prompt.to_string(),
+    })
+}
+
+fn load(path: &Path) -> Result<Option<Rules>, Error> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let text = fs::read_to_string(path)
+        .map_err(|e| oops(format!("Could not read {path:?}: {e}")))?;
+    serde_json::from_str(&text)
+        .map(Some)
+        .map_err(|e| oops(format!("{path:?} is invalid: {e}")))
+}
+
+fn compile(rules: Rules) -> Option<String> {
+    let mut out = String::new();
+    let mut list = |heading: &str, items: &[String]| {
+        if items.is_empty() {
+            return;
+        }
+        let _ = writeln!(out, "\n## {heading}\n");
+        for item in items {
+            let _ = writeln!(out, "- {item}");
+        }
+    };
+    list("Naming", &rules.naming);
+    list("Error handling", &rules.error_handling);
+    let forbidden: Vec<String> = rules
+        .forbidden_apis
+        .iter()
+        .map(|f| match &f.reason {
+            Some(reason) => format!("`{}`: {reason}", f.api),
+            None => format!("`{}`", f.api),
+        })
+        .collect();
+    list("Forbidden APIs (any new use is an error)", &forbidden);
+    list("Other conventions", &rules.conventions);
+    if out.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "# Project rules\n\nThis project's team has agreed on the following \
+        rules. Point out code which breaks them, even where they concern \
+        style, and cite the rule.\n{out}"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compile() {
+        let rules: Rules = serde_json::from_str(
+            r#"{
+                "error_handling": ["Never unwrap outside of tests"],
+                "forbidden_apis": [
+                    {"api": "std::process::exit", "reason": "return an Error"},
+                    {"api": "dbg!"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let section = compile(rules).unwrap();
+        assert!(section.starts_with("# Project rules\n\n"));
+        assert!(section.ends_with(
+            "\n## Error handling\n\n\
+            - Never unwrap outside of tests\n\
+            \n## Forbidden APIs (any new use is an error)\n\n\
+            - `std::process::exit`: return an Error\n\
+            - `dbg!`\n"
+        ));
+        assert!(!section.contains("Naming"));
+        assert!(compile(Rules::default()).is_none());
+        assert!(serde_json::from_str::<Rules>(r#"{"nmaing": []}"#).is_err());
+    }
+}
@@ -0,0 +1,295 @@
+//! Run commands on behalf of the LLM (i.e, the `--command` of
+//! [crate::agent]) with a restricted environment, configured with `sandbox`
+//! in `config.json` (see [crate::config]); i.e,
+//!
+//! ```json
+//! {
+//!   "sandbox": {
+//!     "env": ["PATH", "HOME", "CARGO_HOME"],
+//!     "timeout": 600,
+//!     "network": false
+//!   }
+//! }
+//! ```
+//!
+//! - `env`: the only environment variables which commands can see; API keys
+//!   and other secrets in `yap`'s environment are not passed on. Defaults to
+//!   [DEFAULT_ENV].
+//! - `timeout`: seconds before the command (and everything it started) is
+//!   killed. Defaults to 300.
+//! - `network`: set to `false` to run commands without network access. This
+//!   uses `unshare` on Linux (which needs unprivileged user namespaces), and
+//!   `sandbox-exec` on macOS; elsewhere, commands fail rather than run with
+//!   the network.
+//!
+//! `STDIN` is closed, and only the end of each of `STDOUT` and `STDERR` is
+//! kept, without terminal escapes, so that a noisy command can neither
+//! exhaust memory nor flood the conversation.
+
+use crate::err::{Error, Oops};
+use regex::Regex;
+use serde::Deserialize;
+use std::{
+    io::{self, Read},
+    path::Path,
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::OnceLock,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The environment variables which commands can see, unless `env` is set.
+pub const DEFAULT_ENV: &[&str] = &[
+    "PATH",
+    "HOME",
+    "USER",
+    "LANG",
+    "LC_ALL",
+    "TERM",
+    "TMPDIR",
+    "CARGO_HOME",
+    "RUSTUP_HOME",
+];
+
+/// Only this many bytes of the end of `STDOUT` and `STDERR` are kept.
+const OUTPUT_LIMIT: usize = 16_000;
+
+/// How often we check whether the command has exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Restrictions for commands which the LLM may run.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sandbox {
+    /// Environment variables which are passed through to commands.
+    pub env: Vec<String>,
+    /// Seconds before the command is killed.
+    pub timeout: u64,
+    /// Whether commands may use the network.
+    pub network: bool,
+}
+
+impl Default for Sandbox {
+    fn default() -> Self {
+        Self {
+            env: DEFAULT_ENV.iter().map(|v| v.to_string()).collect(),
+            timeout: 300,
+            network: true,
+        }
+    }
+}
+
+/// What a sandboxed command printed, and how it exited.
+#[derive(Debug)]
+pub struct Output {
+    /// `None` if the command was killed after the timeout.
+    pub status: Option<ExitStatus>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+fn oops(why: String) -> Error {
+    Error::default().wrap(Oops::SandboxError).because(why)
+}
+
+impl Sandbox {
+    /// Run `command` with `sh -c` in `cwd`, within the sandbox's limits.
+    pub fn run(&self, command: &str, cwd: &Path) -> Result<Output, Error> {
+        let mut cmd = self.wrap(command)?;
+        cmd.current_dir(cwd)
+            .env_clear()
+            .envs(
+                self.env
+                    .iter()
+                    .filter_map(|k| std::env::var_os(k).map(|v| (k, v))),
+            )
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            // A process group of its own, so that a timeout kills everything
+            // which the command started.
+            cmd.process_group(0);
+        }
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| oops(format!("Could not run {command:?}: {e}")))?;
+        let stdout = child.stdout.take().expect("STDOUT is piped");
+        let stderr = child.stderr.take().expect("STDERR is piped");
+        let stdout = thread::spawn(move || tail(stdout));
+        let stderr = thread::spawn(move || tail(stderr));
+
+        let deadline = Instant::now() + Duration::from_secs(self.timeout);
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break Some(status),
+                Ok(None) if Instant::now() >= deadline => {
+                    kill(&mut child);
+                    break None;
+                }
+                Ok(None) => thread::sleep(POLL_INTERVAL),
+                Err(e) => {
+                    kill(&mut child);
+                    return Err(oops(format!(
+                        "Could not wait for {command:?}: {e}"
+                    )));
+                }
+            }
+        };
+        Ok(Output {
+            status,
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+        })
+    }
+
+    /// `sh -c command`, without network access if the sandbox forbids it.
+    fn wrap(&self, command: &str) -> Result<Command, Error> {
+        if self.network {
+            let mut cmd = Command::new("sh");
+            cmd.args(["-c", command]);
+            return Ok(cmd);
+        }
+        if cfg!(target_os = "linux") {
+            let mut cmd = Command::new("unshare");
+            cmd.args(["--net", "--map-root-user", "sh", "-c", command]);
+            Ok(cmd)
+        } else if cfg!(target_os = "macos") {
+            let mut cmd = Command::new("sandbox-exec");
+            cmd.args([
+                "-p",
+                "(version 1) (allow default) (deny network*)",
+                "sh",
+                "-c",
+                command,
+            ]);
+            Ok(cmd)
+        } else {
+            Err(oops(
+                "Commands cannot be run without network access on this platform; set sandbox.network to true to allow it".into(),
+            ))
+        }
+    }
+}
+
+impl Output {
+    /// A report of how the command went, to send to the LLM.
+    pub fn report(&self, command: &str) -> String {
+        let status = match self.status {
+            Some(status) => format!("`{command}` exited with {status}."),
+            None => format!("`{command}` timed out, and was killed."),
+        };
+        format!(
+            "{status}\n\nSTDOUT:\n```\n{}\n```\n\nSTDERR:\n```\n{}\n```",
+            self.stdout, self.stderr
+        )
+    }
+}
+
+#[cfg(unix)]
+fn kill(child: &mut Child) {
+    // SAFETY: `kill` has no memory effects; the negative PID signals the
+    // process group which the child leads.
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    let _ = child.wait();
+}
+
+#[cfg(not(unix))]
+fn kill(child: &mut Child) {
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+/// Read `reader` to the end, keeping only the last [OUTPUT_LIMIT] bytes, as
+/// text without terminal escapes.
+fn tail(mut reader: impl Read) -> String {
+    let mut kept = Vec::new();
+    let mut cut = 0;
+    let mut buf = [0; 8192];
+    loop {
+        match reader.read(&mut buf) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Ok(0) | Err(_) => break,
+            Ok(n) => kept.extend_from_slice(&buf[..n]),
+        }
+        if kept.len() > 2 * OUTPUT_LIMIT {
+            let excess = kept.len() - OUTPUT_LIMIT;
+            kept.drain(..excess);
+            cut += excess;
+        }
+    }
+    if kept.len() > OUTPUT_LIMIT {
+        let excess = kept.len() - OUTPUT_LIMIT;
+        kept.drain(..excess);
+        cut += excess;
+    }
+    let text = String::from_utf8_lossy(&kept);
+    let text = escapes().replace_all(&text, "");
+    if cut > 0 {
+        format!("[{cut} bytes cut]\n{text}")
+    } else {
+        text.into_owned()
+    }
+}
+
+fn escapes() -> &'static Regex {
+    static ESCAPES: OnceLock<Regex> = OnceLock::new();
+    ESCAPES.get_or_init(|| {
+        Regex::new(r"\x1b\[[0-9;?]*[ -/]*[@-~]|\x1b\][^\x07]*\x07")
+            .expect("valid regex")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let sandbox = Sandbox {
+            env: vec!["PATH".into()],
+            ..Default::default()
+        };
+        let output = sandbox
+            .run(
+                "pwd; echo \"${HOME:-unset}\"; echo oops >&2; exit 3",
+                Path::new("/"),
+            )
+            .unwrap();
+        assert_eq!(output.status.and_then(|s| s.code()), Some(3));
+        assert_eq!(output.stdout, "/\nunset\n");
+        assert_eq!(output.stderr, "oops\n");
+        assert!(output
+            .report("x")
+            .starts_with("`x` exited with exit status: 3."));
+    }
+
+    #[test]
+    fn test_run_timeout() {
+        let sandbox = Sandbox {
+            timeout: 1,
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let output = sandbox
+            .run("echo started; sleep 30 & wait", Path::new("."))
+            .unwrap();
+        assert!(output.status.is_none());
+        assert_eq!(output.stdout, "started\n");
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_tail() {
+        assert_eq!(tail("\x1b[31merror\x1b[0m: bad".as_bytes()), "error: bad");
+        let long = format!("{}end", "x".repeat(3 * OUTPUT_LIMIT));
+        let text = tail(long.as_bytes());
+        assert!(text
+            .starts_with(&format!("[{} bytes cut]\n", 2 * OUTPUT_LIMIT + 3)));
+        assert!(text.ends_with("xend"));
+    }
+}
@@ -0,0 +1,206 @@
+//! Read text aloud with `yap say`; i.e, to listen to a long explanation away
+//! from the screen.
+//!
+//! Text longer than OpenAI accepts in one request is split at paragraph or
+//! sentence boundaries, and the pieces are spoken in turn. The audio is
+//! written to a file, or else played with the first audio player found on
+//! `$PATH`.
+
+use crate::{
+    db,
+    err::{Error, Oops},
+    openai::{
+        audio_api::{self, SpeechFormat, Voice},
+        OpenAI, Role,
+    },
+};
+use std::{
+    env, fs,
+    io::{self, ErrorKind, Read},
+    path::Path,
+    process::Command,
+};
+
+/// Commands which can play a `.wav` file, in order of preference. The file's
+/// path is appended.
+const PLAYERS: [&[&str]; 5] = [
+    &["afplay"],
+    &["paplay"],
+    &["aplay", "-q"],
+    &["ffplay", "-nodisp", "-autoexit", "-loglevel", "quiet"],
+    &["mpv", "--really-quiet"],
+];
+
+fn oops(why: String) -> Error {
+    Error::default().wrap(Oops::SayError).because(why)
+}
+
+/// Entrypoint for `yap say`. Speaks the text on `STDIN`, or the last reply
+/// in the active chat if `last` is set. The audio is written to `output`
+/// (an `.mp3` or `.wav` file) if it is given, and played otherwise.
+pub fn say(
+    open_ai: &OpenAI,
+    voice: Voice,
+    last: bool,
+    output: Option<&Path>,
+) -> Result<(), Error> {
+    let text = if last { last_reply()? } else { read_stdin()? };
+    if text.trim().is_empty() {
+        return Err(oops("There is nothing to say".into()));
+    }
+    let mp3 = match output {
+        None => false,
+        Some(path) => match path.extension().and_then(|e| e.to_str()) {
+            Some("mp3") => true,
+            Some("wav") => false,
+            _ => {
+                return Err(oops(format!(
+                    "{path:?} must be an .mp3 or .wav file"
+                )))
+            }
+        },
+    };
+    // MP3 streams can simply be concatenated, but WAV files can't, so raw
+    // samples are requested instead, and given a header at the end.
+    let format = if mp3 {
+        SpeechFormat::Mp3
+    } else {
+        SpeechFormat::Pcm
+    };
+    let mut audio = Vec::new();
+    for piece in split(&text, audio_api::MAX_SPEECH_CHARS) {
+        audio.extend(audio_api::speech(open_ai, piece, voice, format)?);
+    }
+    if !mp3 {
+        audio = wav(&audio);
+    }
+    match output {
+        Some(path) => fs::write(path, audio)
+            .map_err(|e| oops(format!("Could not write {path:?}: {e}"))),
+        None => play(&audio),
+    }
+}
+
+fn read_stdin() -> Result<String, Error> {
+    let mut text = String::new();
+    io::stdin().read_to_string(&mut text).map_err(|e| {
+        Error::default()
+            .wrap(Oops::SayError)
+            .wrap(Oops::StdinReadError)
+            .because(e.kind().to_string())
+    })?;
+    Ok(text)
+}
+
+/// The content of the last assistant message in the active chat.
+fn last_reply() -> Result<String, Error> {
+    let id = db::get_active_chat()?
+        .ok_or_else(|| oops("No chat is active".into()))?;
+    db::get_chat(&id)?
+        .messages
+        .into_iter()
+        .rev()
+        .filter(|m| matches!(m.role, Role::Assistant))
+        .find_map(|m| m.content)
+        .ok_or_else(|| oops("The active chat has no replies".into()))
+}
+
+/// Split `text` into pieces of at most `max` characters, preferring to split
+/// between paragraphs, then lines, then sentences, then words.
+fn split(text: &str, max: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = text.trim();
+    while rest.chars().count() > max {
+        let limit = rest.char_indices().nth(max).map_or(rest.len(), |(i, _)| i);
+        let head = &rest[..limit];
+        let end = ["\n\n", "\n", ". ", " "]
+            .iter()
+            .find_map(|sep| head.rfind(sep).map(|i| i + sep.len()))
+            .unwrap_or(limit);
+        pieces.push(rest[..end].trim());
+        rest = rest[end..].trim_start();
+    }
+    if !rest.is_empty() {
+        pieces.push(rest);
+    }
+    pieces
+}
+
+/// Wrap [SpeechFormat::Pcm] samples in a WAV header.
+fn wav(pcm: &[u8]) -> Vec<u8> {
+    let (channels, bits) = (1u16, 16u16);
+    let block_align = channels * bits / 8;
+    let byte_rate = audio_api::PCM_SAMPLE_RATE * u32::from(block_align);
+    let data_len = pcm.len() as u32;
+    let mut wav = Vec::with_capacity(44 + pcm.len());
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&channels.to_le_bytes());
+    wav.extend_from_slice(&audio_api::PCM_SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&byte_rate.to_le_bytes());
+    wav.extend_from_slice(&block_align.to_le_bytes());
+    wav.extend_from_slice(&bits.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    wav.extend_from_slice(pcm);
+    wav
+}
+
+/// Play a WAV file with the first of [PLAYERS] which is installed.
+fn play(wav: &[u8]) -> Result<(), Error> {
+    let path =
+        env::temp_dir().join(format!("yap-say-{}.wav", std::process::id()));
+    fs::write(&path, wav)
+        .map_err(|e| oops(format!("Could not write {path:?}: {e}")))?;
+    let mut result = Err(oops(
+        "No audio player was found; install one of afplay, paplay, aplay, ffplay, or mpv, or pass --output".into(),
+    ));
+    for player in PLAYERS {
+        match Command::new(player[0])
+            .args(&player[1..])
+            .arg(&path)
+            .status()
+        {
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => {
+                result = Err(oops(format!("Could not run {}: {e}", player[0])))
+            }
+            Ok(status) if !status.success() => {
+                result = Err(oops(format!("{} failed: {status}", player[0])))
+            }
+            Ok(_) => result = Ok(()),
+        }
+        break;
+    }
+    let _ = fs::remove_file(&path);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split("  hello  ", 10), vec!["hello"]);
+        assert_eq!(
+            split("One. Two.\n\nThree four five.", 16),
+            vec!["One. Two.", "Three four five."]
+        );
+        assert_eq!(split("One two. Three", 10), vec!["One two.", "Three"]);
+        assert_eq!(split("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+        assert!(split("", 4).is_empty());
+    }
+
+    #[test]
+    fn test_wav() {
+        let wav = wav(&[0, 0, 1, 0]);
+        assert_eq!(wav.len(), 48);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(&wav[40..44], &4u32.to_le_bytes());
+    }
+}
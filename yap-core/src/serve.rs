@@ -0,0 +1,793 @@
+//! `yap serve`: a long-lived process for editor plugins to talk to, so that
+//! they don't pay for process startup on every request.
+//!
+//! `yap serve` listens on `127.0.0.1:<port>`. The protocol is
+//! [JSON-RPC 2.0](https://www.jsonrpc.org/specification), with one JSON
+//! object per line in each direction. Requests on one connection are
+//! answered in order; open more connections to send requests concurrently.
+//! The methods are;
+//!
+//! - `complete`: `{"prompt": "...", "n": 2, "no_cache": false}` returns
+//!   `{"completions": ["..."], "refusals": []}`. The other options of `yap
+//!   complete` are accepted, too: `lang`, `filename`, `suffix_file`,
+//!   `prompt_file`, `repo_map`, and `strip_fences`.
+//! - `chat`: `{"prompt": "...", "chat_id": "<uuid>", "new": false}` returns
+//!   `{"chat_id": "<uuid>", "reply": "..."}`. Without `chat_id`, the active
+//!   chat is used, or a new chat is started if `new` is set.
+//! - `annotate`: `{"file": "src/main.rs", "line_start": 1, "line_end": 40,
+//!   "prompt": "..."}` returns `{"file": "src/main.rs", "annotations":
+//!   [{"line_number": 3, "line_end": 9, "content": "..."}]}`. The file is not
+//!   modified. `line_end` is omitted for annotations about a single line.
+//!   Instead of `line_start` and `line_end`, `"symbol": "Chat::new"` limits
+//!   annotations to one function or type. With `"summary": true`, an
+//!   overview of the file is included as `summary`.
+//!
+//! When a method fails, the error's `data` is `{"exit_code": <n>}`; the code
+//! which the matching `yap` subcommand would have exited with.
+//!
+//! Only `prompt` and `file` are required. Every method also accepts `cwd`,
+//! the directory to answer the request in, as though `yap` had been run
+//! there; relative paths, the project's [crate::ctx] files, and the active chat
+//! all depend on it.
+//!
+//! Any process on the machine can connect to `127.0.0.1`, including web
+//! pages, so every request to `yap serve` must also pass `token`; a secret
+//! which is written to `~/.local/state/yap/serve.token` (see [token_file])
+//! each time `yap serve` starts, and which only the current user can read.
+//! The connection is closed after a request with the wrong token, or after
+//! any line which isn't a JSON-RPC request, i.e, the headers of an HTTP
+//! request. For example;
+//!
+//! ```bash
+//! token=$(cat ~/.local/state/yap/serve.token)
+//! echo '{"jsonrpc": "2.0", "id": 1, "method": "complete", "params": {"token": "'$token'", "prompt": "fn main() {"}}' \
+//!     | nc -q1 localhost 7411
+//! ```
+//!
+//! `yap daemon` speaks the same protocol over a Unix socket, at
+//! `~/.local/state/yap/daemon.sock` by default, which only the current user
+//! can connect to, so it doesn't need a `token`. Run it in the background
+//! (i.e, `yap daemon &`, or from a systemd user unit); `config.json` is
+//! loaded once (see [crate::config::Settings::keep_loaded]), and connections
+//! to OpenAI are kept alive between requests, so each request skips process
+//! startup and the TLS handshake. While it runs, `yap complete`, `yap chat`,
+//! and `yap annotate` send their requests to it; see [Daemon].
+//!
+//! ```bash
+//! echo '{"jsonrpc": "2.0", "id": 1, "method": "complete", "params": {"prompt": "fn main() {"}}' \
+//!     | socat - UNIX-CONNECT:$HOME/.local/state/yap/daemon.sock
+//! ```
+
+use crate::{
+    annotate, chat, complete,
+    config::Settings,
+    db,
+    err::{Error, Oops},
+    files,
+    openai::{ClientOpts, Content, OpenAI},
+};
+use log::{debug, info};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use std::{
+    env,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    path::{Path, PathBuf},
+    thread,
+};
+use uuid::Uuid;
+
+/// A client for each method, so that each uses the model configured for the
+/// matching subcommand, and is recorded under that subcommand in
+/// [crate::usage].
+#[derive(Clone)]
+struct Clients {
+    complete: OpenAI,
+    chat: OpenAI,
+    annotate: OpenAI,
+}
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CompleteParams {
+    prompt: String,
+    n: Option<u8>,
+    #[serde(default)]
+    no_cache: bool,
+    lang: Option<String>,
+    filename: Option<PathBuf>,
+    suffix_file: Option<PathBuf>,
+    prompt_file: Option<PathBuf>,
+    #[serde(default)]
+    repo_map: bool,
+    strip_fences: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ChatParams {
+    prompt: String,
+    chat_id: Option<Uuid>,
+    #[serde(default)]
+    new: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AnnotateParams {
+    file: PathBuf,
+    line_start: Option<usize>,
+    line_end: Option<usize>,
+    symbol: Option<String>,
+    prompt: Option<String>,
+    #[serde(default)]
+    summary: bool,
+}
+
+/// The ways in which a request can fail, per the JSON-RPC spec.
+#[derive(Debug)]
+enum RpcError {
+    Parse(String),
+    Unauthorized,
+    MethodNotFound(String),
+    InvalidParams(String),
+    Failed(Error),
+}
+
+impl RpcError {
+    fn to_json(&self) -> Value {
+        let (code, message) = match self {
+            Self::Parse(e) => (-32700, format!("Parse error: {e}")),
+            Self::Unauthorized => (
+                -32001,
+                "Unauthorized: pass the token from serve.token as `token`"
+                    .to_string(),
+            ),
+            Self::MethodNotFound(m) => {
+                (-32601, format!("Method not found: {m}"))
+            }
+            Self::InvalidParams(e) => (-32602, format!("Invalid params: {e}")),
+            Self::Failed(e) => (-32000, e.summary()),
+        };
+        let mut error = json!({"code": code, "message": message});
+        // The exit code which `yap` would have exited with, so that
+        // [Daemon::call] can pass it on.
+        if let Self::Failed(e) = self {
+            error["data"] = json!({"exit_code": e.exit_code()});
+        }
+        error
+    }
+}
+
+impl Clients {
+    /// Requests are always sent, and not printed or saved, so `dry_run`,
+    /// `show_prompt`, and `debug_http` are ignored.
+    fn new(opts: &ClientOpts) -> Result<Self, Error> {
+        let opts = ClientOpts {
+            dry_run: false,
+            show_prompt: false,
+            debug_http: None,
+            ..opts.clone()
+        };
+        let client = |command| OpenAI::from_env(command, &opts);
+        Ok(Self {
+            complete: client("complete")?,
+            chat: client("chat")?,
+            annotate: client("annotate")?,
+        })
+    }
+}
+
+fn oops(why: String) -> Error {
+    Error::default().wrap(Oops::ServeError).because(why)
+}
+
+/// Entrypoint for `yap serve`. Serves requests until the process is killed.
+pub fn serve(port: u16, opts: &ClientOpts) -> Result<(), Error> {
+    Settings::keep_loaded()?;
+    let clients = Clients::new(opts)?;
+    let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|e| {
+        oops(format!("Could not listen on 127.0.0.1:{port}: {e}"))
+    })?;
+    let token = Uuid::new_v4().simple().to_string();
+    let path = token_file()?;
+    write_token(&path, &token)?;
+    eprintln!(
+        "yap is listening on 127.0.0.1:{port}; the token is in {}",
+        path.display()
+    );
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => match stream.try_clone() {
+                Ok(writer) => {
+                    spawn_connection(&clients, Some(&token), stream, writer)
+                }
+                Err(e) => info!("Could not clone a connection: {e}"),
+            },
+            Err(e) => info!("Could not accept a connection: {e}"),
+        }
+    }
+    Ok(())
+}
+
+/// The file which `yap serve` writes its token to; see the module docs.
+pub fn token_file() -> Result<PathBuf, Error> {
+    Ok(db::get_or_create_persistence_dir()?.join("serve.token"))
+}
+
+/// Write `token` to `path`, so that only the current user can read it. An
+/// existing file is replaced rather than truncated, since it may have been
+/// created with looser permissions.
+fn write_token(path: &Path, token: &str) -> Result<(), Error> {
+    let why = |e: std::io::Error| {
+        oops(format!("Could not write the token to {path:?}: {e}"))
+    };
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            return Err(why(e))
+        }
+        _ => {}
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(token.as_bytes()))
+        .map_err(why)
+}
+
+/// The socket which `yap daemon` listens on by default.
+pub fn default_socket() -> Result<PathBuf, Error> {
+    Ok(db::get_or_create_persistence_dir()?.join("daemon.sock"))
+}
+
+/// Entrypoint for `yap daemon`. Like [serve], but listens on the Unix socket
+/// `socket` (or [default_socket]) until the process is killed. The socket is
+/// removed on Ctrl-C, and a stale socket left by a daemon which was killed
+/// some other way is replaced.
+#[cfg(unix)]
+pub fn daemon(socket: Option<&Path>, opts: &ClientOpts) -> Result<(), Error> {
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    Settings::keep_loaded()?;
+    let clients = Clients::new(opts)?;
+    let path = match socket {
+        Some(path) => path.to_path_buf(),
+        None => default_socket()?,
+    };
+    if path.exists() {
+        if UnixStream::connect(&path).is_ok() {
+            return Err(oops(format!(
+                "Another daemon is already listening on {path:?}"
+            )));
+        }
+        std::fs::remove_file(&path).map_err(|e| {
+            oops(format!("Could not remove the stale socket {path:?}: {e}"))
+        })?;
+    }
+    // Other users must not be able to spend your API key, so the socket is
+    // created without group or other permissions.
+    // SAFETY: umask is always safe to call, and nothing else in the process
+    // is creating files yet.
+    let umask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(&path);
+    unsafe { libc::umask(umask) };
+    let listener = listener
+        .map_err(|e| oops(format!("Could not listen on {path:?}: {e}")))?;
+    let cleanup = path.clone();
+    ctrlc::set_handler(move || {
+        let _ = std::fs::remove_file(&cleanup);
+        std::process::exit(130);
+    })
+    .map_err(|e| oops(format!("Could not handle Ctrl-C: {e}")))?;
+    eprintln!("yap is listening on {}", path.display());
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => match stream.try_clone() {
+                Ok(writer) => spawn_connection(&clients, None, stream, writer),
+                Err(e) => info!("Could not clone a connection: {e}"),
+            },
+            Err(e) => info!("Could not accept a connection: {e}"),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn daemon(_socket: Option<&Path>, _opts: &ClientOpts) -> Result<(), Error> {
+    Err(oops(
+        "`yap daemon` needs Unix sockets; use `yap serve` instead".into(),
+    ))
+}
+
+/// Serve one connection on its own thread.
+fn spawn_connection(
+    clients: &Clients,
+    token: Option<&str>,
+    reader: impl Read + Send + 'static,
+    writer: impl Write + Send + 'static,
+) {
+    let clients = clients.clone();
+    let token = token.map(str::to_string);
+    thread::spawn(move || {
+        if let Err(e) =
+            handle_connection(&clients, token.as_deref(), reader, writer)
+        {
+            e.display();
+        }
+    });
+}
+
+/// Answer each request on the connection, until it is closed. With a
+/// `token`, every request must pass it. The connection is closed after the
+/// first line which isn't an authorized JSON-RPC request.
+fn handle_connection(
+    clients: &Clients,
+    token: Option<&str>,
+    reader: impl Read,
+    mut writer: impl Write,
+) -> Result<(), Error> {
+    let io_error = |e: std::io::Error| oops(format!("Connection error: {e}"));
+    for line in BufReader::new(reader).lines() {
+        let line = line.map_err(io_error)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request = serde_json::from_str::<Request>(&line)
+            .map_err(|e| RpcError::Parse(e.to_string()))
+            .and_then(|request| authorize(request, token));
+        let response = match request {
+            Ok(request) => handle(clients, request),
+            Err(e) => {
+                let response = response(Value::Null, Err(e));
+                writeln!(writer, "{response}").map_err(io_error)?;
+                return Ok(());
+            }
+        };
+        writeln!(writer, "{response}").map_err(io_error)?;
+    }
+    Ok(())
+}
+
+/// Take `token` out of the request's params, and check that it matches.
+fn authorize(
+    mut request: Request,
+    token: Option<&str>,
+) -> Result<Request, RpcError> {
+    let given = request
+        .params
+        .as_object_mut()
+        .and_then(|params| params.remove("token"));
+    match token {
+        Some(token)
+            if given.as_ref().and_then(Value::as_str) != Some(token) =>
+        {
+            Err(RpcError::Unauthorized)
+        }
+        _ => Ok(request),
+    }
+}
+
+/// Answer one request with a JSON-RPC response.
+fn handle(clients: &Clients, mut request: Request) -> Value {
+    debug!("serve: {} {}", request.method, request.params);
+    let cwd = request
+        .params
+        .as_object_mut()
+        .and_then(|params| params.remove("cwd"));
+    let result = match cwd {
+        Some(cwd) => params::<PathBuf>(cwd).and_then(|cwd| {
+            in_dir(&cwd, || call(clients, &request.method, request.params))
+        }),
+        None => call(clients, &request.method, request.params),
+    };
+    response(request.id, result)
+}
+
+fn call(
+    clients: &Clients,
+    method: &str,
+    args: Value,
+) -> Result<Value, RpcError> {
+    match method {
+        "complete" => params(args).and_then(|p| rpc_complete(clients, p)),
+        "chat" => params(args).and_then(|p| rpc_chat(clients, p)),
+        "annotate" => params(args).and_then(|p| rpc_annotate(clients, p)),
+        other => Err(RpcError::MethodNotFound(other.to_string())),
+    }
+}
+
+/// Answer a request in the directory `cwd`; see [files::in_dir]. The
+/// process's working directory is left alone, so that requests from other
+/// directories can be answered at the same time.
+fn in_dir(
+    cwd: &Path,
+    f: impl FnOnce() -> Result<Value, RpcError>,
+) -> Result<Value, RpcError> {
+    if !cwd.is_absolute() || !cwd.is_dir() {
+        return Err(RpcError::InvalidParams(format!(
+            "cwd must be an absolute path to a directory, not {cwd:?}"
+        )));
+    }
+    files::in_dir(cwd, f)
+}
+
+fn params<T: for<'a> Deserialize<'a>>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params)
+        .map_err(|e| RpcError::InvalidParams(e.to_string()))
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err(e) => json!({"jsonrpc": "2.0", "id": id, "error": e.to_json()}),
+    }
+}
+
+/// Like `yap complete`, refusals are only an error if every candidate was
+/// refused.
+fn rpc_complete(
+    clients: &Clients,
+    p: CompleteParams,
+) -> Result<Value, RpcError> {
+    let opts = complete::Opts {
+        no_cache: p.no_cache,
+        n: p.n,
+        json: false,
+        lang: p.lang,
+        filename: p.filename,
+        suffix_file: p.suffix_file.as_deref().map(files::resolve),
+        repo_map: p.repo_map,
+        strip_fences: p.strip_fences,
+        prompt_file: p.prompt_file.as_deref().map(files::resolve),
+    };
+    let response = complete::completions(&clients.complete, &opts, p.prompt)
+        .map_err(RpcError::Failed)?;
+    let (mut completions, mut refusals) = (Vec::new(), Vec::new());
+    for choice in &response.choices {
+        match choice.message.parse().map_err(RpcError::Failed)? {
+            Content::Normal(text) => completions.push(text),
+            Content::Refusal(text) => refusals.push(text),
+        }
+    }
+    if completions.is_empty() {
+        return Err(RpcError::Failed(
+            Error::default()
+                .wrap(Oops::OpenAIRefusal)
+                .wrap(Oops::CompletionError)
+                .because(format!("The model refused: {}", refusals.join("; "))),
+        ));
+    }
+    Ok(json!({"completions": completions, "refusals": refusals}))
+}
+
+fn rpc_chat(clients: &Clients, p: ChatParams) -> Result<Value, RpcError> {
+    let chat_id = match (p.chat_id, p.new) {
+        (Some(_), true) => {
+            return Err(RpcError::InvalidParams(
+                "chat_id and new cannot be used together".into(),
+            ))
+        }
+        (Some(id), false) => id,
+        (None, new) => {
+            let active = if new {
+                None
+            } else {
+                db::get_active_chat().map_err(RpcError::Failed)?
+            };
+            match active {
+                Some(id) => id,
+                None => {
+                    let id = Uuid::new_v4();
+                    db::set_chat_id(&id).map_err(RpcError::Failed)?;
+                    id
+                }
+            }
+        }
+    };
+    let reply = chat::reply(&clients.chat, &chat_id, p.prompt)
+        .map_err(RpcError::Failed)?;
+    Ok(json!({"chat_id": chat_id, "reply": reply}))
+}
+
+fn rpc_annotate(
+    clients: &Clients,
+    p: AnnotateParams,
+) -> Result<Value, RpcError> {
+    let (line_start, line_end) = match &p.symbol {
+        Some(symbol) => {
+            annotate::symbol_lines(&p.file, symbol).map_err(RpcError::Failed)?
+        }
+        None => (p.line_start.unwrap_or(1), p.line_end),
+    };
+    annotate::annotations(
+        &clients.annotate,
+        p.prompt.as_deref(),
+        &p.file,
+        line_start,
+        line_end,
+        p.summary,
+    )
+    .map_err(RpcError::Failed)
+}
+
+/// A connection to a running `yap daemon`; see [Daemon::connect]. `yap
+/// complete`, `yap chat`, and `yap annotate` send their requests through
+/// one when they can, so that they skip process startup costs like reading
+/// `config.json` and the TLS handshake with OpenAI.
+pub struct Daemon {
+    #[cfg(unix)]
+    stream: BufReader<std::os::unix::net::UnixStream>,
+    next_id: u64,
+}
+
+impl Daemon {
+    /// Connect to the daemon on [default_socket], if one is listening. Set
+    /// `YAP_NO_DAEMON` to always run locally. `YAP_MOCK`, `YAP_RECORD`, and
+    /// `YAP_REPLAY` only apply to this process, so nothing is forwarded
+    /// while they are set.
+    pub fn connect() -> Option<Self> {
+        let local = ["YAP_NO_DAEMON", "YAP_MOCK", "YAP_RECORD", "YAP_REPLAY"];
+        if local.iter().any(|var| env::var_os(var).is_some()) {
+            return None;
+        }
+        Self::connect_to(&default_socket().ok()?)
+    }
+
+    #[cfg(unix)]
+    fn connect_to(path: &Path) -> Option<Self> {
+        let stream = std::os::unix::net::UnixStream::connect(path).ok()?;
+        debug!("Forwarding to the daemon on {path:?}");
+        Some(Self {
+            stream: BufReader::new(stream),
+            next_id: 0,
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn connect_to(_path: &Path) -> Option<Self> {
+        None
+    }
+
+    /// Call `method` with `params`, in the current directory, and return
+    /// its result.
+    pub fn call<T: DeserializeOwned>(
+        &mut self,
+        method: &str,
+        mut params: Value,
+    ) -> Result<T, Error> {
+        let cwd = env::current_dir().map_err(|e| {
+            oops(format!("Could not get the current directory: {e}"))
+        })?;
+        params["cwd"] = json!(cwd);
+        self.next_id += 1;
+        let mut response = self.send(&json!({
+            "jsonrpc": "2.0",
+            "id": self.next_id,
+            "method": method,
+            "params": params,
+        }))?;
+        if let Some(error) = response.get("error") {
+            return Err(remote_error(error));
+        }
+        serde_json::from_value(response["result"].take()).map_err(|e| {
+            oops(format!(
+                "yap daemon sent an unexpected {method} result: {e}"
+            ))
+        })
+    }
+
+    #[cfg(unix)]
+    fn send(&mut self, request: &Value) -> Result<Value, Error> {
+        let io_error = |e: std::io::Error| {
+            oops(format!("Lost the connection to yap daemon: {e}"))
+        };
+        writeln!(self.stream.get_mut(), "{request}").map_err(io_error)?;
+        let mut line = String::new();
+        if self.stream.read_line(&mut line).map_err(io_error)? == 0 {
+            return Err(oops("yap daemon closed the connection".into()));
+        }
+        serde_json::from_str(&line)
+            .map_err(|e| oops(format!("yap daemon sent invalid JSON: {e}")))
+    }
+
+    #[cfg(not(unix))]
+    fn send(&mut self, _request: &Value) -> Result<Value, Error> {
+        Err(oops("`yap daemon` needs Unix sockets".into()))
+    }
+}
+
+/// Rebuild an error sent by `yap daemon`, keeping its exit code; see
+/// [RpcError::to_json].
+fn remote_error(error: &Value) -> Error {
+    let message = error["message"].as_str().unwrap_or("unknown error");
+    let root = error["data"]["exit_code"]
+        .as_i64()
+        .and_then(Oops::from_exit_code);
+    match root {
+        Some(root) => Error::default().wrap(root),
+        None => Error::default(),
+    }
+    .wrap(Oops::ServeError)
+    .because(format!("yap daemon: {message}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_params() {
+        let p: ChatParams =
+            params(json!({"prompt": "hi", "new": true})).unwrap();
+        assert_eq!(p.prompt, "hi");
+        assert!(p.new && p.chat_id.is_none());
+        assert!(matches!(
+            params::<ChatParams>(json!({"prompt": "hi", "bogus": 1})),
+            Err(RpcError::InvalidParams(_))
+        ));
+        assert!(matches!(
+            params::<AnnotateParams>(json!({"prompt": "hi"})),
+            Err(RpcError::InvalidParams(_))
+        ));
+    }
+
+    #[test]
+    fn test_remote_error() {
+        let e = Error::default()
+            .wrap(Oops::OpenAIPoverty)
+            .wrap(Oops::CompletionError);
+        let e = remote_error(&RpcError::Failed(e).to_json());
+        assert!(e.has(&Oops::ServeError));
+        assert_eq!(e.exit_code(), 6);
+        let e =
+            remote_error(&RpcError::MethodNotFound("nope".into()).to_json());
+        assert_eq!(e.exit_code(), 1);
+    }
+
+    #[test]
+    fn test_response() {
+        assert_eq!(
+            response(json!(1), Ok(json!({"reply": "hi"}))),
+            json!({"jsonrpc": "2.0", "id": 1, "result": {"reply": "hi"}})
+        );
+        let err =
+            response(json!("a"), Err(RpcError::MethodNotFound("nope".into())));
+        assert_eq!(err["id"], "a");
+        assert_eq!(err["error"]["code"], -32601);
+    }
+
+    fn clients() -> Clients {
+        let fixtures = env::temp_dir()
+            .join(format!("yap-test-serve-{}", std::process::id()));
+        Clients {
+            complete: OpenAI::replaying(fixtures.clone()),
+            chat: OpenAI::replaying(fixtures.clone()),
+            annotate: OpenAI::replaying(fixtures),
+        }
+    }
+
+    /// Send `input` over a connection, and return each line of the reply.
+    fn converse(token: Option<&str>, input: &str) -> Vec<Value> {
+        let mut out = Vec::new();
+        handle_connection(&clients(), token, input.as_bytes(), &mut out)
+            .unwrap();
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_http_request() {
+        // What a web page's `no-cors` POST to `yap serve` looks like.
+        let body = r#"{"jsonrpc": "2.0", "id": 1, "method": "bogus"}"#;
+        let input = format!(
+            "POST / HTTP/1.1\r\nHost: 127.0.0.1:7411\r\n\
+            Content-Type: text/plain\r\n\r\n{body}\n"
+        );
+        let replies = converse(None, &input);
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0]["error"]["code"], -32700);
+    }
+
+    #[test]
+    fn test_token() {
+        let request = |token: &str| {
+            json!({
+                "jsonrpc": "2.0",
+                "method": "bogus",
+                "params": {"token": token},
+            })
+        };
+        let input = format!("{}\n{}\n", request("s3cret"), request("s3cret"));
+        let replies = converse(Some("s3cret"), &input);
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[1]["error"]["code"], -32601);
+
+        let input = format!("{}\n{}\n", request("guess"), request("s3cret"));
+        let replies = converse(Some("s3cret"), &input);
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0]["error"]["code"], -32001);
+
+        let replies = converse(
+            Some("s3cret"),
+            "{\"jsonrpc\": \"2.0\", \"method\": \"bogus\"}\n",
+        );
+        assert_eq!(replies[0]["error"]["code"], -32001);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_token() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = env::temp_dir()
+            .join(format!("yap-test-token-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("serve.token");
+        std::fs::write(&path, "old").unwrap();
+        write_token(&path, "new").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_daemon_call() {
+        use std::os::unix::net::UnixListener;
+
+        let dir = env::temp_dir()
+            .join(format!("yap-test-daemon-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("daemon.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        let fixtures = dir.clone();
+        thread::spawn(move || {
+            // A daemon which echoes the params of the first request, and
+            // then serves the rest normally.
+            let mut stream = BufReader::new(listener.accept().unwrap().0);
+            let mut line = String::new();
+            stream.read_line(&mut line).unwrap();
+            let request: Value = serde_json::from_str(&line).unwrap();
+            let reply =
+                response(request["id"].clone(), Ok(request["params"].clone()));
+            writeln!(stream.get_mut(), "{reply}").unwrap();
+            let clients = Clients {
+                complete: OpenAI::replaying(fixtures.clone()),
+                chat: OpenAI::replaying(fixtures.clone()),
+                annotate: OpenAI::replaying(fixtures),
+            };
+            let writer = stream.get_ref().try_clone().unwrap();
+            handle_connection(&clients, None, stream, writer).unwrap();
+        });
+
+        let mut daemon = Daemon::connect_to(&socket).unwrap();
+        let echo: Value = daemon.call("chat", json!({"prompt": "hi"})).unwrap();
+        assert_eq!(echo["prompt"], "hi");
+        assert_eq!(echo["cwd"], json!(env::current_dir().unwrap()));
+        let e = daemon.call::<Value>("bogus", json!({})).unwrap_err();
+        assert!(e.has(&Oops::ServeError));
+        let e = daemon
+            .call::<Value>("annotate", json!({"file": "no-such-file.rs"}))
+            .unwrap_err();
+        assert!(e.summary().contains("no-such-file.rs"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,82 @@
+//! Find code which is similar to a snippet with `yap similar`, using the
+//! project's [index].
+
+use crate::{
+    embeddings,
+    err::{Error, Oops},
+    index,
+    openai::OpenAI,
+};
+use std::io::{self, Read};
+
+/// Entrypoint for `yap similar`. Reads a snippet from `STDIN`, and prints the
+/// `limit` most similar chunks in the index as `file:line_start-line_end`,
+/// followed by the cosine similarity.
+pub fn similar(open_ai: &OpenAI, limit: usize) -> Result<(), Error> {
+    let mut snippet = String::new();
+    io::stdin().read_to_string(&mut snippet).map_err(|e| {
+        Error::default()
+            .wrap(Oops::SimilarError)
+            .wrap(Oops::StdinReadError)
+            .because(e.kind().to_string())
+    })?;
+    if snippet.trim().is_empty() {
+        return Err(Error::default()
+            .wrap(Oops::SimilarError)
+            .because("The snippet on STDIN is empty".into()));
+    }
+    let index = index::load()?;
+    let (query, _) = embeddings::embed(open_ai, &[snippet])?;
+    let query = &query[0];
+
+    let mut stale = 0;
+    let mut scores = Vec::new();
+    for chunk in &index.chunks {
+        match embeddings::get(&chunk.key)? {
+            Some(embedding) => {
+                scores.push((cosine_similarity(query, &embedding), chunk))
+            }
+            None => stale += 1,
+        }
+    }
+    if stale > 0 {
+        log::warn!(
+            "{stale} chunks are missing from the embedding cache; run `yap index` again"
+        );
+    }
+    scores.sort_by(|a, b| b.0.total_cmp(&a.0));
+    for (score, chunk) in scores.into_iter().take(limit) {
+        println!(
+            "{}:{}-{}\t{score:.3}",
+            chunk.file.display(),
+            chunk.line_start,
+            chunk.line_end
+        );
+    }
+    Ok(())
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(a, b)| a * b).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cosine_similarity() {
+        assert!(
+            (cosine_similarity(&[1.0, 0.0], &[2.0, 0.0]) - 1.0).abs() < 1e-6
+        );
+        assert!(cosine_similarity(&[1.0, 0.0], &[0.0, 1.0]).abs() < 1e-6);
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 1.0]), 0.0);
+    }
+}
@@ -0,0 +1,83 @@
+//! An elapsed-time spinner on `STDERR`, so that slow requests don't look
+//! hung.
+//!
+//! Any number of [Spinner]s may be alive at once (e.g, while [crate::pool]
+//! sends requests concurrently), but they share one line of output, which is
+//! cleared once the last of them is dropped. Nothing is drawn if `STDERR` is
+//! not a terminal.
+
+use std::{
+    io::{self, IsTerminal, Write},
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+const FRAMES: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+/// How often the spinner is redrawn.
+const TICK: Duration = Duration::from_millis(100);
+
+/// The number of live spinners, and whether the drawing thread is running.
+static STATE: Mutex<(usize, bool)> = Mutex::new((0, false));
+
+/// Shows a spinner until dropped.
+pub struct Spinner {
+    visible: bool,
+}
+
+impl Spinner {
+    /// Start spinning, with `label` describing what we are waiting for.
+    pub fn start(label: String) -> Self {
+        if !io::stderr().is_terminal() {
+            return Self { visible: false };
+        }
+        let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+        state.0 += 1;
+        if !state.1 {
+            state.1 = true;
+            thread::spawn(move || spin(label));
+        }
+        Self { visible: true }
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        if !self.visible {
+            return;
+        }
+        let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+        state.0 -= 1;
+        if state.0 == 0 {
+            // Clear the line right away, so that it doesn't get mixed up
+            // with whatever is printed next.
+            draw("\r\x1b[2K");
+        }
+    }
+}
+
+fn spin(label: String) {
+    let start = Instant::now();
+    for frame in FRAMES.iter().cycle() {
+        thread::sleep(TICK);
+        let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+        if state.0 == 0 {
+            state.1 = false;
+            return;
+        }
+        let elapsed = start.elapsed().as_secs_f32();
+        let requests = if state.0 > 1 {
+            format!(" ({} requests)", state.0)
+        } else {
+            String::new()
+        };
+        draw(&format!("\r\x1b[2K{frame} {label}{requests} {elapsed:.1}s"));
+    }
+}
+
+fn draw(text: &str) {
+    let mut stderr = io::stderr();
+    let _ = stderr.write_all(text.as_bytes());
+    let _ = stderr.flush();
+}
@@ -0,0 +1,192 @@
+//! Find the public functions and types in source files, for
+//! [crate::repomap].
+//!
+//! Symbols are found with per-language regular expressions, rather than a
+//! real parser, so the results are approximate. Languages without patterns
+//! have no symbols.
+
+use crate::lang::Language;
+use regex::Regex;
+use std::sync::LazyLock;
+
+/// A symbol's declaration.
+#[derive(Debug, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    /// The declaration, up to (but not including) the body, with whitespace
+    /// collapsed; i.e, `pub fn get_chat(id: &Uuid) -> Result<Chat, Error>`.
+    pub signature: String,
+    /// The first line of the declaration, starting from 1.
+    pub line: usize,
+}
+
+/// A line which begins a declaration. The `name` group captures the
+/// symbol's name.
+struct Pattern {
+    language: &'static str,
+    regex: &'static str,
+    /// Characters which end the signature, i.e, the start of the body.
+    terminators: &'static [char],
+}
+
+const PATTERNS: &[Pattern] = &[
+    Pattern {
+        language: "Rust",
+        regex: r"^\s*pub(\([^)]*\))?\s+(const\s+)?(async\s+)?(unsafe\s+)?(fn|struct|enum|trait|type|const|static|mod|union)\s+(?<name>\w+)",
+        terminators: &['{', ';', '='],
+    },
+    Pattern {
+        language: "Rust",
+        regex: r"^impl\b(<[^>]*>)?\s+(?<name>[\w:<>, ]+?)\s*(\{|where|$)",
+        terminators: &['{'],
+    },
+    Pattern {
+        language: "Python",
+        regex: r"^\s*(async\s+)?(def|class)\s+(?<name>[A-Za-z]\w*|__init__)\b",
+        terminators: &[':'],
+    },
+    Pattern {
+        language: "JavaScript",
+        regex: r"^export\s+(default\s+)?(async\s+)?(function\*?|class|const|let|var)\s+(?<name>[\w$]+)",
+        terminators: &['{', '='],
+    },
+    Pattern {
+        language: "TypeScript",
+        regex: r"^export\s+(default\s+)?(declare\s+)?(abstract\s+)?(async\s+)?(function\*?|class|const|let|var|interface|type|enum)\s+(?<name>[\w$]+)",
+        terminators: &['{', '='],
+    },
+    Pattern {
+        language: "Go",
+        regex: r"^(func(\s+\([^)]*\))?|type)\s+(?<name>[A-Z]\w*)",
+        terminators: &['{'],
+    },
+];
+
+/// Signatures which span more lines than this are cut short.
+const MAX_SIGNATURE_LINES: usize = 8;
+
+static COMPILED: LazyLock<Vec<(&'static Pattern, Regex)>> =
+    LazyLock::new(|| {
+        PATTERNS
+            .iter()
+            .map(|p| (p, Regex::new(p.regex).expect("patterns are valid")))
+            .collect()
+    });
+
+/// The symbols declared in `source`, in order.
+pub fn outline(language: &Language, source: &str) -> Vec<Symbol> {
+    let patterns: Vec<_> = COMPILED
+        .iter()
+        .filter(|(p, _)| p.language == language.name)
+        .collect();
+    let lines: Vec<&str> = source.lines().collect();
+    let mut symbols = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        for (pattern, regex) in &patterns {
+            if let Some(captures) = regex.captures(line) {
+                symbols.push(Symbol {
+                    name: captures["name"].trim().to_string(),
+                    signature: signature(&lines[i..], pattern.terminators),
+                    line: i + 1,
+                });
+                break;
+            }
+        }
+    }
+    symbols
+}
+
+/// The declaration at the start of `lines`, up to the first terminator
+/// outside of brackets.
+fn signature(lines: &[&str], terminators: &[char]) -> String {
+    let mut signature = String::new();
+    let mut depth = 0i32;
+    for line in lines.iter().take(MAX_SIGNATURE_LINES) {
+        for c in line.trim().chars() {
+            match c {
+                '(' | '[' | '<' => depth += 1,
+                // `->` and `=>` are not closing brackets.
+                '>' if signature.ends_with('-') || signature.ends_with('=') => {
+                }
+                ')' | ']' | '>' => depth -= 1,
+                c if depth <= 0 && terminators.contains(&c) => {
+                    return collapse(&signature);
+                }
+                _ => {}
+            }
+            signature.push(c);
+        }
+        signature.push(' ');
+    }
+    if lines.len() > MAX_SIGNATURE_LINES {
+        signature.push_str("...");
+    }
+    collapse(&signature)
+}
+
+fn collapse(s: &str) -> String {
+    let s = s.split_whitespace().collect::<Vec<_>>().join(" ");
+    // Multi-line argument lists leave a trailing comma and spaces inside
+    // the brackets.
+    s.replace("( ", "(").replace(", )", ")").replace(",)", ")")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lang;
+
+    #[test]
+    fn test_outline_rust() {
+        let source = "use std::fs;
+
+pub fn get_chat(id: &Uuid) -> Result<Chat, Error> {
+    todo!()
+}
+
+fn private() {}
+
+impl Chat {
+    pub fn new(
+        model: Model,
+        tags: Vec<String>,
+    ) -> Self {
+        todo!()
+    }
+}
+
+pub struct Chat;
+";
+        let symbols = outline(lang::by_name("rust").unwrap(), source);
+        let signatures: Vec<(&str, &str, usize)> = symbols
+            .iter()
+            .map(|s| (s.name.as_str(), s.signature.as_str(), s.line))
+            .collect();
+        assert_eq!(
+            signatures,
+            vec![
+                (
+                    "get_chat",
+                    "pub fn get_chat(id: &Uuid) -> Result<Chat, Error>",
+                    3
+                ),
+                ("Chat", "impl Chat", 9),
+                (
+                    "new",
+                    "pub fn new(model: Model, tags: Vec<String>) -> Self",
+                    10
+                ),
+                ("Chat", "pub struct Chat", 18),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_outline_python() {
+        let source = "class Chat:\n    def __init__(self, id):\n        pass\n\n    def _private(self):\n        pass\n";
+        let symbols = outline(lang::by_name("py").unwrap(), source);
+        let names: Vec<&str> =
+            symbols.iter().map(|s| s.signature.as_str()).collect();
+        assert_eq!(names, vec!["class Chat", "def __init__(self, id)"]);
+    }
+}
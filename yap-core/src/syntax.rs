@@ -0,0 +1,551 @@
+//! Parse source files with [tree-sitter](https://tree-sitter.github.io/),
+//! so that commands can work with syntactic units (functions, types, `impl`
+//! blocks, and so on) instead of raw line ranges.
+//!
+//! Rust, Python, JavaScript, TypeScript, and Go are supported. For other
+//! languages, [units] returns `None`, and [find] falls back to [guess].
+
+use crate::{
+    err::{Error, Oops},
+    lang,
+};
+use regex::Regex;
+use std::{ops::RangeInclusive, path::Path};
+use tree_sitter::{Language, Node, Parser};
+
+/// A function, type, or other named item in a source file.
+#[derive(Debug, PartialEq)]
+pub struct Unit {
+    /// The unit's name, qualified by its enclosing units; e.g, `Chat::new`
+    /// for a method in `impl Chat`, or `Chat.__init__` in Python.
+    pub name: String,
+    /// 1-based, and including any doc comments and attributes (or
+    /// decorators) immediately above the unit.
+    pub lines: RangeInclusive<usize>,
+    /// The number of units which enclose this one.
+    pub depth: usize,
+}
+
+impl Unit {
+    /// Whether `name` refers to this unit; either by its qualified name, or
+    /// by the unqualified name (e.g, `new` for `Chat::new`).
+    pub fn is_named(&self, name: &str) -> bool {
+        self.name == name
+            || self
+                .name
+                .rsplit(['.', ':'])
+                .next()
+                .is_some_and(|last| last == name)
+    }
+}
+
+/// Node kinds which are units, for one language.
+struct Grammar {
+    language: Language,
+    /// Units which have a `name` field.
+    named: &'static [&'static str],
+    /// Units which are named by another field; i.e, an `impl` block by its
+    /// `type`.
+    named_by: &'static [(&'static str, &'static str)],
+    /// Separates the names of nested units.
+    separator: &'static str,
+}
+
+/// Nodes which wrap a unit without adding a name; the unit's lines are
+/// extended to cover them.
+const WRAPPERS: &[&str] = &[
+    "decorated_definition",
+    "export_statement",
+    "lexical_declaration",
+    "variable_declaration",
+    "type_declaration",
+];
+
+/// Nodes which are attached to the unit below them.
+const PREAMBLE: &[&str] =
+    &["line_comment", "block_comment", "comment", "attribute_item"];
+
+const JAVASCRIPT_UNITS: &[&str] = &[
+    "function_declaration",
+    "generator_function_declaration",
+    "class_declaration",
+    "method_definition",
+];
+
+const TYPESCRIPT_UNITS: &[&str] = &[
+    "function_declaration",
+    "generator_function_declaration",
+    "class_declaration",
+    "abstract_class_declaration",
+    "method_definition",
+    "interface_declaration",
+    "type_alias_declaration",
+    "enum_declaration",
+    "internal_module",
+];
+
+/// `const f = () => {}` is named by its declarator.
+const JAVASCRIPT_NAMED_BY: &[(&str, &str)] = &[("variable_declarator", "name")];
+
+fn grammar(path: &Path) -> Option<Grammar> {
+    let grammar = match lang::by_path(path)?.name {
+        "Rust" => Grammar {
+            language: tree_sitter_rust::LANGUAGE.into(),
+            named: &[
+                "function_item",
+                "function_signature_item",
+                "struct_item",
+                "enum_item",
+                "union_item",
+                "trait_item",
+                "mod_item",
+                "type_item",
+                "const_item",
+                "static_item",
+                "macro_definition",
+            ],
+            named_by: &[("impl_item", "type")],
+            separator: "::",
+        },
+        "Python" => Grammar {
+            language: tree_sitter_python::LANGUAGE.into(),
+            named: &["function_definition", "class_definition"],
+            named_by: &[],
+            separator: ".",
+        },
+        "JavaScript" => Grammar {
+            language: tree_sitter_javascript::LANGUAGE.into(),
+            named: JAVASCRIPT_UNITS,
+            named_by: JAVASCRIPT_NAMED_BY,
+            separator: ".",
+        },
+        "TypeScript" => Grammar {
+            language: if path.extension().is_some_and(|e| e == "tsx") {
+                tree_sitter_typescript::LANGUAGE_TSX.into()
+            } else {
+                tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into()
+            },
+            named: TYPESCRIPT_UNITS,
+            named_by: JAVASCRIPT_NAMED_BY,
+            separator: ".",
+        },
+        "Go" => Grammar {
+            language: tree_sitter_go::LANGUAGE.into(),
+            named: &["function_declaration", "method_declaration", "type_spec"],
+            named_by: &[],
+            separator: ".",
+        },
+        _ => return None,
+    };
+    Some(grammar)
+}
+
+/// The units in `source`, in order of appearance, or `None` if the
+/// language of `path` is not supported.
+pub fn units(path: &Path, source: &str) -> Option<Vec<Unit>> {
+    let grammar = grammar(path)?;
+    let mut parser = Parser::new();
+    parser.set_language(&grammar.language).ok()?;
+    let tree = parser.parse(source, None)?;
+    let mut units = Vec::new();
+    collect(
+        &grammar,
+        source.as_bytes(),
+        tree.root_node(),
+        &mut Vec::new(),
+        &mut units,
+    );
+    Some(units)
+}
+
+/// The lines of the unit in `source` which [Unit::is_named] `name`. A
+/// qualified name (e.g, `Chat::new`) is preferred over others which share
+/// its last segment. If the language of `path` is not supported, the lines
+/// are found with [guess].
+pub fn find(
+    path: &Path,
+    source: &str,
+    name: &str,
+) -> Result<RangeInclusive<usize>, Error> {
+    let Some(units) = units(path, source) else {
+        return guess(path, source, name);
+    };
+    let mut matches: Vec<&Unit> =
+        units.iter().filter(|u| u.name == name).collect();
+    if matches.is_empty() {
+        matches = units.iter().filter(|u| u.is_named(name)).collect();
+    }
+    match matches[..] {
+        [unit] => Ok(unit.lines.clone()),
+        [] => Err(Error::default()
+            .wrap(Oops::SymbolError)
+            .because(format!("{name} was not found in {path:?}"))),
+        _ => Err(Error::default().wrap(Oops::SymbolError).because(format!(
+            "{name} is ambiguous in {path:?}; it could be any of: {}",
+            matches
+                .iter()
+                .map(|u| format!(
+                    "{} (lines {}-{})",
+                    u.name,
+                    u.lines.start(),
+                    u.lines.end()
+                ))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))),
+    }
+}
+
+/// Find `name` in a file without a parser. The declaration is found with
+/// regular expressions for common keywords (`def`, `function`, `class`,
+/// etc.), or else for C-style functions (`int main(`, or `main(` at the start
+/// of a line). It ends with its matching brace, or, for languages without
+/// braces, where the indentation returns to the level of the declaration.
+pub fn guess(
+    path: &Path,
+    source: &str,
+    name: &str,
+) -> Result<RangeInclusive<usize>, Error> {
+    // Qualified names (`Chat::new`, `Chat.new`) can't be resolved without a
+    // parser, so only the last segment is used.
+    let name = name.rsplit(['.', ':']).next().unwrap_or(name);
+    let name = regex::escape(name);
+    let keyword = Regex::new(&format!(
+        r"\b(fn|func|function|fun|def|defp|defmodule|class|struct|enum|union|interface|trait|type|module|object|record|protocol|sub|proc|macro)\s+([\w.:]+[.:])?{name}\b|^\s*{name}\s*\(\)"
+    ))
+    .expect("the pattern is valid");
+    let c_style = Regex::new(&format!(
+        r"^(\s*([\w:<>,\[\]*&]+\s+)+|)[*&]*{name}\s*\([^;]*$"
+    ))
+    .expect("the pattern is valid");
+    let lines: Vec<&str> = source.lines().collect();
+    let mut starts: Vec<usize> = (0..lines.len())
+        .filter(|i| keyword.is_match(lines[*i]))
+        .collect();
+    if starts.is_empty() {
+        starts = (0..lines.len())
+            .filter(|i| {
+                c_style.is_match(lines[*i])
+                    && !lines[*i].trim_start().starts_with("return ")
+            })
+            .collect();
+    }
+    match starts[..] {
+        [start] => {
+            Ok(preamble(&lines, start) + 1..=block_end(&lines, start) + 1)
+        }
+        [] => Err(Error::default()
+            .wrap(Oops::SymbolError)
+            .because(format!("{name} was not found in {path:?}"))),
+        _ => Err(Error::default().wrap(Oops::SymbolError).because(format!(
+            "{name} is ambiguous in {path:?}; it is declared on lines {}",
+            starts
+                .iter()
+                .map(|i| (i + 1).to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))),
+    }
+}
+
+/// The index of the first line of comments or annotations directly above
+/// `lines[start]`.
+fn preamble(lines: &[&str], start: usize) -> usize {
+    let is_comment = |line: &str| {
+        let line = line.trim_start();
+        ["//", "/*", "*", "--", "@", "# ", "##", ";;"]
+            .iter()
+            .any(|p| line.starts_with(p))
+            || line == "#"
+    };
+    let mut first = start;
+    while first > 0 && is_comment(lines[first - 1]) {
+        first -= 1;
+    }
+    first
+}
+
+/// The index of the last line of the block which begins at `lines[start]`.
+fn block_end(lines: &[&str], start: usize) -> usize {
+    let mut parens = 0;
+    let mut braces = 0;
+    let mut opened = false;
+    for (i, line) in lines.iter().enumerate().skip(start) {
+        let mut in_string = false;
+        for c in line.chars() {
+            match c {
+                '"' => in_string = !in_string,
+                _ if in_string => {}
+                '(' => parens += 1,
+                ')' => parens -= 1,
+                '{' if opened || parens <= 0 => {
+                    braces += 1;
+                    opened = true;
+                }
+                '}' if opened => braces -= 1,
+                // A declaration without a body; i.e, `struct Chat;`.
+                ';' if !opened && parens <= 0 => return i,
+                _ => {}
+            }
+            if opened && braces == 0 {
+                return i;
+            }
+        }
+        // The opening brace may be on the next line, but otherwise, the
+        // body doesn't have braces.
+        let next_opens = lines
+            .get(i + 1)
+            .is_some_and(|l| l.trim_start().starts_with('{'));
+        if !opened && parens <= 0 && !next_opens {
+            break;
+        }
+    }
+    let indent = |line: &str| line.len() - line.trim_start().len();
+    let level = indent(lines[start]);
+    let mut end = start;
+    for (i, line) in lines.iter().enumerate().skip(start + 1) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if indent(line) <= level {
+            // Include closing keywords, like `end` in Ruby and Lua.
+            let trimmed = line.trim();
+            if trimmed.starts_with("end") || trimmed.starts_with('}') {
+                end = i;
+            }
+            break;
+        }
+        end = i;
+    }
+    end
+}
+
+/// The last line of each top-level unit in `source`, which are good places
+/// to split the file into chunks. Empty if the language of `path` is not
+/// supported.
+pub fn boundaries(path: &Path, source: &str) -> Vec<usize> {
+    units(path, source)
+        .unwrap_or_default()
+        .iter()
+        .filter(|u| u.depth == 0)
+        .map(|u| *u.lines.end())
+        .collect()
+}
+
+fn collect(
+    grammar: &Grammar,
+    source: &[u8],
+    node: Node,
+    scope: &mut Vec<String>,
+    units: &mut Vec<Unit>,
+) {
+    let name = name(grammar, source, node);
+    if let Some(name) = &name {
+        let mut qualified = scope.clone();
+        qualified.push(name.clone());
+        units.push(Unit {
+            name: qualified.join(grammar.separator),
+            lines: lines(node),
+            depth: scope.len(),
+        });
+        scope.push(name.clone());
+    }
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        collect(grammar, source, child, scope, units);
+    }
+    if name.is_some() {
+        scope.pop();
+    }
+}
+
+/// The name of `node`, if it is a unit.
+fn name(grammar: &Grammar, source: &[u8], node: Node) -> Option<String> {
+    let field = if grammar.named.contains(&node.kind()) {
+        "name"
+    } else {
+        let (_, field) =
+            grammar.named_by.iter().find(|(k, _)| *k == node.kind())?;
+        // Declarators are only units if they declare a function or class.
+        if node.kind() == "variable_declarator" {
+            let value = node.child_by_field_name("value")?;
+            if !["arrow_function", "function_expression", "class"]
+                .contains(&value.kind())
+            {
+                return None;
+            }
+        }
+        field
+    };
+    let name = node.child_by_field_name(field)?.utf8_text(source).ok()?;
+    Some(name.to_string())
+}
+
+/// The lines of `node`, extended to cover its wrappers and preamble.
+fn lines(node: Node) -> RangeInclusive<usize> {
+    let mut outer = node;
+    while let Some(parent) = outer.parent() {
+        let single = parent.named_child_count() == 1
+            || ["decorated_definition", "export_statement"]
+                .contains(&parent.kind());
+        if WRAPPERS.contains(&parent.kind()) && single {
+            outer = parent;
+        } else {
+            break;
+        }
+    }
+    let end = outer.end_position().row + 1;
+    let mut start = outer.start_position().row;
+    let mut above = outer.prev_named_sibling();
+    while let Some(sibling) = above {
+        if !PREAMBLE.contains(&sibling.kind())
+            || sibling.end_position().row + 1 < start
+        {
+            break;
+        }
+        start = sibling.start_position().row;
+        above = sibling.prev_named_sibling();
+    }
+    start + 1..=end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_units_rust() {
+        let source = "use std::fs;
+
+/// A chat.
+#[derive(Debug)]
+pub struct Chat;
+
+impl Chat {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+// Not attached to `save_chat`.
+
+pub fn save_chat() {}
+";
+        let units = units(Path::new("db.rs"), source).unwrap();
+        assert_eq!(
+            units,
+            vec![
+                Unit {
+                    name: "Chat".into(),
+                    lines: 3..=5,
+                    depth: 0
+                },
+                Unit {
+                    name: "Chat".into(),
+                    lines: 7..=11,
+                    depth: 0
+                },
+                Unit {
+                    name: "Chat::new".into(),
+                    lines: 8..=10,
+                    depth: 1
+                },
+                Unit {
+                    name: "save_chat".into(),
+                    lines: 15..=15,
+                    depth: 0
+                },
+            ]
+        );
+        assert!(units[2].is_named("new"));
+        assert!(units[2].is_named("Chat::new"));
+        assert!(!units[2].is_named("Chat"));
+    }
+
+    #[test]
+    fn test_units_python() {
+        let source = "import os
+
+@dataclass
+class Chat:
+    def save(self):
+        pass
+
+save = lambda: None
+";
+        let units = units(Path::new("db.py"), source).unwrap();
+        let names: Vec<(&str, RangeInclusive<usize>)> = units
+            .iter()
+            .map(|u| (u.name.as_str(), u.lines.clone()))
+            .collect();
+        assert_eq!(names, vec![("Chat", 3..=6), ("Chat.save", 5..=6)]);
+    }
+
+    #[test]
+    fn test_units_typescript() {
+        let source = "export const handler = async () => {
+  return 1;
+};
+
+export interface Props {}
+";
+        let units = units(Path::new("app.ts"), source).unwrap();
+        let names: Vec<(&str, RangeInclusive<usize>)> = units
+            .iter()
+            .map(|u| (u.name.as_str(), u.lines.clone()))
+            .collect();
+        assert_eq!(names, vec![("handler", 1..=3), ("Props", 5..=5)]);
+    }
+
+    #[test]
+    fn test_unsupported() {
+        assert!(units(Path::new("notes.txt"), "hello").is_none());
+    }
+
+    #[test]
+    fn test_guess() {
+        let java = "class Db {
+    // Save the chat.
+    @Override
+    public void saveChat(Chat chat)
+    {
+        if (chat.isEmpty()) { return; }
+        write(\"}\");
+    }
+
+    public Chat getChat(String id) {
+        return saveChat(null);
+    }
+}
+";
+        let path = Path::new("Db.java");
+        assert_eq!(find(path, java, "saveChat").unwrap(), 2..=8);
+        assert_eq!(find(path, java, "Db.getChat").unwrap(), 10..=12);
+        assert!(find(path, java, "deleteChat")
+            .unwrap_err()
+            .has(&Oops::SymbolError));
+
+        let ruby = "class Db
+  def save_chat(chat)
+    write({ id: chat.id })
+  end
+end
+";
+        let path = Path::new("db.rb");
+        assert_eq!(find(path, ruby, "save_chat").unwrap(), 2..=4);
+        assert_eq!(find(path, ruby, "Db").unwrap(), 1..=5);
+
+        let c = "struct chat;
+
+static int
+save_chat(struct chat *c)
+{
+    return 0;
+}
+";
+        let path = Path::new("db.c");
+        assert_eq!(find(path, c, "save_chat").unwrap(), 4..=7);
+        // `struct chat` appears in both declarations.
+        assert!(find(path, c, "chat").unwrap_err().has(&Oops::SymbolError));
+    }
+}
@@ -0,0 +1,217 @@
+use crate::err::{Error, Oops};
+use std::{
+    env,
+    io::{self, BufRead, IsTerminal, Write},
+    process::{Command, Stdio},
+};
+
+const DEFAULT_COLS: u16 = 80;
+const DEFAULT_LINES: u16 = 24;
+
+/// The width of the terminal, in columns.
+pub fn cols() -> u16 {
+    size().0
+}
+
+/// The height of the terminal, in rows.
+pub fn lines() -> u16 {
+    size().1
+}
+
+/// The terminal's `(cols, rows)`. We ask the terminal attached to `STDOUT`,
+/// `STDERR`, or `STDIN` (in that order), and otherwise fall back to
+/// `$COLUMNS` and `$LINES`, or else 80x24.
+pub fn size() -> (u16, u16) {
+    let (cols, rows) = window_size().unwrap_or((0, 0));
+    (
+        nonzero(cols)
+            .or_else(|| env_size("COLUMNS"))
+            .unwrap_or(DEFAULT_COLS),
+        nonzero(rows)
+            .or_else(|| env_size("LINES"))
+            .unwrap_or(DEFAULT_LINES),
+    )
+}
+
+fn nonzero(n: u16) -> Option<u16> {
+    (n > 0).then_some(n)
+}
+
+fn env_size(var: &str) -> Option<u16> {
+    env::var(var).ok()?.trim().parse().ok().and_then(nonzero)
+}
+
+#[cfg(unix)]
+fn window_size() -> Option<(u16, u16)> {
+    [libc::STDOUT_FILENO, libc::STDERR_FILENO, libc::STDIN_FILENO]
+        .into_iter()
+        .find_map(|fd| {
+            let mut size = libc::winsize {
+                ws_row: 0,
+                ws_col: 0,
+                ws_xpixel: 0,
+                ws_ypixel: 0,
+            };
+            // SAFETY: TIOCGWINSZ only writes a `winsize` into `size`.
+            let ok =
+                unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) } == 0;
+            (ok && size.ws_col > 0).then_some((size.ws_col, size.ws_row))
+        })
+}
+
+#[cfg(not(unix))]
+fn window_size() -> Option<(u16, u16)> {
+    None
+}
+
+/// The width which output to `STDOUT` should fit in, or `None` if `STDOUT` is
+/// not a terminal, in which case output should not be truncated.
+pub fn output_width() -> Option<usize> {
+    io::stdout().is_terminal().then(|| usize::from(cols()))
+}
+
+/// Whether output to `STDOUT` may be styled with ANSI escapes; i.e, `STDOUT`
+/// is a terminal, and `$NO_COLOR` is unset.
+pub fn styled() -> bool {
+    io::stdout().is_terminal() && color_allowed()
+}
+
+/// Like [styled], but for output to `STDERR`.
+pub fn styled_stderr() -> bool {
+    io::stderr().is_terminal() && color_allowed()
+}
+
+fn color_allowed() -> bool {
+    env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+}
+
+/// Print `text` to `STDOUT`. If `STDOUT` is a terminal and `text` is taller
+/// than the terminal, `text` is piped into `$PAGER` instead (or `less`, if
+/// `$PAGER` is unset).
+pub fn page(text: &str) -> Result<(), Error> {
+    if !io::stdout().is_terminal()
+        || text.lines().count() < usize::from(lines())
+    {
+        println!("{text}");
+        return Ok(());
+    }
+    let pager = env::var("PAGER")
+        .ok()
+        .filter(|p| !p.trim().is_empty())
+        .unwrap_or("less".into());
+    let mut child = Command::new("sh")
+        .args(["-c", &pager])
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            Error::default()
+                .wrap(Oops::CommandError)
+                .because(format!("could not start pager {pager:?}: {e}"))
+        })?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // The pager may exit before reading everything (i.e, the user quits
+        // early), so a broken pipe here is not an error.
+        if let Err(e) = writeln!(stdin, "{text}") {
+            if e.kind() != io::ErrorKind::BrokenPipe {
+                return Err(Error::default().wrap(Oops::CommandError).because(
+                    format!("could not write to pager {pager:?}: {e}"),
+                ));
+            }
+        }
+    }
+    child.wait().map_err(|e| {
+        Error::default()
+            .wrap(Oops::CommandError)
+            .because(format!("pager {pager:?} failed: {e}"))
+    })?;
+    Ok(())
+}
+
+/// Print `question` to `STDERR`, and read a line of input from `STDIN`.
+/// Returns `None` if `STDIN` is not a terminal, since there is nobody to
+/// answer.
+pub fn ask(question: &str) -> Result<Option<String>, Error> {
+    if !io::stdin().is_terminal() {
+        return Ok(None);
+    }
+    eprint!("{question}");
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer).map_err(|e| {
+        Error::default()
+            .wrap(Oops::StdinReadError)
+            .because(format!("could not read answer to {question:?}: {e}"))
+    })?;
+    Ok(Some(answer.trim().to_string()))
+}
+
+/// An answer to a review prompt like those of `git add -p`; see [ask].
+#[derive(Debug, PartialEq)]
+pub enum Decision {
+    Accept,
+    Reject,
+    Edit,
+    /// Accept this item, and all of those which follow.
+    AcceptAll,
+    /// Reject this item, and all of those which follow.
+    Quit,
+}
+
+impl Decision {
+    pub fn parse(answer: &str) -> Option<Self> {
+        match answer.to_lowercase().as_str() {
+            "y" | "yes" => Some(Self::Accept),
+            "n" | "no" => Some(Self::Reject),
+            "e" | "edit" => Some(Self::Edit),
+            "a" | "all" => Some(Self::AcceptAll),
+            "q" | "quit" => Some(Self::Quit),
+            _ => None,
+        }
+    }
+}
+
+/// Let the user edit `text` in `$VISUAL` or `$EDITOR` (or else `vi`), and
+/// return the result.
+pub fn edit(text: &str) -> Result<String, Error> {
+    let oops =
+        |why: String| Error::default().wrap(Oops::CommandError).because(why);
+    let editor = ["VISUAL", "EDITOR"]
+        .into_iter()
+        .find_map(|var| env::var(var).ok().filter(|e| !e.trim().is_empty()))
+        .unwrap_or("vi".into());
+    let path =
+        env::temp_dir().join(format!("yap-edit-{}.txt", std::process::id()));
+    std::fs::write(&path, text)
+        .map_err(|e| oops(format!("could not write {path:?}: {e}")))?;
+    // `$EDITOR` may include arguments, i.e, `code --wait`.
+    let status = Command::new("sh")
+        .args(["-c", &format!("{editor} \"$1\""), "sh"])
+        .arg(&path)
+        .status()
+        .map_err(|e| oops(format!("could not start editor {editor:?}: {e}")));
+    let edited = std::fs::read_to_string(&path)
+        .map_err(|e| oops(format!("could not read {path:?}: {e}")));
+    let _ = std::fs::remove_file(&path);
+    match status? {
+        status if status.success() => edited,
+        status => Err(oops(format!("editor {editor:?} failed: {status}"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decision_parse() {
+        assert_eq!(Decision::parse("Y"), Some(Decision::Accept));
+        assert_eq!(Decision::parse("edit"), Some(Decision::Edit));
+        assert_eq!(Decision::parse("q"), Some(Decision::Quit));
+        assert_eq!(Decision::parse(""), None);
+    }
+
+    #[test]
+    fn test_size_is_never_zero() {
+        let (cols, rows) = size();
+        assert!(cols > 0 && rows > 0);
+    }
+}
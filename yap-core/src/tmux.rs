@@ -0,0 +1,59 @@
+//! Capture the scrollback of a tmux pane, for `yap chat --tmux-pane`; i.e,
+//! to ask about an error without copying and pasting it.
+
+use crate::err::{Error, Oops};
+use std::{env, process::Command};
+
+/// How many lines of scrollback are captured, including the visible pane.
+const CAPTURE_LINES: usize = 200;
+
+fn oops(why: String) -> Error {
+    Error::default().wrap(Oops::TmuxError).because(why)
+}
+
+/// The text in the pane `target` (in any form which `tmux -t` accepts, e.g.
+/// `%3` or `{last}`), or else the current pane. Long lines which tmux
+/// wrapped are joined, and trailing blank lines are removed.
+pub fn capture(target: Option<&str>) -> Result<String, Error> {
+    if target.is_none() && env::var_os("TMUX").is_none() {
+        return Err(oops(
+            "Not running inside tmux; pass a pane to capture, e.g. `--tmux-pane=%1`".into(),
+        ));
+    }
+    let start = format!("-{CAPTURE_LINES}");
+    let mut command = Command::new("tmux");
+    command.args(["capture-pane", "-p", "-J", "-S", &start]);
+    if let Some(target) = target {
+        command.args(["-t", target]);
+    }
+    let output = command
+        .output()
+        .map_err(|e| oops(format!("Could not run tmux: {e}")))?;
+    if !output.status.success() {
+        return Err(oops(format!(
+            "tmux capture-pane failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim_end()
+        .to_string())
+}
+
+/// `prompt`, preceded by the captured pane `contents`.
+pub fn with_pane(contents: &str, prompt: &str) -> String {
+    format!("Terminal output from tmux:\n```\n{contents}\n```\n\n{prompt}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_pane() {
+        assert_eq!(
+            with_pane("$ cargo build\nerror[E0308]", "what does this mean?"),
+            "Terminal output from tmux:\n```\n$ cargo build\nerror[E0308]\n```\n\nwhat does this mean?"
+        );
+    }
+}
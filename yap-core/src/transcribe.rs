@@ -0,0 +1,43 @@
+//! Transcribe audio files with `yap transcribe`; i.e, to turn a voice memo
+//! about some code into text, or straight into a chat message.
+
+use crate::{
+    chat,
+    err::{Error, Oops},
+    openai::{audio_api, OpenAI},
+};
+use std::{fs, path::Path};
+
+fn oops(why: String) -> Error {
+    Error::default().wrap(Oops::TranscribeError).because(why)
+}
+
+/// Entrypoint for `yap transcribe`. Prints the transcript of `file`. If
+/// `into_chat` is set, the transcript is sent to the active chat as a prompt
+/// instead, as if it were passed to `yap chat`.
+pub fn transcribe(
+    open_ai: &OpenAI,
+    file: &Path,
+    into_chat: bool,
+) -> Result<(), Error> {
+    let audio = fs::read(file)
+        .map_err(|e| oops(format!("Could not read {file:?}: {e}")))?;
+    if audio.len() > audio_api::MAX_UPLOAD_BYTES {
+        return Err(oops(format!(
+            "{file:?} is larger than OpenAI's limit of {}MB",
+            audio_api::MAX_UPLOAD_BYTES / 1024 / 1024
+        )));
+    }
+    let transcript = audio_api::transcribe(open_ai, file, &audio)?;
+    let transcript = transcript.trim();
+    if into_chat {
+        if transcript.is_empty() {
+            return Err(oops(format!("No speech was found in {file:?}")));
+        }
+        eprintln!("> {transcript}");
+        chat::chat(open_ai, &[transcript.to_string()], &chat::Opts::default())
+    } else {
+        println!("{transcript}");
+        Ok(())
+    }
+}
@@ -0,0 +1,268 @@
+//! Record each request to OpenAI in `~/.local/state/yap/usage.jsonl`, and
+//! summarize the log with `yap stats`.
+//!
+//! Each line of the log is one [Record]. Recording is best-effort; a request
+//! never fails because its usage could not be recorded. Responses served
+//! from [crate::cache] are not recorded, since they don't cost anything.
+
+use crate::{
+    date, db,
+    err::{Error, Oops},
+    openai::{Model, OpenAI, Usage},
+    term,
+};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// The maximum number of days shown in the requests-per-day table.
+const DAYS: usize = 14;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Record {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub command: String,
+    pub model: Model,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub latency_ms: u64,
+}
+
+impl Record {
+    fn tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+fn log_path() -> Result<PathBuf, Error> {
+    Ok(db::get_or_create_persistence_dir()?.join("usage.jsonl"))
+}
+
+/// Append a request to the usage log.
+pub fn record(open_ai: &OpenAI, usage: Usage, latency: Duration) {
+    // Mock responses cost nothing.
+    if open_ai.is_mock() {
+        return;
+    }
+    let record = Record {
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        command: open_ai.command.to_string(),
+        model: open_ai.model.clone(),
+        prompt_tokens: usage.prompt_tokens,
+        completion_tokens: usage.completion_tokens,
+        latency_ms: latency.as_millis() as u64,
+    };
+    if let Err(e) = append(&record) {
+        warn!("Could not record usage: {e:?}");
+    }
+}
+
+fn append(record: &Record) -> Result<(), Error> {
+    let path = log_path()?;
+    let mut line = serde_json::to_string(record).map_err(|e| {
+        Error::default()
+            .wrap(Oops::StatsError)
+            .because(format!("Could not serialize usage record: {e}"))
+    })?;
+    line.push('\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut f| f.write_all(line.as_bytes()))
+        .map_err(|e| {
+            Error::default()
+                .wrap(Oops::StatsError)
+                .because(format!("Could not write to {path:?}: {e}"))
+        })
+}
+
+fn load() -> Result<Vec<Record>, Error> {
+    let path = log_path()?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let text = fs::read_to_string(&path).map_err(|e| {
+        Error::default()
+            .wrap(Oops::StatsError)
+            .because(format!("Could not read {path:?}: {e}"))
+    })?;
+    Ok(text
+        .lines()
+        .filter_map(|line| {
+            serde_json::from_str(line)
+                .inspect_err(|e| debug!("Skipping bad usage record: {e}"))
+                .ok()
+        })
+        .collect())
+}
+
+/// Entrypoint for `yap stats`.
+pub fn stats() -> Result<(), Error> {
+    let records = load()?;
+    if records.is_empty() {
+        println!("No usage has been recorded yet.");
+        return Ok(());
+    }
+    term::page(&report(
+        &records,
+        term::output_width().unwrap_or(usize::MAX),
+    ))
+}
+
+/// Running totals for one row of a table.
+#[derive(Default)]
+struct Totals {
+    requests: u64,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    latency_ms: u64,
+}
+
+impl Totals {
+    fn add(&mut self, r: &Record) {
+        self.requests += 1;
+        self.prompt_tokens += r.prompt_tokens;
+        self.completion_tokens += r.completion_tokens;
+        self.latency_ms += r.latency_ms;
+    }
+    fn avg_latency(&self) -> String {
+        format!(
+            "{:.1}s",
+            self.latency_ms as f64 / self.requests as f64 / 1000.
+        )
+    }
+}
+
+fn report(records: &[Record], width: usize) -> String {
+    let mut days: BTreeMap<String, Totals> = BTreeMap::new();
+    let mut models: BTreeMap<String, Totals> = BTreeMap::new();
+    let mut commands: BTreeMap<&str, Totals> = BTreeMap::new();
+    let mut all = Totals::default();
+    for r in records {
+        days.entry(date::date(r.timestamp)).or_default().add(r);
+        models.entry(r.model.to_string()).or_default().add(r);
+        commands.entry(&r.command).or_default().add(r);
+        all.add(r);
+    }
+
+    let day_rows = days
+        .iter()
+        .rev()
+        .take(DAYS)
+        .map(|(day, t)| {
+            vec![
+                day.clone(),
+                t.requests.to_string(),
+                (t.prompt_tokens + t.completion_tokens).to_string(),
+            ]
+        })
+        .collect();
+    let model_rows = models
+        .iter()
+        .map(|(model, t)| {
+            vec![
+                model.clone(),
+                t.requests.to_string(),
+                t.prompt_tokens.to_string(),
+                t.completion_tokens.to_string(),
+                (t.prompt_tokens + t.completion_tokens).to_string(),
+            ]
+        })
+        .collect();
+    let mut commands: Vec<_> = commands.into_iter().collect();
+    commands.sort_by_key(|(_, t)| std::cmp::Reverse(t.requests));
+    let command_rows = commands
+        .iter()
+        .map(|(command, t)| {
+            vec![
+                command.to_string(),
+                t.requests.to_string(),
+                (t.prompt_tokens + t.completion_tokens).to_string(),
+                t.avg_latency(),
+            ]
+        })
+        .collect();
+
+    format!(
+        "{} requests, {} tokens, {} average latency\n\n\
+        Requests per day (up to {DAYS} most recent days)\n{}\n\
+        Tokens per model\n{}\n\
+        Top commands\n{}",
+        all.requests,
+        records.iter().map(Record::tokens).sum::<u64>(),
+        all.avg_latency(),
+        table(&["Date", "Requests", "Tokens"], day_rows, width),
+        table(
+            &["Model", "Requests", "Prompt", "Completion", "Total"],
+            model_rows,
+            width
+        ),
+        table(
+            &["Command", "Requests", "Tokens", "Avg latency"],
+            command_rows,
+            width
+        ),
+    )
+}
+
+/// Render an aligned table. The first column is left-aligned and the rest
+/// are right-aligned; lines are cut off at `width` columns.
+pub fn table(headers: &[&str], rows: Vec<Vec<String>>, width: usize) -> String {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+    for row in &rows {
+        for (w, cell) in widths.iter_mut().zip(row) {
+            *w = (*w).max(cell.chars().count());
+        }
+    }
+    let headers = headers.iter().map(|h| h.to_string()).collect();
+    let rule = widths.iter().map(|w| "-".repeat(*w)).collect();
+    std::iter::once(headers)
+        .chain(std::iter::once(rule))
+        .chain(rows)
+        .map(|row| {
+            let line = row
+                .iter()
+                .zip(&widths)
+                .enumerate()
+                .map(|(i, (cell, w))| {
+                    if i == 0 {
+                        format!("{cell:<w$}")
+                    } else {
+                        format!("{cell:>w$}")
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("  ");
+            let line: String = line.trim_end().chars().take(width).collect();
+            line + "\n"
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table() {
+        let rows = vec![vec!["gpt-4o".into(), "12".into()]];
+        assert_eq!(
+            table(&["Model", "Requests"], rows.clone(), 80),
+            "Model   Requests\n------  --------\ngpt-4o        12\n"
+        );
+        assert!(table(&["Model", "Requests"], rows, 8)
+            .lines()
+            .all(|l| l.len() <= 8));
+    }
+}
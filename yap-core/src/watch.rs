@@ -0,0 +1,122 @@
+//! Re-run a `yap` command whenever files change, with `yap watch`; i.e, for
+//! continuous feedback while editing.
+//!
+//! Files are polled for changes, since that works the same everywhere. A
+//! burst of saves only triggers one run; the command runs once the files
+//! have been quiet for [DEBOUNCE]. Changes made by the command itself (i.e,
+//! by `yap annotate`) don't trigger another run. Press Ctrl-C to stop.
+//!
+//! Global options like `--model` are not passed along to the command, but
+//! they can be given after `--`; i.e, `yap watch -f a.rs -- --model o3 review`.
+
+use crate::err::{Error, Oops};
+use std::{
+    env, fs,
+    path::PathBuf,
+    process::Command,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+/// How often files are checked for changes.
+const POLL: Duration = Duration::from_millis(250);
+
+/// How long files must go unchanged before the command runs.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+fn oops(why: String) -> Error {
+    Error::default().wrap(Oops::WatchError).because(why)
+}
+
+/// Entrypoint for `yap watch`. Runs `yap <command>` now, and then again each
+/// time one of `files` changes. If `command` is empty, `prompt` is asked
+/// about `files` with `yap ask`.
+pub fn watch(
+    files: &[PathBuf],
+    prompt: Option<&str>,
+    command: &[String],
+) -> Result<(), Error> {
+    let args = command_args(files, prompt, command)?;
+    let yap = env::current_exe()
+        .map_err(|e| oops(format!("Could not find the yap executable: {e}")))?;
+    loop {
+        eprintln!("\n[yap watch] yap {}", args.join(" "));
+        match Command::new(&yap).args(&args).status() {
+            Ok(status) if !status.success() => {
+                eprintln!("[yap watch] command failed: {status}")
+            }
+            Ok(_) => {}
+            Err(e) => return Err(oops(format!("Could not run yap: {e}"))),
+        }
+        wait_for_change(files);
+    }
+}
+
+/// The arguments to pass to `yap` on each run.
+fn command_args(
+    files: &[PathBuf],
+    prompt: Option<&str>,
+    command: &[String],
+) -> Result<Vec<String>, Error> {
+    if !command.is_empty() {
+        return Ok(command.to_vec());
+    }
+    let prompt = prompt.ok_or_else(|| {
+        oops("Pass --prompt, or a command to run after `--`".into())
+    })?;
+    let mut args = vec!["ask".to_string()];
+    for file in files {
+        args.push("--file".into());
+        args.push(file.display().to_string());
+    }
+    args.push(prompt.to_string());
+    Ok(args)
+}
+
+/// The modification time of each file, or `None` for files which don't
+/// exist (yet).
+fn mtimes(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|f| fs::metadata(f).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// Block until the files' modification times change, and then stay the
+/// same for [DEBOUNCE].
+fn wait_for_change(files: &[PathBuf]) {
+    let seen = mtimes(files);
+    let mut current = seen.clone();
+    while current == seen {
+        thread::sleep(POLL);
+        current = mtimes(files);
+    }
+    loop {
+        thread::sleep(DEBOUNCE);
+        let settled = mtimes(files);
+        if settled == current {
+            return;
+        }
+        current = settled;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_args() {
+        let files = [PathBuf::from("src/lib.rs")];
+        assert_eq!(
+            command_args(&files, Some("check docs"), &[]).unwrap(),
+            vec!["ask", "--file", "src/lib.rs", "check docs"]
+        );
+        let command = ["review".to_string()];
+        assert_eq!(
+            command_args(&files, None, &command).unwrap(),
+            vec!["review"]
+        );
+        assert!(command_args(&files, None, &[]).is_err());
+    }
+}
@@ -0,0 +1,286 @@
+//! Run a declarative, multi-step LLM pipeline with `yap run workflow.yaml`,
+//! for tasks which are repeated often enough to write down; i.e,
+//!
+//! ```yaml
+//! vars:
+//!   file: src/main.rs
+//! steps:
+//!   - name: summary
+//!     prompt: Summarize {{file}}.
+//!     files: ["{{file}}"]
+//!   - name: review
+//!     prompt: |
+//!       Here is a summary of {{file}}:
+//!
+//!       {{summary}}
+//!
+//!       Review it for bugs.
+//!     files: ["{{file}}"]
+//!   - name: tests
+//!     model: gpt-4o
+//!     prompt: "Write unit tests which would catch these bugs: {{review}}"
+//!     output: tests.rs
+//! ```
+//!
+//! Steps run in order. `{{name}}` is replaced by the output of the step with
+//! that name, or else by the variable; variables may be overridden with `yap
+//! run --var file=src/lib.rs`. Each step may set its own `system` prompt and
+//! `model`, attach `files` (see [files::expand]), and write its output to a
+//! file with `output`. Like any other change to a file, an `output` is only
+//! written after [confirmation](crate::confirm), and the file is backed up
+//! first, so `yap undo` restores it; see [crate::backup]. The output of the
+//! last step is printed, unless it was written to a file. References are
+//! checked before anything is sent, so a typo doesn't waste the steps before
+//! it.
+
+use crate::{
+    backup,
+    confirm::{confirm, Operation},
+    err::{Error, Oops},
+    files, filter,
+    openai::{
+        chat, CompletionPayload, Content, Message, Model, OpenAI, PayloadOpts,
+        Role,
+    },
+};
+use regex::Regex;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Workflow {
+    #[serde(default)]
+    vars: BTreeMap<String, String>,
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Step {
+    name: String,
+    prompt: String,
+    system: Option<String>,
+    model: Option<Model>,
+    #[serde(default)]
+    files: Vec<String>,
+    output: Option<String>,
+}
+
+fn oops(why: String) -> Error {
+    Error::default().wrap(Oops::WorkflowError).because(why)
+}
+
+/// Parse `key=value` for `yap run --var`.
+pub fn parse_var(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .ok_or_else(|| format!("{s:?} is not a variable like key=value"))
+}
+
+/// Entrypoint for `yap run`. With `yes`, outputs are written without asking.
+pub fn run(
+    open_ai: &OpenAI,
+    path: &Path,
+    vars: &[(String, String)],
+    yes: bool,
+) -> Result<(), Error> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| oops(format!("Could not read {path:?}: {e}")))?;
+    let mut workflow: Workflow = serde_yaml::from_str(&text)
+        .map_err(|e| oops(format!("{path:?} is invalid: {e}")))?;
+    workflow.vars.extend(vars.iter().cloned());
+    check(&workflow)?;
+
+    let mut values: HashMap<String, String> =
+        workflow.vars.into_iter().collect();
+    let count = workflow.steps.len();
+    for (i, step) in workflow.steps.iter().enumerate() {
+        eprintln!("[{}/{count}] {}", i + 1, step.name);
+        let output = run_step(open_ai, step, &values)?;
+        let output = filter::apply("run", &output)?;
+        let written = match &step.output {
+            Some(file) => write_output(
+                &PathBuf::from(render(file, &values)),
+                &output,
+                yes,
+            )?,
+            None => false,
+        };
+        if !written && i + 1 == count {
+            println!("{}", output.trim_end());
+        }
+        values.insert(step.name.clone(), output);
+    }
+    Ok(())
+}
+
+/// Write a step's `output` to `file`, if the user confirms it. Returns
+/// whether it was written.
+fn write_output(file: &Path, output: &str, yes: bool) -> Result<bool, Error> {
+    let question = format!("Write {}? [y/N] ", file.display());
+    if !confirm(Operation::FileWrite, &question, yes)
+        .map_err(|e| e.wrap(Oops::WorkflowError))?
+    {
+        eprintln!("Did not write {}", file.display());
+        return Ok(false);
+    }
+    backup::save("run", file).map_err(|e| e.wrap(Oops::WorkflowError))?;
+    file.parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(file, output))
+        .map_err(|e| oops(format!("Could not write {file:?}: {e}")))?;
+    eprintln!("Wrote {}", file.display());
+    Ok(true)
+}
+
+fn run_step(
+    open_ai: &OpenAI,
+    step: &Step,
+    values: &HashMap<String, String>,
+) -> Result<String, Error> {
+    let open_ai = match &step.model {
+        Some(model) => open_ai.with_model(model.clone()),
+        None => open_ai.clone(),
+    };
+    let mut messages = Vec::new();
+    if let Some(system) = &step.system {
+        messages.push(Message::new(Role::System, render(system, values)));
+    }
+    let paths: Vec<PathBuf> = step
+        .files
+        .iter()
+        .map(|f| PathBuf::from(render(f, values)))
+        .collect();
+    for file in files::expand(&paths)? {
+        let contents = fs::read_to_string(&file)
+            .map_err(|e| oops(format!("Could not read {file:?}: {e}")))?;
+        messages.push(Message::new(
+            Role::User,
+            format!("File: {}\n```\n{contents}\n```", file.display()),
+        ));
+    }
+    messages.push(Message::new(Role::User, render(&step.prompt, values)));
+
+    let payload =
+        CompletionPayload::new(&open_ai, messages, PayloadOpts::default());
+    let response = chat(&open_ai, &payload).map_err(|e| {
+        e.wrap(Oops::WorkflowError)
+            .because(format!("Step {:?} failed", step.name))
+    })?;
+    match response.choices[0].message.parse()? {
+        Content::Normal(output) => Ok(output.to_string()),
+        Content::Refusal(refusal) => Err(Error::default()
+            .wrap(Oops::OpenAIRefusal)
+            .wrap(Oops::WorkflowError)
+            .because(format!("Step {:?} was refused: {refusal}", step.name))),
+    }
+}
+
+fn reference() -> &'static Regex {
+    static REFERENCE: OnceLock<Regex> = OnceLock::new();
+    REFERENCE.get_or_init(|| {
+        Regex::new(r"\{\{\s*([A-Za-z0-9_-]+)\s*\}\}").expect("valid regex")
+    })
+}
+
+/// Replace each `{{name}}` in `template` with its value. References have
+/// already been [check]ed.
+fn render(template: &str, values: &HashMap<String, String>) -> String {
+    reference()
+        .replace_all(template, |c: &regex::Captures| {
+            values.get(&c[1]).cloned().unwrap_or_default()
+        })
+        .into_owned()
+}
+
+/// Check that step names are unique, and that each step only refers to
+/// variables and earlier steps.
+fn check(workflow: &Workflow) -> Result<(), Error> {
+    if workflow.steps.is_empty() {
+        return Err(oops("The workflow has no steps".into()));
+    }
+    let mut known: HashSet<&str> =
+        workflow.vars.keys().map(String::as_str).collect();
+    for step in &workflow.steps {
+        let templates = [Some(&step.prompt), step.system.as_ref()]
+            .into_iter()
+            .flatten()
+            .chain(&step.files)
+            .chain(&step.output);
+        for template in templates {
+            for c in reference().captures_iter(template) {
+                if !known.contains(&c[1]) {
+                    return Err(oops(format!(
+                        "Step {:?} refers to {{{{{}}}}}, which is not a variable or an earlier step",
+                        step.name, &c[1]
+                    )));
+                }
+            }
+        }
+        if !known.insert(&step.name) {
+            return Err(oops(format!(
+                "There is already a step or variable named {:?}",
+                step.name
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(yaml: &str) -> Workflow {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_check() {
+        let workflow = parse(
+            "vars: {file: a.rs}\n\
+            steps:\n\
+            - {name: summary, prompt: 'Summarize {{file}}'}\n\
+            - {name: review, prompt: '{{ summary }}', output: '{{file}}.md'}\n",
+        );
+        assert!(check(&workflow).is_ok());
+        let forward = parse(
+            "steps:\n\
+            - {name: a, prompt: '{{b}}'}\n\
+            - {name: b, prompt: 'hi'}\n",
+        );
+        assert!(check(&forward).is_err());
+        let duplicate = parse(
+            "vars: {a: x}\n\
+            steps:\n\
+            - {name: a, prompt: 'hi'}\n",
+        );
+        assert!(check(&duplicate).is_err());
+        assert!(check(&parse("steps: []")).is_err());
+    }
+
+    #[test]
+    fn test_render() {
+        let values = HashMap::from([
+            ("file".to_string(), "a.rs".to_string()),
+            ("summary".to_string(), "It {{adds}}.".to_string()),
+        ]);
+        // Values are not rendered again.
+        assert_eq!(
+            render("{{summary}} See {{ file }}.", &values),
+            "It {{adds}}. See a.rs."
+        );
+    }
+
+    #[test]
+    fn test_parse_var() {
+        assert_eq!(parse_var("a=b=c"), Ok(("a".into(), "b=c".into())));
+        assert!(parse_var("a").is_err());
+    }
+}